@@ -0,0 +1,144 @@
+/*
+ * Submit and reap throughput of the queue code, against the running kernel:
+ *
+ *     cargo bench --bench ring
+ *
+ * Every iteration submits a batch of BATCH entries and reaps all of their
+ * completions. The SQPOLL ring only enters to wake its kernel thread and
+ * spins on the completion queue, so it shows the cost of entering at all.
+ * Its kernel thread needs a CPU of its own: on a single CPU the spinning
+ * reaper keeps it from running until the scheduler steps in.
+ */
+use bounded::{
+    builder::IoUringBuilder,
+    cqe::Completions,
+    fixed_buf::{FixedBuffers, FixedParams},
+    io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::{
+    fs::{remove_file, OpenOptions},
+    hint::spin_loop,
+    io::Write,
+    os::fd::AsRawFd,
+};
+
+const BATCH: u32 = 32;
+const READ_LEN: usize = 4096;
+
+fn reap(ring: &mut IoUring<'_>, count: u32) {
+    let mut reaped = 0;
+    while reaped < count {
+        match ring.next_completion() {
+            Some(_) => reaped += 1,
+            None => {
+                ring.submit_and_wait(1).unwrap();
+            }
+        }
+    }
+}
+
+fn spin_reap(ring: &mut IoUring<'_>, count: u32) {
+    let mut reaped = 0;
+    while reaped < count {
+        match ring.next_completion() {
+            Some(_) => reaped += 1,
+            None => spin_loop(),
+        }
+    }
+}
+
+fn prepare_nops(ring: &mut IoUring<'_>) {
+    for _ in 0..BATCH {
+        ring.next_sqe().unwrap().nop();
+    }
+}
+
+fn nop_batch(ring: &mut IoUring<'_>) {
+    prepare_nops(ring);
+    ring.submit_and_wait(BATCH).unwrap();
+    reap(ring, BATCH);
+}
+
+fn polled_nop_batch(ring: &mut IoUring<'_>) {
+    prepare_nops(ring);
+    ring.submit().unwrap();
+    spin_reap(ring, BATCH);
+}
+
+fn nops(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nop");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    let mut ring = IoUring::initialize(BATCH, IoUringParams::default()).unwrap();
+    group.bench_function("enter per batch", |b| b.iter(|| nop_batch(&mut ring)));
+
+    let mut registered = IoUring::initialize(BATCH, IoUringParams::default()).unwrap();
+    registered.register_ring_fd().unwrap();
+    group.bench_function("registered ring fd", |b| {
+        b.iter(|| nop_batch(&mut registered))
+    });
+
+    let mut polled = IoUringBuilder::new()
+        .setup_flags(IoUringSetupFlags::SqPool)
+        .build(BATCH)
+        .unwrap();
+    group.bench_function("sqpoll", |b| b.iter(|| polled_nop_batch(&mut polled)));
+
+    group.finish();
+}
+
+fn reads(c: &mut Criterion) {
+    let path = std::env::temp_dir().join(format!("vargasync-bench-{}", std::process::id()));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(&[7u8; READ_LEN]).unwrap();
+    let fd = file.as_raw_fd();
+
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Bytes((BATCH as usize * READ_LEN) as u64));
+
+    let mut ring = IoUring::initialize(BATCH, IoUringParams::default()).unwrap();
+    let mut buffers = vec![[0u8; READ_LEN]; BATCH as usize];
+    group.bench_function(BenchmarkId::new("read", READ_LEN), |b| {
+        b.iter(|| {
+            for buffer in buffers.iter_mut() {
+                unsafe {
+                    ring.next_sqe()
+                        .unwrap()
+                        .read(fd, buffer.as_mut_ptr(), READ_LEN as u32, 0)
+                };
+            }
+            ring.submit_and_wait(BATCH).unwrap();
+            reap(&mut ring, BATCH);
+        })
+    });
+
+    let mut fixed = FixedBuffers::new(BATCH as u16, READ_LEN);
+    ring.register_fixed_buffers(&mut fixed).unwrap();
+    let params: Vec<FixedParams> = fixed
+        .buffers_mut()
+        .map(|mut buffer| FixedParams::from(&mut buffer))
+        .collect();
+    group.bench_function(BenchmarkId::new("read_fixed", READ_LEN), |b| {
+        b.iter(|| {
+            for buffer in &params {
+                unsafe { ring.next_sqe().unwrap().read_fixed(fd, *buffer, 0) };
+            }
+            ring.submit_and_wait(BATCH).unwrap();
+            reap(&mut ring, BATCH);
+        })
+    });
+    ring.unregister_buffers().unwrap();
+
+    group.finish();
+    remove_file(path).unwrap();
+}
+
+criterion_group!(benches, nops, reads);
+criterion_main!(benches);
@@ -0,0 +1,136 @@
+use crate::{
+    arch::MmapOffset,
+    entry::{CqeEntry, SqeEntry},
+    io_uring::{atomic_u32, io_uring_queue_mmap, IoUring, IoUringSetupFlags},
+    memory::page_size,
+    mmap::{MMap, MapOptions},
+    syscalls::{RealSyscalls, UringSyscalls},
+};
+use linux_raw_sys::io_uring::{io_uring_params, IORING_OFF_SQ_RING};
+use std::{
+    fs,
+    io::{self, ErrorKind, Result},
+    mem::zeroed,
+    os::fd::{AsRawFd, OwnedFd},
+    sync::{atomic::Ordering, Arc},
+};
+
+/*
+ * The kernel starts the sq array on a cache line after the cqes.
+ */
+const SQ_ARRAY_ALIGNMENT: usize = 64;
+
+/*
+ * Rebuilds the params of a ring someone else set up, e.g. a launcher that
+ * passed the fd over a unix socket. The offsets are a property of the kernel,
+ * not of the ring, so a throwaway ring with the same entry sizes provides
+ * them. The sizes come from the ring header and whether a poller thread runs
+ * from fdinfo. Rings set up with NO_SQARRAY can't be adopted, and S and C
+ * must be the entry sizes the ring was created with.
+ */
+pub(crate) fn from_fd<'a, S: SqeEntry, C: CqeEntry>(fd: OwnedFd) -> Result<IoUring<'a, S, C>> {
+    let syscalls = RealSyscalls;
+    let layout = S::SETUP_FLAGS | C::SETUP_FLAGS;
+
+    let mut params: io_uring_params = unsafe { zeroed() };
+    params.flags = layout.bits();
+    drop(syscalls.setup(1, &mut params)?);
+
+    let header = MMap::new(
+        &fd,
+        IORING_OFF_SQ_RING as MmapOffset,
+        page_size(),
+        MapOptions::LAZY,
+    )?;
+    let read = |offset: u32| {
+        header
+            .add_offset(offset as usize)
+            .map(|pointer| unsafe { atomic_u32(pointer) }.load(Ordering::Acquire))
+            .ok_or_else(|| io::Error::other("the ring header is not mapped"))
+    };
+    params.sq_entries = read(params.sq_off.ring_entries)?;
+    params.cq_entries = read(params.cq_off.ring_entries)?;
+    let sq_tail = read(params.sq_off.tail)?;
+    drop(header);
+
+    if params.sq_entries == 0 || params.cq_entries == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the fd does not look like an io_uring ring",
+        ));
+    }
+
+    let cqes_end = params.cq_off.cqes as usize + params.cq_entries as usize * C::SIZE;
+    params.sq_off.array = cqes_end.next_multiple_of(SQ_ARRAY_ALIGNMENT) as u32;
+
+    if has_sq_thread(&fd)? {
+        params.flags |= IoUringSetupFlags::SqPool.bits();
+    }
+
+    let mut ring = io_uring_queue_mmap(fd, &params, Arc::new(syscalls))?;
+    ring.send_queue.sqe_head = sq_tail;
+    ring.send_queue.sqe_tail = sq_tail;
+
+    Ok(ring)
+}
+
+/*
+ * fdinfo of a ring reports the pid of the SQPOLL thread, -1 without one.
+ */
+fn has_sq_thread(fd: &OwnedFd) -> Result<bool> {
+    let info = fs::read_to_string(format!("/proc/self/fdinfo/{}", fd.as_raw_fd()))?;
+
+    Ok(info
+        .lines()
+        .find_map(|line| line.strip_prefix("SqThread:"))
+        .is_some_and(|pid| pid.trim() != "-1"))
+}
+
+#[cfg(test)]
+mod when_adopting_a_ring_fd {
+    use crate::{
+        cqe::Completions,
+        entry::{Cqe32, Sqe128},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+
+    #[test]
+    pub fn the_adopted_ring_picks_up_where_the_other_left() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().user_data, 1);
+
+        let fd = ring.as_fd().try_clone_to_owned().unwrap();
+        let mut adopted = IoUring::from_fd(fd).unwrap();
+
+        assert_ne!(adopted.as_raw_fd(), ring.as_raw_fd());
+        adopted.next_sqe().unwrap().nop().user_data(2);
+        adopted.submit_and_wait(1).unwrap();
+        assert_eq!(adopted.next_completion().unwrap().user_data, 2);
+    }
+
+    #[test]
+    pub fn big_entries_are_adopted_with_their_type() {
+        let ring = IoUring::<Sqe128, Cqe32>::initialize_sized(4, IoUringParams::default()).unwrap();
+        let fd = ring.as_fd().try_clone_to_owned().unwrap();
+
+        let mut adopted = IoUring::<Sqe128, Cqe32>::from_fd_sized(fd).unwrap();
+
+        for user_data in 0..4 {
+            adopted.next_sqe().unwrap().nop().user_data(user_data);
+        }
+        adopted.submit_and_wait(4).unwrap();
+        for user_data in 0..4 {
+            assert_eq!(adopted.next_completion().unwrap().user_data, user_data);
+        }
+    }
+
+    #[test]
+    pub fn other_fds_are_refused() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+
+        assert!(IoUring::from_fd(OwnedFd::from(file)).is_err());
+    }
+}
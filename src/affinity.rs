@@ -0,0 +1,233 @@
+use libc::{cpu_set_t, sched_getaffinity, sched_setaffinity, CPU_ISSET, CPU_SET, CPU_SETSIZE};
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind, Result},
+    mem::{size_of, zeroed},
+};
+
+/*
+ * Set of cpus as understood by sched_setaffinity(2).
+ */
+#[derive(Clone, Copy)]
+pub struct CpuSet {
+    set: cpu_set_t,
+}
+
+impl CpuSet {
+    pub fn new() -> Self {
+        CpuSet {
+            set: unsafe { zeroed() },
+        }
+    }
+
+    /*
+     * The cpus the calling thread may run on.
+     */
+    pub fn current() -> Result<Self> {
+        let mut cpus = Self::new();
+        if unsafe { sched_getaffinity(0, size_of::<cpu_set_t>(), &mut cpus.set) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(cpus)
+    }
+
+    pub fn with_cpu(mut self, cpu: u32) -> Self {
+        self.insert(cpu);
+        self
+    }
+
+    pub fn insert(&mut self, cpu: u32) {
+        if (cpu as i32) < CPU_SETSIZE {
+            unsafe { CPU_SET(cpu as usize, &mut self.set) };
+        }
+    }
+
+    pub fn contains(&self, cpu: u32) -> bool {
+        (cpu as i32) < CPU_SETSIZE && unsafe { CPU_ISSET(cpu as usize, &self.set) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..CPU_SETSIZE as u32).filter(|cpu| self.contains(*cpu))
+    }
+
+    pub fn first(&self) -> Option<u32> {
+        self.iter().next()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first().is_none()
+    }
+
+    /*
+     * Parses the list format of sysfs and cpusets, e.g. "0-3,8".
+     */
+    pub fn parse_list(list: &str) -> Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "malformed cpu list");
+        let mut cpus = Self::new();
+
+        for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let first: u32 = first.parse().map_err(|_| invalid())?;
+            let last: u32 = last.parse().map_err(|_| invalid())?;
+            (first..=last).for_each(|cpu| cpus.insert(cpu));
+        }
+
+        Ok(cpus)
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<u32> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(cpus: I) -> Self {
+        let mut set = Self::new();
+        cpus.into_iter().for_each(|cpu| set.insert(cpu));
+        set
+    }
+}
+
+impl PartialEq for CpuSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for CpuSet {}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/*
+ * Pins the calling thread, e.g. the one submitting to a ring, to `cpus`.
+ */
+pub fn pin_current_thread(cpus: &CpuSet) -> Result<()> {
+    if unsafe { sched_setaffinity(0, size_of::<cpu_set_t>(), &cpus.set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/*
+ * Hardware threads of the same core as `cpu`, itself included.
+ */
+pub fn thread_siblings(cpu: u32) -> Result<CpuSet> {
+    topology(cpu, "thread_siblings_list")
+}
+
+/*
+ * Cpus of the same package as `cpu`, itself included.
+ */
+pub fn core_siblings(cpu: u32) -> Result<CpuSet> {
+    topology(cpu, "core_siblings_list")
+}
+
+fn topology(cpu: u32, list: &str) -> Result<CpuSet> {
+    let path = format!("/sys/devices/system/cpu/cpu{}/topology/{}", cpu, list);
+    CpuSet::parse_list(&fs::read_to_string(path)?)
+}
+
+/*
+ * Where the SQPOLL thread and the application thread submitting to it go:
+ * two cpus close enough to share caches, so the entries and completions
+ * they hand each other stay warm, without competing for the same cpu.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiblingPlacement {
+    pub poller: u32,
+    pub application: u32,
+}
+
+impl SiblingPlacement {
+    /*
+     * Picks two cpus the calling thread may run on, hardware threads of one
+     * core when there are some, else cores of one package.
+     */
+    pub fn detect() -> Result<Self> {
+        let allowed = CpuSet::current()?;
+
+        choose(&allowed, thread_siblings)
+            .or_else(|| choose(&allowed, core_siblings))
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::Unsupported,
+                    "no two cpus of the same core or package are available",
+                )
+            })
+    }
+}
+
+fn choose<F>(allowed: &CpuSet, siblings: F) -> Option<SiblingPlacement>
+where
+    F: Fn(u32) -> Result<CpuSet>,
+{
+    allowed.iter().find_map(|application| {
+        let siblings = siblings(application).ok()?;
+        let poller = siblings
+            .iter()
+            .find(|cpu| *cpu != application && allowed.contains(*cpu))?;
+
+        Some(SiblingPlacement {
+            poller,
+            application,
+        })
+    })
+}
+
+#[cfg(test)]
+mod when_placing_threads_on_cpus {
+    use crate::affinity::{choose, pin_current_thread, CpuSet, SiblingPlacement};
+    use std::io::{self, ErrorKind};
+
+    #[test]
+    pub fn cpu_lists_are_parsed_like_sysfs_writes_them() {
+        let cpus = CpuSet::parse_list("0-2,8\n").unwrap();
+
+        assert_eq!(cpus.iter().collect::<Vec<_>>(), vec![0, 1, 2, 8]);
+        assert_eq!(
+            CpuSet::parse_list("1-x").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    pub fn the_current_thread_can_be_pinned_to_its_own_cpus() {
+        let cpus = CpuSet::current().unwrap();
+
+        pin_current_thread(&cpus).unwrap();
+
+        assert_eq!(CpuSet::current().unwrap(), cpus);
+    }
+
+    #[test]
+    pub fn the_poller_goes_to_an_allowed_sibling() {
+        let allowed: CpuSet = [1, 2, 3].into_iter().collect();
+        let pairs = |cpu: u32| Ok(CpuSet::new().with_cpu(cpu & !1).with_cpu(cpu | 1));
+
+        assert_eq!(
+            choose(&allowed, pairs),
+            Some(SiblingPlacement {
+                poller: 3,
+                application: 2,
+            })
+        );
+    }
+
+    #[test]
+    pub fn cpus_without_siblings_have_no_placement() {
+        let allowed: CpuSet = [0, 2].into_iter().collect();
+        let alone = |cpu: u32| Ok(CpuSet::new().with_cpu(cpu));
+        let unknown = |_| Err(io::Error::from(ErrorKind::NotFound));
+
+        assert_eq!(choose(&allowed, alone), None);
+        assert_eq!(choose(&allowed, unknown), None);
+    }
+}
@@ -1 +1,121 @@
+use libc::{c_int, c_long, c_void, size_t, MAP_FAILED};
+use linux_raw_sys::general::{__NR_io_uring_enter, __NR_io_uring_register, __NR_io_uring_setup};
+use std::{
+    io::{self, ErrorKind, Result},
+    ptr::NonNull,
+};
 
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "arm"
+)))]
+compile_error!(
+    "the io_uring syscalls are only wired up for x86_64, i686, aarch64, riscv64 and armv7"
+);
+
+/*
+ * io_uring came after the syscall tables of the architectures were unified,
+ * so its numbers are the same on every target the crate supports. They are
+ * spelled out here rather than taken from linux-raw-sys so a new target is
+ * added on purpose, and the asserts below check linux-raw-sys agrees on
+ * whatever target the crate is built for.
+ */
+pub(crate) const SYS_IO_URING_SETUP: c_long = 425;
+pub(crate) const SYS_IO_URING_ENTER: c_long = 426;
+pub(crate) const SYS_IO_URING_REGISTER: c_long = 427;
+
+const _: () = assert!(SYS_IO_URING_SETUP == __NR_io_uring_setup as c_long);
+const _: () = assert!(SYS_IO_URING_ENTER == __NR_io_uring_enter as c_long);
+const _: () = assert!(SYS_IO_URING_REGISTER == __NR_io_uring_register as c_long);
+
+/*
+ * Offset of a mapping of the ring fd, kept as a u64 up to the mmap call. The
+ * offsets of provided buffer rings take all 32 bits, which the 32 bit off_t
+ * of glibc on i686 and armv7 turns negative, so those targets map through
+ * mmap64.
+ */
+pub(crate) type MmapOffset = u64;
+
+#[cfg(not(all(target_env = "gnu", target_pointer_width = "32")))]
+use libc::{mmap as native_mmap, off_t as native_off_t};
+#[cfg(all(target_env = "gnu", target_pointer_width = "32"))]
+use libc::{mmap64 as native_mmap, off64_t as native_off_t};
+
+/// # Safety
+///
+/// Same as mmap(2), a mapping over `addr` replaces whatever was there.
+pub(crate) unsafe fn mmap(
+    addr: *mut c_void,
+    len: size_t,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: MmapOffset,
+) -> Result<NonNull<c_void>> {
+    let offset = native_off_t::try_from(offset).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "the offset does not fit the off_t of the target",
+        )
+    })?;
+
+    match native_mmap(addr, len, prot, flags, fd, offset) {
+        MAP_FAILED => Err(io::Error::last_os_error()),
+        addr => Ok(NonNull::new_unchecked(addr)),
+    }
+}
+
+#[cfg(test)]
+mod when_targeting_an_architecture {
+    use crate::{
+        arch::{mmap, native_off_t, MmapOffset},
+        buf_ring::{BufRingFlags, MAX_MMAP_GROUP_ID},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{MAP_ANONYMOUS, MAP_SHARED, PROT_READ};
+    use linux_raw_sys::io_uring::{IORING_OFF_PBUF_RING, IORING_OFF_PBUF_SHIFT};
+    use std::{io::ErrorKind, ptr::null_mut};
+
+    #[test]
+    pub fn the_offset_of_the_last_buffer_ring_fits_off_t() {
+        let offset = IORING_OFF_PBUF_RING as MmapOffset
+            | (MAX_MMAP_GROUP_ID as MmapOffset) << IORING_OFF_PBUF_SHIFT;
+
+        assert!(native_off_t::try_from(offset).is_ok());
+    }
+
+    #[test]
+    pub fn the_last_buffer_ring_is_mapped() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let buffers = ring
+            .register_buf_ring(MAX_MMAP_GROUP_ID, 4, BufRingFlags::Mmap)
+            .unwrap();
+
+        assert_eq!(buffers.group_id(), MAX_MMAP_GROUP_ID);
+        ring.unregister_buf_ring(buffers).unwrap();
+        assert!(matches!(
+            ring.register_buf_ring(MAX_MMAP_GROUP_ID + 1, 4, BufRingFlags::Mmap),
+            Err(error) if error.kind() == ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    pub fn an_offset_past_off_t_is_refused() {
+        let result = unsafe {
+            mmap(
+                null_mut(),
+                4096,
+                PROT_READ,
+                MAP_SHARED | MAP_ANONYMOUS,
+                -1,
+                u64::MAX,
+            )
+        };
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * The atomics behind the ring indexes. Under cfg(loom) they are loom's, so
+ * the model tests below can explore the interleavings of a producer and a
+ * consumer of a ring with the orderings the queues use:
+ *
+ *     RUSTFLAGS="--cfg loom" cargo test --release --lib atomic::
+ *
+ * The views of the indexes the kernel maps can only be loom atomics in name,
+ * a loom build is good for the model tests alone.
+ */
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicU32, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{fence, AtomicU32, Ordering};
+
+/*
+ * The head and tail of a ring, seen from either end. The producer moves the
+ * tail and reads the head, the consumer the other way round; each publishes
+ * its own index with Release and reads the other with Acquire, so what was
+ * written to a slot before the index moved is visible once it is read.
+ *
+ * Relaxed, both ways, when the two ends only meet in a syscall, which
+ * orders everything before it on one side with everything after it on the
+ * other, see LocalRing.
+ */
+pub(crate) struct RingIndexes<'i> {
+    head: &'i AtomicU32,
+    tail: &'i AtomicU32,
+    relaxed: bool,
+}
+
+impl<'i> RingIndexes<'i> {
+    pub(crate) fn new(head: &'i AtomicU32, tail: &'i AtomicU32, relaxed: bool) -> Self {
+        RingIndexes {
+            head,
+            tail,
+            relaxed,
+        }
+    }
+
+    fn acquire(&self) -> Ordering {
+        if self.relaxed {
+            Ordering::Relaxed
+        } else {
+            Ordering::Acquire
+        }
+    }
+
+    fn release(&self) -> Ordering {
+        if self.relaxed {
+            Ordering::Relaxed
+        } else {
+            Ordering::Release
+        }
+    }
+
+    /*
+     * Producer end: where the consumer got to, the slots before it are free
+     * again.
+     */
+    pub(crate) fn consumed(&self) -> u32 {
+        self.head.load(self.acquire())
+    }
+
+    /*
+     * Producer end: hands the slots up to `tail` to the consumer.
+     */
+    pub(crate) fn publish(&self, tail: u32) {
+        self.tail.store(tail, self.release());
+    }
+
+    /*
+     * Consumer end: where it is at, only the consumer moves it.
+     */
+    pub(crate) fn head(&self) -> u32 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    /*
+     * Consumer end: where the producer got to, the slots before it are
+     * filled.
+     */
+    pub(crate) fn produced(&self) -> u32 {
+        self.tail.load(self.acquire())
+    }
+
+    /*
+     * Consumer end: gives `count` slots back to the producer.
+     */
+    pub(crate) fn consume(&self, count: u32) {
+        self.head
+            .store(self.head().wrapping_add(count), self.release());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod when_exploring_the_ring_protocol {
+    use crate::atomic::{AtomicU32, RingIndexes};
+    use loom::{
+        cell::UnsafeCell,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    const ENTRIES: u32 = 2;
+
+    struct Ring {
+        head: AtomicU32,
+        tail: AtomicU32,
+        slots: [UnsafeCell<u64>; ENTRIES as usize],
+    }
+
+    impl Ring {
+        fn new() -> Self {
+            Ring {
+                head: AtomicU32::new(0),
+                tail: AtomicU32::new(0),
+                slots: [UnsafeCell::new(0), UnsafeCell::new(0)],
+            }
+        }
+
+        fn indexes(&self, relaxed: bool) -> RingIndexes<'_> {
+            RingIndexes::new(&self.head, &self.tail, relaxed)
+        }
+    }
+
+    #[test]
+    pub fn entries_wrap_around_between_two_threads() {
+        loom::model(|| {
+            let ring = Arc::new(Ring::new());
+
+            let consumer_ring = ring.clone();
+            let consumer = thread::spawn(move || {
+                let indexes = consumer_ring.indexes(false);
+                let mut received = Vec::new();
+                while received.len() < 3 {
+                    let head = indexes.head();
+                    if indexes.produced() == head {
+                        thread::yield_now();
+                        continue;
+                    }
+                    let slot = &consumer_ring.slots[(head % ENTRIES) as usize];
+                    received.push(slot.with(|value| unsafe { *value }));
+                    indexes.consume(1);
+                }
+                received
+            });
+
+            let indexes = ring.indexes(false);
+            for (tail, value) in (0..3u32).zip([10, 20, 30]) {
+                while tail.wrapping_sub(indexes.consumed()) == ENTRIES {
+                    thread::yield_now();
+                }
+                let slot = &ring.slots[(tail % ENTRIES) as usize];
+                slot.with_mut(|slot| unsafe { *slot = value });
+                indexes.publish(tail + 1);
+            }
+
+            assert_eq!(consumer.join().unwrap(), [10, 20, 30]);
+        });
+    }
+
+    /*
+     * The lock stands for io_uring_enter: the consumer only looks at the
+     * ring inside it, after the producer entered.
+     */
+    #[test]
+    pub fn relaxed_indexes_hold_when_the_ends_meet_in_a_syscall() {
+        loom::model(|| {
+            let ring = Arc::new(Ring::new());
+            let enter = Arc::new(Mutex::new(false));
+
+            let kernel_ring = ring.clone();
+            let kernel_enter = enter.clone();
+            let kernel = thread::spawn(move || {
+                let entered = kernel_enter.lock().unwrap();
+                if !*entered {
+                    return None;
+                }
+                let indexes = kernel_ring.indexes(true);
+                let head = indexes.head();
+                if indexes.produced() == head {
+                    return None;
+                }
+                let value =
+                    kernel_ring.slots[(head % ENTRIES) as usize].with(|value| unsafe { *value });
+                indexes.consume(1);
+                Some(value)
+            });
+
+            let indexes = ring.indexes(true);
+            ring.slots[0].with_mut(|slot| unsafe { *slot = 7 });
+            indexes.publish(1);
+            *enter.lock().unwrap() = true;
+
+            if let Some(value) = kernel.join().unwrap() {
+                assert_eq!(value, 7);
+            }
+        });
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    buf_ring::{BufRing, BufRingFlags, BufferSegment, Replenish},
+    cqe::Completion,
+    entry::{CqeEntry, SqeEntry},
+    io_uring::IoUring,
+};
+use std::io::{self, ErrorKind, Result};
+
+/*
+ * A class of buffers of the same length, see BufPool.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClass {
+    pub buffer_len: u32,
+    /*
+     * A power of two, the buffer ring of the class has as many entries.
+     */
+    pub buffers: u16,
+}
+
+/*
+ * Provided buffers in several sizes, one buffer group per size class, e.g.
+ * 2 KiB, 16 KiB and 64 KiB. An operation selects from the group of the
+ * smallest class its data fits in, so small messages do not each tie up a
+ * buffer sized for the largest. The buffers are allocated by the rings and
+ * go back to the kernel as soon as they are released.
+ */
+pub struct BufPool<'a> {
+    /*
+     * By buffer length, smallest first.
+     */
+    classes: Vec<BufRing<'a>>,
+    lengths: Vec<u32>,
+}
+
+impl<'a> BufPool<'a> {
+    /*
+     * Registers a buffer ring per class, with group ids from `first_group`
+     * on in order of buffer length.
+     */
+    pub fn register<S: SqeEntry, C: CqeEntry>(
+        ring: &IoUring<'a, S, C>,
+        first_group: u16,
+        classes: &[SizeClass],
+    ) -> Result<Self> {
+        let mut sorted = classes.to_vec();
+        sorted.sort_by_key(|class| class.buffer_len);
+        if sorted
+            .windows(2)
+            .any(|pair| pair[0].buffer_len == pair[1].buffer_len)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "two size classes have the same buffer length",
+            ));
+        }
+
+        let mut pool = BufPool {
+            classes: Vec::new(),
+            lengths: Vec::new(),
+        };
+        for (group_id, class) in (first_group..).zip(&sorted) {
+            let policy = Replenish {
+                low_watermark: class.buffers,
+                buffer_len: class.buffer_len,
+                max_buffers: class.buffers,
+            };
+            let mut buffers =
+                match ring.register_buf_ring(group_id, class.buffers, BufRingFlags::empty()) {
+                    Ok(buffers) => buffers,
+                    Err(error) => {
+                        pool.unregister(ring)?;
+                        return Err(error);
+                    }
+                };
+            let refilled = buffers.set_replenish(Some(policy));
+            pool.classes.push(buffers);
+            pool.lengths.push(class.buffer_len);
+            if let Err(error) = refilled {
+                pool.unregister(ring)?;
+                return Err(error);
+            }
+        }
+
+        Ok(pool)
+    }
+
+    /*
+     * Group of the smallest class with buffers of at least `len` bytes, to
+     * select from with Sqe::buffer_select. None when `len` is longer than
+     * every class.
+     */
+    pub fn group_for(&self, len: usize) -> Option<u16> {
+        let class = self
+            .lengths
+            .iter()
+            .position(|&buffer_len| buffer_len as usize >= len)?;
+
+        Some(self.classes[class].group_id())
+    }
+
+    pub fn buffer_len(&self, group_id: u16) -> Option<u32> {
+        let class = self.class(group_id)?;
+        Some(self.lengths[class])
+    }
+
+    pub fn buf_ring(&self, group_id: u16) -> Option<&BufRing<'a>> {
+        self.classes.get(self.class(group_id)?)
+    }
+
+    /*
+     * Accounts a completion that selected a buffer of `group_id`.
+     */
+    pub fn consume(&mut self, group_id: u16, completion: &Completion) -> Option<BufferSegment> {
+        let class = self.class(group_id)?;
+        self.classes[class].consume(completion)
+    }
+
+    pub fn data(&self, group_id: u16, segment: &BufferSegment) -> Option<&[u8]> {
+        self.buf_ring(group_id)?.data(segment)
+    }
+
+    /*
+     * Gives a consumed buffer back to the kernel.
+     */
+    pub fn release(&mut self, group_id: u16, buffer_id: u16) {
+        if let Some(class) = self.class(group_id) {
+            self.classes[class].release(buffer_id);
+        }
+    }
+
+    pub fn unregister<S: SqeEntry, C: CqeEntry>(self, ring: &IoUring<'a, S, C>) -> Result<()> {
+        let mut result = Ok(());
+        for buffers in self.classes {
+            if let Err(error) = ring.unregister_buf_ring(buffers) {
+                result = Err(error);
+            }
+        }
+
+        result
+    }
+
+    fn class(&self, group_id: u16) -> Option<usize> {
+        self.classes
+            .iter()
+            .position(|buffers| buffers.group_id() == group_id)
+    }
+}
+
+#[cfg(test)]
+mod when_picking_buffers_by_size {
+    use crate::{
+        buf_pool::{BufPool, SizeClass},
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{
+        io::{pipe, ErrorKind, Write},
+        os::fd::AsRawFd,
+        ptr::null_mut,
+    };
+
+    fn classes() -> [SizeClass; 2] {
+        [
+            SizeClass {
+                buffer_len: 1024,
+                buffers: 2,
+            },
+            SizeClass {
+                buffer_len: 64,
+                buffers: 4,
+            },
+        ]
+    }
+
+    #[test]
+    pub fn the_smallest_class_that_fits_is_picked() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut pool = BufPool::register(&ring, 10, &classes()).unwrap();
+        assert_eq!(pool.group_for(16), Some(10));
+        assert_eq!(pool.group_for(500), Some(11));
+        assert_eq!(pool.group_for(4096), None);
+
+        let (reader, mut writer) = pipe().unwrap();
+        writer.write_all(b"small").unwrap();
+        let group_id = pool.group_for(5).unwrap();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), null_mut(), 64, u64::MAX)
+        }
+        .buffer_select(group_id);
+        ring.submit_and_wait(1).unwrap();
+
+        let completion = ring.next_completion().unwrap();
+        let segment = pool.consume(group_id, &completion).unwrap();
+        assert_eq!(pool.data(group_id, &segment).unwrap(), b"small");
+        assert_eq!(pool.buf_ring(group_id).unwrap().available(), 3);
+        pool.release(group_id, segment.buffer_id);
+        assert_eq!(pool.buf_ring(group_id).unwrap().available(), 4);
+
+        pool.unregister(&ring).unwrap();
+    }
+
+    #[test]
+    pub fn classes_of_the_same_length_are_refused() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut classes = classes();
+        classes[1].buffer_len = 1024;
+
+        let error = BufPool::register(&ring, 10, &classes).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
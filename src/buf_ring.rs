@@ -0,0 +1,703 @@
+use crate::{
+    arch::MmapOffset,
+    cqe::Completion,
+    entry::{CqeEntry, SqeEntry},
+    io_uring::IoUring,
+    mmap::{MMap, MapOptions},
+    syscalls::IoUringOpCode,
+};
+use bitflags::bitflags;
+use libc::c_void;
+use linux_raw_sys::io_uring::{
+    io_uring_buf, io_uring_buf_reg, io_uring_register_pbuf_ring_flags, IORING_OFF_MMAP_MASK,
+    IORING_OFF_PBUF_RING, IORING_OFF_PBUF_SHIFT,
+};
+use std::{
+    io::{self, ErrorKind, Result},
+    mem::size_of,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+const MAX_ENTRIES: u16 = 1 << 15;
+/*
+ * The kernel reads the group of a ring it allocated back from the bits of
+ * the mmap offset below IORING_OFF_MMAP_MASK, higher groups cannot be mapped.
+ */
+pub(crate) const MAX_MMAP_GROUP_ID: u16 = (!IORING_OFF_MMAP_MASK >> IORING_OFF_PBUF_SHIFT) as u16;
+
+/*
+ * The tail shares the first entry with the buffer at index 0, it lives in
+ * the resv field of that io_uring_buf.
+ */
+const TAIL_OFFSET: usize = 14;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BufRingFlags: u16 {
+        /*
+         * The kernel allocates the ring and the application maps it, so the
+         * ring memory does not have to be page aligned by hand.
+         */
+        const Mmap = io_uring_register_pbuf_ring_flags::IOU_PBUF_RING_MMAP as u16;
+        /*
+         * A buffer is consumed a completion at a time instead of whole, the
+         * kernel keeps it until it is full, see BufRing::consume.
+         */
+        const Incremental = io_uring_register_pbuf_ring_flags::IOU_PBUF_RING_INC as u16;
+    }
+}
+
+/*
+ * Part of a provided buffer filled by a completion. With incremental
+ * consumption offset is where the data starts, and more tells the kernel
+ * still holds the buffer for the next completions.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSegment {
+    pub buffer_id: u16,
+    pub offset: u32,
+    pub len: u32,
+    pub more: bool,
+}
+
+/*
+ * When and how a BufRing refills itself, see BufRing::set_replenish.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replenish {
+    /*
+     * Below this many buffers left with the kernel, the ring refills.
+     */
+    pub low_watermark: u16,
+    /*
+     * Length of the buffers the ring allocates itself.
+     */
+    pub buffer_len: u32,
+    /*
+     * Buffers the ring may have in all, the ones added by hand included. No
+     * more than its entries.
+     */
+    pub max_buffers: u16,
+}
+
+/*
+ * Ring of provided buffers for a buffer group. Buffers are added with add,
+ * handed to the kernel with commit, and picked by the kernel for entries
+ * prepared with Sqe::buffer_select. The buffer id of a completion tells
+ * which one was used, it belongs to the application again from there on.
+ */
+pub struct BufRing<'a> {
+    ring: MMap<'a>,
+    group_id: u16,
+    entries: u16,
+    flags: BufRingFlags,
+    staged: u16,
+    /*
+     * Entries the kernel has consumed, as far as the completions seen tell.
+     */
+    head: u16,
+    /*
+     * Length and bytes already consumed of each buffer, indexed by buffer id.
+     */
+    lengths: Vec<u32>,
+    consumed: Vec<u32>,
+    /*
+     * Address of each buffer, indexed by buffer id, to add it again once
+     * released.
+     */
+    addrs: Vec<u64>,
+    released: Vec<u16>,
+    replenish: Option<Replenish>,
+    /*
+     * Buffers the ring allocated to refill itself, by buffer id. The kernel
+     * may write to them until the ring is unregistered, they are leaked
+     * otherwise.
+     */
+    allocated: Vec<(u16, Box<[u8]>)>,
+}
+
+impl<'a> BufRing<'a> {
+    pub(crate) fn register<S: SqeEntry, C: CqeEntry>(
+        io_uring: &IoUring<'a, S, C>,
+        group_id: u16,
+        entries: u16,
+        flags: BufRingFlags,
+    ) -> Result<BufRing<'a>> {
+        if !entries.is_power_of_two() || entries > MAX_ENTRIES {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "buffer ring entries must be a power of two up to {}, got {}",
+                    MAX_ENTRIES, entries
+                ),
+            ));
+        }
+
+        if flags.contains(BufRingFlags::Mmap) && group_id > MAX_MMAP_GROUP_ID {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "a kernel allocated buffer ring takes a group up to {}, got {}",
+                    MAX_MMAP_GROUP_ID, group_id
+                ),
+            ));
+        }
+
+        let len = entries as usize * size_of::<io_uring_buf>();
+        let ring = if flags.contains(BufRingFlags::Mmap) {
+            None
+        } else {
+            Some(MMap::anonymous(len)?)
+        };
+
+        let registration = io_uring_buf_reg {
+            ring_addr: ring
+                .as_ref()
+                .and_then(|ring| ring.add_offset(0))
+                .map_or(0, |addr| addr.as_ptr() as u64),
+            ring_entries: entries as u32,
+            bgid: group_id,
+            flags: flags.bits(),
+            resv: [0; 3],
+        };
+
+        io_uring.register(
+            IoUringOpCode::IoRingRegisterPbufRing,
+            &registration as *const io_uring_buf_reg as *const c_void,
+            1,
+        )?;
+
+        let ring = match ring {
+            Some(ring) => ring,
+            None => {
+                let offset = IORING_OFF_PBUF_RING as MmapOffset
+                    | (group_id as MmapOffset) << IORING_OFF_PBUF_SHIFT;
+                io_uring.syscalls.mmap(
+                    &io_uring.ring_file_descriptor,
+                    offset,
+                    len,
+                    MapOptions::POPULATED,
+                )?
+            }
+        };
+
+        Ok(BufRing {
+            ring,
+            group_id,
+            entries,
+            flags,
+            staged: 0,
+            head: 0,
+            lengths: Vec::new(),
+            consumed: Vec::new(),
+            addrs: Vec::new(),
+            released: Vec::new(),
+            replenish: None,
+            allocated: Vec::new(),
+        })
+    }
+
+    pub(crate) fn unregister<S: SqeEntry, C: CqeEntry>(
+        mut self,
+        io_uring: &IoUring<'a, S, C>,
+    ) -> Result<()> {
+        let registration = io_uring_buf_reg {
+            ring_addr: 0,
+            ring_entries: 0,
+            bgid: self.group_id,
+            flags: 0,
+            resv: [0; 3],
+        };
+
+        io_uring.register(
+            IoUringOpCode::IoRingUnregisterPbufRing,
+            &registration as *const io_uring_buf_reg as *const c_void,
+            1,
+        )?;
+        self.allocated.clear();
+
+        Ok(())
+    }
+
+    pub fn group_id(&self) -> u16 {
+        self.group_id
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    pub fn flags(&self) -> BufRingFlags {
+        self.flags
+    }
+
+    fn entry(&self, position: u16) -> *mut io_uring_buf {
+        let index = position & (self.entries - 1);
+        self.ring
+            .add_offset(index as usize * size_of::<io_uring_buf>())
+            .unwrap()
+            .as_ptr() as *mut io_uring_buf
+    }
+
+    /*
+     * The kernel only reads the tail, the atomic store orders the entry
+     * writes before it.
+     */
+    fn tail(&self) -> &AtomicU16 {
+        let tail = self.ring.add_offset(TAIL_OFFSET).unwrap();
+        unsafe { &*(tail.as_ptr() as *const AtomicU16) }
+    }
+
+    /// Stages a buffer, the kernel does not see it until commit.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be valid for writes of `len` bytes until the kernel hands
+    /// the buffer back in a completion, or the ring is unregistered.
+    pub unsafe fn add(&mut self, addr: *mut u8, len: u32, buffer_id: u16) {
+        let tail = self.tail().load(Ordering::Relaxed);
+        let entry = self.entry(tail.wrapping_add(self.staged));
+
+        (*entry).addr = addr as u64;
+        (*entry).len = len;
+        (*entry).bid = buffer_id;
+        self.staged = self.staged.wrapping_add(1);
+
+        let slot = buffer_id as usize;
+        if self.lengths.len() <= slot {
+            self.lengths.resize(slot + 1, 0);
+            self.consumed.resize(slot + 1, 0);
+            self.addrs.resize(slot + 1, 0);
+        }
+        self.lengths[slot] = len;
+        self.consumed[slot] = 0;
+        self.addrs[slot] = addr as u64;
+    }
+
+    /*
+     * The bytes of `segment`, for buffers the ring allocated itself, None for
+     * the ones added by hand.
+     */
+    pub fn data(&self, segment: &BufferSegment) -> Option<&[u8]> {
+        let index = self
+            .allocated
+            .binary_search_by_key(&segment.buffer_id, |(buffer_id, _)| *buffer_id)
+            .ok()?;
+
+        self.allocated[index]
+            .1
+            .get(segment.offset as usize..(segment.offset + segment.len) as usize)
+    }
+
+    /*
+     * Buffers committed to the kernel and not handed back yet.
+     */
+    pub fn available(&self) -> u16 {
+        self.tail().load(Ordering::Relaxed).wrapping_sub(self.head)
+    }
+
+    /*
+     * Refills the ring whenever fewer than the low watermark of `policy`
+     * buffers are left with the kernel: first with the buffers released
+     * since, then with new ones the ring allocates, up to the maximum. The
+     * check runs on each consume and release, so a multishot receive gets
+     * buffers back before it runs dry and ends with ENOBUFS. None turns it
+     * off.
+     */
+    pub fn set_replenish(&mut self, policy: Option<Replenish>) -> Result<()> {
+        if policy.is_some_and(|policy| policy.max_buffers > self.entries) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a buffer ring cannot hold more buffers than its entries",
+            ));
+        }
+        self.replenish = policy;
+        self.replenish();
+
+        Ok(())
+    }
+
+    /*
+     * Hands a buffer back from the application once it is done with the
+     * data, the ring adds it again when it refills. Only with a replenish
+     * policy, without one buffers are added back with add.
+     */
+    pub fn release(&mut self, buffer_id: u16) {
+        if (buffer_id as usize) < self.addrs.len() && !self.released.contains(&buffer_id) {
+            self.released.push(buffer_id);
+        }
+        self.replenish();
+    }
+
+    /*
+     * Refills the ring as the replenish policy says, returns the number of
+     * buffers added.
+     */
+    pub fn replenish(&mut self) -> u16 {
+        let Some(policy) = self.replenish else {
+            return 0;
+        };
+        if self.available() >= policy.low_watermark {
+            return 0;
+        }
+
+        let mut added = 0;
+        while let Some(buffer_id) = self.released.pop() {
+            let slot = buffer_id as usize;
+            let (addr, len) = (self.addrs[slot], self.lengths[slot]);
+            unsafe { self.add(addr as *mut u8, len, buffer_id) };
+            added += 1;
+        }
+        while self.lengths.len() < policy.max_buffers as usize {
+            let mut buffer = vec![0u8; policy.buffer_len as usize].into_boxed_slice();
+            let buffer_id = self.lengths.len() as u16;
+            unsafe { self.add(buffer.as_mut_ptr(), policy.buffer_len, buffer_id) };
+            self.allocated.push((buffer_id, buffer));
+            added += 1;
+        }
+        self.commit();
+
+        added
+    }
+
+    /*
+     * Accounts a completion that used a buffer of this ring and tells where
+     * its data is. Once more is false the buffer is back with the
+     * application and can be added again.
+     */
+    pub fn consume(&mut self, completion: &Completion) -> Option<BufferSegment> {
+        let buffer_id = completion.buffer_id()?;
+        let len = completion.result.max(0) as u32;
+        let more = self.flags.contains(BufRingFlags::Incremental) && completion.buffer_more();
+        let consumed = self.consumed.get_mut(buffer_id as usize)?;
+
+        let offset = if self.flags.contains(BufRingFlags::Incremental) {
+            *consumed
+        } else {
+            0
+        };
+        *consumed = if more { offset + len } else { 0 };
+        if !more {
+            self.head = self.head.wrapping_add(1);
+            self.replenish();
+        }
+
+        Some(BufferSegment {
+            buffer_id,
+            offset,
+            len,
+            more,
+        })
+    }
+
+    /*
+     * Splits a bundle completion into the buffers it filled. The kernel takes
+     * them in ring order starting at the one in the completion, so every
+     * completion of this ring has to go through consume or consume_bundle.
+     * Not supported with incremental consumption.
+     */
+    pub fn consume_bundle(&mut self, completion: &Completion) -> Option<Vec<BufferSegment>> {
+        let buffer_id = completion.buffer_id()?;
+        if self.flags.contains(BufRingFlags::Incremental)
+            || unsafe { (*self.entry(self.head)).bid } != buffer_id
+        {
+            return None;
+        }
+
+        let mut remaining = completion.result.max(0) as u32;
+        let mut segments = vec![];
+        while remaining > 0 {
+            let buffer_id = unsafe { (*self.entry(self.head)).bid };
+            let len = remaining.min(*self.lengths.get(buffer_id as usize)?);
+
+            segments.push(BufferSegment {
+                buffer_id,
+                offset: 0,
+                len,
+                more: false,
+            });
+            remaining -= len;
+            self.head = self.head.wrapping_add(1);
+        }
+        self.replenish();
+
+        Some(segments)
+    }
+
+    /*
+     * Bytes of the buffer the kernel can still fill.
+     */
+    pub fn remaining(&self, buffer_id: u16) -> Option<u32> {
+        let slot = buffer_id as usize;
+        Some(self.lengths.get(slot)? - self.consumed.get(slot)?)
+    }
+
+    /*
+     * Publishes the staged buffers to the kernel.
+     */
+    pub fn commit(&mut self) {
+        let tail = self.tail();
+        tail.store(
+            tail.load(Ordering::Relaxed).wrapping_add(self.staged),
+            Ordering::Release,
+        );
+        self.staged = 0;
+    }
+}
+
+impl Drop for BufRing<'_> {
+    fn drop(&mut self) {
+        for (_, buffer) in self.allocated.drain(..) {
+            Box::leak(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_providing_buffers_through_a_ring {
+    use crate::{
+        buf_ring::{BufRingFlags, BufferSegment},
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+
+    fn read_through_a_buffer_ring(flags: BufRingFlags) {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffers = ring.register_buf_ring(7, 4, flags).unwrap();
+        let mut memory = [0u8; 4 * 16];
+        for (buffer_id, chunk) in memory.chunks_mut(16).enumerate() {
+            unsafe { buffers.add(chunk.as_mut_ptr(), 16, buffer_id as u16) };
+        }
+        buffers.commit();
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert_eq!(
+            unsafe { libc::write(fds[1], b"hello".as_ptr() as *const _, 5) },
+            5
+        );
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], std::ptr::null_mut(), 16, u64::MAX)
+        }
+        .buffer_select(buffers.group_id());
+        ring.submit_and_wait(1).unwrap();
+
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.result, 5);
+        assert_eq!(completion.buffer_id(), Some(0));
+        assert_eq!(&memory[..5], b"hello");
+
+        ring.unregister_buf_ring(buffers).unwrap();
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn an_application_allocated_ring_feeds_reads() {
+        read_through_a_buffer_ring(BufRingFlags::empty());
+    }
+
+    #[test]
+    pub fn a_kernel_allocated_ring_feeds_reads() {
+        read_through_a_buffer_ring(BufRingFlags::Mmap);
+    }
+
+    #[test]
+    pub fn incremental_buffers_are_filled_across_completions() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffers = ring
+            .register_buf_ring(3, 1, BufRingFlags::Mmap | BufRingFlags::Incremental)
+            .unwrap();
+        let mut memory = [0u8; 16];
+        unsafe { buffers.add(memory.as_mut_ptr(), 16, 9) };
+        buffers.commit();
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+        let mut segments = vec![];
+        for chunk in [&b"abc"[..], &b"defg"[..]] {
+            unsafe { libc::write(fds[1], chunk.as_ptr() as *const _, chunk.len()) };
+            unsafe {
+                ring.next_sqe()
+                    .unwrap()
+                    .read(fds[0], std::ptr::null_mut(), 16, u64::MAX)
+            }
+            .buffer_select(3);
+            ring.submit_and_wait(1).unwrap();
+            let completion = ring.next_completion().unwrap();
+            segments.push(buffers.consume(&completion).unwrap());
+        }
+
+        assert_eq!(
+            segments,
+            vec![
+                BufferSegment {
+                    buffer_id: 9,
+                    offset: 0,
+                    len: 3,
+                    more: true
+                },
+                BufferSegment {
+                    buffer_id: 9,
+                    offset: 3,
+                    len: 4,
+                    more: true
+                }
+            ]
+        );
+        assert_eq!(&memory[..7], b"abcdefg");
+        assert_eq!(buffers.remaining(9), Some(9));
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn a_bundle_reports_every_buffer_it_filled() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffers = ring.register_buf_ring(5, 4, BufRingFlags::Mmap).unwrap();
+        let mut memory = [0u8; 4 * 4];
+        for (buffer_id, chunk) in memory.chunks_mut(4).enumerate().rev() {
+            unsafe { buffers.add(chunk.as_mut_ptr(), 4, buffer_id as u16) };
+        }
+        buffers.commit();
+
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) },
+            0
+        );
+        unsafe { libc::write(fds[1], b"0123456789".as_ptr() as *const _, 10) };
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .recv(fds[0], std::ptr::null_mut(), 0, 0)
+        }
+        .buffer_select(5)
+        .bundle();
+        ring.submit_and_wait(1).unwrap();
+
+        /*
+         * The kernel may stop short of the data available, but a bundle
+         * takes more than one buffer.
+         */
+        let completion = ring.next_completion().unwrap();
+        assert!(completion.result > 4);
+        let segments = buffers.consume_bundle(&completion).unwrap();
+        let mut expected = vec![];
+        let mut remaining = completion.result as u32;
+        for buffer_id in (0..4).rev() {
+            if remaining > 0 {
+                expected.push((buffer_id, remaining.min(4)));
+                remaining -= remaining.min(4);
+            }
+        }
+        let segments: Vec<(u16, u32)> = segments
+            .iter()
+            .map(|segment| (segment.buffer_id, segment.len))
+            .collect();
+        assert_eq!(segments, expected);
+        assert_eq!(&memory[12..16], b"0123");
+        assert_eq!(&memory[8..12], b"4567");
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn the_entries_must_be_a_power_of_two() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        assert!(ring.register_buf_ring(1, 3, BufRingFlags::Mmap).is_err());
+    }
+}
+
+#[cfg(test)]
+mod when_replenishing_a_buffer_ring {
+    use crate::{
+        buf_ring::{BufRingFlags, Replenish},
+        cqe::{Completion, Completions},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{
+        io::{pipe, ErrorKind, PipeReader, PipeWriter, Write},
+        os::fd::AsRawFd,
+        ptr::null_mut,
+    };
+
+    fn read_once(
+        ring: &mut IoUring<'_>,
+        (reader, writer): &mut (PipeReader, PipeWriter),
+        group_id: u16,
+    ) -> Completion {
+        writer.write_all(b"burst").unwrap();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), null_mut(), 16, u64::MAX)
+        }
+        .buffer_select(group_id);
+        ring.submit_and_wait(1).unwrap();
+        ring.next_completion().unwrap()
+    }
+
+    #[test]
+    pub fn buffers_come_back_below_the_watermark() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut buffers = ring.register_buf_ring(5, 8, BufRingFlags::empty()).unwrap();
+        buffers
+            .set_replenish(Some(Replenish {
+                low_watermark: 2,
+                buffer_len: 16,
+                max_buffers: 4,
+            }))
+            .unwrap();
+        assert_eq!(buffers.available(), 4);
+
+        let mut pipe = pipe().unwrap();
+        let used: Vec<u16> = (0..3)
+            .map(|_| {
+                let completion = read_once(&mut ring, &mut pipe, buffers.group_id());
+                buffers.consume(&completion).unwrap().buffer_id
+            })
+            .collect();
+        assert_eq!(buffers.available(), 1);
+
+        buffers.release(used[0]);
+        assert_eq!(buffers.available(), 2);
+        buffers.release(used[1]);
+        assert_eq!(buffers.available(), 2);
+
+        let completion = read_once(&mut ring, &mut pipe, buffers.group_id());
+        buffers.consume(&completion).unwrap();
+        assert_eq!(buffers.available(), 2);
+
+        ring.unregister_buf_ring(buffers).unwrap();
+    }
+
+    #[test]
+    pub fn the_cap_stays_within_the_entries() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffers = ring.register_buf_ring(6, 4, BufRingFlags::empty()).unwrap();
+
+        let error = buffers
+            .set_replenish(Some(Replenish {
+                low_watermark: 1,
+                buffer_len: 16,
+                max_buffers: 8,
+            }))
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        ring.unregister_buf_ring(buffers).unwrap();
+    }
+}
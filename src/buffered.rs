@@ -0,0 +1,294 @@
+use crate::{
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    fs::File,
+    io_uring::IoUring,
+};
+use log::debug;
+use std::io::{BufRead, Read, Result, Write};
+
+/*
+ * Larger than the 8 KiB of std: each refill or flush is a submission and a
+ * wait, so fewer and bigger operations pay off more than with plain read(2).
+ */
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/*
+ * Reads `file` from `offset` on through `ring`, a buffer at a time, and hands
+ * the bytes out through Read and BufRead. Reads at least as big as the buffer
+ * go to the file directly when nothing is buffered.
+ */
+pub struct BufReader<'f, 'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    file: &'f File,
+    offset: u64,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> BufReader<'f, 'r, 'a, S, C> {
+    pub fn new(ring: &'r mut IoUring<'a, S, C>, file: &'f File, offset: u64) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, ring, file, offset)
+    }
+
+    pub fn with_capacity(
+        capacity: usize,
+        ring: &'r mut IoUring<'a, S, C>,
+        file: &'f File,
+        offset: u64,
+    ) -> Self {
+        BufReader {
+            ring,
+            file,
+            offset,
+            buf: vec![0u8; capacity].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /*
+     * Offset in the file of the next byte handed out.
+     */
+    pub fn position(&self) -> u64 {
+        self.offset - (self.filled - self.pos) as u64
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> Read for BufReader<'f, 'r, 'a, S, C> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos == self.filled && buf.len() >= self.buf.len() {
+            let read = self.file.read_at(self.ring, buf, self.offset)?;
+            self.offset += read as u64;
+            return Ok(read);
+        }
+
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+
+        Ok(count)
+    }
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> BufRead for BufReader<'f, 'r, 'a, S, C> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos == self.filled {
+            let read = self.file.read_at(self.ring, &mut self.buf, self.offset)?;
+            self.offset += read as u64;
+            self.pos = 0;
+            self.filled = read;
+        }
+
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
+
+/*
+ * Collects writes to `file` from `offset` on and writes them through `ring`
+ * a buffer at a time, with write_all_at, so the sync mode of the file
+ * applies to every flush. Writes at least as big as the buffer go to the
+ * file directly once the buffer is flushed.
+ *
+ * Dropping the writer flushes it, but a failure can only be logged then,
+ * call flush to see it.
+ */
+pub struct BufWriter<'f, 'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    file: &'f File,
+    offset: u64,
+    buf: Vec<u8>,
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> BufWriter<'f, 'r, 'a, S, C> {
+    pub fn new(ring: &'r mut IoUring<'a, S, C>, file: &'f File, offset: u64) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, ring, file, offset)
+    }
+
+    pub fn with_capacity(
+        capacity: usize,
+        ring: &'r mut IoUring<'a, S, C>,
+        file: &'f File,
+        offset: u64,
+    ) -> Self {
+        BufWriter {
+            ring,
+            file,
+            offset,
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /*
+     * Offset in the file the next byte written goes to.
+     */
+    pub fn position(&self) -> u64 {
+        self.offset + self.buf.len() as u64
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn flush_buf(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        self.file.write_all_at(self.ring, &self.buf, self.offset)?;
+        self.offset += self.buf.len() as u64;
+        self.buf.clear();
+
+        Ok(())
+    }
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> Write for BufWriter<'f, 'r, 'a, S, C> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buf.len() + buf.len() > self.buf.capacity() {
+            self.flush_buf()?;
+        }
+
+        if buf.len() >= self.buf.capacity() {
+            let written = self.file.write_at(self.ring, buf, self.offset)?;
+            self.offset += written as u64;
+            return Ok(written);
+        }
+
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()
+    }
+}
+
+impl<'f, 'r, 'a, S: SqeEntry, C: CqeEntry> Drop for BufWriter<'f, 'r, 'a, S, C> {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush_buf() {
+            debug!("could not flush the buffered writes: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_buffering_file_io {
+    use crate::{
+        buffered::{BufReader, BufWriter},
+        fs::File,
+        io_uring::{IoUring, IoUringParams},
+        trace::{TraceEvent, Tracer},
+    };
+    use std::{
+        env::temp_dir,
+        fs,
+        io::{BufRead, Read, Write},
+        path::PathBuf,
+        process::id,
+    };
+
+    fn scratch(name: &str) -> (File, PathBuf) {
+        let path = temp_dir().join(format!("vargasync-{}-{}", name, id()));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (File::from(file), path)
+    }
+
+    fn submissions(ring: &IoUring<'_>) -> usize {
+        ring.tracer()
+            .unwrap()
+            .events()
+            .filter(|event| matches!(event, TraceEvent::Submitted(_)))
+            .count()
+    }
+
+    #[test]
+    pub fn small_writes_are_coalesced_until_flushed() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(64));
+        let (file, path) = scratch("buf-writer");
+
+        let mut writer = BufWriter::with_capacity(64, &mut ring, &file, 4);
+        for _ in 0..10 {
+            writer.write_all(b"line\n").unwrap();
+        }
+        assert_eq!(writer.position(), 54);
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert_eq!(submissions(&ring), 1);
+        assert_eq!(&fs::read(&path).unwrap()[4..], b"line\n".repeat(10));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn dropping_the_writer_flushes_it() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (file, path) = scratch("buf-writer-drop");
+
+        let mut writer = BufWriter::new(&mut ring, &file, 0);
+        writer.write_all(b"kept").unwrap();
+        drop(writer);
+
+        assert_eq!(fs::read(&path).unwrap(), b"kept");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn lines_are_read_a_buffer_at_a_time() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(64));
+        let (file, path) = scratch("buf-reader");
+        fs::write(&path, b"first\nsecond\nthird\n").unwrap();
+
+        let mut reader = BufReader::with_capacity(32, &mut ring, &file, 6);
+        let lines: Vec<String> = reader.by_ref().lines().map(|line| line.unwrap()).collect();
+        let position = reader.position();
+        drop(reader);
+
+        assert_eq!(lines, vec!["second", "third"]);
+        assert_eq!(position, 19);
+        assert_eq!(submissions(&ring), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn big_reads_bypass_the_buffer() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (file, path) = scratch("buf-reader-big");
+        fs::write(&path, [7u8; 100]).unwrap();
+
+        let mut reader = BufReader::with_capacity(16, &mut ring, &file, 0);
+        let mut contents = [0u8; 64];
+        reader.read_exact(&mut contents).unwrap();
+
+        assert!(reader.buffer().is_empty());
+        assert_eq!(reader.position(), 64);
+        assert_eq!(contents, [7u8; 64]);
+        drop(reader);
+        fs::remove_file(path).unwrap();
+    }
+}
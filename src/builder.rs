@@ -0,0 +1,367 @@
+use crate::{
+    affinity::{pin_current_thread, CpuSet, SiblingPlacement},
+    entry::{CqeEntry, SqeEntry},
+    io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+    memory::{KERNEL_MAX_CQ_ENTRIES, KERNEL_MAX_ENTRIES},
+    sqe::IoPriority,
+};
+use std::io::{self, ErrorKind, Result};
+
+/*
+ * What to do with the memory shared with the kernel: the ring mappings and
+ * the registered buffers.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryOptions {
+    /*
+     * madvise(MADV_DONTFORK) so a forked child does not get copy-on-write
+     * pages the kernel is still writing to.
+     */
+    pub dont_fork: bool,
+    /*
+     * mlock the memory so the hot path never takes a page fault.
+     */
+    pub lock: bool,
+    /*
+     * Fault in the ring mappings at setup. The completion ring is otherwise
+     * faulted in as it fills, which keeps the setup of a big one cheap.
+     */
+    pub populate: bool,
+}
+
+#[derive(Default)]
+pub struct IoUringBuilder {
+    params: IoUringParams,
+    memory_options: MemoryOptions,
+    default_priority: Option<IoPriority>,
+    in_flight_limit: Option<u32>,
+    sq_thread_cpus: Option<CpuSet>,
+    sibling_cores: bool,
+    cq_entries: Option<u32>,
+    skip_entry_zeroing: bool,
+}
+
+impl IoUringBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn setup_flags(mut self, flags: IoUringSetupFlags) -> Self {
+        self.params.flags |= flags.bits();
+        self
+    }
+
+    /*
+     * Sizes the completion queue on its own, CQSIZE, instead of twice the
+     * submission queue: multishot requests post many completions per entry.
+     * At least as many as the submission queue, rounded up to a power of two
+     * as well.
+     */
+    pub fn cq_entries(mut self, entries: u32) -> Self {
+        self.cq_entries = Some(entries);
+        self
+    }
+
+    pub fn dont_fork(mut self) -> Self {
+        self.memory_options.dont_fork = true;
+        self
+    }
+
+    pub fn populate(mut self) -> Self {
+        self.memory_options.populate = true;
+        self
+    }
+
+    pub fn lock_memory(mut self) -> Self {
+        self.memory_options.lock = true;
+        self
+    }
+
+    pub fn default_io_priority(mut self, priority: IoPriority) -> Self {
+        self.default_priority = Some(priority);
+        self
+    }
+
+    /*
+     * See IoUring::set_in_flight_limit.
+     */
+    pub fn in_flight_limit(mut self, limit: u32) -> Self {
+        self.in_flight_limit = Some(limit);
+        self
+    }
+
+    /*
+     * Runs the SQPOLL thread on the first cpu of `cpus`, the kernel pins it
+     * to a single one.
+     */
+    pub fn sq_thread_affinity(mut self, cpus: &CpuSet) -> Self {
+        self.sq_thread_cpus = Some(*cpus);
+        self
+    }
+
+    /*
+     * Preset for SQPOLL rings: the poller and the thread calling build, which
+     * is expected to do the submitting, are pinned to two cpus of one core,
+     * or of one package without SMT. See SiblingPlacement.
+     */
+    pub fn sqpoll_on_sibling_cores(mut self) -> Self {
+        self.sibling_cores = true;
+        self
+    }
+
+    /// Hands out entry slots as the previous operation left them, see
+    /// IoUring::set_entry_zeroing.
+    ///
+    /// # Safety
+    ///
+    /// Entries prepared through Sqe::raw_sqe must have every field the
+    /// kernel reads for their opcode written.
+    pub unsafe fn without_entry_zeroing(mut self) -> Self {
+        self.skip_entry_zeroing = true;
+        self
+    }
+
+    pub fn build<'a>(self, entries: u32) -> Result<IoUring<'a>> {
+        self.build_sized(entries)
+    }
+
+    pub fn build_sized<'a, S: SqeEntry, C: CqeEntry>(
+        self,
+        entries: u32,
+    ) -> Result<IoUring<'a, S, C>> {
+        let mut params = self.params;
+        let clamp =
+            IoUringSetupFlags::from_bits_retain(params.flags).contains(IoUringSetupFlags::Clamp);
+        let entries = ring_entries(entries, clamp)?;
+        if let Some(cq_entries) = self.cq_entries {
+            params.flags |= IoUringSetupFlags::CqSize.bits();
+            params.cq_entries = completion_entries(cq_entries, entries, clamp)?;
+        }
+        let placement = if self.sibling_cores {
+            Some(SiblingPlacement::detect()?)
+        } else {
+            None
+        };
+
+        let sq_thread_cpu = match (placement, self.sq_thread_cpus) {
+            (Some(placement), _) => Some(placement.poller),
+            (None, Some(cpus)) => Some(cpus.first().ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, "no cpu for the SQPOLL thread")
+            })?),
+            (None, None) => None,
+        };
+        if let Some(cpu) = sq_thread_cpu {
+            params.flags |= (IoUringSetupFlags::SqPool | IoUringSetupFlags::SqAff).bits();
+            params.sq_thread_cpu = cpu;
+        }
+
+        let mut ring = IoUring::initialize_sized(entries, params)?;
+        ring.apply_memory_options(self.memory_options)?;
+        ring.set_default_io_priority(self.default_priority);
+        ring.set_in_flight_limit(self.in_flight_limit)?;
+        if self.skip_entry_zeroing {
+            unsafe { ring.set_entry_zeroing(false) };
+        }
+
+        if let Some(placement) = placement {
+            pin_current_thread(&CpuSet::new().with_cpu(placement.application))?;
+        }
+
+        Ok(ring)
+    }
+}
+
+/*
+ * The kernel rounds `entries` up to a power of two and refuses more than
+ * KERNEL_MAX_ENTRIES with a bare EINVAL, unless Clamp is set. Rounded here, and
+ * refused with a reason.
+ */
+fn ring_entries(entries: u32, clamp: bool) -> Result<u32> {
+    match entries {
+        0 => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "a ring needs at least one entry",
+        )),
+        entries if entries > KERNEL_MAX_ENTRIES && clamp => Ok(KERNEL_MAX_ENTRIES),
+        entries if entries > KERNEL_MAX_ENTRIES => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} entries, the kernel sets up {} at most, or clamps to it with IoUringSetupFlags::Clamp",
+                entries, KERNEL_MAX_ENTRIES
+            ),
+        )),
+        entries => Ok(entries.next_power_of_two()),
+    }
+}
+
+/*
+ * Same for the completion queue, which must not be smaller than the
+ * submission queue of `sq_entries`.
+ */
+fn completion_entries(entries: u32, sq_entries: u32, clamp: bool) -> Result<u32> {
+    match entries {
+        entries if entries < sq_entries => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} completion entries, fewer than the {} submission entries",
+                entries, sq_entries
+            ),
+        )),
+        entries if entries > KERNEL_MAX_CQ_ENTRIES && clamp => Ok(KERNEL_MAX_CQ_ENTRIES),
+        entries if entries > KERNEL_MAX_CQ_ENTRIES => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} completion entries, the kernel sets up {} at most, or clamps to it with IoUringSetupFlags::Clamp",
+                entries, KERNEL_MAX_CQ_ENTRIES
+            ),
+        )),
+        entries => Ok(entries.next_power_of_two()),
+    }
+}
+
+#[cfg(test)]
+mod when_building_a_ring {
+    use crate::{
+        affinity::CpuSet,
+        builder::IoUringBuilder,
+        io_uring::IoUringSetupFlags,
+        memory::{KERNEL_MAX_CQ_ENTRIES, KERNEL_MAX_ENTRIES},
+        sqe::{IoPriority, IoPriorityClass},
+    };
+    use libc::{c_void, iovec};
+    use std::io::ErrorKind;
+
+    #[test]
+    pub fn ring_memory_can_be_excluded_from_fork_and_locked() {
+        let ring = IoUringBuilder::new().dont_fork().lock_memory().build(8);
+
+        assert!(ring.is_ok());
+    }
+
+    #[test]
+    pub fn the_rings_can_be_populated_at_setup() {
+        let ring = IoUringBuilder::new().populate().build(256);
+
+        assert!(ring.is_ok());
+    }
+
+    #[test]
+    pub fn registered_buffers_follow_the_memory_options() {
+        let ring = IoUringBuilder::new().dont_fork().build(8).unwrap();
+        let mut buffer = vec![0u8; 8192];
+        let buffers = [iovec {
+            iov_base: buffer.as_mut_ptr() as *mut c_void,
+            iov_len: buffer.len(),
+        }];
+
+        unsafe { ring.register_buffers(&buffers).unwrap() };
+
+        assert!(ring.unregister_buffers().is_ok());
+    }
+
+    #[test]
+    pub fn the_default_io_priority_is_handed_to_the_ring() {
+        let priority = IoPriority::new(IoPriorityClass::BestEffort, 6);
+        let ring = IoUringBuilder::new()
+            .default_io_priority(priority)
+            .build(8)
+            .unwrap();
+
+        assert_eq!(ring.default_io_priority(), Some(priority));
+    }
+
+    #[test]
+    pub fn the_sq_thread_goes_to_the_first_cpu_of_the_set() {
+        let cpus = CpuSet::current().unwrap();
+
+        let ring = IoUringBuilder::new()
+            .sq_thread_affinity(&cpus)
+            .build(8)
+            .unwrap();
+
+        assert!(ring
+            .setup_flags()
+            .contains(IoUringSetupFlags::SqPool | IoUringSetupFlags::SqAff));
+    }
+
+    #[test]
+    pub fn an_empty_cpu_set_is_refused() {
+        let ring = IoUringBuilder::new()
+            .sq_thread_affinity(&CpuSet::new())
+            .build(8);
+
+        assert_eq!(
+            ring.err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    pub fn entries_are_rounded_up_to_a_power_of_two() {
+        let ring = IoUringBuilder::new().build(5).unwrap();
+
+        assert_eq!(ring.send_queue.ring_entries(), 8);
+    }
+
+    #[test]
+    pub fn a_ring_without_entries_is_refused() {
+        let ring = IoUringBuilder::new().build(0);
+
+        assert_eq!(
+            ring.err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    pub fn too_many_entries_are_refused_unless_clamped() {
+        let refused = IoUringBuilder::new().build(KERNEL_MAX_ENTRIES + 1);
+        let clamped = IoUringBuilder::new()
+            .setup_flags(IoUringSetupFlags::Clamp)
+            .build(KERNEL_MAX_ENTRIES + 1)
+            .unwrap();
+
+        assert_eq!(
+            refused.err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(clamped.send_queue.ring_entries(), KERNEL_MAX_ENTRIES);
+    }
+
+    #[test]
+    pub fn the_completion_queue_can_be_sized_on_its_own() {
+        let ring = IoUringBuilder::new().cq_entries(100).build(8).unwrap();
+
+        assert_eq!(ring.send_queue.ring_entries(), 8);
+        assert_eq!(ring.complete_queue.ring_entries(), 128);
+        assert!(ring.setup_flags().contains(IoUringSetupFlags::CqSize));
+    }
+
+    #[test]
+    pub fn a_completion_queue_smaller_than_the_submission_queue_is_refused() {
+        let ring = IoUringBuilder::new().cq_entries(4).build(8);
+
+        assert_eq!(
+            ring.err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    pub fn too_many_completion_entries_are_refused_unless_clamped() {
+        let refused = IoUringBuilder::new()
+            .cq_entries(KERNEL_MAX_CQ_ENTRIES + 1)
+            .build(8);
+        let clamped = IoUringBuilder::new()
+            .setup_flags(IoUringSetupFlags::Clamp)
+            .cq_entries(KERNEL_MAX_CQ_ENTRIES + 1)
+            .build(8)
+            .unwrap();
+
+        assert_eq!(
+            refused.err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(clamped.complete_queue.ring_entries(), KERNEL_MAX_CQ_ENTRIES);
+    }
+}
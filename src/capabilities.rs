@@ -0,0 +1,138 @@
+use crate::{io_uring::IoUringFeatures, opcode::IoUringOperation, probe::Probe};
+use libc::{uname, utsname};
+use std::{
+    ffi::CStr,
+    fmt::{Debug, Display, Formatter},
+    io::{self, ErrorKind, Result},
+    mem::zeroed,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    pub fn current() -> Result<Self> {
+        let mut name: utsname = unsafe { zeroed() };
+
+        if unsafe { uname(&mut name) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let release = unsafe { CStr::from_ptr(name.release.as_ptr()) }.to_string_lossy();
+        Self::parse(&release)
+    }
+
+    /*
+     * Parses releases such as "6.8.0-31-generic", ignoring everything after the
+     * numeric part of each component.
+     */
+    pub fn parse(release: &str) -> Result<Self> {
+        let mut components = release.split('.').map(|component| {
+            component
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse::<u32>()
+                .ok()
+        });
+
+        let major = components.next().flatten().ok_or(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("could not parse kernel release {}", release),
+        ))?;
+        let minor = components.next().flatten().unwrap_or(0);
+        let patch = components.next().flatten().unwrap_or(0);
+
+        Ok(KernelVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl Display for KernelVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/*
+ * Everything the host told us about its io_uring support, meant to be logged
+ * at startup or pasted into support tickets.
+ */
+pub struct Capabilities {
+    pub features: IoUringFeatures,
+    pub probe: Probe,
+    pub kernel_version: KernelVersion,
+}
+
+impl Capabilities {
+    pub fn supported_operations(&self) -> Vec<IoUringOperation> {
+        self.probe.supported_operations()
+    }
+}
+
+impl Debug for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capabilities")
+            .field("features", &self.features)
+            .field("supported_operations", &self.supported_operations())
+            .field("kernel_version", &self.kernel_version)
+            .finish()
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "kernel: {}", self.kernel_version)?;
+
+        let features: Vec<&str> = self.features.iter_names().map(|(name, _)| name).collect();
+        writeln!(f, "features: {}", features.join(", "))?;
+
+        let operations: Vec<String> = self
+            .supported_operations()
+            .iter()
+            .map(|operation| format!("{:?}", operation))
+            .collect();
+        write!(f, "supported operations: {}", operations.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod when_reporting_capabilities {
+    use crate::{
+        capabilities::KernelVersion,
+        io_uring::{IoUring, IoUringFeatures, IoUringParams},
+    };
+
+    #[test]
+    pub fn kernel_release_is_parsed() {
+        let version = KernelVersion::parse("6.8.0-31-generic").unwrap();
+
+        assert_eq!(
+            version,
+            KernelVersion {
+                major: 6,
+                minor: 8,
+                patch: 0
+            }
+        );
+    }
+
+    #[test]
+    pub fn report_contains_features_and_operations() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let capabilities = ring.capabilities().unwrap();
+        let report = capabilities.to_string();
+
+        assert!(capabilities.features.contains(IoUringFeatures::SingleMmap));
+        assert!(report.contains("SingleMmap"));
+        assert!(report.contains("Nop"));
+    }
+}
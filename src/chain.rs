@@ -0,0 +1,248 @@
+use crate::{
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+    sqe::{IoUringSqeFlags, Sqe},
+};
+use libc::ECANCELED;
+use std::io::{self, ErrorKind, Result};
+
+/*
+ * A link chain submitted and waited for as a whole. Each link is prepared by
+ * the caller with a user_data of its own, the chain takes care of the
+ * IoLink flags and of telling the results apart: when a link fails, or a
+ * read or write comes up short, the kernel cancels the rest of the chain
+ * and each of them completes with ECANCELED, which ChainResult sorts out.
+ */
+pub struct Chain<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    user_data: Vec<u64>,
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Chain<'r, 'a, S, C> {
+    pub fn new(ring: &'r mut IoUring<'a, S, C>) -> Self {
+        Chain {
+            ring,
+            user_data: Vec::new(),
+        }
+    }
+
+    /*
+     * Appends a link prepared by `prepare`, which must give it a user_data
+     * no other link of the chain has. WouldBlock when the submission queue
+     * is full, the links pushed so far stay queued as a chain of their own.
+     */
+    pub fn push<F>(&mut self, prepare: F) -> Result<&mut Self>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        if self.ring.sq_space_left() == 0 {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        }
+
+        /*
+         * The previous link only gets its IoLink now, so the chain always
+         * ends with a link that carries none.
+         */
+        if !self.user_data.is_empty() {
+            if let Some(mut previous) = self.ring.send_queue.last_entry() {
+                previous[1] |= IoUringSqeFlags::IoLink.bits();
+                self.ring.send_queue.overwrite_last_entry(&previous);
+            }
+        }
+        if let Some(sqe) = self.ring.next_sqe() {
+            prepare(sqe);
+        }
+
+        let user_data = self
+            .ring
+            .send_queue
+            .pending_sqes()
+            .last()
+            .map_or(0, |sqe| sqe.user_data);
+        self.user_data.push(user_data);
+
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.user_data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.user_data.is_empty()
+    }
+
+    /*
+     * Submits the chain and waits for every link.
+     */
+    pub fn run(self) -> Result<ChainResult> {
+        let links = self
+            .ring
+            .wait_for_completions(&self.user_data)?
+            .into_iter()
+            .map(|completion| LinkResult {
+                user_data: completion.user_data,
+                result: completion.result,
+            })
+            .collect();
+
+        Ok(ChainResult { links })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkResult {
+    pub user_data: u64,
+    pub result: i32,
+}
+
+/*
+ * Results of the links of a chain, in chain order.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainResult {
+    links: Vec<LinkResult>,
+}
+
+impl ChainResult {
+    pub fn links(&self) -> &[LinkResult] {
+        &self.links
+    }
+
+    /*
+     * Index of the link that broke the chain, None when every link ran. That
+     * is the first one that failed, or, when the first failure is an
+     * ECANCELED behind a link that succeeded, that link: a read or write
+     * that came up short breaks the chain with a positive result.
+     */
+    pub fn broken_at(&self) -> Option<usize> {
+        let failed = self.links.iter().position(|link| link.result < 0)?;
+
+        if failed > 0 && self.links[failed].result == -ECANCELED {
+            return Some(failed - 1);
+        }
+        Some(failed)
+    }
+
+    /*
+     * The links the kernel cancelled because the chain broke before them.
+     */
+    pub fn cancelled(&self) -> &[LinkResult] {
+        match self.broken_at() {
+            Some(broken) => &self.links[broken + 1..],
+            None => &[],
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.broken_at().is_none()
+    }
+
+    /*
+     * The result of each link, or the error of the link that broke the
+     * chain. A link that broke it with a short transfer gives
+     * ErrorKind::UnexpectedEof.
+     */
+    pub fn into_result(self) -> Result<Vec<u32>> {
+        if let Some(broken) = self.broken_at() {
+            let result = self.links[broken].result;
+            if result < 0 {
+                return Err(io::Error::from_raw_os_error(-result));
+            }
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("link {} of the chain came up short", broken),
+            ));
+        }
+
+        Ok(self.links.iter().map(|link| link.result as u32).collect())
+    }
+}
+
+#[cfg(test)]
+mod when_a_chain_breaks {
+    use crate::{
+        chain::{Chain, LinkResult},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{EBADF, ECANCELED};
+    use std::{
+        io::{pipe, ErrorKind, Write},
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    pub fn the_failed_link_and_the_cancelled_ones_are_told_apart() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut buf = [0u8; 8];
+        let mut chain = Chain::new(&mut ring);
+        chain.push(|sqe| sqe.nop().user_data(1)).unwrap();
+        chain
+            .push(|sqe| unsafe { sqe.read(-1, buf.as_mut_ptr(), 8, 0) }.user_data(2))
+            .unwrap();
+        chain.push(|sqe| sqe.nop().user_data(3)).unwrap();
+        chain.push(|sqe| sqe.nop().user_data(4)).unwrap();
+
+        let result = chain.run().unwrap();
+
+        assert_eq!(result.broken_at(), Some(1));
+        assert_eq!(result.links()[1].result, -EBADF);
+        assert_eq!(
+            result.cancelled(),
+            [
+                LinkResult {
+                    user_data: 3,
+                    result: -ECANCELED
+                },
+                LinkResult {
+                    user_data: 4,
+                    result: -ECANCELED
+                },
+            ]
+        );
+        assert_eq!(
+            result.into_result().unwrap_err().raw_os_error(),
+            Some(EBADF)
+        );
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_short_read_breaks_the_chain() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let (reader, mut writer) = pipe().unwrap();
+        writer.write_all(b"abc").unwrap();
+        let mut buf = [0u8; 8];
+        let fd = reader.as_raw_fd();
+        let mut chain = Chain::new(&mut ring);
+        chain
+            .push(|sqe| unsafe { sqe.read(fd, buf.as_mut_ptr(), 8, 0) }.user_data(1))
+            .unwrap();
+        chain.push(|sqe| sqe.nop().user_data(2)).unwrap();
+
+        let result = chain.run().unwrap();
+
+        assert_eq!(result.broken_at(), Some(0));
+        assert_eq!(result.cancelled().len(), 1);
+        assert_eq!(
+            result.into_result().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    pub fn a_whole_chain_gives_every_result() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut chain = Chain::new(&mut ring);
+        chain.push(|sqe| sqe.nop().user_data(1)).unwrap();
+        chain.push(|sqe| sqe.nop().user_data(2)).unwrap();
+
+        let result = chain.run().unwrap();
+
+        assert!(result.is_complete());
+        assert_eq!(result.into_result().unwrap(), [0, 0]);
+    }
+}
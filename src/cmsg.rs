@@ -0,0 +1,307 @@
+use libc::{
+    cmsghdr, in6_pktinfo, in_pktinfo, timespec, IPPROTO_IP, IPPROTO_IPV6, IPV6_PKTINFO,
+    IPV6_TCLASS, IP_PKTINFO, IP_TOS, SCM_RIGHTS, SCM_TIMESTAMPING, SCM_TIMESTAMPNS, SOL_SOCKET,
+};
+use std::{
+    mem::size_of,
+    net::{Ipv4Addr, Ipv6Addr},
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    ptr::{read_unaligned, write_unaligned},
+    time::Duration,
+};
+
+/*
+ * Headers and data of control messages are aligned to a long, like
+ * CMSG_ALIGN does.
+ */
+const fn align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+const HEADER_LEN: usize = align(size_of::<cmsghdr>());
+
+/*
+ * A control message received with recvmsg, the ones this crate knows how to
+ * read decoded, the others as they came.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage<'b> {
+    /*
+     * SO_TIMESTAMPING, times since the epoch of the clock that stamped the
+     * packet. None for the stamps the socket did not ask for.
+     */
+    Timestamping {
+        software: Option<Duration>,
+        hardware: Option<Duration>,
+    },
+    /*
+     * SO_TIMESTAMPNS, the time the packet reached the socket.
+     */
+    TimestampNs(Duration),
+    /*
+     * IP_PKTINFO, the interface the packet came in on, the local address it
+     * was routed to and the destination address of its header.
+     */
+    PacketInfo {
+        interface: u32,
+        local: Ipv4Addr,
+        destination: Ipv4Addr,
+    },
+    /*
+     * IPV6_PKTINFO, the interface the packet came in on and its destination.
+     */
+    PacketInfoV6 {
+        interface: u32,
+        destination: Ipv6Addr,
+    },
+    /*
+     * IP_TOS of an IPv4 packet, with IP_RECVTOS.
+     */
+    Tos(u8),
+    /*
+     * IPV6_TCLASS of an IPv6 packet, with IPV6_RECVTCLASS.
+     */
+    TrafficClass(u8),
+    /*
+     * Anything else, SCM_RIGHTS included, see net::recv_fds for those.
+     */
+    Other {
+        level: i32,
+        kind: i32,
+        data: &'b [u8],
+    },
+}
+
+/*
+ * Iterates the control messages in the control buffer of a recvmsg, as
+ * much of it as msg_controllen tells was filled. A message that claims more
+ * than is left ends the iteration.
+ */
+#[derive(Debug, Clone)]
+pub struct ControlMessages<'b> {
+    control: &'b [u8],
+}
+
+impl<'b> ControlMessages<'b> {
+    pub fn new(control: &'b [u8]) -> Self {
+        ControlMessages { control }
+    }
+}
+
+impl<'b> Iterator for ControlMessages<'b> {
+    type Item = ControlMessage<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.control.len() < size_of::<cmsghdr>() {
+            return None;
+        }
+
+        let header: cmsghdr = unsafe { read_unaligned(self.control.as_ptr().cast()) };
+        let len = header.cmsg_len as usize;
+        if len < HEADER_LEN || len > self.control.len() {
+            self.control = &[];
+            return None;
+        }
+
+        let data = &self.control[HEADER_LEN..len];
+        self.control = &self.control[align(len).min(self.control.len())..];
+
+        Some(decode(header.cmsg_level, header.cmsg_type, data))
+    }
+}
+
+fn decode(level: i32, kind: i32, data: &[u8]) -> ControlMessage<'_> {
+    match (level, kind) {
+        (SOL_SOCKET, SCM_TIMESTAMPING) if data.len() >= 3 * size_of::<timespec>() => {
+            let stamps: [timespec; 3] = unsafe { read_unaligned(data.as_ptr().cast()) };
+            ControlMessage::Timestamping {
+                software: stamp(&stamps[0]),
+                hardware: stamp(&stamps[2]),
+            }
+        }
+        (SOL_SOCKET, SCM_TIMESTAMPNS) if data.len() >= size_of::<timespec>() => {
+            let time: timespec = unsafe { read_unaligned(data.as_ptr().cast()) };
+            ControlMessage::TimestampNs(stamp(&time).unwrap_or_default())
+        }
+        (IPPROTO_IP, IP_PKTINFO) if data.len() >= size_of::<in_pktinfo>() => {
+            let info: in_pktinfo = unsafe { read_unaligned(data.as_ptr().cast()) };
+            ControlMessage::PacketInfo {
+                interface: info.ipi_ifindex as u32,
+                local: Ipv4Addr::from(u32::from_be(info.ipi_spec_dst.s_addr)),
+                destination: Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)),
+            }
+        }
+        (IPPROTO_IPV6, IPV6_PKTINFO) if data.len() >= size_of::<in6_pktinfo>() => {
+            let info: in6_pktinfo = unsafe { read_unaligned(data.as_ptr().cast()) };
+            ControlMessage::PacketInfoV6 {
+                interface: info.ipi6_ifindex,
+                destination: Ipv6Addr::from(info.ipi6_addr.s6_addr),
+            }
+        }
+        /*
+         * IP_TOS comes as a byte, IPV6_TCLASS as an int.
+         */
+        (IPPROTO_IP, IP_TOS) if !data.is_empty() => ControlMessage::Tos(data[0]),
+        (IPPROTO_IPV6, IPV6_TCLASS) if data.len() >= size_of::<i32>() => {
+            let class: i32 = unsafe { read_unaligned(data.as_ptr().cast()) };
+            ControlMessage::TrafficClass(class as u8)
+        }
+        _ => ControlMessage::Other { level, kind, data },
+    }
+}
+
+/*
+ * A control buffer with a single SCM_RIGHTS message carrying `fds`, in u64s
+ * so it is aligned for the header.
+ */
+pub(crate) fn rights(fds: &[BorrowedFd<'_>]) -> Vec<u64> {
+    let len = HEADER_LEN + fds.len() * size_of::<RawFd>();
+    let mut control = rights_buffer(fds.len());
+    let bytes = control.as_mut_ptr() as *mut u8;
+
+    let mut header: cmsghdr = unsafe { std::mem::zeroed() };
+    header.cmsg_len = len as _;
+    header.cmsg_level = SOL_SOCKET;
+    header.cmsg_type = SCM_RIGHTS;
+    unsafe { write_unaligned(bytes.cast(), header) };
+    for (index, fd) in fds.iter().enumerate() {
+        let at = HEADER_LEN + index * size_of::<RawFd>();
+        unsafe { write_unaligned(bytes.add(at).cast(), fd.as_raw_fd()) };
+    }
+
+    control
+}
+
+/*
+ * A zeroed control buffer with room for an SCM_RIGHTS message of `count`
+ * fds.
+ */
+pub(crate) fn rights_buffer(count: usize) -> Vec<u64> {
+    let len = align(HEADER_LEN + count * size_of::<RawFd>());
+    vec![0u64; len.div_ceil(size_of::<u64>())]
+}
+
+/*
+ * Takes ownership of the fds of the SCM_RIGHTS messages in `control`. The
+ * kernel installed them in this process when it received the message, they
+ * leak unless they are picked up.
+ */
+pub(crate) fn received_fds(control: &[u8]) -> Vec<OwnedFd> {
+    ControlMessages::new(control)
+        .filter_map(|message| match message {
+            ControlMessage::Other {
+                level: SOL_SOCKET,
+                kind: SCM_RIGHTS,
+                data,
+            } => Some(data),
+            _ => None,
+        })
+        .flat_map(|data| data.chunks_exact(size_of::<RawFd>()))
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(read_unaligned(fd.as_ptr() as *const RawFd)) })
+        .collect()
+}
+
+fn stamp(time: &timespec) -> Option<Duration> {
+    if time.tv_sec == 0 && time.tv_nsec == 0 {
+        return None;
+    }
+
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(test)]
+mod when_reading_control_messages {
+    use crate::{
+        cmsg::{ControlMessage, ControlMessages},
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{
+        iovec, msghdr, setsockopt, socklen_t, IPPROTO_IP, IP_PKTINFO, IP_RECVTOS, IP_TOS,
+        SOF_TIMESTAMPING_RX_SOFTWARE, SOF_TIMESTAMPING_SOFTWARE, SOL_SOCKET, SO_TIMESTAMPING,
+    };
+    use std::{
+        mem::{size_of, zeroed},
+        net::{Ipv4Addr, UdpSocket},
+        os::fd::AsRawFd,
+    };
+
+    fn enable(socket: &UdpSocket, level: i32, option: i32, value: u32) {
+        let result = unsafe {
+            setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &value as *const u32 as *const _,
+                size_of::<u32>() as socklen_t,
+            )
+        };
+        assert_eq!(result, 0);
+    }
+
+    #[test]
+    pub fn packet_metadata_comes_decoded() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enable(&receiver, IPPROTO_IP, IP_PKTINFO, 1);
+        enable(&receiver, IPPROTO_IP, IP_RECVTOS, 1);
+        enable(&sender, IPPROTO_IP, IP_TOS, 0x10);
+        enable(
+            &receiver,
+            SOL_SOCKET,
+            SO_TIMESTAMPING,
+            SOF_TIMESTAMPING_SOFTWARE | SOF_TIMESTAMPING_RX_SOFTWARE,
+        );
+        sender
+            .send_to(b"query", receiver.local_addr().unwrap())
+            .unwrap();
+
+        let mut payload = [0u8; 16];
+        let mut control = [0u64; 32];
+        let mut iov = iovec {
+            iov_base: payload.as_mut_ptr().cast(),
+            iov_len: payload.len(),
+        };
+        let mut msg: msghdr = unsafe { zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = size_of::<[u64; 32]>();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .recvmsg(receiver.as_raw_fd(), &mut msg, 0)
+        };
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().result, 5);
+
+        let bytes = unsafe {
+            std::slice::from_raw_parts(control.as_ptr() as *const u8, msg.msg_controllen)
+        };
+        let messages: Vec<_> = ControlMessages::new(bytes).collect();
+
+        assert!(messages.contains(&ControlMessage::Tos(0x10)));
+        assert!(messages.iter().any(|message| matches!(
+            message,
+            ControlMessage::PacketInfo { destination, .. } if *destination == Ipv4Addr::LOCALHOST
+        )));
+        assert!(messages.iter().any(|message| matches!(
+            message,
+            ControlMessage::Timestamping {
+                software: Some(_),
+                hardware: None
+            }
+        )));
+    }
+
+    #[test]
+    pub fn a_message_longer_than_the_buffer_ends_the_iteration() {
+        let mut control = [0u64; 4];
+        control[0] = 64;
+        let bytes = unsafe { std::slice::from_raw_parts(control.as_ptr() as *const u8, 32) };
+
+        assert_eq!(ControlMessages::new(bytes).count(), 0);
+        assert_eq!(ControlMessages::new(&[]).count(), 0);
+    }
+}
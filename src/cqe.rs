@@ -0,0 +1,132 @@
+use bitflags::bitflags;
+use linux_raw_sys::io_uring::{
+    io_uring_cqe, IORING_CQE_BUFFER_SHIFT, IORING_CQE_F_BUFFER, IORING_CQE_F_BUF_MORE,
+    IORING_CQE_F_MORE, IORING_CQE_F_NOTIF, IORING_CQE_F_SOCK_NONEMPTY,
+};
+
+bitflags! {
+    /*
+     * The low bits of cqe->flags. The upper 16 bits carry the buffer id when
+     * Buffer is set, they are kept so buffer_id can read them.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CqeFlags: u32 {
+        const Buffer = IORING_CQE_F_BUFFER; /* upper 16 bits are the buffer id */
+        const More = IORING_CQE_F_MORE; /* multishot, more completions follow */
+        const SockNonEmpty = IORING_CQE_F_SOCK_NONEMPTY; /* more data to read after a recv */
+        const Notif = IORING_CQE_F_NOTIF; /* zero copy notification, not a request completion */
+        const BufMore = IORING_CQE_F_BUF_MORE; /* incremental buffer stays with the kernel */
+    }
+}
+
+impl CqeFlags {
+    pub fn from_raw(flags: u32) -> Self {
+        Self::from_bits_retain(flags)
+    }
+
+    pub fn buffer_id(&self) -> Option<u16> {
+        if self.contains(CqeFlags::Buffer) {
+            Some((self.bits() >> IORING_CQE_BUFFER_SHIFT) as u16)
+        } else {
+            None
+        }
+    }
+}
+
+/*
+ * Copy of a completion queue entry, taken before the slot is handed back to
+ * the kernel.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub user_data: u64,
+    pub result: i32,
+    pub flags: u32,
+}
+
+impl Completion {
+    pub fn cqe_flags(&self) -> CqeFlags {
+        CqeFlags::from_raw(self.flags)
+    }
+
+    /*
+     * Id of the provided buffer the kernel picked, for entries prepared with
+     * Sqe::buffer_select.
+     */
+    pub fn buffer_id(&self) -> Option<u16> {
+        self.cqe_flags().buffer_id()
+    }
+
+    /*
+     * With incremental buffer rings, the buffer was only partly filled and
+     * stays with the kernel.
+     */
+    pub fn buffer_more(&self) -> bool {
+        self.cqe_flags().contains(CqeFlags::BufMore)
+    }
+
+    /*
+     * A multishot request stays armed and will post more completions.
+     */
+    pub fn more(&self) -> bool {
+        self.cqe_flags().contains(CqeFlags::More)
+    }
+
+    /*
+     * The zero copy notification of a send, the buffer is free again. Not the
+     * result of the send itself.
+     */
+    pub fn notification(&self) -> bool {
+        self.cqe_flags().contains(CqeFlags::Notif)
+    }
+}
+
+impl From<&io_uring_cqe> for Completion {
+    fn from(cqe: &io_uring_cqe) -> Self {
+        Completion {
+            user_data: cqe.user_data,
+            result: cqe.res,
+            flags: cqe.flags,
+        }
+    }
+}
+
+/*
+ * Source of completions. Implemented by the ring itself and by decorators
+ * that sit in front of it.
+ */
+pub trait Completions {
+    fn next_completion(&mut self) -> Option<Completion>;
+}
+
+impl<C: Completions + ?Sized> Completions for &mut C {
+    fn next_completion(&mut self) -> Option<Completion> {
+        (**self).next_completion()
+    }
+}
+
+#[cfg(test)]
+mod when_decoding_cqe_flags {
+    use crate::cqe::{Completion, CqeFlags};
+
+    #[test]
+    pub fn the_buffer_id_comes_from_the_upper_bits() {
+        let flags = CqeFlags::from_raw((42 << 16) | 1 | 2);
+
+        assert!(flags.contains(CqeFlags::Buffer | CqeFlags::More));
+        assert_eq!(flags.buffer_id(), Some(42));
+    }
+
+    #[test]
+    pub fn there_is_no_buffer_id_without_the_buffer_flag() {
+        let completion = Completion {
+            user_data: 0,
+            result: 0,
+            flags: (42 << 16) | CqeFlags::SockNonEmpty.bits(),
+        };
+
+        assert_eq!(completion.buffer_id(), None);
+        assert!(completion.cqe_flags().contains(CqeFlags::SockNonEmpty));
+        assert!(!completion.more());
+    }
+}
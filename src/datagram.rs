@@ -0,0 +1,555 @@
+use crate::{
+    buf_ring::{BufRing, BufRingFlags},
+    cqe::Completion,
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+    net::{raw_socket_addr, RecvMsgOut},
+};
+use bytes::Bytes;
+use futures_core::Stream;
+use futures_sink::Sink;
+use libc::{c_void, iovec, msghdr, sockaddr_storage, socklen_t, ENOBUFS};
+use linux_raw_sys::io_uring::io_uring_recvmsg_out;
+use log::debug;
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Result},
+    mem::{self, size_of, zeroed},
+    net::{self, SocketAddr},
+    os::fd::AsRawFd,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/*
+ * user_data of the multishot recvmsg of a socket, of its sendmsg entries and
+ * of the cancel of the recvmsg when the socket is dropped. One UdpSocket per
+ * ring at a time.
+ */
+const RECVMSG_USER_DATA: u64 = u64::MAX - 14;
+const SENDMSG_USER_DATA: u64 = u64::MAX - 15;
+const CANCEL_USER_DATA: u64 = u64::MAX - 16;
+
+const DEFAULT_BUFFERS: u16 = 64;
+
+/*
+ * Room for the io_uring_recvmsg_out header, an IPv6 address and an Ethernet
+ * sized payload.
+ */
+const DEFAULT_BUFFER_LEN: u32 = 2048;
+
+/*
+ * Datagrams queued through the Sink before they are sent without waiting
+ * for a flush.
+ */
+const SEND_BATCH: usize = 32;
+
+struct Outgoing {
+    payload: Bytes,
+    addr: sockaddr_storage,
+    addr_len: socklen_t,
+    iov: iovec,
+    msg: msghdr,
+}
+
+/*
+ * A UDP socket as a Stream of the datagrams it receives and a Sink of the
+ * ones to send, with their peer addresses.
+ *
+ * Datagrams are received by a multishot recvmsg into buffers of a provided
+ * buffer ring of group `group_id`, so a burst of them costs one entry. A
+ * datagram bigger than a buffer is truncated, like with recv(2). Datagrams
+ * fed to the Sink are sent with a sendmsg each, submitted together on flush
+ * or once SEND_BATCH of them are queued.
+ *
+ * poll_next and poll_flush submit what they prepared and return Pending
+ * until its completions arrive. A batch being sent is set apart from the
+ * datagrams fed after it, the kernel reads its msghdrs until the last of
+ * them completes.
+ */
+pub struct UdpSocket<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    socket: net::UdpSocket,
+    buffers: Option<BufRing<'a>>,
+    memory: Box<[u8]>,
+    buffer_len: u32,
+    msg: Box<msghdr>,
+    receiving: bool,
+    received: VecDeque<(Bytes, SocketAddr)>,
+    outgoing: VecDeque<Outgoing>,
+    sending: Box<[Outgoing]>,
+    unsent: usize,
+    send_error: Option<io::Error>,
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> UdpSocket<'r, 'a, S, C> {
+    pub fn new(
+        ring: &'r mut IoUring<'a, S, C>,
+        socket: net::UdpSocket,
+        group_id: u16,
+    ) -> Result<Self> {
+        Self::with_buffers(ring, socket, group_id, DEFAULT_BUFFERS, DEFAULT_BUFFER_LEN)
+    }
+
+    /*
+     * Receives into `count` buffers of `len` bytes, `count` must be a power
+     * of two. Each buffer also holds the header and address of its datagram.
+     */
+    pub fn with_buffers(
+        ring: &'r mut IoUring<'a, S, C>,
+        socket: net::UdpSocket,
+        group_id: u16,
+        count: u16,
+        len: u32,
+    ) -> Result<Self> {
+        let overhead = size_of::<io_uring_recvmsg_out>() + size_of::<sockaddr_storage>();
+        if (len as usize) <= overhead {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("receive buffers must be bigger than {} bytes", overhead),
+            ));
+        }
+
+        let mut buffers = ring.register_buf_ring(group_id, count, BufRingFlags::empty())?;
+        let mut memory = vec![0u8; count as usize * len as usize].into_boxed_slice();
+        for (buffer_id, buffer) in memory.chunks_mut(len as usize).enumerate() {
+            unsafe { buffers.add(buffer.as_mut_ptr(), len, buffer_id as u16) };
+        }
+        buffers.commit();
+
+        let mut msg: Box<msghdr> = Box::new(unsafe { zeroed() });
+        msg.msg_namelen = size_of::<sockaddr_storage>() as socklen_t;
+
+        Ok(UdpSocket {
+            ring,
+            socket,
+            buffers: Some(buffers),
+            memory,
+            buffer_len: len,
+            msg,
+            receiving: false,
+            received: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            sending: Box::default(),
+            unsent: 0,
+            send_error: None,
+        })
+    }
+
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.socket
+    }
+
+    /*
+     * Arms the multishot recvmsg unless it still is.
+     */
+    fn arm(&mut self) -> Result<()> {
+        if self.receiving {
+            return Ok(());
+        }
+        let Some(group_id) = self.buffers.as_ref().map(BufRing::group_id) else {
+            return Err(io::Error::other("the socket was shut down"));
+        };
+
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        let fd = self.socket.as_raw_fd();
+        let msg = &*self.msg as *const msghdr;
+        let Some(sqe) = self.ring.next_sqe() else {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        };
+        unsafe { sqe.recvmsg_multishot(fd, msg, 0, group_id) }.user_data(RECVMSG_USER_DATA);
+        self.receiving = true;
+
+        Ok(())
+    }
+
+    /*
+     * Takes the recvmsg completions reaped so far, setting aside the ones
+     * of other requests.
+     */
+    fn reap(&mut self) -> Result<()> {
+        while let Some(completion) = self.ring.take_deferred_completion(RECVMSG_USER_DATA) {
+            self.received_message(completion)?;
+        }
+        while let Some(completion) = self.ring.next_queued_completion() {
+            if completion.user_data == RECVMSG_USER_DATA {
+                self.received_message(completion)?;
+            } else {
+                self.ring.defer_completion(completion);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn received_message(&mut self, completion: Completion) -> Result<()> {
+        if !completion.more() {
+            self.receiving = false;
+        }
+        /*
+         * ENOBUFS ends the multishot when every buffer is taken, they are
+         * all back by now and the next poll arms it again.
+         */
+        match completion.result {
+            result if result == -ENOBUFS => return Ok(()),
+            result if result < 0 => return Err(io::Error::from_raw_os_error(-result)),
+            _ => {}
+        }
+
+        let Some(buffers) = self.buffers.as_mut() else {
+            return Ok(());
+        };
+        let Some(segment) = buffers.consume(&completion) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "a message completed without a buffer",
+            ));
+        };
+
+        let start = segment.buffer_id as usize * self.buffer_len as usize;
+        let received = &self.memory[start..start + segment.len as usize];
+        if let Some(message) = RecvMsgOut::parse(received, &self.msg) {
+            if let Some(addr) = message.address() {
+                if message.is_truncated() {
+                    debug!("a datagram from {} was truncated", addr);
+                }
+                self.received
+                    .push_back((Bytes::copy_from_slice(message.payload()), addr));
+            }
+        }
+
+        let buffer = self.memory[start..].as_mut_ptr();
+        unsafe { buffers.add(buffer, self.buffer_len, segment.buffer_id) };
+        buffers.commit();
+
+        Ok(())
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let result = self.arm().and_then(|_| self.ring.submit());
+        if let Err(error) = result.and_then(|_| self.reap()) {
+            return Poll::Ready(Err(error));
+        }
+        if !self.received.is_empty() || !self.receiving {
+            return Poll::Ready(Ok(()));
+        }
+
+        match self.ring.register_waker(cx) {
+            Ok(()) => Poll::Pending,
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+
+    /*
+     * Sends the queued datagrams, as many at a time as the submission queue
+     * takes. Every one is attempted, the first failure is returned once the
+     * queue is empty. Datagrams leave the queue as their batch is prepared,
+     * so a failure never sends them again.
+     */
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        loop {
+            while self.unsent > 0 {
+                let completion = match self.ring.poll_completion(SENDMSG_USER_DATA, cx) {
+                    Poll::Ready(completion) => completion,
+                    Poll::Pending => return Poll::Pending,
+                };
+                match completion {
+                    Ok(completion) => {
+                        self.unsent -= 1;
+                        if completion.result < 0 && self.send_error.is_none() {
+                            self.send_error =
+                                Some(io::Error::from_raw_os_error(-completion.result));
+                        }
+                    }
+                    /*
+                     * The ring abandoned the rest of the batch.
+                     */
+                    Err(error) => {
+                        self.unsent = 0;
+                        self.sending = Box::default();
+                        return Poll::Ready(Err(error));
+                    }
+                }
+            }
+            self.sending = Box::default();
+
+            if self.outgoing.is_empty() {
+                return Poll::Ready(self.send_error.take().map_or(Ok(()), Err));
+            }
+            if let Err(error) = self.send_batch() {
+                return Poll::Ready(Err(error));
+            }
+        }
+    }
+
+    /*
+     * Prepares a sendmsg for as many queued datagrams as the submission
+     * queue has room for, moved to a batch of their own first so their
+     * msghdrs stay put while the kernel reads them.
+     */
+    fn send_batch(&mut self) -> Result<()> {
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        let count = self.outgoing.len().min(self.ring.sq_space_left() as usize);
+        if count == 0 {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        }
+        self.sending = self.outgoing.drain(..count).collect();
+
+        let fd = self.socket.as_raw_fd();
+        for outgoing in self.sending.iter_mut() {
+            let Some(sqe) = self.ring.next_sqe() else {
+                break;
+            };
+            outgoing.iov = iovec {
+                iov_base: outgoing.payload.as_ptr() as *mut c_void,
+                iov_len: outgoing.payload.len(),
+            };
+            outgoing.msg.msg_name = &mut outgoing.addr as *mut sockaddr_storage as *mut c_void;
+            outgoing.msg.msg_namelen = outgoing.addr_len;
+            outgoing.msg.msg_iov = &mut outgoing.iov;
+            outgoing.msg.msg_iovlen = 1;
+            unsafe { sqe.sendmsg(fd, &outgoing.msg, 0) }.user_data(SENDMSG_USER_DATA);
+            self.unsent += 1;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Cancels the recvmsg and waits for its last completion, the buffers can
+     * only go once the kernel is done with them.
+     */
+    fn shut_down(&mut self) -> Result<()> {
+        if self.unsent > 0 {
+            self.ring.abandon(&[SENDMSG_USER_DATA]);
+            self.unsent = 0;
+        }
+        if self.receiving {
+            if self.ring.sq_space_left() == 0 {
+                self.ring.submit()?;
+            }
+            if let Some(sqe) = self.ring.next_sqe() {
+                sqe.cancel(RECVMSG_USER_DATA).user_data(CANCEL_USER_DATA);
+            }
+            self.ring.submit()?;
+            self.ring.wait_for_completion(CANCEL_USER_DATA)?;
+
+            while self.receiving {
+                if !self.ring.wait_for_completion(RECVMSG_USER_DATA)?.more() {
+                    self.receiving = false;
+                }
+            }
+        }
+        while self
+            .ring
+            .take_deferred_completion(RECVMSG_USER_DATA)
+            .is_some()
+        {}
+
+        if let Some(buffers) = self.buffers.take() {
+            self.ring.unregister_buf_ring(buffers)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Stream for UdpSocket<'r, 'a, S, C> {
+    type Item = Result<(Bytes, SocketAddr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(datagram) = this.received.pop_front() {
+                return Poll::Ready(Some(Ok(datagram)));
+            }
+            match this.poll_receive(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(error))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Sink<(Bytes, SocketAddr)> for UdpSocket<'r, 'a, S, C> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.outgoing.len() >= SEND_BATCH {
+            return this.poll_send(cx);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, (payload, addr): (Bytes, SocketAddr)) -> Result<()> {
+        let (addr, addr_len) = raw_socket_addr(&addr);
+        self.get_mut().outgoing.push_back(Outgoing {
+            payload,
+            addr,
+            addr_len,
+            iov: unsafe { zeroed() },
+            msg: unsafe { zeroed() },
+        });
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_send(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Drop for UdpSocket<'r, 'a, S, C> {
+    fn drop(&mut self) {
+        if let Err(error) = self.shut_down() {
+            debug!("could not stop receiving on the socket: {}", error);
+            /*
+             * The kernel may still write to the buffers and read the msghdr.
+             */
+            mem::forget(mem::take(&mut self.memory));
+            mem::forget(mem::replace(&mut self.msg, Box::new(unsafe { zeroed() })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_using_a_udp_socket_as_stream_and_sink {
+    use crate::{
+        datagram::UdpSocket,
+        io_uring::{IoUring, IoUringParams},
+        trace::{TraceEvent, Tracer},
+    };
+    use bytes::Bytes;
+    use futures::{executor::block_on, executor::block_on_stream, SinkExt, StreamExt};
+    use std::{net, thread, time::Duration};
+
+    fn bound() -> net::UdpSocket {
+        net::UdpSocket::bind("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    pub fn received_datagrams_come_with_their_sender() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let receiver = bound();
+        let address = receiver.local_addr().unwrap();
+        let peer = bound();
+        for payload in [&b"first"[..], b"second", b"third"] {
+            peer.send_to(payload, address).unwrap();
+        }
+
+        let socket = UdpSocket::with_buffers(&mut ring, receiver, 1, 2, 512).unwrap();
+        let received: Vec<_> = block_on_stream(socket)
+            .take(3)
+            .map(|datagram| datagram.unwrap())
+            .collect();
+
+        let sender = peer.local_addr().unwrap();
+        assert_eq!(
+            received,
+            vec![
+                (Bytes::from_static(b"first"), sender),
+                (Bytes::from_static(b"second"), sender),
+                (Bytes::from_static(b"third"), sender),
+            ]
+        );
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn datagrams_fed_to_the_sink_are_sent_together_on_flush() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(64));
+        let peer = bound();
+        let address = peer.local_addr().unwrap();
+
+        let mut socket = UdpSocket::new(&mut ring, bound(), 1).unwrap();
+        block_on(async {
+            socket.feed((Bytes::from_static(b"ping"), address)).await?;
+            socket.feed((Bytes::from_static(b"pong"), address)).await?;
+            socket.flush().await
+        })
+        .unwrap();
+        let sender = socket.socket().local_addr().unwrap();
+        drop(socket);
+
+        let submissions = ring
+            .tracer()
+            .unwrap()
+            .events()
+            .filter(|event| matches!(event, TraceEvent::Submitted(_)))
+            .count();
+        assert_eq!(submissions, 2);
+
+        let mut buffer = [0u8; 16];
+        for expected in [&b"ping"[..], b"pong"] {
+            let (len, from) = peer.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], expected);
+            assert_eq!(from, sender);
+        }
+    }
+
+    #[test]
+    pub fn a_stream_waiting_for_a_datagram_is_woken_when_it_arrives() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let receiver = bound();
+        let address = receiver.local_addr().unwrap();
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            bound().send_to(b"late", address).unwrap();
+        });
+        let mut socket = UdpSocket::new(&mut ring, receiver, 1).unwrap();
+        let (payload, _) = block_on(socket.next()).unwrap().unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(payload, Bytes::from_static(b"late"));
+    }
+
+    #[test]
+    pub fn datagrams_of_a_failed_flush_are_not_sent_again() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let peer = bound();
+        let address = peer.local_addr().unwrap();
+        let unreachable = "[::1]:9".parse().unwrap();
+
+        let mut socket = UdpSocket::new(&mut ring, bound(), 1).unwrap();
+        let flushed = block_on(async {
+            socket
+                .feed((Bytes::from_static(b"lost"), unreachable))
+                .await?;
+            socket.feed((Bytes::from_static(b"once"), address)).await?;
+            socket.flush().await
+        });
+        assert!(flushed.is_err());
+        block_on(async {
+            socket.feed((Bytes::from_static(b"last"), address)).await?;
+            socket.flush().await
+        })
+        .unwrap();
+        drop(socket);
+
+        let mut buffer = [0u8; 16];
+        for expected in [&b"once"[..], b"last"] {
+            let (len, _) = peer.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], expected);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    entry::{CqeEntry, SqeEntry},
+    io_uring::{IoUring, DEADLINE_USER_DATA},
+    sqe::{IoUringSqeFlags, Sqe},
+};
+use linux_raw_sys::io_uring::__kernel_timespec;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, ErrorKind, Result},
+    time::Instant,
+};
+
+/*
+ * Backlog key: entries with a deadline first, earliest first, then the ones
+ * without, each in the order they were pushed.
+ */
+type Key = (bool, Option<Instant>, u64);
+
+/*
+ * Entries prepared ahead of the submission queue and dispatched earliest
+ * deadline first. Each entry with a deadline goes out linked to a timeout
+ * for the time it has left, so the kernel fails it with ECANCELED once the
+ * deadline passes, and one that missed it while queued is cancelled right
+ * away. The linked timeouts complete inside the ring, the caller only sees
+ * the completions of its own entries.
+ *
+ * The kernel reads the time of a linked timeout when it takes the entry, so
+ * the scheduler keeps each one until the submission queue head has moved
+ * past its timeout, and frees it on the next dispatch. Dropped with timeouts
+ * the kernel may not have taken yet, it leaks their times rather than free
+ * memory the kernel may still read.
+ */
+#[derive(Debug, Default)]
+pub struct DeadlineScheduler {
+    backlog: BTreeMap<Key, Vec<u8>>,
+    pushed: u64,
+    /*
+     * Time of each linked timeout dispatched, with the position in the
+     * submission queue just past it. Boxed, the kernel has their addresses.
+     */
+    timespecs: VecDeque<(u32, Box<__kernel_timespec>)>,
+}
+
+impl DeadlineScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+     * Prepares an entry with `prepare` and queues it with `deadline`, None
+     * for entries that only go out after those with one. The entry gets the
+     * default priority of `ring`, the ring it has to be dispatched to. It
+     * cannot be linked, the backlog is reordered and its link goes to the
+     * timeout.
+     */
+    pub fn push<S: SqeEntry, C: CqeEntry, F>(
+        &mut self,
+        ring: &IoUring<'_, S, C>,
+        deadline: Option<Instant>,
+        prepare: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        let entry = ring.detached_entry(prepare);
+        let links = (IoUringSqeFlags::IoLink | IoUringSqeFlags::IoHardLink).bits();
+        if entry[1] & links != 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the scheduler cannot keep link chains together",
+            ));
+        }
+
+        self.backlog
+            .insert((deadline.is_none(), deadline, self.pushed), entry);
+        self.pushed += 1;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.backlog.is_empty()
+    }
+
+    /*
+     * Earliest deadline in the backlog.
+     */
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.backlog
+            .keys()
+            .next()
+            .and_then(|(_, deadline, _)| *deadline)
+    }
+
+    /*
+     * Moves as many entries as fit into the submission queue of `ring`,
+     * earliest deadline first, returns how many. An entry with a deadline
+     * takes two slots, itself and its timeout.
+     */
+    pub fn dispatch<S: SqeEntry, C: CqeEntry>(&mut self, ring: &mut IoUring<'_, S, C>) -> usize {
+        self.release_taken(ring);
+
+        let mut moved = 0;
+        while let Some(entry) = self.backlog.first_entry() {
+            let deadline = entry.key().1;
+            if 1 + deadline.is_some() as u32 > ring.sq_space_left() {
+                break;
+            }
+            if ring.next_sqe().is_none() {
+                break;
+            }
+            let mut entry = entry.remove();
+
+            match deadline {
+                Some(deadline) => {
+                    entry[1] |= IoUringSqeFlags::IoLink.bits();
+                    ring.send_queue.overwrite_last_entry(&entry);
+
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    let timespec = Box::new(__kernel_timespec {
+                        tv_sec: left.as_secs() as i64,
+                        tv_nsec: left.subsec_nanos() as i64,
+                    });
+                    if let Some(sqe) = ring.next_sqe() {
+                        unsafe { sqe.link_timeout(&*timespec) }.user_data(DEADLINE_USER_DATA);
+                    }
+                    self.timespecs
+                        .push_back((ring.send_queue.sqe_tail, timespec));
+                }
+                None => ring.send_queue.overwrite_last_entry(&entry),
+            }
+            moved += 1;
+        }
+
+        moved
+    }
+
+    /*
+     * Dispatches and submits, one submit cycle.
+     */
+    pub fn submit<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<usize> {
+        self.dispatch(ring);
+        let submitted = ring.submit()?;
+        self.release_taken(ring);
+
+        Ok(submitted)
+    }
+
+    /*
+     * Frees the times of the timeouts the kernel has taken.
+     */
+    fn release_taken<S: SqeEntry, C: CqeEntry>(&mut self, ring: &IoUring<'_, S, C>) {
+        let consumed = ring.send_queue.consumed();
+        while let Some((end, _)) = self.timespecs.front() {
+            if (consumed.wrapping_sub(*end) as i32) < 0 {
+                break;
+            }
+            self.timespecs.pop_front();
+        }
+    }
+}
+
+impl Drop for DeadlineScheduler {
+    fn drop(&mut self) {
+        for (_, timespec) in self.timespecs.drain(..) {
+            Box::leak(timespec);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_scheduling_by_deadline {
+    use crate::{
+        cqe::Completions,
+        deadline::DeadlineScheduler,
+        io_uring::{IoUring, IoUringParams},
+        sqe::IoUringSqeFlags,
+    };
+    use linux_raw_sys::errno::ECANCELED;
+    use std::{
+        io::{pipe, ErrorKind},
+        os::fd::AsRawFd,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn the_earliest_deadline_goes_first() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut scheduler = DeadlineScheduler::new();
+        let now = Instant::now();
+        scheduler
+            .push(&ring, None, |sqe| sqe.nop().user_data(0))
+            .unwrap();
+        scheduler
+            .push(&ring, Some(now + Duration::from_secs(2)), |sqe| {
+                sqe.nop().user_data(2)
+            })
+            .unwrap();
+        scheduler
+            .push(&ring, Some(now + Duration::from_secs(1)), |sqe| {
+                sqe.nop().user_data(1)
+            })
+            .unwrap();
+        assert_eq!(
+            scheduler.next_deadline(),
+            Some(now + Duration::from_secs(1))
+        );
+
+        assert_eq!(scheduler.dispatch(&mut ring), 3);
+        ring.submit_and_wait(3).unwrap();
+
+        let order: Vec<u64> = std::iter::from_fn(|| ring.next_completion())
+            .map(|completion| completion.user_data)
+            .collect();
+        assert_eq!(order, [1, 2, 0]);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_missed_deadline_cancels_the_entry() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut scheduler = DeadlineScheduler::new();
+        let (reader, _writer) = pipe().unwrap();
+        let mut buf = [0u8; 8];
+        let fd = reader.as_raw_fd();
+        scheduler
+            .push(
+                &ring,
+                Some(Instant::now() + Duration::from_millis(20)),
+                |sqe| unsafe { sqe.read(fd, buf.as_mut_ptr(), 8, 0) }.user_data(7),
+            )
+            .unwrap();
+
+        scheduler.submit(&mut ring).unwrap();
+
+        let completion = ring.wait_for_completion(7).unwrap();
+        assert_eq!(completion.result, -(ECANCELED as i32));
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn the_times_of_timeouts_taken_by_the_kernel_are_freed() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut scheduler = DeadlineScheduler::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        for round in 0..3 {
+            for user_data in 0..2 {
+                scheduler
+                    .push(&ring, Some(deadline), |sqe| {
+                        sqe.nop().user_data(round * 2 + user_data)
+                    })
+                    .unwrap();
+            }
+            assert_eq!(scheduler.dispatch(&mut ring), 2);
+            assert_eq!(scheduler.timespecs.len(), 2);
+
+            scheduler.submit(&mut ring).unwrap();
+            assert!(scheduler.timespecs.is_empty());
+            ring.submit_and_wait(2).unwrap();
+            ring.advance_completions(ring.cq_ready());
+        }
+    }
+
+    #[test]
+    pub fn linked_entries_are_refused() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut scheduler = DeadlineScheduler::new();
+
+        let error = scheduler
+            .push(&ring, None, |sqe| sqe.nop().flags(IoUringSqeFlags::IoLink))
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(scheduler.is_empty());
+    }
+}
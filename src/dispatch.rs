@@ -0,0 +1,266 @@
+use crate::cqe::{Completion, Completions};
+use std::collections::{HashMap, VecDeque};
+
+/*
+ * Limits of one tick of a dispatch loop. None is no limit.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchBudget {
+    /*
+     * Completions handed out per tick, after which the loop gets to run its
+     * other work before dispatching more.
+     */
+    pub per_tick: Option<usize>,
+    /*
+     * Completions of one user_data per tick. A multishot request of a busy
+     * connection keeps posting under the same user_data, the excess waits
+     * for the next tick so the completions of other requests get through.
+     */
+    pub per_user_data: Option<usize>,
+    /*
+     * Completions held back for a later tick at most. Once that many wait,
+     * the tick ends rather than pull more out of the source, a flood of one
+     * user_data stays in the completion queue instead of piling up here.
+     */
+    pub held: Option<usize>,
+}
+
+/*
+ * Decorator over a completion source that hands out completions in ticks,
+ * within a budget. next_completion returns None once the tick is spent,
+ * next_tick starts a new one. Completions held back over the budget of
+ * their user_data come first in the next tick, in the order they were
+ * reaped, so a request never sees its completions reordered.
+ */
+pub struct Dispatcher<C> {
+    inner: C,
+    budget: DispatchBudget,
+    dispatched: usize,
+    per_user_data: HashMap<u64, usize>,
+    /*
+     * Held back in an earlier tick, handed out before anything new.
+     */
+    carried: VecDeque<Completion>,
+    held: VecDeque<Completion>,
+    /*
+     * Completions in `held` per user_data.
+     */
+    held_per_user_data: HashMap<u64, usize>,
+}
+
+impl<C: Completions> Dispatcher<C> {
+    pub fn new(inner: C, budget: DispatchBudget) -> Self {
+        Dispatcher {
+            inner,
+            budget,
+            dispatched: 0,
+            per_user_data: HashMap::new(),
+            carried: VecDeque::new(),
+            held: VecDeque::new(),
+            held_per_user_data: HashMap::new(),
+        }
+    }
+
+    pub fn budget(&self) -> DispatchBudget {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: DispatchBudget) {
+        self.budget = budget;
+    }
+
+    /*
+     * Completions handed out in this tick.
+     */
+    pub fn dispatched(&self) -> usize {
+        self.dispatched
+    }
+
+    /*
+     * Completions waiting for a later tick.
+     */
+    pub fn held(&self) -> usize {
+        self.carried.len() + self.held.len()
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.budget
+            .per_tick
+            .is_some_and(|limit| self.dispatched >= limit)
+    }
+
+    pub fn next_tick(&mut self) {
+        self.dispatched = 0;
+        self.per_user_data.clear();
+        self.carried.append(&mut self.held);
+        self.held_per_user_data.clear();
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /*
+     * Counts `completion` against the budget of its user_data, false when
+     * that is spent. A user_data with completions held back stays held, or
+     * a later completion would overtake them.
+     */
+    fn admit(&mut self, completion: &Completion) -> bool {
+        if self.held_per_user_data.contains_key(&completion.user_data) {
+            return false;
+        }
+
+        let count = self.per_user_data.entry(completion.user_data).or_default();
+        if self
+            .budget
+            .per_user_data
+            .is_some_and(|limit| *count >= limit)
+        {
+            return false;
+        }
+        *count += 1;
+
+        true
+    }
+
+    fn hold(&mut self, completion: Completion) {
+        *self
+            .held_per_user_data
+            .entry(completion.user_data)
+            .or_default() += 1;
+        self.held.push_back(completion);
+    }
+
+    fn holds_enough(&self) -> bool {
+        self.budget
+            .held
+            .is_some_and(|limit| self.held.len() >= limit)
+    }
+}
+
+impl<C: Completions> Completions for Dispatcher<C> {
+    fn next_completion(&mut self) -> Option<Completion> {
+        if self.is_spent() {
+            return None;
+        }
+
+        let completion = loop {
+            let completion = match self.carried.pop_front() {
+                Some(completion) => completion,
+                None if self.holds_enough() => return None,
+                None => self.inner.next_completion()?,
+            };
+            if self.admit(&completion) {
+                break completion;
+            }
+            self.hold(completion);
+        };
+        self.dispatched += 1;
+
+        Some(completion)
+    }
+}
+
+#[cfg(test)]
+mod when_dispatching_within_a_budget {
+    use crate::{
+        cqe::{Completion, Completions},
+        dispatch::{DispatchBudget, Dispatcher},
+    };
+    use std::collections::VecDeque;
+
+    struct ScriptedCompletions(VecDeque<Completion>);
+
+    impl Completions for ScriptedCompletions {
+        fn next_completion(&mut self) -> Option<Completion> {
+            self.0.pop_front()
+        }
+    }
+
+    fn completions(user_data: &[u64]) -> ScriptedCompletions {
+        ScriptedCompletions(
+            user_data
+                .iter()
+                .map(|&user_data| Completion {
+                    user_data,
+                    result: 0,
+                    flags: 0,
+                })
+                .collect(),
+        )
+    }
+
+    fn tick<C: Completions>(dispatcher: &mut Dispatcher<C>) -> Vec<u64> {
+        let user_data = std::iter::from_fn(|| dispatcher.next_completion())
+            .map(|completion| completion.user_data)
+            .collect();
+        dispatcher.next_tick();
+        user_data
+    }
+
+    #[test]
+    pub fn a_tick_stops_at_its_budget() {
+        let budget = DispatchBudget {
+            per_tick: Some(2),
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(completions(&[1, 2, 3]), budget);
+
+        assert_eq!(tick(&mut dispatcher), [1, 2]);
+        assert_eq!(tick(&mut dispatcher), [3]);
+        assert_eq!(tick(&mut dispatcher), Vec::<u64>::new());
+    }
+
+    #[test]
+    pub fn a_hot_user_data_does_not_starve_the_others() {
+        let budget = DispatchBudget {
+            per_user_data: Some(2),
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(completions(&[7, 7, 7, 7, 1, 7, 2]), budget);
+
+        assert_eq!(tick(&mut dispatcher), [7, 7, 1, 2]);
+        assert_eq!(dispatcher.held(), 3);
+        assert_eq!(tick(&mut dispatcher), [7, 7]);
+        assert_eq!(tick(&mut dispatcher), [7]);
+    }
+
+    #[test]
+    pub fn a_tick_ends_once_enough_completions_are_held() {
+        let budget = DispatchBudget {
+            per_user_data: Some(1),
+            held: Some(2),
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(completions(&[7, 7, 7, 7, 1]), budget);
+
+        assert_eq!(tick(&mut dispatcher), [7]);
+        assert_eq!(dispatcher.inner.0.len(), 2);
+        assert_eq!(tick(&mut dispatcher), [7]);
+        assert_eq!(tick(&mut dispatcher), [7, 1]);
+        assert_eq!(tick(&mut dispatcher), [7]);
+    }
+
+    #[test]
+    pub fn completions_of_a_request_stay_in_order() {
+        let mut script = completions(&[7, 7, 7]);
+        for (result, completion) in script.0.iter_mut().enumerate() {
+            completion.result = result as i32;
+        }
+        let budget = DispatchBudget {
+            per_user_data: Some(1),
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(script, budget);
+
+        let mut results = Vec::new();
+        while results.len() < 3 {
+            while let Some(completion) = dispatcher.next_completion() {
+                results.push(completion.result);
+            }
+            dispatcher.next_tick();
+        }
+
+        assert_eq!(results, [0, 1, 2]);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::io_uring::IoUringSetupFlags;
+use linux_raw_sys::io_uring::{io_uring_cqe, io_uring_sqe};
+use std::mem::size_of;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/*
+ * Size of the submission queue entries of a ring. Part of the ring type, so
+ * the stride is a constant on the submission path instead of a flag check.
+ */
+pub trait SqeEntry: private::Sealed + 'static {
+    const SIZE: usize;
+    const SETUP_FLAGS: IoUringSetupFlags;
+}
+
+/*
+ * Size of the completion queue entries of a ring.
+ */
+pub trait CqeEntry: private::Sealed + 'static {
+    const SIZE: usize;
+    const SETUP_FLAGS: IoUringSetupFlags;
+}
+
+/*
+ * Regular 64 byte sqes.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sqe64;
+
+/*
+ * 128 byte sqes, IORING_SETUP_SQE128. The second half carries the payload of
+ * commands such as IORING_OP_URING_CMD.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sqe128;
+
+/*
+ * Regular 16 byte cqes.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cqe16;
+
+/*
+ * 32 byte cqes, IORING_SETUP_CQE32. The extra two words are in big_cqe.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cqe32;
+
+impl private::Sealed for Sqe64 {}
+impl private::Sealed for Sqe128 {}
+impl private::Sealed for Cqe16 {}
+impl private::Sealed for Cqe32 {}
+
+impl SqeEntry for Sqe64 {
+    const SIZE: usize = size_of::<io_uring_sqe>();
+    const SETUP_FLAGS: IoUringSetupFlags = IoUringSetupFlags::empty();
+}
+
+impl SqeEntry for Sqe128 {
+    const SIZE: usize = 2 * size_of::<io_uring_sqe>();
+    const SETUP_FLAGS: IoUringSetupFlags = IoUringSetupFlags::Sqe128;
+}
+
+impl CqeEntry for Cqe16 {
+    const SIZE: usize = size_of::<io_uring_cqe>();
+    const SETUP_FLAGS: IoUringSetupFlags = IoUringSetupFlags::empty();
+}
+
+impl CqeEntry for Cqe32 {
+    const SIZE: usize = 2 * size_of::<io_uring_cqe>();
+    const SETUP_FLAGS: IoUringSetupFlags = IoUringSetupFlags::Cqe32;
+}
+
+/*
+ * Setup flags a ring with these entry types must be created with. Any other
+ * Sqe128 or Cqe32 bit in `flags` is a mismatch with the type.
+ */
+pub(crate) fn entry_setup_flags<S: SqeEntry, C: CqeEntry>(
+    flags: IoUringSetupFlags,
+) -> Option<IoUringSetupFlags> {
+    let sizes = IoUringSetupFlags::Sqe128 | IoUringSetupFlags::Cqe32;
+    let layout = S::SETUP_FLAGS | C::SETUP_FLAGS;
+
+    if layout.contains(flags & sizes) {
+        Some(flags | layout)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod when_choosing_entry_sizes {
+    use crate::{
+        cqe::Completions,
+        entry::{Cqe16, Cqe32, Sqe128, Sqe64},
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+        syscalls::mock::{MockSyscalls, SyscallRecord},
+    };
+    use linux_raw_sys::io_uring::IORING_OFF_SQES;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn big_entries_round_trip_through_the_kernel() {
+        let mut ring =
+            IoUring::<Sqe128, Cqe32>::initialize_sized(4, IoUringParams::default()).unwrap();
+
+        for user_data in 0..4 {
+            ring.next_sqe().unwrap().nop().user_data(user_data);
+        }
+        ring.submit_and_wait(4).unwrap();
+
+        for user_data in 0..4 {
+            assert_eq!(ring.next_completion().unwrap().user_data, user_data);
+        }
+    }
+
+    #[test]
+    pub fn the_setup_flags_follow_the_type() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        IoUring::<Sqe128, Cqe16>::initialize_sized_with_syscalls(
+            4,
+            IoUringParams::default(),
+            syscalls.clone(),
+        )
+        .unwrap();
+
+        let records = syscalls.records();
+        assert_eq!(
+            records[0],
+            SyscallRecord::Setup {
+                entries: 4,
+                flags: IoUringSetupFlags::Sqe128.bits()
+            }
+        );
+        assert!(records.contains(&SyscallRecord::Mmap {
+            offset: IORING_OFF_SQES as u64,
+            len: 4 * 128,
+            populate: true
+        }));
+    }
+
+    #[test]
+    pub fn flags_that_disagree_with_the_type_are_rejected() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::Cqe32.bits(),
+            ..Default::default()
+        };
+
+        assert!(IoUring::<Sqe64, Cqe16>::initialize_sized(4, params).is_err());
+    }
+}
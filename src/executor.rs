@@ -0,0 +1,661 @@
+use crate::{
+    cqe::{Completion, Completions},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::{IoUring, IoUringSetupFlags},
+    sqe::Sqe,
+    unpark::Unparker,
+};
+use log::debug;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    future::Future,
+    hint,
+    io::{self, ErrorKind, Result},
+    pin::{pin, Pin},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+/*
+ * Task id of the future given to block_on.
+ */
+const MAIN_TASK: usize = usize::MAX;
+
+type Task<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/*
+ * How the executor waits for a completion when no task can run, trading
+ * CPU for the latency of waking up.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+    /*
+     * Polls the completion queue without entering the kernel, the thread
+     * never sleeps. Needs a ring that posts completions without being
+     * entered, or that says when it would have to be, see
+     * IoUring::task_work_pending.
+     */
+    Spin,
+    /*
+     * Polls the completion queue for up to the given time, then parks in
+     * io_uring_enter.
+     */
+    SpinThenWait(Duration),
+    /*
+     * Parks in io_uring_enter right away.
+     */
+    #[default]
+    Block,
+}
+
+/*
+ * A single threaded executor driving its tasks from the completions of one
+ * ring, one per core in a thread-per-core design. A task waiting for an
+ * operation is woken by the loop as it reaps the completion, without an
+ * eventfd or reactor in between, and when no task can run the thread parks
+ * in io_uring_enter until the next completion, or spins, see WaitStrategy.
+ *
+ * The executor hands out the user_data of the entries it submits for its
+ * tasks, other entries must not be put on its ring. Wakers can be used from
+ * other threads, which wake the loop through an Unparker, see
+ * Unparker::msg_ring.
+ *
+ * Executor is a handle, clones drive the same tasks, so a task can spawn
+ * others and submit entries through a clone it owns.
+ */
+pub struct Executor<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    inner: Rc<Inner<'a, S, C>>,
+}
+
+impl<S: SqeEntry, C: CqeEntry> Clone for Executor<'_, S, C> {
+    fn clone(&self) -> Self {
+        Executor {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Inner<'a, S: SqeEntry, C: CqeEntry> {
+    ring: RefCell<IoUring<'a, S, C>>,
+    tasks: RefCell<HashMap<usize, Task<'a>>>,
+    next_task: Cell<usize>,
+    woken: Arc<Mutex<VecDeque<usize>>>,
+    operations: RefCell<HashMap<u64, Operation>>,
+    next_user_data: Cell<u64>,
+    unparker: Unparker,
+    thread: ThreadId,
+    wait_strategy: Cell<WaitStrategy>,
+}
+
+enum Operation {
+    Waiting(Waker),
+    Completed(Completion),
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Executor<'a, S, C> {
+    pub fn new(ring: IoUring<'a, S, C>) -> Result<Self> {
+        let unparker = ring.unparker()?;
+
+        Ok(Executor {
+            inner: Rc::new(Inner {
+                ring: RefCell::new(ring),
+                tasks: RefCell::new(HashMap::new()),
+                next_task: Cell::new(0),
+                woken: Arc::new(Mutex::new(VecDeque::new())),
+                operations: RefCell::new(HashMap::new()),
+                next_user_data: Cell::new(0),
+                unparker,
+                thread: thread::current().id(),
+                wait_strategy: Cell::new(WaitStrategy::default()),
+            }),
+        })
+    }
+
+    pub fn wait_strategy(&self) -> WaitStrategy {
+        self.inner.wait_strategy.get()
+    }
+
+    /*
+     * InvalidInput for WaitStrategy::Spin on a ring that keeps completions
+     * in task work until it is entered, COOP_TASKRUN or DEFER_TASKRUN,
+     * unless it was set up with TASKRUN_FLAG to tell.
+     */
+    pub fn set_wait_strategy(&self, strategy: WaitStrategy) -> Result<()> {
+        if strategy == WaitStrategy::Spin {
+            let flags = self.inner.ring.borrow().setup_flags();
+            if flags.intersects(IoUringSetupFlags::CoopTaskRun | IoUringSetupFlags::DeferTaskRun)
+                && !flags.contains(IoUringSetupFlags::TaskRunFlag)
+            {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "the ring would keep its completions from a spinning thread",
+                ));
+            }
+        }
+
+        self.inner.wait_strategy.set(strategy);
+        Ok(())
+    }
+
+    /*
+     * Runs `future` to completion on this thread, together with the tasks
+     * spawned on the executor. Tasks still pending when it completes stay
+     * on the executor for the next block_on.
+     */
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = self.waker(MAIN_TASK);
+        let mut cx = Context::from_waker(&waker);
+        self.wake(MAIN_TASK);
+
+        loop {
+            let woken: Vec<usize> = self.take_woken();
+            for id in woken {
+                if id == MAIN_TASK {
+                    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                        return output;
+                    }
+                } else {
+                    self.run_task(id);
+                }
+            }
+
+            /*
+             * Completions are dispatched between every round of tasks, so
+             * a task that yields lets the ones waiting for the ring go.
+             */
+            let park = self
+                .inner
+                .woken
+                .lock()
+                .map_or(true, |woken| woken.is_empty());
+            if let Err(error) = self.dispatch(park) {
+                debug!("could not drive the ring of the executor: {}", error);
+            }
+        }
+    }
+
+    /*
+     * Runs `future` as a task of its own on this thread. Dropping the handle
+     * detaches the task.
+     */
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'a,
+        F::Output: 'a,
+    {
+        let state = Rc::new(RefCell::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let joined = state.clone();
+        let task = Box::pin(async move {
+            let output = future.await;
+            let mut state = joined.borrow_mut();
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        let id = self.inner.next_task.get();
+        self.inner.next_task.set(id.wrapping_add(1) % MAIN_TASK);
+        self.inner.tasks.borrow_mut().insert(id, task);
+        self.wake(id);
+
+        JoinHandle { state }
+    }
+
+    /*
+     * Submits the entry prepared by `prepare` once awaited and resolves to
+     * its completion. The executor sets the user_data. Dropping the future
+     * before it resolves drops the completion, the entry is not cancelled.
+     */
+    pub fn submit<F>(&self, prepare: F) -> Submission<'a, S, C, F>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        Submission {
+            executor: self.clone(),
+            prepare: Some(prepare),
+            user_data: None,
+        }
+    }
+
+    /*
+     * Lets the other tasks and the completions reaped meanwhile go first.
+     */
+    pub fn yield_now(&self) -> YieldNow {
+        YieldNow { yielded: false }
+    }
+
+    /*
+     * For what the executor does not cover, e.g. registering files. Must not
+     * be used to prepare entries.
+     */
+    pub fn with_ring<R>(&self, f: impl FnOnce(&mut IoUring<'a, S, C>) -> R) -> R {
+        f(&mut self.inner.ring.borrow_mut())
+    }
+
+    fn waker(&self, id: usize) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            id,
+            woken: self.inner.woken.clone(),
+            unparker: self.inner.unparker.clone(),
+            thread: self.inner.thread,
+        }))
+    }
+
+    fn wake(&self, id: usize) {
+        if let Ok(mut woken) = self.inner.woken.lock() {
+            woken.push_back(id);
+        }
+    }
+
+    fn take_woken(&self) -> Vec<usize> {
+        self.inner
+            .woken
+            .lock()
+            .map(|mut woken| woken.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    fn run_task(&self, id: usize) {
+        /*
+         * Out of the map while it runs, it may spawn others. A task woken
+         * twice is gone by the second time once it finished.
+         */
+        let Some(mut task) = self.inner.tasks.borrow_mut().remove(&id) else {
+            return;
+        };
+        let waker = self.waker(id);
+        if task
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            self.inner.tasks.borrow_mut().insert(id, task);
+        }
+    }
+
+    /*
+     * Submits what the tasks prepared and hands the completions reaped to
+     * the operations waiting for them, waiting for one first with `park`
+     * the way the wait strategy says.
+     */
+    fn dispatch(&self, park: bool) -> Result<()> {
+        let mut ring = self.inner.ring.borrow_mut();
+        match (park, self.inner.wait_strategy.get()) {
+            (true, WaitStrategy::Block) => {
+                ring.submit_and_wait(1)?;
+            }
+            (true, WaitStrategy::Spin) => {
+                ring.submit()?;
+                self.spin(&ring, None);
+            }
+            (true, WaitStrategy::SpinThenWait(limit)) => {
+                ring.submit()?;
+                if !self.spin(&ring, Some(Instant::now() + limit)) {
+                    ring.submit_and_wait(1)?;
+                }
+            }
+            (false, _) => {
+                ring.submit()?;
+            }
+        }
+
+        let mut operations = self.inner.operations.borrow_mut();
+        while let Some(completion) = ring.next_completion() {
+            match operations.remove(&completion.user_data) {
+                Some(Operation::Waiting(waker)) => {
+                    operations.insert(completion.user_data, Operation::Completed(completion));
+                    waker.wake();
+                }
+                Some(completed) => {
+                    operations.insert(completion.user_data, completed);
+                }
+                None => debug!(
+                    "dropped the completion of an abandoned operation {}",
+                    completion.user_data
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Whether a completion came in, or a task was woken from another
+     * thread, before `deadline`.
+     */
+    fn spin(&self, ring: &IoUring<'a, S, C>, deadline: Option<Instant>) -> bool {
+        loop {
+            if ring.cq_ready() > 0
+                || self
+                    .inner
+                    .woken
+                    .lock()
+                    .map_or(true, |woken| !woken.is_empty())
+            {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    woken: Arc<Mutex<VecDeque<usize>>>,
+    unparker: Unparker,
+    thread: ThreadId,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Ok(mut woken) = self.woken.lock() {
+            woken.push_back(self.id);
+        }
+        /*
+         * The executor thread only wakes tasks while it runs, another thread
+         * may find it parked on the ring.
+         */
+        if thread::current().id() != self.thread {
+            if let Err(error) = self.unparker.unpark() {
+                debug!("could not unpark the executor: {}", error);
+            }
+        }
+    }
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/*
+ * Resolves to the output of a task spawned with spawn_local.
+ */
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Submission<'a, S: SqeEntry, C: CqeEntry, F> {
+    executor: Executor<'a, S, C>,
+    prepare: Option<F>,
+    user_data: Option<u64>,
+}
+
+impl<S: SqeEntry, C: CqeEntry, F> Submission<'_, S, C, F>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+{
+    fn prepare(&mut self) -> Result<u64> {
+        let inner = &self.executor.inner;
+        let mut ring = inner.ring.borrow_mut();
+        if ring.sq_space_left() == 0 {
+            ring.submit()?;
+        }
+        let user_data = inner.next_user_data.get();
+        let Some(sqe) = ring.next_sqe() else {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        };
+        if let Some(prepare) = self.prepare.take() {
+            prepare(sqe).user_data(user_data);
+        }
+        inner.next_user_data.set(user_data.wrapping_add(1));
+
+        Ok(user_data)
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry, F> Future for Submission<'_, S, C, F>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_> + Unpin,
+{
+    type Output = Result<Completion>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let user_data = match this.user_data {
+            Some(user_data) => user_data,
+            None => {
+                let user_data = this.prepare()?;
+                this.user_data = Some(user_data);
+                user_data
+            }
+        };
+
+        let mut operations = this.executor.inner.operations.borrow_mut();
+        match operations.remove(&user_data) {
+            Some(Operation::Completed(completion)) => {
+                this.user_data = None;
+                Poll::Ready(Ok(completion))
+            }
+            _ => {
+                operations.insert(user_data, Operation::Waiting(cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry, F> Drop for Submission<'_, S, C, F> {
+    fn drop(&mut self) {
+        if let Some(user_data) = self.user_data {
+            self.executor
+                .inner
+                .operations
+                .borrow_mut()
+                .remove(&user_data);
+        }
+    }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod when_running_tasks_on_the_ring {
+    use crate::{
+        executor::{Executor, WaitStrategy},
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+    };
+    use std::{
+        cell::RefCell,
+        future::poll_fn,
+        io::{pipe, ErrorKind, Write},
+        os::fd::AsRawFd,
+        rc::Rc,
+        sync::mpsc::channel,
+        task::{Poll, Waker},
+        thread,
+        time::Duration,
+    };
+
+    fn executor() -> Executor<'static> {
+        Executor::new(IoUring::initialize(8, IoUringParams::default()).unwrap()).unwrap()
+    }
+
+    #[test]
+    pub fn spawned_tasks_complete_their_operations() {
+        let executor = executor();
+        let handles: Vec<_> = (0..3)
+            .map(|n| {
+                let tasks = executor.clone();
+                executor.spawn_local(async move {
+                    let completion = tasks.submit(|sqe| sqe.nop()).await.unwrap();
+                    (n, completion.result)
+                })
+            })
+            .collect();
+
+        let results = executor.block_on(async {
+            let mut results = Vec::new();
+            for handle in handles {
+                results.push(handle.await);
+            }
+            results
+        });
+
+        assert_eq!(results, [(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(executor.with_ring(|ring| ring.in_flight()), 0);
+    }
+
+    #[test]
+    pub fn a_task_waiting_on_the_ring_lets_others_run() {
+        let executor = executor();
+        let (reader, mut writer) = pipe().unwrap();
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let reading = executor.clone();
+        let read_order = order.clone();
+        let read = executor.spawn_local(async move {
+            let mut buf = [0u8; 5];
+            let fd = reader.as_raw_fd();
+            let ptr = buf.as_mut_ptr();
+            let completion = reading
+                .submit(move |sqe| unsafe { sqe.read(fd, ptr, 5, 0) })
+                .await
+                .unwrap();
+            read_order.borrow_mut().push("read");
+            (completion.result, buf)
+        });
+        let yielding = executor.clone();
+        let yield_order = order.clone();
+        executor.spawn_local(async move {
+            yielding.yield_now().await;
+            yield_order.borrow_mut().push("yielded");
+            writer.write_all(b"hello").unwrap();
+        });
+
+        let (result, buf) = executor.block_on(read);
+
+        assert_eq!(result, 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(*order.borrow(), ["yielded", "read"]);
+    }
+
+    #[test]
+    pub fn a_wake_from_another_thread_unparks_the_loop() {
+        let executor = executor();
+        let (sender, receiver) = channel::<Waker>();
+        let waker = thread::spawn(move || {
+            let waker = receiver.recv().unwrap();
+            thread::sleep(Duration::from_millis(20));
+            waker.wake();
+        });
+
+        let mut sent = false;
+        executor.block_on(poll_fn(|cx| {
+            if sent {
+                return Poll::Ready(());
+            }
+            sender.send(cx.waker().clone()).unwrap();
+            sent = true;
+            Poll::Pending
+        }));
+
+        waker.join().unwrap();
+    }
+
+    fn read_written_later(executor: &Executor<'static>) -> i32 {
+        let (reader, mut writer) = pipe().unwrap();
+        let writing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            writer.write_all(b"late").unwrap();
+        });
+
+        let mut buf = [0u8; 4];
+        let fd = reader.as_raw_fd();
+        let ptr = buf.as_mut_ptr();
+        let completion = executor
+            .block_on(executor.submit(move |sqe| unsafe { sqe.read(fd, ptr, 4, 0) }))
+            .unwrap();
+        writing.join().unwrap();
+
+        completion.result
+    }
+
+    #[test]
+    pub fn a_spinning_loop_sees_completions_posted_later() {
+        let executor = executor();
+        executor.set_wait_strategy(WaitStrategy::Spin).unwrap();
+
+        assert_eq!(read_written_later(&executor), 4);
+    }
+
+    #[test]
+    pub fn a_bounded_spin_falls_back_to_waiting() {
+        let executor = executor();
+        executor
+            .set_wait_strategy(WaitStrategy::SpinThenWait(Duration::from_millis(1)))
+            .unwrap();
+
+        assert_eq!(read_written_later(&executor), 4);
+        assert_eq!(
+            executor.wait_strategy(),
+            WaitStrategy::SpinThenWait(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    pub fn spinning_on_a_ring_that_defers_its_completions_is_refused() {
+        let params = IoUringParams {
+            flags: (IoUringSetupFlags::SingleIssuer | IoUringSetupFlags::DeferTaskRun).bits(),
+            ..Default::default()
+        };
+        let executor = Executor::new(IoUring::initialize(8, params).unwrap()).unwrap();
+
+        let error = executor
+            .set_wait_strategy(WaitStrategy::Spin)
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(executor.wait_strategy(), WaitStrategy::Block);
+    }
+}
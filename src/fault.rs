@@ -0,0 +1,224 @@
+use crate::cqe::{Completion, Completions};
+use libc::{EAGAIN, ECANCELED};
+use std::collections::VecDeque;
+
+/*
+ * Probability, between 0.0 and 1.0, of each fault being applied to a
+ * completion.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FaultRates {
+    /*
+     * Positive results are cut short, as a read hitting a partial buffer.
+     */
+    pub short_read: f64,
+    pub again: f64,
+    pub canceled: f64,
+    /*
+     * The completion is held back, as the kernel does when the cq is full,
+     * until flush_overflow is called.
+     */
+    pub overflow: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedFaults {
+    pub short_reads: u64,
+    pub again: u64,
+    pub canceled: u64,
+    pub overflows: u64,
+}
+
+/*
+ * Decorator over a completion source that turns real completions into the
+ * failures applications have to cope with. The faults come from a seeded
+ * generator, so a failing run can be replayed with the same seed.
+ */
+pub struct FaultInjector<C> {
+    inner: C,
+    rates: FaultRates,
+    state: u64,
+    overflowed: VecDeque<Completion>,
+    flushed: VecDeque<Completion>,
+    injected: InjectedFaults,
+}
+
+impl<C: Completions> FaultInjector<C> {
+    pub fn new(inner: C, rates: FaultRates, seed: u64) -> Self {
+        FaultInjector {
+            inner,
+            rates,
+            state: seed.max(1),
+            overflowed: VecDeque::new(),
+            flushed: VecDeque::new(),
+            injected: InjectedFaults::default(),
+        }
+    }
+
+    pub fn has_overflowed(&self) -> bool {
+        !self.overflowed.is_empty()
+    }
+
+    /*
+     * Counterpart of entering the kernel with GETEVENTS while the cq is in
+     * overflow: held back completions become visible again, in order.
+     */
+    pub fn flush_overflow(&mut self) {
+        self.flushed.append(&mut self.overflowed);
+    }
+
+    pub fn injected(&self) -> InjectedFaults {
+        self.injected
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /*
+     * xorshift64, good enough to spread faults and fully deterministic.
+     */
+    fn next_random(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn happens(&mut self, rate: f64) -> bool {
+        rate > 0.0 && (self.next_random() as f64 / u64::MAX as f64) < rate
+    }
+}
+
+impl<C: Completions> Completions for FaultInjector<C> {
+    fn next_completion(&mut self) -> Option<Completion> {
+        if let Some(completion) = self.flushed.pop_front() {
+            return Some(completion);
+        }
+
+        loop {
+            let mut completion = self.inner.next_completion()?;
+
+            if self.happens(self.rates.overflow) {
+                self.injected.overflows += 1;
+                self.overflowed.push_back(completion);
+                continue;
+            }
+
+            if self.happens(self.rates.canceled) {
+                self.injected.canceled += 1;
+                completion.result = -ECANCELED;
+            } else if self.happens(self.rates.again) {
+                self.injected.again += 1;
+                completion.result = -EAGAIN;
+            } else if completion.result > 1 && self.happens(self.rates.short_read) {
+                self.injected.short_reads += 1;
+                completion.result =
+                    1 + (self.next_random() % (completion.result as u64 - 1)) as i32;
+            }
+
+            return Some(completion);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_injecting_faults {
+    use crate::{
+        cqe::{Completion, Completions},
+        fault::{FaultInjector, FaultRates},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{EAGAIN, ECANCELED};
+    use std::collections::VecDeque;
+
+    struct ScriptedCompletions(VecDeque<Completion>);
+
+    impl Completions for ScriptedCompletions {
+        fn next_completion(&mut self) -> Option<Completion> {
+            self.0.pop_front()
+        }
+    }
+
+    fn reads(count: u64) -> ScriptedCompletions {
+        ScriptedCompletions(
+            (0..count)
+                .map(|user_data| Completion {
+                    user_data,
+                    result: 4096,
+                    flags: 0,
+                })
+                .collect(),
+        )
+    }
+
+    fn drain<C: Completions>(completions: &mut C) -> Vec<Completion> {
+        std::iter::from_fn(|| completions.next_completion()).collect()
+    }
+
+    #[test]
+    pub fn the_same_seed_injects_the_same_faults() {
+        let rates = FaultRates {
+            short_read: 0.3,
+            again: 0.2,
+            canceled: 0.1,
+            overflow: 0.0,
+        };
+
+        let first = drain(&mut FaultInjector::new(reads(100), rates, 42));
+        let second = drain(&mut FaultInjector::new(reads(100), rates, 42));
+
+        assert_eq!(first, second);
+        assert!(first.iter().any(|c| c.result == -EAGAIN));
+        assert!(first.iter().any(|c| c.result == -ECANCELED));
+        assert!(first.iter().any(|c| c.result > 0 && c.result < 4096));
+    }
+
+    #[test]
+    pub fn a_rate_of_one_always_injects() {
+        let rates = FaultRates {
+            again: 1.0,
+            ..Default::default()
+        };
+        let mut injector = FaultInjector::new(reads(10), rates, 7);
+
+        let completions = drain(&mut injector);
+
+        assert!(completions.iter().all(|c| c.result == -EAGAIN));
+        assert_eq!(injector.injected().again, 10);
+    }
+
+    #[test]
+    pub fn overflowed_completions_show_up_after_a_flush() {
+        let rates = FaultRates {
+            overflow: 1.0,
+            ..Default::default()
+        };
+        let mut injector = FaultInjector::new(reads(3), rates, 7);
+
+        assert!(injector.next_completion().is_none());
+        assert!(injector.has_overflowed());
+
+        injector.flush_overflow();
+
+        assert_eq!(drain(&mut injector).len(), 3);
+    }
+
+    #[test]
+    pub fn ring_completions_can_be_decorated() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(9);
+        ring.submit_and_wait(1).unwrap();
+        let rates = FaultRates {
+            canceled: 1.0,
+            ..Default::default()
+        };
+
+        let completion = FaultInjector::new(&mut ring, rates, 1)
+            .next_completion()
+            .unwrap();
+
+        assert_eq!(completion.user_data, 9);
+        assert_eq!(completion.result, -ECANCELED);
+    }
+}
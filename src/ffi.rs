@@ -0,0 +1,408 @@
+use crate::{
+    builder::MemoryOptions,
+    entry::{CqeEntry, SqeEntry},
+    io_uring::{
+        DeferredCloses, IoUring, IoUringCompleteQueue, IoUringFeatures, IoUringQueueOwnership,
+        IoUringSendQueue, IoUringSetupFlags,
+    },
+    mmap::MMap,
+    owned_buf::HeldBuffers,
+    payload::OpPayloads,
+    spans::OpSpans,
+    syscalls::RealSyscalls,
+};
+use libc::c_void;
+use linux_raw_sys::io_uring::{
+    io_uring_cqe, io_uring_sqe, IORING_SETUP_CQE32, IORING_SETUP_SQE128,
+};
+use log::debug;
+use std::{
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind, Result},
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, IntoRawFd, OwnedFd},
+    ptr::{self, null_mut, NonNull},
+    sync::Arc,
+};
+
+/*
+ * liburing keeps the ring fd registered with the ring itself when this bit is
+ * set in int_flags, enter_ring_fd is then an index and not a real fd.
+ */
+const INT_FLAG_REG_RING: u8 = 1;
+
+/*
+ * Same layout as liburing's struct io_uring_sq.
+ */
+#[repr(C)]
+#[derive(Debug)]
+pub struct RawIoUringSq {
+    pub khead: *mut u32,
+    pub ktail: *mut u32,
+    pub kring_mask: *mut u32,
+    pub kring_entries: *mut u32,
+    pub kflags: *mut u32,
+    pub kdropped: *mut u32,
+    pub array: *mut u32,
+    pub sqes: *mut io_uring_sqe,
+    pub sqe_head: u32,
+    pub sqe_tail: u32,
+    pub ring_sz: usize,
+    pub ring_ptr: *mut c_void,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub pad: [u32; 2],
+}
+
+/*
+ * Same layout as liburing's struct io_uring_cq.
+ */
+#[repr(C)]
+#[derive(Debug)]
+pub struct RawIoUringCq {
+    pub khead: *mut u32,
+    pub ktail: *mut u32,
+    pub kring_mask: *mut u32,
+    pub kring_entries: *mut u32,
+    pub kflags: *mut u32,
+    pub koverflow: *mut u32,
+    pub cqes: *mut io_uring_cqe,
+    pub ring_sz: usize,
+    pub ring_ptr: *mut c_void,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub pad: [u32; 2],
+}
+
+/*
+ * Same layout as liburing's struct io_uring, so a pointer to it can be handed
+ * to liburing functions and back.
+ */
+#[repr(C)]
+#[derive(Debug)]
+pub struct RawIoUring {
+    pub sq: RawIoUringSq,
+    pub cq: RawIoUringCq,
+    pub flags: u32,
+    pub ring_fd: i32,
+    pub features: u32,
+    pub enter_ring_fd: i32,
+    pub int_flags: u8,
+    pub pad: [u8; 3],
+    pub pad2: u32,
+}
+
+fn as_u32(pointer: NonNull<c_void>) -> *mut u32 {
+    pointer.as_ptr() as *mut u32
+}
+
+fn non_null<T>(pointer: *mut T, name: &str) -> Result<NonNull<c_void>> {
+    NonNull::new(pointer as *mut c_void)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} is null", name)))
+}
+
+pub(crate) fn into_raw_parts<S: SqeEntry, C: CqeEntry>(ring: IoUring<'_, S, C>) -> RawIoUring {
+    /*
+     * Whoever gets the parts takes over the requests in flight as well, so
+     * the ring must not drain them on drop: the queues and the fd are moved
+     * out, the rest is dropped in place.
+     */
+    let mut ring = ManuallyDrop::new(ring);
+    if let Err(error) = ring.unregister_ring_fd() {
+        debug!("could not unregister the ring fd: {}", error);
+    }
+    let (send_queue, complete_queue, ring_file_descriptor) = unsafe {
+        (
+            ptr::read(&ring.send_queue),
+            ptr::read(&ring.complete_queue),
+            ptr::read(&ring.ring_file_descriptor),
+        )
+    };
+    let (flags, features) = (ring.flags, ring.features);
+    unsafe {
+        ptr::drop_in_place(&mut ring.syscalls);
+        ptr::drop_in_place(&mut ring.tracer);
+        ptr::drop_in_place(&mut ring.spans);
+        ptr::drop_in_place(&mut ring.deferred);
+        ptr::drop_in_place(&mut ring.held_buffers);
+        ptr::drop_in_place(&mut ring.payloads);
+        ptr::drop_in_place(&mut ring.deferred_closes);
+        ptr::drop_in_place(&mut ring.awaited);
+        ptr::drop_in_place(&mut ring.wait_region);
+        ptr::drop_in_place(&mut ring.watcher);
+    }
+
+    let sq_ring_mask = send_queue.ring_mask();
+    let sq_ring_entries = send_queue.ring_entries();
+    let cq_ring_mask = complete_queue.ring_mask();
+    let cq_ring_entries = complete_queue.ring_entries();
+
+    let (sq_ring_ptr, sq_ring_sz) = send_queue.ring.into_raw();
+    let (sqes, _) = send_queue.sqes.into_raw();
+    let (cq_ring_ptr, cq_ring_sz) = match complete_queue.ring {
+        IoUringQueueOwnership::Owns(ring) => ring.into_raw(),
+        IoUringQueueOwnership::Refers => (sq_ring_ptr, sq_ring_sz),
+    };
+
+    let ring_fd = ring_file_descriptor.into_raw_fd();
+
+    RawIoUring {
+        sq: RawIoUringSq {
+            khead: as_u32(send_queue.head),
+            ktail: as_u32(send_queue.tail),
+            kring_mask: as_u32(send_queue.mask),
+            kring_entries: as_u32(send_queue.entries),
+            kflags: as_u32(send_queue.flags),
+            kdropped: as_u32(send_queue.dropped),
+            array: as_u32(send_queue.array),
+            sqes: sqes.as_ptr() as *mut io_uring_sqe,
+            sqe_head: send_queue.sqe_head,
+            sqe_tail: send_queue.sqe_tail,
+            ring_sz: sq_ring_sz,
+            ring_ptr: sq_ring_ptr.as_ptr(),
+            ring_mask: sq_ring_mask,
+            ring_entries: sq_ring_entries,
+            pad: [0; 2],
+        },
+        cq: RawIoUringCq {
+            khead: as_u32(complete_queue.head),
+            ktail: as_u32(complete_queue.tail),
+            kring_mask: as_u32(complete_queue.mask),
+            kring_entries: as_u32(complete_queue.entries),
+            kflags: as_u32(complete_queue.flags),
+            koverflow: as_u32(complete_queue.overflow),
+            cqes: complete_queue.cqes.as_ptr() as *mut io_uring_cqe,
+            ring_sz: cq_ring_sz,
+            ring_ptr: cq_ring_ptr.as_ptr(),
+            ring_mask: cq_ring_mask,
+            ring_entries: cq_ring_entries,
+            pad: [0; 2],
+        },
+        flags: flags.bits(),
+        ring_fd,
+        features: features.bits(),
+        enter_ring_fd: ring_fd,
+        int_flags: 0,
+        pad: [0; 3],
+        pad2: 0,
+    }
+}
+
+/*
+ * Takes ownership of everything raw points to. The pointers are not
+ * validated beyond being non null, see IoUring::from_raw_parts.
+ */
+pub(crate) unsafe fn from_raw_parts<'a, S: SqeEntry, C: CqeEntry>(
+    raw: RawIoUring,
+) -> Result<IoUring<'a, S, C>> {
+    let sizes = IORING_SETUP_SQE128 | IORING_SETUP_CQE32;
+    if raw.flags & sizes != (S::SETUP_FLAGS | C::SETUP_FLAGS).bits() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the entry sizes of the ring do not match the requested ring type",
+        ));
+    }
+
+    if raw.int_flags & INT_FLAG_REG_RING > 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "ring fd is registered, unregister it before handing the ring over",
+        ));
+    }
+
+    if raw.ring_fd < 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid ring fd {}", raw.ring_fd),
+        ));
+    }
+
+    let sq = &raw.sq;
+    let cq = &raw.cq;
+
+    /*
+     * Every pointer is checked before any mapping is adopted, otherwise a
+     * failure halfway would unmap memory the caller still owns.
+     */
+    let sq_ring_ptr = non_null(sq.ring_ptr, "sq.ring_ptr")?;
+    let sqes = non_null(sq.sqes, "sq.sqes")?;
+    let cq_ring_ptr = non_null(cq.ring_ptr, "cq.ring_ptr")?;
+    let sq_head = non_null(sq.khead, "sq.khead")?;
+    let sq_tail = non_null(sq.ktail, "sq.ktail")?;
+    let sq_mask = non_null(sq.kring_mask, "sq.kring_mask")?;
+    let sq_entries = non_null(sq.kring_entries, "sq.kring_entries")?;
+    let sq_flags = non_null(sq.kflags, "sq.kflags")?;
+    let dropped = non_null(sq.kdropped, "sq.kdropped")?;
+    let array = non_null(sq.array, "sq.array")?;
+    let cq_head = non_null(cq.khead, "cq.khead")?;
+    let cq_tail = non_null(cq.ktail, "cq.ktail")?;
+    let cq_mask = non_null(cq.kring_mask, "cq.kring_mask")?;
+    let cq_entries = non_null(cq.kring_entries, "cq.kring_entries")?;
+    let cq_flags = non_null(cq.kflags, "cq.kflags")?;
+    let overflow = non_null(cq.koverflow, "cq.koverflow")?;
+    let cqes = non_null(cq.cqes, "cq.cqes")?;
+
+    let send_queue = IoUringSendQueue {
+        head: sq_head,
+        tail: sq_tail,
+        mask: sq_mask,
+        entries: sq_entries,
+        flags: sq_flags,
+        dropped,
+        array,
+        ring: MMap::new_with_address(sq_ring_ptr, sq.ring_sz),
+        sqes: MMap::new_with_address(sqes, sq.ring_entries as usize * S::SIZE),
+        sqe_head: sq.sqe_head,
+        sqe_tail: sq.sqe_tail,
+        relaxed: false,
+        zero_entries: true,
+        producers: false,
+        entry: PhantomData,
+    };
+
+    let ring = if cq_ring_ptr == sq_ring_ptr {
+        IoUringQueueOwnership::Refers
+    } else {
+        IoUringQueueOwnership::Owns(MMap::new_with_address(cq_ring_ptr, cq.ring_sz))
+    };
+
+    let complete_queue = IoUringCompleteQueue {
+        head: cq_head,
+        tail: cq_tail,
+        mask: cq_mask,
+        entries: cq_entries,
+        flags: cq_flags,
+        overflow,
+        ring,
+        cqes,
+        relaxed: false,
+        entry: PhantomData,
+    };
+
+    Ok(IoUring {
+        send_queue,
+        complete_queue,
+        flags: IoUringSetupFlags::from_bits_retain(raw.flags),
+        features: IoUringFeatures::from_bits_retain(raw.features),
+        memory_options: MemoryOptions::default(),
+        ring_file_descriptor: OwnedFd::from_raw_fd(raw.ring_fd),
+        syscalls: Arc::new(RealSyscalls),
+        tracer: None,
+        spans: OpSpans::default(),
+        default_priority: None,
+        deferred: VecDeque::new(),
+        held_buffers: HeldBuffers::default(),
+        payloads: OpPayloads::default(),
+        deferred_closes: DeferredCloses::default(),
+        in_flight: 0,
+        awaited: HashMap::new(),
+        in_flight_limit: None,
+        shut_down: false,
+        wait_region: None,
+        issuer: Cell::new(None),
+        watcher: None,
+        registered_ring: None,
+    })
+}
+
+impl Default for RawIoUring {
+    fn default() -> Self {
+        let sq = RawIoUringSq {
+            khead: null_mut(),
+            ktail: null_mut(),
+            kring_mask: null_mut(),
+            kring_entries: null_mut(),
+            kflags: null_mut(),
+            kdropped: null_mut(),
+            array: null_mut(),
+            sqes: null_mut(),
+            sqe_head: 0,
+            sqe_tail: 0,
+            ring_sz: 0,
+            ring_ptr: null_mut(),
+            ring_mask: 0,
+            ring_entries: 0,
+            pad: [0; 2],
+        };
+        let cq = RawIoUringCq {
+            khead: null_mut(),
+            ktail: null_mut(),
+            kring_mask: null_mut(),
+            kring_entries: null_mut(),
+            kflags: null_mut(),
+            koverflow: null_mut(),
+            cqes: null_mut(),
+            ring_sz: 0,
+            ring_ptr: null_mut(),
+            ring_mask: 0,
+            ring_entries: 0,
+            pad: [0; 2],
+        };
+
+        RawIoUring {
+            sq,
+            cq,
+            flags: 0,
+            ring_fd: -1,
+            features: 0,
+            enter_ring_fd: -1,
+            int_flags: 0,
+            pad: [0; 3],
+            pad2: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_handing_the_ring_over_to_liburing {
+    use crate::{
+        cqe::Completions,
+        entry::{Cqe16, Cqe32, Sqe64},
+        ffi::RawIoUring,
+        io_uring::{IoUring, IoUringParams},
+    };
+
+    #[test]
+    pub fn the_layout_matches_struct_io_uring() {
+        assert_eq!(size_of::<RawIoUring>(), 216);
+        assert_eq!(std::mem::offset_of!(RawIoUring, cq), 104);
+        assert_eq!(std::mem::offset_of!(RawIoUring, flags), 192);
+    }
+
+    #[test]
+    pub fn a_ring_survives_the_round_trip() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let raw = ring.into_raw_parts();
+
+        assert!(raw.ring_fd >= 0);
+        assert_eq!(raw.sq.ring_entries, 4);
+        assert_eq!(raw.sq.ring_mask, 3);
+
+        let mut ring: IoUring = unsafe { IoUring::from_raw_parts(raw) }.unwrap();
+        ring.next_sqe().unwrap().nop().user_data(11);
+        ring.submit_and_wait(1).unwrap();
+
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.user_data, 11);
+    }
+
+    #[test]
+    pub fn a_ring_of_another_entry_size_is_rejected() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let raw = ring.into_raw_parts();
+
+        assert!(unsafe { IoUring::<Sqe64, Cqe32>::from_raw_parts(raw) }.is_err());
+    }
+
+    #[test]
+    pub fn null_pointers_are_rejected() {
+        let raw = RawIoUring {
+            ring_fd: 0,
+            ..Default::default()
+        };
+
+        assert!(unsafe { IoUring::<Sqe64, Cqe16>::from_raw_parts(raw) }.is_err());
+    }
+}
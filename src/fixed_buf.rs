@@ -0,0 +1,291 @@
+use libc::{c_void, iovec};
+use std::ops::Range;
+
+/*
+ * Buffers meant to be registered with IORING_REGISTER_BUFFERS. The memory is
+ * owned here, operations address it through BufSlice and BufSliceMut, which
+ * borrow the part they cover: two operations can only write to the same
+ * bytes if they could also alias them in safe code, which they can't.
+ */
+#[derive(Debug)]
+pub struct FixedBuffers {
+    buffers: Vec<Box<[u8]>>,
+}
+
+impl FixedBuffers {
+    pub fn new(count: u16, size: usize) -> Self {
+        FixedBuffers {
+            buffers: (0..count)
+                .map(|_| vec![0u8; size].into_boxed_slice())
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn buffer(&self, index: u16) -> Option<BufSlice<'_>> {
+        self.buffers.get(index as usize).map(|bytes| BufSlice {
+            index,
+            offset: 0,
+            bytes,
+        })
+    }
+
+    pub fn buffer_mut(&mut self, index: u16) -> Option<BufSliceMut<'_>> {
+        self.buffers
+            .get_mut(index as usize)
+            .map(|bytes| BufSliceMut {
+                index,
+                offset: 0,
+                bytes,
+            })
+    }
+
+    /*
+     * Every buffer at once, each one can go to a different operation.
+     */
+    pub fn buffers_mut(&mut self) -> impl Iterator<Item = BufSliceMut<'_>> {
+        self.buffers
+            .iter_mut()
+            .enumerate()
+            .map(|(index, bytes)| BufSliceMut {
+                index: index as u16,
+                offset: 0,
+                bytes,
+            })
+    }
+
+    pub(crate) fn iovecs(&mut self) -> Vec<iovec> {
+        self.buffers
+            .iter_mut()
+            .map(|buffer| iovec {
+                iov_base: buffer.as_mut_ptr() as *mut c_void,
+                iov_len: buffer.len(),
+            })
+            .collect()
+    }
+}
+
+/*
+ * What a READ_FIXED or WRITE_FIXED entry needs to know about its buffer.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedParams {
+    pub addr: u64,
+    pub len: u32,
+    pub buf_index: u16,
+}
+
+/*
+ * Part of a registered buffer an operation may read from.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct BufSlice<'b> {
+    index: u16,
+    offset: usize,
+    bytes: &'b [u8],
+}
+
+impl<'b> BufSlice<'b> {
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /*
+     * Offset of the slice from the start of the registered buffer.
+     */
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &'b [u8] {
+        self.bytes
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Option<BufSlice<'b>> {
+        let offset = self.offset + range.start;
+        self.bytes.get(range).map(|bytes| BufSlice {
+            index: self.index,
+            offset,
+            bytes,
+        })
+    }
+}
+
+/*
+ * Part of a registered buffer an operation may write to. Not Copy, splitting
+ * is the only way to get several of them out of one buffer.
+ */
+#[derive(Debug)]
+pub struct BufSliceMut<'b> {
+    index: u16,
+    offset: usize,
+    bytes: &'b mut [u8],
+}
+
+impl<'b> BufSliceMut<'b> {
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.bytes
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+
+    pub fn slice(self, range: Range<usize>) -> Option<BufSliceMut<'b>> {
+        let offset = self.offset + range.start;
+        self.bytes.get_mut(range).map(|bytes| BufSliceMut {
+            index: self.index,
+            offset,
+            bytes,
+        })
+    }
+
+    pub fn split_at(self, mid: usize) -> Option<(BufSliceMut<'b>, BufSliceMut<'b>)> {
+        if mid > self.bytes.len() {
+            return None;
+        }
+
+        let (left, right) = self.bytes.split_at_mut(mid);
+        Some((
+            BufSliceMut {
+                index: self.index,
+                offset: self.offset,
+                bytes: left,
+            },
+            BufSliceMut {
+                index: self.index,
+                offset: self.offset + mid,
+                bytes: right,
+            },
+        ))
+    }
+}
+
+impl From<&BufSlice<'_>> for FixedParams {
+    fn from(slice: &BufSlice<'_>) -> Self {
+        FixedParams {
+            addr: slice.bytes.as_ptr() as u64,
+            len: slice.bytes.len() as u32,
+            buf_index: slice.index,
+        }
+    }
+}
+
+impl From<&mut BufSliceMut<'_>> for FixedParams {
+    fn from(slice: &mut BufSliceMut<'_>) -> Self {
+        FixedParams {
+            addr: slice.bytes.as_mut_ptr() as u64,
+            len: slice.bytes.len() as u32,
+            buf_index: slice.index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_slicing_registered_buffers {
+    use crate::{
+        cqe::Completions,
+        fixed_buf::{FixedBuffers, FixedParams},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{close, pipe};
+
+    #[test]
+    pub fn slices_keep_track_of_where_they_are() {
+        let mut buffers = FixedBuffers::new(2, 64);
+
+        let (head, tail) = buffers.buffer_mut(1).unwrap().split_at(16).unwrap();
+        let tail = tail.slice(8..24).unwrap();
+
+        assert_eq!((head.index(), head.offset(), head.len()), (1, 0, 16));
+        assert_eq!((tail.index(), tail.offset(), tail.len()), (1, 24, 16));
+    }
+
+    #[test]
+    pub fn out_of_range_slices_are_refused() {
+        let mut buffers = FixedBuffers::new(1, 64);
+
+        assert!(buffers.buffer(1).is_none());
+        assert!(buffers.buffer(0).unwrap().slice(60..70).is_none());
+        assert!(buffers.buffer_mut(0).unwrap().split_at(65).is_none());
+    }
+
+    #[test]
+    pub fn the_params_point_into_the_registered_buffer() {
+        let buffers = FixedBuffers::new(1, 64);
+        let slice = buffers.buffer(0).unwrap().slice(4..12).unwrap();
+
+        let params = FixedParams::from(&slice);
+
+        assert_eq!(
+            params.addr,
+            buffers.buffer(0).unwrap().as_slice().as_ptr() as u64 + 4
+        );
+        assert_eq!((params.len, params.buf_index), (8, 0));
+    }
+
+    #[test]
+    pub fn fixed_reads_and_writes_round_trip_through_the_kernel() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffers = FixedBuffers::new(2, 32);
+        ring.register_fixed_buffers(&mut buffers).unwrap();
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+
+        let mut slices = buffers.buffers_mut();
+        let mut source = slices.next().unwrap().slice(0..5).unwrap();
+        let mut target = slices.next().unwrap().slice(10..15).unwrap();
+        source.as_mut_slice().copy_from_slice(b"fixed");
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .write_fixed(fds[1], (&mut source).into(), 0);
+            ring.submit_and_wait(1).unwrap();
+            assert_eq!(ring.next_completion().unwrap().result, 5);
+
+            ring.next_sqe()
+                .unwrap()
+                .read_fixed(fds[0], (&mut target).into(), 0);
+            ring.submit_and_wait(1).unwrap();
+            assert_eq!(ring.next_completion().unwrap().result, 5);
+
+            close(fds[0]);
+            close(fds[1]);
+        }
+
+        assert_eq!(target.as_slice(), b"fixed");
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    entry::{CqeEntry, SqeEntry},
+    io_uring::IoUring,
+    syscalls::{io_uring_register, IoUringOpCode},
+};
+use libc::c_void;
+use linux_raw_sys::io_uring::io_uring_files_update;
+use log::debug;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::Result,
+    os::fd::{AsFd, OwnedFd},
+    sync::{Arc, Mutex},
+};
+
+/*
+ * The registered file table of a ring, handing out its slots as SlotGuards.
+ * A guard empties its slot when dropped and gives it back, so a server that
+ * accepts into the table for days does not lose a slot to every connection
+ * whose close went missing.
+ *
+ * Guards can be dropped on any thread: the slot is emptied with a
+ * FILES_UPDATE through io_uring_register, not an entry on the ring. The
+ * table holds a copy of the ring fd for that, which keeps the ring alive as
+ * long as a guard is.
+ */
+#[derive(Debug, Clone)]
+pub struct FixedTable {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    ring_fd: OwnedFd,
+    capacity: u32,
+    free: Mutex<BinaryHeap<Reverse<u32>>>,
+}
+
+impl FixedTable {
+    pub(crate) fn register<S: SqeEntry, C: CqeEntry>(
+        ring: &IoUring<'_, S, C>,
+        count: u32,
+    ) -> Result<Self> {
+        let ring_fd = ring.as_fd().try_clone_to_owned()?;
+        ring.register_files_sparse(count)?;
+
+        Ok(FixedTable {
+            shared: Arc::new(Shared {
+                ring_fd,
+                capacity: count,
+                free: Mutex::new((0..count).map(Reverse).collect()),
+            }),
+        })
+    }
+
+    /*
+     * The lowest free slot, None when every slot is taken.
+     */
+    pub fn allocate(&self) -> Option<SlotGuard> {
+        let Reverse(slot) = self
+            .shared
+            .free
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .pop()?;
+
+        Some(SlotGuard {
+            shared: self.shared.clone(),
+            slot,
+        })
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.shared.capacity
+    }
+
+    pub fn available(&self) -> usize {
+        self.shared
+            .free
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .len()
+    }
+}
+
+/*
+ * A slot of a FixedTable, for file_slot, close_direct and entries with
+ * IoUringSqeFlags::FixedFile. Dropping it empties the slot, closing the
+ * file left in it once the requests using it are done, and hands the slot
+ * back to the table; an empty slot, e.g. after close_direct, is fine.
+ */
+#[derive(Debug)]
+pub struct SlotGuard {
+    shared: Arc<Shared>,
+    slot: u32,
+}
+
+impl SlotGuard {
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        let fd: i32 = -1;
+        let update = io_uring_files_update {
+            offset: self.slot,
+            resv: 0,
+            fds: &fd as *const i32 as u64,
+        };
+
+        let result = unsafe {
+            io_uring_register(
+                &self.shared.ring_fd,
+                IoUringOpCode::IoRingRegisterFilesUpdate,
+                &update as *const io_uring_files_update as *const c_void,
+                1,
+            )
+        };
+
+        /*
+         * A slot that could not be emptied may still hold a file, it is
+         * better lost than handed out again.
+         */
+        match result {
+            Ok(_) => self
+                .shared
+                .free
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .push(Reverse(self.slot)),
+            Err(error) => debug!("could not empty fixed file slot {}: {}", self.slot, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_handing_out_fixed_file_slots {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        sqe::IoUringSqeFlags,
+        testing::ring_with_temp_file,
+    };
+    use libc::{AT_FDCWD, EBADF, O_RDONLY};
+    use std::ffi::CString;
+
+    #[test]
+    pub fn slots_come_back_when_their_guard_is_dropped() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let table = ring.register_fixed_table(2).unwrap();
+
+        let first = table.allocate().unwrap();
+        let second = table.allocate().unwrap();
+        assert_eq!((first.slot(), second.slot()), (0, 1));
+        assert!(table.allocate().is_none());
+
+        drop(first);
+
+        assert_eq!(table.available(), 1);
+        assert_eq!(table.allocate().unwrap().slot(), 0);
+    }
+
+    #[test]
+    pub fn a_dropped_guard_empties_its_slot() {
+        let (mut ring, file) = ring_with_temp_file(b"slot").unwrap();
+        let table = ring.register_fixed_table(1).unwrap();
+        let c_path = CString::new(file.path().to_str().unwrap()).unwrap();
+
+        let guard = table.allocate().unwrap();
+        let slot = guard.slot();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .openat(AT_FDCWD, c_path.as_ptr(), O_RDONLY, 0)
+                .file_slot(slot)
+        };
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().result, 0);
+
+        drop(guard);
+
+        let mut buf = [0u8; 4];
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(slot as i32, buf.as_mut_ptr(), 4, 0)
+                .flags(IoUringSqeFlags::FixedFile)
+        };
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().result, -EBADF);
+    }
+}
@@ -0,0 +1,1498 @@
+use crate::{
+    cqe::Completion,
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::{DeferredCloses, IoUring, IoUringFeatures},
+    owned_buf::OwnedBuf,
+    sqe::{FsyncFlags, IoUringSqeFlags, Sqe},
+};
+use libc::{
+    fcntl, lseek, pipe2, statx, statx_timestamp, AT_FDCWD, AT_SYMLINK_NOFOLLOW, EAGAIN, EINTR,
+    F_GETPIPE_SZ, O_CLOEXEC, O_RDONLY, POLLOUT, SEEK_CUR, STATX_BASIC_STATS, STATX_BTIME,
+    SYNC_FILE_RANGE_WAIT_AFTER, SYNC_FILE_RANGE_WAIT_BEFORE, SYNC_FILE_RANGE_WRITE, S_IFDIR,
+    S_IFLNK, S_IFMT, S_IFREG,
+};
+use std::{
+    ffi::CString,
+    fmt::{Debug, Formatter},
+    fs::File as StdFile,
+    future::Future,
+    io::{self, ErrorKind, Result},
+    mem::{zeroed, MaybeUninit},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    pin::Pin,
+    slice,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/*
+ * user_data of the entries this module submits and waits for. The top bit is
+ * what OpScope reserves as well, neither can run while the other is waiting.
+ */
+const OPEN_USER_DATA: u64 = u64::MAX - 2;
+const READ_USER_DATA: u64 = u64::MAX - 1;
+const CLOSE_USER_DATA: u64 = u64::MAX;
+const SPLICE_USER_DATA: u64 = u64::MAX - 3;
+const POLL_USER_DATA: u64 = u64::MAX - 4;
+const STATX_USER_DATA: u64 = u64::MAX - 5;
+const WRITE_USER_DATA: u64 = u64::MAX - 6;
+const SYNC_USER_DATA: u64 = u64::MAX - 7;
+
+/*
+ * How much of a write File::write_at makes durable before it returns.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /*
+     * Nothing, the data sits in the page cache until written back.
+     */
+    #[default]
+    None,
+    /*
+     * The data and the metadata needed to read it back, like fdatasync.
+     */
+    Data,
+    /*
+     * The data and all of the metadata, like fsync.
+     */
+    All,
+}
+
+/*
+ * File driven through a ring. Every call submits to the ring it is given and
+ * waits for the result, except submit_readv which leaves the read in flight.
+ * The sync mode is the durability contract of writes: the flush is linked
+ * behind each write, in the same submission.
+ */
+pub struct File {
+    /*
+     * Only None while being dropped.
+     */
+    fd: Option<OwnedFd>,
+    sync_mode: SyncMode,
+    deferred_closes: Option<DeferredCloses>,
+    /*
+     * Position of read and write on kernels without IORING_FEAT_RW_CUR_POS,
+     * taken from the fd the first time it is needed.
+     */
+    position: Option<u64>,
+}
+
+impl File {
+    pub fn open(path: impl AsRef<Path>) -> Result<File> {
+        Ok(StdFile::open(path)?.into())
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> Result<File> {
+        Ok(StdFile::create(path)?.into())
+    }
+
+    /*
+     * Closes the file through `ring` when it is dropped: whatever is still
+     * in flight on it is cancelled first and the fd is only closed after,
+     * so a completion can never refer to a recycled fd. Without it the fd is
+     * closed right away by Drop.
+     */
+    pub fn cancel_on_drop<S: SqeEntry, C: CqeEntry>(mut self, ring: &IoUring<'_, S, C>) -> Self {
+        self.deferred_closes = Some(ring.deferred_closes());
+        self
+    }
+
+    /*
+     * Leaves a readv in flight, see IoUring::readv_owned.
+     */
+    pub fn submit_readv<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buffers: Vec<OwnedBuf>,
+        offset: u64,
+        user_data: u64,
+    ) -> std::result::Result<(), Vec<OwnedBuf>> {
+        ring.readv_owned(self.raw_fd(), buffers, offset, user_data)
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.fd.as_ref().map_or(-1, |fd| fd.as_raw_fd())
+    }
+
+    pub fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.sync_mode = mode;
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn read_at<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Result<usize> {
+        let len = buffer_len(buf.len())?;
+        let fd = self.raw_fd();
+
+        run(ring, READ_USER_DATA, |sqe| unsafe {
+            sqe.read(fd, buf.as_mut_ptr(), len, offset)
+        })
+        .map(|read| read as usize)
+    }
+
+    /*
+     * Same as read_at into a buffer that was never initialized, so a large
+     * one does not have to be zeroed first. Returns the part the read
+     * filled, which is initialized from then on.
+     */
+    pub fn read_uninit_at<'b, S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &'b mut [MaybeUninit<u8>],
+        offset: u64,
+    ) -> Result<&'b mut [u8]> {
+        let len = buffer_len(buf.len())?;
+        let fd = self.raw_fd();
+
+        let read = run(ring, READ_USER_DATA, |sqe| unsafe {
+            sqe.read(fd, buf.as_mut_ptr() as *mut u8, len, offset)
+        })?;
+
+        Ok(unsafe { assume_init(buf, read as usize) })
+    }
+
+    /*
+     * Writes `buf` at `offset` and flushes it as the sync mode asks. The
+     * flush is hard linked, so it runs after a short write as well.
+     */
+    pub fn write_at<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &[u8],
+        offset: u64,
+    ) -> Result<usize> {
+        let len = buffer_len(buf.len())?;
+
+        check_result(self.write_result_at(ring, buf, len, offset)?).map(|written| written as usize)
+    }
+
+    /*
+     * Result of the completion of write_at, the one of the flush when the
+     * write went through but the flush failed.
+     */
+    fn write_result_at<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &[u8],
+        len: u32,
+        offset: u64,
+    ) -> Result<i32> {
+        let fd = self.raw_fd();
+
+        let flags = match self.sync_mode {
+            SyncMode::None => {
+                return run_completion(ring, WRITE_USER_DATA, |sqe| unsafe {
+                    sqe.write(fd, buf.as_ptr(), len, offset)
+                })
+                .map(|completion| completion.result);
+            }
+            SyncMode::Data => FsyncFlags::DataSync,
+            SyncMode::All => FsyncFlags::empty(),
+        };
+
+        reserve(ring, 2)?;
+        if let Some(sqe) = ring.next_sqe() {
+            unsafe { sqe.write(fd, buf.as_ptr(), len, offset) }
+                .user_data(WRITE_USER_DATA)
+                .flags(IoUringSqeFlags::IoHardLink);
+        }
+        if let Some(sqe) = ring.next_sqe() {
+            sqe.fsync(fd, flags).user_data(SYNC_USER_DATA);
+        }
+
+        let completions = ring.wait_for_completions(&[WRITE_USER_DATA, SYNC_USER_DATA])?;
+        match (completions[0].result, completions[1].result) {
+            (written, synced) if written >= 0 && synced < 0 => Ok(synced),
+            (written, _) => Ok(written),
+        }
+    }
+
+    /*
+     * Reads at the current position of the file and moves past what was
+     * read, like read(2). Kernels with IORING_FEAT_RW_CUR_POS, 5.6 on, use
+     * and move the position of the fd itself. Before that, the position is
+     * tracked here, starting from the one of the fd, which stays where it
+     * was.
+     */
+    pub fn read<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let offset = self.current_offset(supports_cur_pos(ring))?;
+        let read = self.read_at(ring, buf, offset)?;
+        self.advance(read);
+
+        Ok(read)
+    }
+
+    /*
+     * Writes at the current position, like write(2), see read.
+     */
+    pub fn write<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &[u8],
+    ) -> Result<usize> {
+        let offset = self.current_offset(supports_cur_pos(ring))?;
+        let written = self.write_at(ring, buf, offset)?;
+        self.advance(written);
+
+        Ok(written)
+    }
+
+    /*
+     * The offset to hand the kernel: -1 for the position of the fd when the
+     * kernel takes it, the tracked one otherwise.
+     */
+    fn current_offset(&mut self, cur_pos: bool) -> Result<u64> {
+        if cur_pos {
+            return Ok(u64::MAX);
+        }
+        if let Some(position) = self.position {
+            return Ok(position);
+        }
+
+        let position = unsafe { lseek(self.raw_fd(), 0, SEEK_CUR) };
+        if position < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.position = Some(position as u64);
+
+        Ok(position as u64)
+    }
+
+    fn advance(&mut self, count: usize) {
+        if let Some(position) = &mut self.position {
+            *position += count as u64;
+        }
+    }
+
+    /*
+     * Reads until `buf` is full, a short read is continued with a read of
+     * what is left. UnexpectedEof when the file ends first, what was read
+     * so far is in `buf` then.
+     */
+    pub fn read_exact_at<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Result<()> {
+        let fd = self.raw_fd();
+
+        until_done(buf.len(), ErrorKind::UnexpectedEof, |done, len| {
+            let chunk = &mut buf[done..done + len];
+            run_completion(ring, READ_USER_DATA, |sqe| unsafe {
+                sqe.read(fd, chunk.as_mut_ptr(), len as u32, offset + done as u64)
+            })
+            .map(|completion| completion.result)
+        })
+    }
+
+    /*
+     * Writes the whole of `buf`, a short write is continued with a write of
+     * what is left. Each write is flushed as the sync mode asks.
+     */
+    pub fn write_all_at<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        buf: &[u8],
+        offset: u64,
+    ) -> Result<()> {
+        until_done(buf.len(), ErrorKind::WriteZero, |done, len| {
+            let chunk = &buf[done..done + len];
+            self.write_result_at(ring, chunk, len as u32, offset + done as u64)
+        })
+    }
+
+    pub fn sync_all<S: SqeEntry, C: CqeEntry>(&self, ring: &mut IoUring<'_, S, C>) -> Result<()> {
+        let fd = self.raw_fd();
+        run(ring, SYNC_USER_DATA, |sqe| {
+            sqe.fsync(fd, FsyncFlags::empty())
+        })?;
+
+        Ok(())
+    }
+
+    pub fn sync_data<S: SqeEntry, C: CqeEntry>(&self, ring: &mut IoUring<'_, S, C>) -> Result<()> {
+        let fd = self.raw_fd();
+        run(ring, SYNC_USER_DATA, |sqe| {
+            sqe.fsync(fd, FsyncFlags::DataSync)
+        })?;
+
+        Ok(())
+    }
+
+    /*
+     * Writes back the dirty pages of the range and waits for them, without
+     * flushing metadata or the disk cache. Cheaper than sync_data when only
+     * part of a large file changed.
+     */
+    pub fn sync_range<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        offset: u64,
+        len: u32,
+    ) -> Result<()> {
+        let fd = self.raw_fd();
+        let flags =
+            SYNC_FILE_RANGE_WAIT_BEFORE | SYNC_FILE_RANGE_WRITE | SYNC_FILE_RANGE_WAIT_AFTER;
+        run(ring, SYNC_USER_DATA, |sqe| {
+            sqe.sync_file_range(fd, offset, len, flags)
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if let (Some(fd), Some(deferred_closes)) = (self.fd.take(), &self.deferred_closes) {
+            deferred_closes
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .push(fd);
+        }
+    }
+}
+
+impl Debug for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("File")
+            .field("fd", &self.raw_fd())
+            .field("sync_mode", &self.sync_mode)
+            .field("cancel_on_drop", &self.deferred_closes.is_some())
+            .finish()
+    }
+}
+
+impl From<OwnedFd> for File {
+    fn from(fd: OwnedFd) -> Self {
+        File {
+            fd: Some(fd),
+            sync_mode: SyncMode::default(),
+            deferred_closes: None,
+            position: None,
+        }
+    }
+}
+
+impl From<StdFile> for File {
+    fn from(file: StdFile) -> Self {
+        OwnedFd::from(file).into()
+    }
+}
+
+fn supports_cur_pos<S: SqeEntry, C: CqeEntry>(ring: &IoUring<'_, S, C>) -> bool {
+    ring.features().contains(IoUringFeatures::RwCurPos)
+}
+
+/*
+ * Reads up to `max_len` bytes of the file at `path` with one submission: an
+ * openat into `slot` of the registered file table, a read of the fixed file
+ * and a close of the slot, linked so each runs after the previous one. A
+ * file shorter than `max_len` is a short read, which breaks a regular link,
+ * so the close is hard linked to the read. The ring needs a file table, see
+ * IoUring::register_files_sparse, and `slot` must be free.
+ */
+pub fn read_file<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    path: impl AsRef<Path>,
+    slot: u32,
+    max_len: usize,
+) -> Result<Vec<u8>> {
+    let path = c_path(path.as_ref())?;
+    let len = buffer_len(max_len)?;
+    let mut contents = Vec::with_capacity(max_len);
+
+    reserve(ring, 3)?;
+
+    unsafe {
+        ring.next_sqe()
+            .unwrap()
+            .openat(AT_FDCWD, path.as_ptr(), O_RDONLY, 0)
+            .file_slot(slot)
+            .user_data(OPEN_USER_DATA)
+            .flags(IoUringSqeFlags::IoLink);
+        ring.next_sqe()
+            .unwrap()
+            .read(slot as i32, contents.as_mut_ptr(), len, 0)
+            .user_data(READ_USER_DATA)
+            .flags(IoUringSqeFlags::FixedFile | IoUringSqeFlags::IoHardLink);
+    }
+    ring.next_sqe()
+        .unwrap()
+        .close_direct(slot)
+        .user_data(CLOSE_USER_DATA);
+
+    /*
+     * The kernel may write to `contents` until the read completed, so all
+     * three are waited for, or abandoned, before anything is returned.
+     */
+    let completions =
+        ring.wait_for_completions(&[OPEN_USER_DATA, READ_USER_DATA, CLOSE_USER_DATA])?;
+
+    check(&completions[0])?;
+    let read = check(&completions[1])?;
+    check(&completions[2])?;
+
+    unsafe { contents.set_len(read as usize) };
+    Ok(contents)
+}
+
+/*
+ * What statx reports about a file.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    accessed: SystemTime,
+    modified: SystemTime,
+    changed: SystemTime,
+    created: Option<SystemTime>,
+}
+
+impl Metadata {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /*
+     * File type and permission bits, as in st_mode.
+     */
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn nlink(&self) -> u32 {
+        self.nlink
+    }
+
+    pub fn accessed(&self) -> SystemTime {
+        self.accessed
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    /*
+     * Last status change, ctime.
+     */
+    pub fn changed(&self) -> SystemTime {
+        self.changed
+    }
+
+    /*
+     * Birth time, None when the filesystem does not record it.
+     */
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created
+    }
+}
+
+impl From<&statx> for Metadata {
+    fn from(stat: &statx) -> Self {
+        Metadata {
+            len: stat.stx_size,
+            mode: stat.stx_mode as u32,
+            uid: stat.stx_uid,
+            gid: stat.stx_gid,
+            nlink: stat.stx_nlink,
+            accessed: system_time(&stat.stx_atime),
+            modified: system_time(&stat.stx_mtime),
+            changed: system_time(&stat.stx_ctime),
+            created: (stat.stx_mask & STATX_BTIME > 0).then(|| system_time(&stat.stx_btime)),
+        }
+    }
+}
+
+fn system_time(timestamp: &statx_timestamp) -> SystemTime {
+    let nanos = Duration::new(0, timestamp.tv_nsec);
+
+    if timestamp.tv_sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(timestamp.tv_sec as u64) + nanos
+    } else {
+        UNIX_EPOCH - Duration::from_secs(timestamp.tv_sec.unsigned_abs()) + nanos
+    }
+}
+
+/*
+ * Metadata of the file at `path` with the statx opcode, following symlinks.
+ */
+pub fn metadata<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    path: impl AsRef<Path>,
+) -> Result<Metadata> {
+    stat(ring, path.as_ref(), 0)
+}
+
+/*
+ * Same as metadata, but a symlink is described itself instead of its target.
+ */
+pub fn symlink_metadata<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    path: impl AsRef<Path>,
+) -> Result<Metadata> {
+    stat(ring, path.as_ref(), AT_SYMLINK_NOFOLLOW)
+}
+
+fn stat<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    path: &Path,
+    flags: i32,
+) -> Result<Metadata> {
+    let path = c_path(path)?;
+    let mut stat: statx = unsafe { zeroed() };
+
+    run(ring, STATX_USER_DATA, |sqe| unsafe {
+        sqe.statx(
+            AT_FDCWD,
+            path.as_ptr(),
+            flags,
+            STATX_BASIC_STATS | STATX_BTIME,
+            &mut stat,
+        )
+    })?;
+
+    Ok(Metadata::from(&stat))
+}
+
+/*
+ * A file in the registered file table of a ring, to use with
+ * IoUringSqeFlags::FixedFile.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedFd(u32);
+
+impl FixedFd {
+    pub fn slot(self) -> u32 {
+        self.0
+    }
+}
+
+/*
+ * Opens `path` like openat(2) relative to the working directory and
+ * resolves to the new fd. The open is submitted on the first poll. Dropped
+ * before it resolves, the future abandons the open, a file opened anyway is
+ * not closed.
+ */
+pub fn open_at<'r, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    path: impl AsRef<Path>,
+    flags: i32,
+    mode: u32,
+) -> OpenAt<'r, 'a, S, C> {
+    OpenAt {
+        ring,
+        path: c_path(path.as_ref()),
+        flags,
+        mode,
+        slot: None,
+        armed: false,
+    }
+}
+
+/*
+ * Same as open_at, but the file goes into `slot` of the registered file
+ * table instead of getting an fd. O_CLOEXEC is refused.
+ */
+pub fn open_at_fixed<'r, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    path: impl AsRef<Path>,
+    flags: i32,
+    mode: u32,
+    slot: u32,
+) -> OpenAtFixed<'r, 'a, S, C> {
+    OpenAtFixed(OpenAt {
+        ring,
+        path: c_path(path.as_ref()),
+        flags,
+        mode,
+        slot: Some(slot),
+        armed: false,
+    })
+}
+
+pub struct OpenAt<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    path: Result<CString>,
+    flags: i32,
+    mode: u32,
+    slot: Option<u32>,
+    armed: bool,
+}
+
+impl<S: SqeEntry, C: CqeEntry> OpenAt<'_, '_, S, C> {
+    fn poll_open(&mut self, cx: &mut Context<'_>) -> Poll<Result<u32>> {
+        let path = match &self.path {
+            Ok(path) => path.as_ptr(),
+            Err(error) => {
+                return Poll::Ready(Err(io::Error::new(error.kind(), error.to_string())));
+            }
+        };
+        let (flags, mode, slot) = (self.flags, self.mode, self.slot);
+
+        let opened = poll_run_completion(self.ring, OPEN_USER_DATA, &mut self.armed, cx, |sqe| {
+            let sqe = unsafe { sqe.openat(AT_FDCWD, path, flags, mode) };
+            match slot {
+                Some(slot) => sqe.file_slot(slot),
+                None => sqe,
+            }
+        });
+        match opened {
+            Poll::Ready(completion) => Poll::Ready(completion.and_then(|c| check(&c))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Future for OpenAt<'_, '_, S, C> {
+    type Output = Result<OwnedFd>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.get_mut().poll_open(cx) {
+            Poll::Ready(fd) => {
+                Poll::Ready(fd.map(|fd| unsafe { OwnedFd::from_raw_fd(fd as RawFd) }))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Drop for OpenAt<'_, '_, S, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.ring.abandon(&[OPEN_USER_DATA]);
+        }
+    }
+}
+
+pub struct OpenAtFixed<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16>(OpenAt<'r, 'a, S, C>);
+
+impl<S: SqeEntry, C: CqeEntry> Future for OpenAtFixed<'_, '_, S, C> {
+    type Output = Result<FixedFd>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let open = &mut self.get_mut().0;
+        let slot = open.slot.unwrap_or_default();
+
+        match open.poll_open(cx) {
+            Poll::Ready(opened) => Poll::Ready(opened.map(|_| FixedFd(slot))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The first `len` bytes of `buf`, which the kernel wrote.
+///
+/// # Safety
+///
+/// They must be initialized.
+pub(crate) unsafe fn assume_init(buf: &mut [MaybeUninit<u8>], len: usize) -> &mut [u8] {
+    slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, len.min(buf.len()))
+}
+
+fn buffer_len(len: usize) -> Result<u32> {
+    u32::try_from(len)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "buffer longer than u32::MAX"))
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+/*
+ * Copies the contents of `from` to `to` like std::fs::copy, the data moves
+ * kernel side through a pipe with splice. `to` is created or truncated and
+ * gets the permissions of `from`. Returns the number of bytes copied.
+ */
+pub fn copy<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> Result<u64> {
+    let source = StdFile::open(from)?;
+    let permissions = source.metadata()?.permissions();
+    let target = StdFile::create(to)?;
+    target.set_permissions(permissions)?;
+
+    Splicer::new()?.transfer(
+        ring,
+        source.as_raw_fd(),
+        Some(0),
+        target.as_raw_fd(),
+        Some(0),
+        None,
+    )
+}
+
+/*
+ * Pipe the data goes through between two splices.
+ */
+pub(crate) struct Splicer {
+    read_end: OwnedFd,
+    write_end: OwnedFd,
+    capacity: u32,
+}
+
+impl Splicer {
+    pub(crate) fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read_end, write_end) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        let capacity = unsafe { fcntl(write_end.as_raw_fd(), F_GETPIPE_SZ) };
+        if capacity < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Splicer {
+            read_end,
+            write_end,
+            capacity: capacity as u32,
+        })
+    }
+
+    /*
+     * Moves up to `len` bytes, or everything up to end of file for None, and
+     * returns how many were moved. Each chunk is drained from the pipe before
+     * the next one is spliced in, so a slow `output` holds the copy back
+     * instead of filling the pipe. A non-blocking `output` that is full is
+     * polled until it can take more.
+     */
+    pub(crate) fn transfer<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        input: RawFd,
+        mut in_offset: Option<u64>,
+        output: RawFd,
+        mut out_offset: Option<u64>,
+        len: Option<u64>,
+    ) -> Result<u64> {
+        let mut moved = 0u64;
+
+        loop {
+            let remaining = len.map_or(u64::MAX, |len| len - moved);
+            if remaining == 0 {
+                return Ok(moved);
+            }
+
+            let chunk = remaining.min(self.capacity as u64) as u32;
+            let filled = self.splice(
+                ring,
+                input,
+                in_offset,
+                self.write_end.as_raw_fd(),
+                None,
+                chunk,
+            )?;
+            if filled == 0 {
+                return Ok(moved);
+            }
+
+            let mut left = filled;
+            while left > 0 {
+                let drained = match self.splice(
+                    ring,
+                    self.read_end.as_raw_fd(),
+                    None,
+                    output,
+                    out_offset,
+                    left,
+                ) {
+                    Err(error) if error.raw_os_error() == Some(EAGAIN) => {
+                        wait_until_writable(ring, output)?;
+                        continue;
+                    }
+                    result => result?,
+                };
+                if drained == 0 {
+                    return Err(io::Error::from(ErrorKind::WriteZero));
+                }
+                left -= drained;
+                out_offset = out_offset.map(|offset| offset + drained as u64);
+            }
+
+            in_offset = in_offset.map(|offset| offset + filled as u64);
+            moved += filled as u64;
+        }
+    }
+
+    fn splice<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        fd_in: RawFd,
+        off_in: Option<u64>,
+        fd_out: RawFd,
+        off_out: Option<u64>,
+        len: u32,
+    ) -> Result<u32> {
+        run(ring, SPLICE_USER_DATA, |sqe| {
+            sqe.splice(fd_in, off_in, fd_out, off_out, len, 0)
+        })
+    }
+}
+
+fn wait_until_writable<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    fd: RawFd,
+) -> Result<()> {
+    run(ring, POLL_USER_DATA, |sqe| sqe.poll_add(fd, POLLOUT as u32))?;
+
+    Ok(())
+}
+
+/*
+ * Makes sure `count` entries can be prepared, submitting what is pending if
+ * needed.
+ */
+fn reserve<S: SqeEntry, C: CqeEntry>(ring: &mut IoUring<'_, S, C>, count: u32) -> Result<()> {
+    if ring.sq_space_left() < count {
+        ring.submit()?;
+    }
+    if ring.sq_space_left() < count {
+        return Err(io::Error::new(
+            ErrorKind::WouldBlock,
+            "the submission queue is full",
+        ));
+    }
+
+    Ok(())
+}
+
+/*
+ * Transfers `len` bytes with `transfer`, called with the bytes done so far
+ * and how many to go next, at most u32::MAX, until all of them are done.
+ * `transfer` returns the result of its completion. A transfer of nothing
+ * means the other side is done, which fails with `stalled`. Transfers that
+ * completed with EINTR are tried again; an error of `transfer` itself, e.g.
+ * a failed wait, ends it, the request was abandoned then.
+ */
+pub(crate) fn until_done<F>(len: usize, stalled: ErrorKind, mut transfer: F) -> Result<()>
+where
+    F: FnMut(usize, usize) -> Result<i32>,
+{
+    let mut done = 0;
+
+    while done < len {
+        let next = (len - done).min(u32::MAX as usize);
+        match transfer(done, next)? {
+            0 => {
+                return Err(io::Error::new(
+                    stalled,
+                    "the transfer stopped before all of the buffer went through",
+                ))
+            }
+            result if result == -EINTR => {}
+            result => done += check_result(result)? as usize,
+        }
+    }
+
+    Ok(())
+}
+
+/*
+ * Submits the single entry `prepare` sets up and waits for it.
+ */
+pub(crate) fn run<S: SqeEntry, C: CqeEntry, F>(
+    ring: &mut IoUring<'_, S, C>,
+    user_data: u64,
+    prepare: F,
+) -> Result<u32>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+{
+    check(&run_completion(ring, user_data, prepare)?)
+}
+
+/*
+ * Same as run, for callers that need the flags of the completion as well.
+ * Its result is not checked.
+ */
+pub(crate) fn run_completion<S: SqeEntry, C: CqeEntry, F>(
+    ring: &mut IoUring<'_, S, C>,
+    user_data: u64,
+    prepare: F,
+) -> Result<Completion>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+{
+    reserve(ring, 1)?;
+    if let Some(sqe) = ring.next_sqe() {
+        prepare(sqe).user_data(user_data);
+    }
+
+    ring.wait_for_completion(user_data)
+}
+
+/*
+ * run_completion for futures: the entry is prepared on the first poll and
+ * `armed` tells the next ones it is in flight, Pending until it completes.
+ * What the entry points to must stay put until then, and a future dropped
+ * while armed abandons the request.
+ */
+pub(crate) fn poll_run_completion<S: SqeEntry, C: CqeEntry, F>(
+    ring: &mut IoUring<'_, S, C>,
+    user_data: u64,
+    armed: &mut bool,
+    cx: &mut Context<'_>,
+    prepare: F,
+) -> Poll<Result<Completion>>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+{
+    if !*armed {
+        if let Err(error) = reserve(ring, 1) {
+            return Poll::Ready(Err(error));
+        }
+        if let Some(sqe) = ring.next_sqe() {
+            prepare(sqe).user_data(user_data);
+        }
+        *armed = true;
+    }
+
+    let completion = ring.poll_completion(user_data, cx);
+    if completion.is_ready() {
+        *armed = false;
+    }
+
+    completion
+}
+
+/*
+ * A negative result is the errno the operation failed with.
+ */
+pub(crate) fn check(completion: &Completion) -> Result<u32> {
+    check_result(completion.result)
+}
+
+fn check_result(result: i32) -> Result<u32> {
+    if result < 0 {
+        return Err(io::Error::from_raw_os_error(-result));
+    }
+
+    Ok(result as u32)
+}
+
+#[cfg(test)]
+mod when_reading_a_whole_file {
+    use crate::{
+        cqe::Completions,
+        fs::read_file,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::ENOENT;
+    use std::{env::temp_dir, fs, process::id};
+
+    #[test]
+    pub fn the_chain_returns_the_contents() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_files_sparse(2).unwrap();
+        let path = temp_dir().join(format!("vargasync-read-file-{}", id()));
+        fs::write(&path, b"key = value\n").unwrap();
+
+        let contents = read_file(&mut ring, &path, 1, 64).unwrap();
+
+        assert_eq!(contents, b"key = value\n");
+        assert!(ring.next_completion().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn a_missing_file_fails_the_whole_chain() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_files_sparse(1).unwrap();
+
+        let error = read_file(&mut ring, "/definitely/not/here", 0, 64).unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(ENOENT));
+    }
+}
+
+#[cfg(test)]
+mod when_copying_a_file {
+    use crate::{
+        fs::copy,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{env::temp_dir, fs, os::unix::fs::PermissionsExt, process::id};
+
+    #[test]
+    pub fn the_contents_and_permissions_are_copied() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let from = temp_dir().join(format!("vargasync-copy-from-{}", id()));
+        let to = temp_dir().join(format!("vargasync-copy-to-{}", id()));
+        let contents: Vec<u8> = (0..200_000u32).map(|value| value as u8).collect();
+        fs::write(&from, &contents).unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let copied = copy(&mut ring, &from, &to).unwrap();
+
+        assert_eq!(copied, contents.len() as u64);
+        assert_eq!(fs::read(&to).unwrap(), contents);
+        assert_eq!(
+            fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        fs::remove_file(from).unwrap();
+        fs::remove_file(to).unwrap();
+    }
+
+    #[test]
+    pub fn an_empty_file_copies_nothing() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let from = temp_dir().join(format!("vargasync-copy-empty-from-{}", id()));
+        let to = temp_dir().join(format!("vargasync-copy-empty-to-{}", id()));
+        fs::write(&from, b"").unwrap();
+
+        assert_eq!(copy(&mut ring, &from, &to).unwrap(), 0);
+        assert!(fs::read(&to).unwrap().is_empty());
+        fs::remove_file(from).unwrap();
+        fs::remove_file(to).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_asking_for_metadata {
+    use crate::{
+        fs::{metadata, symlink_metadata},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::ENOENT;
+    use std::{
+        env::temp_dir,
+        fs,
+        os::unix::fs::{symlink, PermissionsExt},
+        process::id,
+    };
+
+    #[test]
+    pub fn statx_agrees_with_std() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let path = temp_dir().join(format!("vargasync-metadata-{}", id()));
+        fs::write(&path, b"twelve bytes").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let metadata = metadata(&mut ring, &path).unwrap();
+        let expected = fs::metadata(&path).unwrap();
+
+        assert_eq!(metadata.len(), 12);
+        assert!(metadata.is_file());
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!(metadata.modified(), expected.modified().unwrap());
+        assert_eq!(metadata.accessed(), expected.accessed().unwrap());
+        assert_eq!(metadata.created(), expected.created().ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn symlinks_are_followed_unless_asked_not_to() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let link = temp_dir().join(format!("vargasync-metadata-link-{}", id()));
+        symlink(temp_dir(), &link).unwrap();
+
+        assert!(metadata(&mut ring, &link).unwrap().is_dir());
+        assert!(symlink_metadata(&mut ring, &link).unwrap().is_symlink());
+        fs::remove_file(link).unwrap();
+    }
+
+    #[test]
+    pub fn a_missing_path_reports_enoent() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = metadata(&mut ring, "/definitely/not/here").unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(ENOENT));
+    }
+}
+
+#[cfg(test)]
+mod when_writing_durably {
+    use crate::{
+        fs::{File, SyncMode},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{env::temp_dir, fs, process::id};
+
+    #[test]
+    pub fn writes_round_trip_in_every_sync_mode() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let path = temp_dir().join(format!("vargasync-durable-{}", id()));
+        let mut file = File::from(
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap(),
+        );
+
+        for (index, mode) in [SyncMode::None, SyncMode::Data, SyncMode::All]
+            .into_iter()
+            .enumerate()
+        {
+            file.set_sync_mode(mode);
+            assert_eq!(
+                file.write_at(&mut ring, b"page", index as u64 * 4).unwrap(),
+                4
+            );
+        }
+
+        let mut contents = [0u8; 12];
+        assert_eq!(file.read_at(&mut ring, &mut contents, 0).unwrap(), 12);
+        assert_eq!(&contents, b"pagepagepage");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn explicit_syncs_reach_the_kernel() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let path = temp_dir().join(format!("vargasync-sync-{}", id()));
+        let file = File::create(&path).unwrap();
+
+        file.write_at(&mut ring, b"journal entry", 0).unwrap();
+
+        file.sync_range(&mut ring, 0, 13).unwrap();
+        file.sync_data(&mut ring).unwrap();
+        file.sync_all(&mut ring).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"journal entry");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn a_failed_write_still_reports_its_error() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let path = temp_dir().join(format!("vargasync-read-only-{}", id()));
+        fs::write(&path, b"").unwrap();
+        let mut file = File::open(&path).unwrap();
+        file.set_sync_mode(SyncMode::All);
+
+        let error = file.write_at(&mut ring, b"nope", 0).unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_reading_and_writing_at_the_current_position {
+    use crate::{
+        fs::File,
+        io_uring::{IoUring, IoUringParams},
+        testing::TempFile,
+    };
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom},
+    };
+
+    #[test]
+    pub fn the_position_moves_with_each_transfer() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let temp = TempFile::new(b"").unwrap();
+        let mut file = File::from(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(temp.path())
+                .unwrap(),
+        );
+
+        assert_eq!(file.write(&mut ring, b"hello ").unwrap(), 6);
+        assert_eq!(file.write(&mut ring, b"world").unwrap(), 5);
+        assert_eq!(temp.contents().unwrap(), b"hello world");
+    }
+
+    #[test]
+    pub fn without_kernel_support_the_position_is_tracked() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let temp = TempFile::new(b"hello world").unwrap();
+        let mut std_file = OpenOptions::new().read(true).open(temp.path()).unwrap();
+        std_file.seek(SeekFrom::Start(6)).unwrap();
+        let mut file = File::from(std_file);
+
+        let offset = file.current_offset(false).unwrap();
+        let mut buf = [0u8; 3];
+        let read = file.read_at(&mut ring, &mut buf, offset).unwrap();
+        file.advance(read);
+        assert_eq!(&buf, b"wor");
+        assert_eq!(file.current_offset(false).unwrap(), 9);
+    }
+}
+
+#[cfg(test)]
+mod when_dropping_a_file_with_requests_in_flight {
+    use crate::{
+        cqe::Completions,
+        fs::File,
+        io_uring::{IoUring, IoUringParams},
+        owned_buf::OwnedBuf,
+    };
+    use libc::{pipe, write, ECANCELED, EPIPE};
+    use std::{
+        io,
+        os::fd::{FromRawFd, OwnedFd},
+    };
+
+    #[test]
+    pub fn the_requests_are_cancelled_before_the_fd_is_closed() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+        let write_end = unsafe { OwnedFd::from_raw_fd(fds[1]) };
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fds[0]) }).cancel_on_drop(&ring);
+
+        file.submit_readv(&mut ring, vec![OwnedBuf::with_capacity(8)], 0, 1)
+            .unwrap();
+        ring.submit().unwrap();
+        drop(file);
+
+        let completion = ring.wait_completion().unwrap();
+        assert_eq!(completion.user_data, 1);
+        assert_eq!(completion.result, -ECANCELED);
+        assert!(ring.take_buffers(&completion).is_some());
+
+        ring.submit_and_wait(0).unwrap();
+        assert!(ring.next_completion().is_none());
+        let written = unsafe { write(fds[1], b"x".as_ptr().cast(), 1) };
+        assert_eq!(written, -1);
+        assert_eq!(io::Error::last_os_error().raw_os_error(), Some(EPIPE));
+        drop(write_end);
+    }
+}
+
+#[cfg(test)]
+mod when_transferring_whole_buffers {
+    use crate::{
+        fs::{until_done, File},
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::EINTR;
+    use std::{
+        env::temp_dir,
+        fs,
+        io::{self, ErrorKind},
+        process::id,
+    };
+
+    #[test]
+    pub fn short_transfers_are_continued_where_they_stopped() {
+        let mut calls = Vec::new();
+
+        until_done(10, ErrorKind::WriteZero, |done, len| {
+            calls.push((done, len));
+            Ok(len.min(4) as i32)
+        })
+        .unwrap();
+
+        assert_eq!(calls, vec![(0, 10), (4, 6), (8, 2)]);
+    }
+
+    #[test]
+    pub fn only_transfers_completing_with_eintr_are_tried_again() {
+        let mut results = vec![
+            Ok(-EINTR),
+            Ok(4),
+            Err(io::Error::from(ErrorKind::Interrupted)),
+        ];
+        let mut calls = 0;
+
+        let error = until_done(10, ErrorKind::WriteZero, |_, _| {
+            calls += 1;
+            results.remove(0)
+        })
+        .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    pub fn a_transfer_of_nothing_stops_with_the_given_error() {
+        let error = until_done(10, ErrorKind::UnexpectedEof, |_, _| Ok(0)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    pub fn whole_buffers_round_trip_and_reading_past_the_end_fails() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let path = temp_dir().join(format!("vargasync-exact-{}", id()));
+        let file = File::from(
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap(),
+        );
+        let contents: Vec<u8> = (0..100_000u32).map(|value| value as u8).collect();
+
+        file.write_all_at(&mut ring, &contents, 10).unwrap();
+        let mut read = vec![0u8; contents.len()];
+        file.read_exact_at(&mut ring, &mut read, 10).unwrap();
+        assert_eq!(read, contents);
+
+        let error = file.read_exact_at(&mut ring, &mut read, 20).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_opening_through_the_ring {
+    use crate::{
+        fs::{open_at, open_at_fixed},
+        io_uring::{IoUring, IoUringParams},
+        testing::TempFile,
+    };
+    use futures::executor::block_on;
+    use libc::{ENOENT, O_CLOEXEC, O_RDONLY};
+    use std::{fs::File, io::Read};
+
+    #[test]
+    pub fn the_future_resolves_to_an_fd() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let temp = TempFile::new(b"contents").unwrap();
+
+        let fd = block_on(open_at(&mut ring, temp.path(), O_RDONLY | O_CLOEXEC, 0)).unwrap();
+        let mut contents = String::new();
+        File::from(fd).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "contents");
+
+        let error = block_on(open_at(&mut ring, "/nonexistent", O_RDONLY, 0)).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(ENOENT));
+    }
+
+    #[test]
+    pub fn a_fixed_open_resolves_to_its_slot() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_files_sparse(2).unwrap();
+        let temp = TempFile::new(b"contents").unwrap();
+
+        let fixed = block_on(open_at_fixed(&mut ring, temp.path(), O_RDONLY, 0, 1)).unwrap();
+
+        assert_eq!(fixed.slot(), 1);
+    }
+}
+
+#[cfg(test)]
+mod when_reading_into_uninitialized_buffers {
+    use crate::{
+        fs::File,
+        io_uring::{IoUring, IoUringParams},
+        net::recv_uninit,
+        testing::{ring_with_socket_pair, TempFile},
+    };
+    use std::{io::Write, mem::MaybeUninit};
+
+    #[test]
+    pub fn only_the_part_read_comes_back() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let temp = TempFile::new(b"contents").unwrap();
+        let file = File::open(temp.path()).unwrap();
+        let mut buf = vec![MaybeUninit::uninit(); 1 << 20];
+
+        let read = file.read_uninit_at(&mut ring, &mut buf, 3).unwrap();
+
+        assert_eq!(read, b"tents");
+    }
+
+    #[test]
+    pub fn a_recv_fills_what_arrived() {
+        let (mut ring, mut local, peer) = ring_with_socket_pair().unwrap();
+        local.write_all(b"ping").unwrap();
+        let mut buf = [MaybeUninit::uninit(); 64];
+
+        assert_eq!(recv_uninit(&mut ring, &peer, &mut buf).unwrap(), b"ping");
+    }
+}
+
+#[cfg(test)]
+mod when_a_signal_interrupts_the_wait {
+    use crate::{fs::File, testing::ring_with_pipe};
+    use libc::{c_int, pthread_kill, pthread_self, sigaction, SIGUSR1};
+    use std::{io::Write, mem::zeroed, os::fd::OwnedFd, ptr::null_mut, thread, time::Duration};
+
+    extern "C" fn interrupt(_: c_int) {}
+
+    #[test]
+    pub fn the_read_is_waited_for_until_it_completes() {
+        let (mut ring, reader, mut writer) = ring_with_pipe().unwrap();
+        let file = File::from(OwnedFd::from(reader));
+        unsafe {
+            let mut action: sigaction = zeroed();
+            action.sa_sigaction = interrupt as *const () as usize;
+            assert_eq!(sigaction(SIGUSR1, &action, null_mut()), 0);
+        }
+
+        let reading = unsafe { pthread_self() };
+        let signaller = thread::spawn(move || {
+            /*
+             * The first signal may land in the enter that submits the read,
+             * which reports the submission rather than the interruption.
+             */
+            for _ in 0..2 {
+                thread::sleep(Duration::from_millis(20));
+                unsafe { pthread_kill(reading, SIGUSR1) };
+            }
+            thread::sleep(Duration::from_millis(20));
+            writer.write_all(b"late").unwrap();
+        });
+        let mut buf = [0u8; 4];
+        let read = file.read_at(&mut ring, &mut buf, 0).unwrap();
+        signaller.join().unwrap();
+
+        assert_eq!(read, 4);
+        assert_eq!(&buf, b"late");
+        assert_eq!(ring.in_flight(), 0);
+    }
+}
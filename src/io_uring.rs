@@ -1,23 +1,80 @@
-use crate::{mmap::MMap, syscalls::io_uring_setup};
-use anyhow::{anyhow, Result};
+use crate::{
+    adopt,
+    arch::MmapOffset,
+    atomic::{fence, AtomicU32, Ordering, RingIndexes},
+    buf_ring::{BufRing, BufRingFlags},
+    builder::MemoryOptions,
+    capabilities::{Capabilities, KernelVersion},
+    cqe::{Completion, Completions},
+    entry::{entry_setup_flags, Cqe16, CqeEntry, Sqe64, SqeEntry},
+    ffi::{self, RawIoUring},
+    fixed_buf::FixedBuffers,
+    fixed_file::FixedTable,
+    mmap::{advise_dont_fork, lock_memory, MMap, MapOptions},
+    op::Op,
+    opcode::IoUringOperation,
+    owned_buf::{Direction, HeldBuffers, OwnedBuf},
+    payload::OpPayloads,
+    probe::Probe,
+    producer::{SqProducer, SqPublisher},
+    sandbox::Restriction,
+    scope::OpScope,
+    spans::OpSpans,
+    sqe::{IoPriority, IoUringSqeFlags, Sqe},
+    stats::{CqFlags, RingStats, SqFlags},
+    submitter::Submitter,
+    syscalls::{
+        Clock, GetEventsArg, IoUringEnterFlags, IoUringOpCode, RealSyscalls, UringSyscalls,
+        WaitRegion,
+    },
+    trace::{SubmissionRecord, TraceEvent, Tracer},
+    unpark::Unparker,
+    watcher::CompletionWatcher,
+};
 use bitflags::bitflags;
-use libc::{c_void, off_t};
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+use libc::{c_void, iovec, ETIME};
 use linux_raw_sys::io_uring::{
-    io_cqring_offsets, io_sqring_offsets, io_uring_cqe, io_uring_params, io_uring_sqe,
-    IORING_FEAT_CQE_SKIP, IORING_FEAT_CUR_PERSONALITY, IORING_FEAT_EXT_ARG, IORING_FEAT_FAST_POLL,
-    IORING_FEAT_LINKED_FILE, IORING_FEAT_NATIVE_WORKERS, IORING_FEAT_NODROP,
-    IORING_FEAT_POLL_32BITS, IORING_FEAT_REG_REG_RING, IORING_FEAT_RSRC_TAGS,
-    IORING_FEAT_RW_CUR_POS, IORING_FEAT_SINGLE_MMAP, IORING_FEAT_SQPOLL_NONFIXED,
-    IORING_FEAT_SUBMIT_STABLE, IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING,
-    IORING_SETUP_ATTACH_WQ, IORING_SETUP_CLAMP, IORING_SETUP_COOP_TASKRUN, IORING_SETUP_CQE32,
-    IORING_SETUP_CQSIZE, IORING_SETUP_DEFER_TASKRUN, IORING_SETUP_IOPOLL, IORING_SETUP_NO_MMAP,
-    IORING_SETUP_REGISTERED_FD_ONLY, IORING_SETUP_R_DISABLED, IORING_SETUP_SINGLE_ISSUER,
-    IORING_SETUP_SQE128, IORING_SETUP_SQPOLL, IORING_SETUP_SQ_AFF, IORING_SETUP_SUBMIT_ALL,
-    IORING_SETUP_TASKRUN_FLAG,
+    io_cqring_offsets, io_sqring_offsets, io_uring_clock_register, io_uring_cqe,
+    io_uring_mem_region_reg, io_uring_params, io_uring_region_desc, io_uring_restriction,
+    io_uring_rsrc_register, io_uring_rsrc_update, io_uring_sqe, IORING_FEAT_CQE_SKIP,
+    IORING_FEAT_CUR_PERSONALITY, IORING_FEAT_EXT_ARG, IORING_FEAT_FAST_POLL,
+    IORING_FEAT_LINKED_FILE, IORING_FEAT_MIN_TIMEOUT, IORING_FEAT_NATIVE_WORKERS,
+    IORING_FEAT_NODROP, IORING_FEAT_NO_IOWAIT, IORING_FEAT_POLL_32BITS,
+    IORING_FEAT_RECVSEND_BUNDLE, IORING_FEAT_REG_REG_RING, IORING_FEAT_RSRC_TAGS,
+    IORING_FEAT_RW_ATTR, IORING_FEAT_RW_CUR_POS, IORING_FEAT_SINGLE_MMAP,
+    IORING_FEAT_SQPOLL_NONFIXED, IORING_FEAT_SUBMIT_STABLE, IORING_MEM_REGION_REG_WAIT_ARG,
+    IORING_MEM_REGION_TYPE_USER, IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING,
+    IORING_RSRC_REGISTER_SPARSE, IORING_SETUP_ATTACH_WQ, IORING_SETUP_CLAMP,
+    IORING_SETUP_COOP_TASKRUN, IORING_SETUP_CQE32, IORING_SETUP_CQSIZE, IORING_SETUP_DEFER_TASKRUN,
+    IORING_SETUP_IOPOLL, IORING_SETUP_NO_MMAP, IORING_SETUP_REGISTERED_FD_ONLY,
+    IORING_SETUP_R_DISABLED, IORING_SETUP_SINGLE_ISSUER, IORING_SETUP_SQE128, IORING_SETUP_SQPOLL,
+    IORING_SETUP_SQ_AFF, IORING_SETUP_SUBMIT_ALL, IORING_SETUP_TASKRUN_FLAG, IORING_SQ_NEED_WAKEUP,
+    IORING_SQ_TASKRUN,
+};
+use log::{debug, warn};
+#[cfg(feature = "futures")]
+use std::pin::Pin;
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    io::{self, ErrorKind, Result},
+    marker::PhantomData,
+    mem::{size_of, zeroed},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd, RawFd},
+    ptr::{null, NonNull},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
 };
-use std::{error::Error, fmt::Display, mem::size_of, os::fd::OwnedFd, ptr::NonNull};
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct IoUringFeatures : u32 {
         const SingleMmap = IORING_FEAT_SINGLE_MMAP;
         const NoDrop = IORING_FEAT_NODROP;
@@ -33,10 +90,15 @@ bitflags! {
         const CqeSkip = IORING_FEAT_CQE_SKIP;
         const LinkedFile = IORING_FEAT_LINKED_FILE;
         const RegRegRing = IORING_FEAT_REG_REG_RING;
+        const RecvSendBundle = IORING_FEAT_RECVSEND_BUNDLE;
+        const MinTimeout = IORING_FEAT_MIN_TIMEOUT;
+        const RwAttr = IORING_FEAT_RW_ATTR;
+        const NoIoWait = IORING_FEAT_NO_IOWAIT;
     }
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct IoUringSetupFlags: u32 {
         const IoPoll = IORING_SETUP_IOPOLL;	/* io_context is polled */
         const SqPool = IORING_SETUP_SQPOLL;	/* SQ poll thread */
@@ -88,27 +150,62 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum IoUringError {
     InvalidArgument,
+    /*
+     * Bits of the setup flags the crate does not know.
+     */
+    UnknownSetupFlags(u32),
+    /*
+     * `flag` only goes with one of `needs`.
+     */
+    MissingSetupFlag {
+        flag: IoUringSetupFlags,
+        needs: IoUringSetupFlags,
+    },
+    ConflictingSetupFlags(IoUringSetupFlags, IoUringSetupFlags),
+    /*
+     * CqSize without a number of completion entries.
+     */
+    MissingCqEntries,
 }
 
 impl Display for IoUringError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match *self {
+        match self {
             IoUringError::InvalidArgument => write!(f, "Invalid Argument"),
+            IoUringError::UnknownSetupFlags(bits) => write!(f, "unknown setup flags {:#x}", bits),
+            IoUringError::MissingSetupFlag { flag, needs } => {
+                write!(f, "{:?} needs one of {:?}", flag, needs)
+            }
+            IoUringError::ConflictingSetupFlags(one, other) => {
+                write!(f, "{:?} does not go with {:?}", one, other)
+            }
+            IoUringError::MissingCqEntries => write!(f, "CqSize without cq_entries"),
         }
     }
 }
 
+impl From<IoUringError> for io::Error {
+    fn from(error: IoUringError) -> Self {
+        io::Error::new(ErrorKind::InvalidInput, error)
+    }
+}
+
 impl Error for IoUringError {
     fn description(&self) -> &str {
         match *self {
             IoUringError::InvalidArgument => "Invalid Argument",
+            IoUringError::UnknownSetupFlags(_) => "unknown setup flags",
+            IoUringError::MissingSetupFlag { .. } => "a setup flag is missing",
+            IoUringError::ConflictingSetupFlags(..) => "conflicting setup flags",
+            IoUringError::MissingCqEntries => "CqSize without cq_entries",
         }
     }
 }
 
+#[derive(Default)]
 pub struct IoUringParams {
     pub sq_entries: u32,
     pub cq_entries: u32,
@@ -122,22 +219,39 @@ pub struct IoUringParams {
     pub cq_off: IoCqRingOffsets,
 }
 
-impl Into<io_cqring_offsets> for &IoCqRingOffsets {
-    fn into(self) -> io_cqring_offsets {
+impl From<&IoCqRingOffsets> for io_cqring_offsets {
+    fn from(offsets: &IoCqRingOffsets) -> Self {
         io_cqring_offsets {
-            head: self.head,
-            tail: self.tail,
-            ring_mask: self.ring_mask,
-            ring_entries: self.ring_entries,
-            overflow: self.overflow,
-            cqes: self.cqes,
-            flags: self.flags,
-            resv1: self.resv1,
-            user_addr: self.user_addr,
+            head: offsets.head,
+            tail: offsets.tail,
+            ring_mask: offsets.ring_mask,
+            ring_entries: offsets.ring_entries,
+            overflow: offsets.overflow,
+            cqes: offsets.cqes,
+            flags: offsets.flags,
+            resv1: 0,
+            user_addr: offsets.user_addr,
+        }
+    }
+}
+
+impl From<io_cqring_offsets> for IoCqRingOffsets {
+    fn from(offsets: io_cqring_offsets) -> Self {
+        IoCqRingOffsets {
+            head: offsets.head,
+            tail: offsets.tail,
+            ring_mask: offsets.ring_mask,
+            ring_entries: offsets.ring_entries,
+            overflow: offsets.overflow,
+            cqes: offsets.cqes,
+            flags: offsets.flags,
+            resv1: offsets.resv1,
+            user_addr: offsets.user_addr,
         }
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct IoCqRingOffsets {
     pub head: u32,
     pub tail: u32,
@@ -150,22 +264,39 @@ pub struct IoCqRingOffsets {
     pub user_addr: u64,
 }
 
-impl Into<io_sqring_offsets> for &IoSqRingOffsets {
-    fn into(self) -> io_sqring_offsets {
+impl From<&IoSqRingOffsets> for io_sqring_offsets {
+    fn from(offsets: &IoSqRingOffsets) -> Self {
         io_sqring_offsets {
-            head: self.head,
-            tail: self.tail,
-            ring_mask: self.ring_mask,
-            ring_entries: self.ring_entries,
-            flags: self.flags,
-            dropped: self.dropped,
-            array: self.array,
-            resv1: self.resv1,
-            user_addr: self.user_addr,
+            head: offsets.head,
+            tail: offsets.tail,
+            ring_mask: offsets.ring_mask,
+            ring_entries: offsets.ring_entries,
+            flags: offsets.flags,
+            dropped: offsets.dropped,
+            array: offsets.array,
+            resv1: 0,
+            user_addr: offsets.user_addr,
+        }
+    }
+}
+
+impl From<io_sqring_offsets> for IoSqRingOffsets {
+    fn from(offsets: io_sqring_offsets) -> Self {
+        IoSqRingOffsets {
+            head: offsets.head,
+            tail: offsets.tail,
+            ring_mask: offsets.ring_mask,
+            ring_entries: offsets.ring_entries,
+            flags: offsets.flags,
+            dropped: offsets.dropped,
+            array: offsets.array,
+            resv1: offsets.resv1,
+            user_addr: offsets.user_addr,
         }
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct IoSqRingOffsets {
     pub head: u32,
     pub tail: u32,
@@ -178,41 +309,404 @@ pub struct IoSqRingOffsets {
     pub user_addr: u64,
 }
 
-impl Into<io_uring_params> for &IoUringParams {
-    fn into(self) -> io_uring_params {
-        io_uring_params {
-            flags: self.flags,
-            cq_entries: self.cq_entries,
-            sq_entries: self.sq_entries,
-            sq_thread_cpu: self.sq_thread_cpu,
-            sq_thread_idle: self.sq_thread_idle,
-            features: self.features,
-            wq_fd: self.wq_fd,
-            resv: self.resv,
-            sq_off: (&self.sq_off).into(),
-            cq_off: (&self.cq_off).into(),
+/*
+ * What io_uring_setup is handed. The reserved fields and the features,
+ * which the kernel fills in, go as zeroes; combinations of setup flags the
+ * kernel refuses with a bare EINVAL are refused here with what is wrong.
+ */
+impl TryFrom<&IoUringParams> for io_uring_params {
+    type Error = IoUringError;
+
+    fn try_from(params: &IoUringParams) -> std::result::Result<Self, IoUringError> {
+        let flags = IoUringSetupFlags::from_bits(params.flags).ok_or(
+            IoUringError::UnknownSetupFlags(params.flags & !IoUringSetupFlags::all().bits()),
+        )?;
+
+        let needs = [
+            (IoUringSetupFlags::SqAff, IoUringSetupFlags::SqPool),
+            (
+                IoUringSetupFlags::RegisteredFdOnly,
+                IoUringSetupFlags::NoMmap,
+            ),
+            (
+                IoUringSetupFlags::DeferTaskRun,
+                IoUringSetupFlags::SingleIssuer,
+            ),
+            (
+                IoUringSetupFlags::TaskRunFlag,
+                IoUringSetupFlags::CoopTaskRun | IoUringSetupFlags::DeferTaskRun,
+            ),
+        ];
+        for (flag, needs) in needs {
+            if flags.contains(flag) && !flags.intersects(needs) {
+                return Err(IoUringError::MissingSetupFlag { flag, needs });
+            }
+        }
+        if flags.contains(IoUringSetupFlags::DeferTaskRun | IoUringSetupFlags::SqPool) {
+            return Err(IoUringError::ConflictingSetupFlags(
+                IoUringSetupFlags::DeferTaskRun,
+                IoUringSetupFlags::SqPool,
+            ));
+        }
+        if flags.contains(IoUringSetupFlags::CqSize) && params.cq_entries == 0 {
+            return Err(IoUringError::MissingCqEntries);
+        }
+
+        Ok(io_uring_params {
+            flags: params.flags,
+            cq_entries: params.cq_entries,
+            sq_entries: params.sq_entries,
+            sq_thread_cpu: params.sq_thread_cpu,
+            sq_thread_idle: params.sq_thread_idle,
+            features: 0,
+            wq_fd: params.wq_fd,
+            resv: [0; 3],
+            sq_off: (&params.sq_off).into(),
+            cq_off: (&params.cq_off).into(),
+        })
+    }
+}
+
+/*
+ * What io_uring_setup filled in.
+ */
+impl From<io_uring_params> for IoUringParams {
+    fn from(params: io_uring_params) -> Self {
+        IoUringParams {
+            sq_entries: params.sq_entries,
+            cq_entries: params.cq_entries,
+            flags: params.flags,
+            sq_thread_cpu: params.sq_thread_cpu,
+            sq_thread_idle: params.sq_thread_idle,
+            features: params.features,
+            wq_fd: params.wq_fd,
+            resv: params.resv,
+            sq_off: params.sq_off.into(),
+            cq_off: params.cq_off.into(),
         }
     }
 }
 
-pub struct IoUringCompleteQueue<'a> {
+pub struct IoUringCompleteQueue<'a, C: CqeEntry = Cqe16> {
     pub(crate) head: NonNull<c_void>,
     pub(crate) tail: NonNull<c_void>,
     pub(crate) mask: NonNull<c_void>,
     pub(crate) entries: NonNull<c_void>,
     pub(crate) flags: NonNull<c_void>,
+    pub(crate) overflow: NonNull<c_void>,
     pub(crate) ring: IoUringQueueOwnership<'a>,
     pub(crate) cqes: NonNull<c_void>,
+    /*
+     * Set while a LocalRing borrows the ring.
+     */
+    pub(crate) relaxed: bool,
+    pub(crate) entry: PhantomData<C>,
 }
 
-pub struct IoUringSendQueue<'a> {
+pub struct IoUringSendQueue<'a, S: SqeEntry = Sqe64> {
     pub(crate) head: NonNull<c_void>,
     pub(crate) tail: NonNull<c_void>,
     pub(crate) mask: NonNull<c_void>,
     pub(crate) entries: NonNull<c_void>,
     pub(crate) flags: NonNull<c_void>,
+    pub(crate) dropped: NonNull<c_void>,
+    pub(crate) array: NonNull<c_void>,
     pub(crate) ring: MMap<'a>,
     pub(crate) sqes: MMap<'a>,
+    /*
+     * Entries handed out to the user but not yet published to the kernel
+     * live between sqe_head and sqe_tail.
+     */
+    pub(crate) sqe_head: u32,
+    pub(crate) sqe_tail: u32,
+    /*
+     * Set while a LocalRing borrows the ring.
+     */
+    pub(crate) relaxed: bool,
+    /*
+     * Whether slots are cleared before they are handed out, see
+     * IoUring::set_entry_zeroing.
+     */
+    pub(crate) zero_entries: bool,
+    /*
+     * Set once the ring is split into SqProducers, which claim the slots
+     * past sqe_tail themselves: the ring must not prepare entries there.
+     */
+    pub(crate) producers: bool,
+    pub(crate) entry: PhantomData<S>,
+}
+
+/*
+ * The ring indexes are shared with the kernel, so every access goes through
+ * an atomic view of the mapped u32.
+ */
+pub(crate) unsafe fn atomic_u32<'b>(pointer: NonNull<c_void>) -> &'b AtomicU32 {
+    &*(pointer.as_ptr() as *const AtomicU32)
+}
+
+/*
+ * Slot `index` of the sqe array starting at `sqes`, cleared when `zeroed`
+ * so nothing of the previous operation is left in it. Preparing an entry
+ * overwrites its first 64 bytes anyway, the rest of a big entry is cleared
+ * either way so nothing leaks from the previous command.
+ */
+pub(crate) unsafe fn entry_slot<S: SqeEntry>(
+    sqes: NonNull<c_void>,
+    index: u32,
+    zeroed: bool,
+) -> *mut io_uring_sqe {
+    let sqe = (sqes.as_ptr() as *mut u8).add(index as usize * S::SIZE) as *mut io_uring_sqe;
+
+    if zeroed {
+        (sqe as *mut u8).write_bytes(0, S::SIZE);
+    } else if S::SIZE > size_of::<io_uring_sqe>() {
+        (sqe.add(1) as *mut u8).write_bytes(0, S::SIZE - size_of::<io_uring_sqe>());
+    }
+
+    sqe
+}
+
+impl<'a, S: SqeEntry> IoUringSendQueue<'a, S> {
+    /*
+     * This end produces, the kernel consumes.
+     */
+    fn indexes(&self) -> RingIndexes<'_> {
+        unsafe { RingIndexes::new(atomic_u32(self.head), atomic_u32(self.tail), self.relaxed) }
+    }
+
+    pub(crate) fn ring_mask(&self) -> u32 {
+        unsafe { *(self.mask.as_ptr() as *const u32) }
+    }
+
+    pub(crate) fn ring_entries(&self) -> u32 {
+        unsafe { *(self.entries.as_ptr() as *const u32) }
+    }
+
+    pub(crate) fn flags(&self) -> u32 {
+        unsafe { atomic_u32(self.flags) }.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn dropped(&self) -> u32 {
+        unsafe { atomic_u32(self.dropped) }.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn space_left(&self) -> u32 {
+        let head = self.indexes().consumed();
+        self.ring_entries() - self.sqe_tail.wrapping_sub(head)
+    }
+
+    pub(crate) fn next_sqe(&mut self) -> Option<&mut io_uring_sqe> {
+        if self.space_left() == 0 {
+            return None;
+        }
+
+        let index = self.sqe_tail & self.ring_mask();
+        self.sqe_tail = self.sqe_tail.wrapping_add(1);
+
+        Some(unsafe { &mut *entry_slot::<S>(self.sqes.add_offset(0)?, index, self.zero_entries) })
+    }
+
+    /*
+     * Whole slot of the entry prepared last, to prepare the same entry again
+     * with overwrite_last_entry.
+     */
+    pub(crate) fn last_entry(&self) -> Option<Vec<u8>> {
+        let slot = self.last_slot()?;
+
+        Some(unsafe { std::slice::from_raw_parts(slot.as_ptr() as *const u8, S::SIZE) }.to_vec())
+    }
+
+    /*
+     * How far the kernel has taken entries, it has read everything they
+     * point to at submission, e.g. the timespec of a timeout, by then.
+     */
+    pub(crate) fn consumed(&self) -> u32 {
+        self.indexes().consumed()
+    }
+
+    pub(crate) fn overwrite_last_entry(&mut self, entry: &[u8]) {
+        if let Some(slot) = self.last_slot() {
+            let len = entry.len().min(S::SIZE);
+            unsafe { (slot.as_ptr() as *mut u8).copy_from_nonoverlapping(entry.as_ptr(), len) };
+        }
+    }
+
+    fn last_slot(&self) -> Option<NonNull<c_void>> {
+        if self.sqe_head == self.sqe_tail {
+            return None;
+        }
+
+        let index = self.sqe_tail.wrapping_sub(1) & self.ring_mask();
+        self.sqes.add_offset(index as usize * S::SIZE)
+    }
+
+    /*
+     * The entries prepared since the last flush, oldest first. The indexes
+     * wrap around, so the range is counted from sqe_head rather than ending
+     * at sqe_tail.
+     */
+    pub(crate) fn pending_sqes(&self) -> impl Iterator<Item = &io_uring_sqe> {
+        let mask = self.ring_mask();
+        let head = self.sqe_head;
+
+        (0..self.sqe_tail.wrapping_sub(head)).filter_map(move |offset| {
+            self.sqes
+                .add_offset((head.wrapping_add(offset) & mask) as usize * S::SIZE)
+                .map(|sqe| unsafe { &*(sqe.as_ptr() as *const io_uring_sqe) })
+        })
+    }
+
+    /*
+     * Turns the prepared entries of `user_data` the kernel was not handed yet
+     * into nops the ring keeps to itself, so they never touch what they were
+     * given. Their link flags stay, the rest of a chain keeps its order.
+     */
+    pub(crate) fn abandon_pending(&mut self, user_data: &[u64]) {
+        let mask = self.ring_mask();
+        let links = (IoUringSqeFlags::IoLink | IoUringSqeFlags::IoHardLink).bits();
+
+        for offset in 0..self.sqe_tail.wrapping_sub(self.sqe_head) {
+            let index = self.sqe_head.wrapping_add(offset) & mask;
+            let Some(slot) = self.sqes.add_offset(index as usize * S::SIZE) else {
+                continue;
+            };
+            let sqe = unsafe { &mut *(slot.as_ptr() as *mut io_uring_sqe) };
+            if user_data.contains(&sqe.user_data) {
+                let flags = sqe.flags & links;
+                *sqe = unsafe { zeroed() };
+                sqe.flags = flags;
+                sqe.user_data = ABANDONED_USER_DATA;
+            }
+        }
+    }
+
+    /*
+     * Publishes the entries prepared since the last flush and returns how
+     * many entries are waiting for the kernel to consume them.
+     */
+    pub(crate) fn flush(&mut self) -> u32 {
+        self.flush_first(self.pending())
+    }
+
+    /*
+     * Hands the first `count` prepared entries to the kernel, the others
+     * stay prepared.
+     */
+    pub(crate) fn flush_first(&mut self, count: u32) -> u32 {
+        if count > 0 {
+            self.sqe_head = self.sqe_head.wrapping_add(count);
+            self.indexes().publish(self.sqe_head);
+        }
+
+        let head = self.indexes().consumed();
+        self.sqe_head.wrapping_sub(head)
+    }
+
+    /*
+     * Entries prepared and not handed to the kernel yet.
+     */
+    pub(crate) fn pending(&self) -> u32 {
+        self.sqe_tail.wrapping_sub(self.sqe_head)
+    }
+}
+
+impl<'a, C: CqeEntry> IoUringCompleteQueue<'a, C> {
+    /*
+     * The kernel produces, this end consumes.
+     */
+    fn indexes(&self) -> RingIndexes<'_> {
+        unsafe { RingIndexes::new(atomic_u32(self.head), atomic_u32(self.tail), self.relaxed) }
+    }
+
+    pub(crate) fn ring_mask(&self) -> u32 {
+        unsafe { *(self.mask.as_ptr() as *const u32) }
+    }
+
+    pub(crate) fn ring_entries(&self) -> u32 {
+        unsafe { *(self.entries.as_ptr() as *const u32) }
+    }
+
+    pub(crate) fn flags(&self) -> u32 {
+        unsafe { atomic_u32(self.flags) }.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn overflow(&self) -> u32 {
+        unsafe { atomic_u32(self.overflow) }.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn ready(&self) -> u32 {
+        let indexes = self.indexes();
+        indexes.produced().wrapping_sub(indexes.head())
+    }
+
+    pub(crate) fn peek(&self) -> Option<Completion> {
+        self.peek_at(0)
+    }
+
+    pub(crate) fn cqe_at(&self, position: u32) -> Option<&io_uring_cqe> {
+        let indexes = self.indexes();
+        let head = indexes.head();
+
+        if indexes.produced().wrapping_sub(head) <= position {
+            return None;
+        }
+
+        let index = (head.wrapping_add(position) & self.ring_mask()) as usize;
+        let cqe = unsafe { (self.cqes.as_ptr() as *const u8).add(index * C::SIZE) };
+        Some(unsafe { &*(cqe as *const io_uring_cqe) })
+    }
+
+    pub(crate) fn peek_at(&self, position: u32) -> Option<Completion> {
+        self.cqe_at(position).map(Completion::from)
+    }
+
+    pub(crate) fn advance(&mut self, count: u32) {
+        self.indexes().consume(count);
+    }
+}
+
+/*
+ * The indexes as the kernel sees them next to the ones not published yet,
+ * for working out where a stuck ring is stuck.
+ */
+impl<'a, S: SqeEntry> Debug for IoUringSendQueue<'a, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoUringSendQueue")
+            .field(
+                "head",
+                &unsafe { atomic_u32(self.head) }.load(Ordering::Acquire),
+            )
+            .field(
+                "tail",
+                &unsafe { atomic_u32(self.tail) }.load(Ordering::Acquire),
+            )
+            .field("sqe_head", &self.sqe_head)
+            .field("sqe_tail", &self.sqe_tail)
+            .field("mask", &self.ring_mask())
+            .field("entries", &self.ring_entries())
+            .field("dropped", &self.dropped())
+            .field("flags", &SqFlags::from_bits_retain(self.flags()))
+            .finish()
+    }
+}
+
+impl<'a, C: CqeEntry> Debug for IoUringCompleteQueue<'a, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoUringCompleteQueue")
+            .field(
+                "head",
+                &unsafe { atomic_u32(self.head) }.load(Ordering::Acquire),
+            )
+            .field(
+                "tail",
+                &unsafe { atomic_u32(self.tail) }.load(Ordering::Acquire),
+            )
+            .field("mask", &self.ring_mask())
+            .field("entries", &self.ring_entries())
+            .field("overflow", &self.overflow())
+            .field("flags", &CqFlags::from_bits_retain(self.flags()))
+            .finish()
+    }
 }
 
 pub(crate) enum IoUringQueueOwnership<'a> {
@@ -220,79 +714,86 @@ pub(crate) enum IoUringQueueOwnership<'a> {
     Refers,
 }
 
-pub(crate) fn setup_cq_ring<'a>(
+pub(crate) fn setup_cq_ring<'a, C: CqeEntry>(
     map: IoUringQueueOwnership<'a>,
     params: &io_uring_params,
     send_ring: &MMap<'a>,
-) -> Result<IoUringCompleteQueue<'a>> {
-    let (head, tail, mask, entries, flags, cqes) = match &map {
-        IoUringQueueOwnership::Owns(ring) => (
-            ring.add_offset(params.cq_off.head as usize)
-                .ok_or(anyhow!("could not set the head for send_io_uring"))?,
-            ring.add_offset(params.cq_off.tail as usize)
-                .ok_or(anyhow!("could not set head pro completion queue"))?,
-            ring.add_offset(params.cq_off.ring_mask as usize)
-                .ok_or(anyhow!("could not set ring mask"))?,
-            ring.add_offset(params.cq_off.ring_entries as usize)
-                .ok_or(anyhow!("could not set entries"))?,
-            ring.add_offset(params.cq_off.flags as usize)
-                .ok_or(anyhow!("could not set flags"))?,
-            ring.add_offset(params.cq_off.cqes as usize)
-                .ok_or(anyhow!("could not set cqes"))?,
-        ),
-        IoUringQueueOwnership::Refers => (
-            send_ring
-                .add_offset(params.cq_off.head as usize)
-                .ok_or(anyhow!("could not set the head for send_io_uring"))?,
-            send_ring
-                .add_offset(params.cq_off.tail as usize)
-                .ok_or(anyhow!("could not set head pro completion queue"))?,
-            send_ring
-                .add_offset(params.cq_off.ring_mask as usize)
-                .ok_or(anyhow!("could not set ring mask"))?,
-            send_ring
-                .add_offset(params.cq_off.ring_entries as usize)
-                .ok_or(anyhow!("could not set entries"))?,
-            send_ring
-                .add_offset(params.cq_off.flags as usize)
-                .ok_or(anyhow!("could not set flags"))?,
-            send_ring
-                .add_offset(params.cq_off.cqes as usize)
-                .ok_or(anyhow!("could not set cqes"))?,
-        ),
+) -> Result<IoUringCompleteQueue<'a, C>> {
+    let ring = match &map {
+        IoUringQueueOwnership::Owns(ring) => ring,
+        IoUringQueueOwnership::Refers => send_ring,
     };
 
+    let head = ring
+        .add_offset(params.cq_off.head as usize)
+        .ok_or_else(|| io::Error::other("could not set the head for send_io_uring"))?;
+    let tail = ring
+        .add_offset(params.cq_off.tail as usize)
+        .ok_or_else(|| io::Error::other("could not set head pro completion queue"))?;
+    let mask = ring
+        .add_offset(params.cq_off.ring_mask as usize)
+        .ok_or_else(|| io::Error::other("could not set ring mask"))?;
+    let entries = ring
+        .add_offset(params.cq_off.ring_entries as usize)
+        .ok_or_else(|| io::Error::other("could not set entries"))?;
+    let flags = ring
+        .add_offset(params.cq_off.flags as usize)
+        .ok_or_else(|| io::Error::other("could not set flags"))?;
+    let overflow = ring
+        .add_offset(params.cq_off.overflow as usize)
+        .ok_or_else(|| io::Error::other("could not set overflow"))?;
+    let cqes = ring
+        .add_offset(params.cq_off.cqes as usize)
+        .ok_or_else(|| io::Error::other("could not set cqes"))?;
+
     Ok(IoUringCompleteQueue {
         head,
         tail,
         mask,
         entries,
         flags,
+        overflow,
         ring: map,
         cqes,
+        relaxed: false,
+        entry: PhantomData,
     })
 }
 
-pub(crate) fn setup_send_ring<'a>(
+pub(crate) fn setup_send_ring<'a, S: SqeEntry>(
     map: MMap<'a>,
     params: &io_uring_params,
     sqes: MMap<'a>,
-) -> Result<IoUringSendQueue<'a>> {
+) -> Result<IoUringSendQueue<'a, S>> {
     let head = map
         .add_offset(params.sq_off.head as usize)
-        .ok_or(anyhow!("could not set the head for send queue"))?;
+        .ok_or_else(|| io::Error::other("could not set the head for send queue"))?;
     let tail = map
         .add_offset(params.sq_off.tail as usize)
-        .ok_or(anyhow!("could not set head for send queue"))?;
+        .ok_or_else(|| io::Error::other("could not set head for send queue"))?;
     let mask = map
         .add_offset(params.sq_off.ring_mask as usize)
-        .ok_or(anyhow!("could not set ring mask"))?;
+        .ok_or_else(|| io::Error::other("could not set ring mask"))?;
     let entries = map
         .add_offset(params.sq_off.ring_entries as usize)
-        .ok_or(anyhow!("could not set entries"))?;
+        .ok_or_else(|| io::Error::other("could not set entries"))?;
     let flags = map
         .add_offset(params.sq_off.flags as usize)
-        .ok_or(anyhow!("could not set flags"))?;
+        .ok_or_else(|| io::Error::other("could not set flags"))?;
+    let dropped = map
+        .add_offset(params.sq_off.dropped as usize)
+        .ok_or_else(|| io::Error::other("could not set dropped"))?;
+    let array = map
+        .add_offset(params.sq_off.array as usize)
+        .ok_or_else(|| io::Error::other("could not set array"))?;
+
+    /*
+     * Entries are always handed out in order, so the indirection array is
+     * filled once with the identity mapping.
+     */
+    for index in 0..params.sq_entries {
+        unsafe { *(array.as_ptr() as *mut u32).add(index as usize) = index };
+    }
 
     Ok(IoUringSendQueue {
         head,
@@ -300,132 +801,3335 @@ pub(crate) fn setup_send_ring<'a>(
         mask,
         entries,
         flags,
+        dropped,
+        array,
         ring: map,
         sqes,
+        sqe_head: 0,
+        sqe_tail: 0,
+        relaxed: false,
+        zero_entries: true,
+        producers: false,
+        entry: PhantomData,
     })
 }
 
-pub struct IoUring<'a> {
-    pub(crate) send_queue: IoUringSendQueue<'a>,
-    pub(crate) complete_queue: IoUringCompleteQueue<'a>,
-    pub(crate) flags: u32,
+/*
+ * The entry sizes are part of the type, IoUring<'a> is the regular ring with
+ * 64 byte sqes and 16 byte cqes.
+ */
+pub struct IoUring<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    pub(crate) send_queue: IoUringSendQueue<'a, S>,
+    pub(crate) complete_queue: IoUringCompleteQueue<'a, C>,
+    pub(crate) flags: IoUringSetupFlags,
+    pub(crate) features: IoUringFeatures,
+    pub(crate) memory_options: MemoryOptions,
     pub(crate) ring_file_descriptor: OwnedFd,
+    pub(crate) syscalls: Arc<dyn UringSyscalls>,
+    pub(crate) tracer: Option<Tracer>,
+    pub(crate) spans: OpSpans,
+    pub(crate) default_priority: Option<IoPriority>,
+    pub(crate) deferred: VecDeque<Completion>,
+    pub(crate) held_buffers: HeldBuffers,
+    pub(crate) payloads: OpPayloads,
+    pub(crate) deferred_closes: DeferredCloses,
+    pub(crate) in_flight: u32,
+    /*
+     * Completions still to come per user_data, tells the failure of an
+     * entry with CqeSkipSuccess, which was never counted, from the
+     * completion of a request in flight.
+     */
+    pub(crate) awaited: HashMap<u64, u32>,
+    pub(crate) in_flight_limit: Option<u32>,
+    pub(crate) shut_down: bool,
+    pub(crate) wait_region: Option<WaitRegion<'a>>,
+    /*
+     * Thread the kernel took for the submitter of a SINGLE_ISSUER ring, the
+     * only one allowed to enter it.
+     */
+    pub(crate) issuer: Cell<Option<ThreadId>>,
+    /*
+     * Started by the first future that has to wait for a completion.
+     */
+    pub(crate) watcher: Option<CompletionWatcher>,
+    /*
+     * Index of the ring fd in the registered ring fds of the thread that
+     * registered it, the only one that can enter through it.
+     */
+    pub(crate) registered_ring: Option<(u32, ThreadId)>,
 }
 
-impl<'a> IoUring<'a> {
-    pub fn initialize(entries: u32, params: IoUringParams) -> Result<IoUring<'a>> {
-        let flags = IoUringSetupFlags::from_bits(params.flags).ok_or(anyhow!("error"))?;
+/*
+ * The raw pointers of a ring point into the mappings it owns and the iovecs
+ * it holds into buffers it owns, so the ring can move to another thread and
+ * go on there, except a SINGLE_ISSUER one, which stays with its issuer.
+ *
+ * It is not Sync: methods taking &self read the queues and set the issuer
+ * without synchronization. Rings are shared through a Submitter, which
+ * locks, or split into producers.
+ */
+unsafe impl<'a, S: SqeEntry, C: CqeEntry> Send for IoUring<'a, S, C> {}
 
-        if flags.contains(IoUringSetupFlags::RegisteredFdOnly)
-            && !(flags.contains(IoUringSetupFlags::NoMmap))
-        {
-            return Err(anyhow!(IoUringError::InvalidArgument));
-        }
+/*
+ * user_data of the entries the ring submits on its own, their completions
+ * never leave the ring: the cancel and close entries of deferred closes, the
+ * cancel of shutdown, the linked timeouts of the deadline scheduler and the
+ * nops and cancels of abandoned requests. Unparkers post theirs from other
+ * threads.
+ */
+pub(crate) const DEFERRED_CLOSE_USER_DATA: u64 = u64::MAX - 8;
+const SHUTDOWN_USER_DATA: u64 = u64::MAX - 9;
+pub(crate) const DEADLINE_USER_DATA: u64 = u64::MAX - 23;
+pub(crate) const UNPARK_USER_DATA: u64 = u64::MAX - 27;
+const ABANDONED_USER_DATA: u64 = u64::MAX - 28;
+
+/*
+ * SMP_CACHE_BYTES, the alignment of the indirection array behind the cqes.
+ */
+const RINGS_ALIGN: usize = 64;
+
+/*
+ * How long a dropped ring waits for the requests it cancels.
+ */
+const DROP_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/*
+ * Pause between attempts to enter a ring that keeps failing while abandoned
+ * requests are drained.
+ */
+const ABANDON_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+fn is_internal(user_data: u64) -> bool {
+    user_data == DEFERRED_CLOSE_USER_DATA
+        || user_data == SHUTDOWN_USER_DATA
+        || user_data == DEADLINE_USER_DATA
+        || user_data == UNPARK_USER_DATA
+        || user_data == ABANDONED_USER_DATA
+}
+
+fn expects_completion(sqe: &io_uring_sqe) -> bool {
+    !is_internal(sqe.user_data) && sqe.flags & IoUringSqeFlags::CqeSkipSuccess.bits() == 0
+}
+
+#[cfg(feature = "futures")]
+pub struct CompletionStream<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+}
+
+#[cfg(feature = "futures")]
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Stream for CompletionStream<'r, 'a, S, C> {
+    type Item = Result<Completion>;
 
-        let parameters: &mut io_uring_params = &mut (&params).into();
-        let fd = unsafe { io_uring_setup(entries, parameters) };
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let ring = &mut self.get_mut().ring;
 
-        if !flags.contains(IoUringSetupFlags::NoMmap) {}
+        if let Some(completion) = ring.next_completion() {
+            return Poll::Ready(Some(Ok(completion)));
+        }
+        if let Err(error) = ring.submit() {
+            return Poll::Ready(Some(Err(error)));
+        }
+        if let Some(completion) = ring.next_completion() {
+            return Poll::Ready(Some(Ok(completion)));
+        }
 
-        Ok(io_uring_queue_mmap(fd, &parameters)?)
+        match ring.register_waker(cx) {
+            Ok(()) => Poll::Pending,
+            Err(error) => Poll::Ready(Some(Err(error))),
+        }
     }
 }
 
 /*
- * For users that want to specify sq_thread_cpu or sq_thread_idle, this
- * interface is a convenient helper for mmap()ing the rings.
- * Returns -errno on error, or zero on success.  On success, 'ring'
- * contains the necessary information to read/write to the rings.
+ * Fds of dropped handles that may still have requests in flight. Shared with
+ * the handles, which push to it from their Drop. The ring cancels whatever is
+ * left on each fd and closes it on its next submission, so the number cannot
+ * be reused while requests still refer to it.
  */
-fn io_uring_queue_mmap<'a>(
-    file_descriptor: OwnedFd,
-    io_uring_params: &io_uring_params,
-) -> Result<IoUring<'a>> {
-    let mut send_ring_size = io_uring_params.sq_off.array as usize
-        + io_uring_params.sq_entries as usize * size_of::<u32>();
-    let mut complete_ring_size = io_uring_params.cq_off.cqes as usize
-        + io_uring_params.cq_entries as usize * size_of::<io_uring_cqe>();
+pub(crate) type DeferredCloses = Arc<Mutex<Vec<OwnedFd>>>;
 
-    if io_uring_params.features as u32 & IORING_FEAT_SINGLE_MMAP > 0 {
-        if complete_ring_size > send_ring_size {
-            send_ring_size = complete_ring_size;
+impl<'a> IoUring<'a> {
+    pub fn initialize(entries: u32, params: IoUringParams) -> Result<IoUring<'a>> {
+        Self::initialize_sized(entries, params)
+    }
+
+    /*
+     * Adopts a ring set up elsewhere from its fd alone, e.g. one passed by a
+     * privileged launcher over a unix socket. See adopt::from_fd for what
+     * can be recovered.
+     */
+    pub fn from_fd(fd: OwnedFd) -> Result<IoUring<'a>> {
+        Self::from_fd_sized(fd)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn initialize_with_syscalls(
+        entries: u32,
+        params: IoUringParams,
+        syscalls: Arc<dyn UringSyscalls>,
+    ) -> Result<IoUring<'a>> {
+        Self::initialize_sized_with_syscalls(entries, params, syscalls)
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> IoUring<'a, S, C> {
+    /*
+     * Sets up a ring with the entry sizes of the type, e.g.
+     * IoUring::<Sqe128, Cqe32>::initialize_sized. The matching setup flags are
+     * added, Sqe128 or Cqe32 in params must agree with the type.
+     */
+    pub fn initialize_sized(entries: u32, params: IoUringParams) -> Result<IoUring<'a, S, C>> {
+        Self::initialize_sized_with_syscalls(entries, params, Arc::new(RealSyscalls))
+    }
+
+    pub(crate) fn initialize_sized_with_syscalls(
+        entries: u32,
+        mut params: IoUringParams,
+        syscalls: Arc<dyn UringSyscalls>,
+    ) -> Result<IoUring<'a, S, C>> {
+        let flags = IoUringSetupFlags::from_bits_retain(params.flags);
+        let flags = entry_setup_flags::<S, C>(flags).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "Sqe128 and Cqe32 setup flags must match the entry types of the ring",
+            )
+        })?;
+        params.flags = flags.bits();
+
+        let parameters = &mut io_uring_params::try_from(&params)?;
+        let fd = syscalls.setup(entries, parameters)?;
+
+        io_uring_queue_mmap(fd, parameters, syscalls)
+    }
+
+    /*
+     * None when the submission queue is full, or for good once the ring was
+     * shut down.
+     */
+    pub fn next_sqe(&mut self) -> Option<Sqe<'_>> {
+        if self.shut_down {
+            return None;
         }
-        complete_ring_size = send_ring_size;
+
+        self.internal_sqe()
     }
 
-    let send_ring = MMap::new(
-        &file_descriptor,
-        IORING_OFF_SQ_RING as off_t,
-        send_ring_size,
-    )?;
+    fn internal_sqe(&mut self) -> Option<Sqe<'_>> {
+        let default_priority = self.default_priority;
+        let cqe_skip = self.supports_cqe_skip();
+        self.send_queue
+            .next_sqe()
+            .map(|raw| Sqe::new(raw, default_priority, cqe_skip))
+    }
 
-    let size = io_uring_params.sq_entries as usize * size_of::<io_uring_sqe>();
+    /*
+     * Number of entries that can be prepared before the submission queue is
+     * full.
+     */
+    pub fn sq_space_left(&self) -> u32 {
+        self.send_queue.space_left()
+    }
 
-    let send_queue_qes = MMap::new(&file_descriptor, IORING_OFF_SQES as off_t, size)?;
+    /*
+     * Blocks until `count` entries can be prepared, for producers that
+     * would otherwise spin on a full queue. Without SQPOLL a submission
+     * frees the whole queue. With SQPOLL the kernel thread takes entries at
+     * its own pace, the wait is an IORING_ENTER_SQ_WAIT that returns as it
+     * takes some. Entries held back by the in flight limit only go once
+     * completions are reaped, which fails with WouldBlock.
+     */
+    pub fn wait_sq_space(&mut self, count: u32) -> Result<()> {
+        if count > self.send_queue.ring_entries() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "more entries than the submission queue holds",
+            ));
+        }
 
-    let send_queue = setup_send_ring(send_ring, io_uring_params, send_queue_qes)?;
+        while self.sq_space_left() < count {
+            self.submit()?;
+            if self.sq_space_left() >= count {
+                break;
+            }
+            if self.send_queue.pending() > 0 {
+                return Err(io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "entries are held back by the in flight limit until completions are reaped",
+                ));
+            }
 
-    let complete_ring = if io_uring_params.features as u32 & IORING_FEAT_SINGLE_MMAP > 0 {
-        IoUringQueueOwnership::Refers
-    } else {
-        IoUringQueueOwnership::Owns(MMap::new(
-            &file_descriptor,
-            IORING_OFF_CQ_RING as off_t,
-            complete_ring_size,
-        )?)
-    };
+            self.check_issuer()?;
+            let (ring_fd, registered) = self.enter_fd();
+            self.syscalls.enter(
+                ring_fd,
+                0,
+                0,
+                IoUringEnterFlags::IoRingEnterSqWait | registered,
+                None,
+            )?;
+        }
 
-    let complete_queue = setup_cq_ring(complete_ring, io_uring_params, &send_queue.ring)?;
+        Ok(())
+    }
 
-    Ok(IoUring {
-        send_queue,
-        complete_queue,
-        flags: io_uring_params.flags,
-        ring_file_descriptor: file_descriptor,
-    })
-}
+    /*
+     * The completions of the ring as a Stream, for callers routing them
+     * themselves. Each poll submits what is pending and, with nothing to
+     * reap, returns Pending until the ring has completions. The stream never
+     * ends, an idle ring only leaves it waiting for the next request.
+     */
+    #[cfg(feature = "futures")]
+    pub fn completions(&mut self) -> CompletionStream<'_, 'a, S, C> {
+        CompletionStream { ring: self }
+    }
 
-#[cfg(test)]
-mod when_initializing_io_uring {
-    use crate::io_uring::{IoCqRingOffsets, IoSqRingOffsets, IoUring, IoUringParams};
+    /*
+     * Hands the ring to a handle several threads can clone and queue entries
+     * through, see Submitter.
+     */
+    pub fn into_submitter(self) -> Submitter<'a, S, C> {
+        Submitter::new(self)
+    }
 
-    #[test]
-    pub fn io_uring_setup_does_not_throw() {
-        let params = IoUringParams {
-            sq_entries: 0,
-            cq_entries: 0,
-            flags: 0,
-            sq_thread_cpu: 0,
-            sq_thread_idle: 0,
-            features: 0,
-            wq_fd: 0,
-            resv: [0, 0, 0],
-            sq_off: IoSqRingOffsets {
-                head: 0,
-                tail: 0,
-                ring_mask: 0,
-                ring_entries: 0,
-                flags: 0,
-                dropped: 0,
-                array: 0,
-                resv1: 0,
-                user_addr: 0,
-            },
-            cq_off: IoCqRingOffsets {
-                head: 0,
-                tail: 0,
-                ring_mask: 0,
-                ring_entries: 0,
-                overflow: 0,
-                cqes: 0,
-                flags: 0,
-                resv1: 0,
-                user_addr: 0,
-            },
-        };
+    /*
+     * Splits the ring into producers that reserve entries without locking and
+     * the single publisher that submits them, see SqPublisher.
+     */
+    pub fn into_producers(self) -> (SqPublisher<'a, S, C>, SqProducer<'a, S, C>) {
+        SqPublisher::new(self)
+    }
 
-        let io_uring = IoUring::initialize(1, IoUringParams::from(params));
+    /*
+     * Single operation prepared by `prepare`, run to completion, optionally
+     * with a timeout. See Op.
+     */
+    pub fn op<F>(&mut self, prepare: F) -> Op<'_, 'a, F, S, C>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        Op::new(self, prepare)
+    }
 
-        assert!(io_uring.is_ok());
+    /*
+     * Runs `f` with an OpScope, see there. Every operation submitted through
+     * the scope has completed by the time this returns.
+     */
+    pub fn scope<'env, F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut OpScope<'_, 'env, 'a, S, C>) -> R,
+    {
+        let mut scope = OpScope::new(self);
+        f(&mut scope)
+    }
+
+    /*
+     * Priority given to every entry prepared from now on, None leaves it to
+     * the kernel, which uses the priority of the submitting task.
+     */
+    pub fn set_default_io_priority(&mut self, priority: Option<IoPriority>) {
+        self.default_priority = priority;
+    }
+
+    pub fn default_io_priority(&self) -> Option<IoPriority> {
+        self.default_priority
+    }
+
+    /*
+     * Prepares an entry with `prepare` outside the submission queue, with
+     * the defaults of this ring, and returns its slot, for backlogs that
+     * copy it in with overwrite_last_entry once there is room.
+     */
+    pub(crate) fn detached_entry<F>(&self, prepare: F) -> Vec<u8>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        /*
+         * Room for the largest entry, aligned like the entries of the ring.
+         */
+        let mut slot = [0u64; 16];
+        let raw = unsafe { &mut *(slot.as_mut_ptr() as *mut io_uring_sqe) };
+        prepare(Sqe::new(
+            raw,
+            self.default_io_priority(),
+            self.supports_cqe_skip(),
+        ));
+
+        unsafe { std::slice::from_raw_parts(slot.as_ptr() as *const u8, S::SIZE) }.to_vec()
+    }
+
+    /// Whether the slot of an entry is zeroed before it is handed out, on
+    /// by default. Every prep method of Sqe writes the whole entry, so the
+    /// zeroing only saves hand written entries from the bytes of the
+    /// operation that used the slot before, at the cost of a 64 or 128 byte
+    /// clear per entry.
+    ///
+    /// # Safety
+    ///
+    /// With zeroing off, every entry prepared through Sqe::raw_sqe must
+    /// have every field the kernel reads for its opcode written, stale bytes
+    /// are submitted as they are.
+    pub unsafe fn set_entry_zeroing(&mut self, zeroing: bool) {
+        self.send_queue.zero_entries = zeroing;
+    }
+
+    pub fn entry_zeroing(&self) -> bool {
+        self.send_queue.zero_entries
+    }
+
+    /*
+     * Caps the requests in flight: a submission hands the kernel only the
+     * prepared entries that fit under `limit`, the rest stay prepared until
+     * completions are reaped. Keeps a small completion queue from
+     * overflowing and bounds the buffers tied up in flight. A link chain is
+     * never split, so it may go over. Entries with CqeSkipSuccess are not
+     * counted, see in_flight.
+     */
+    pub fn set_in_flight_limit(&mut self, limit: Option<u32>) -> Result<()> {
+        if limit == Some(0) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "an in flight limit of 0 would never submit anything",
+            ));
+        }
+        self.in_flight_limit = limit;
+
+        Ok(())
+    }
+
+    pub fn in_flight_limit(&self) -> Option<u32> {
+        self.in_flight_limit
+    }
+
+    /*
+     * How many of the prepared entries fit under the in flight limit.
+     */
+    fn entries_to_flush(&self) -> u32 {
+        let Some(limit) = self.in_flight_limit else {
+            return self.send_queue.pending();
+        };
+
+        let mut budget = limit.saturating_sub(self.in_flight);
+        let mut count = 0;
+        let mut in_chain = false;
+        for sqe in self.send_queue.pending_sqes() {
+            let counted = expects_completion(sqe);
+            if counted && budget == 0 && !in_chain {
+                break;
+            }
+            budget = budget.saturating_sub(counted as u32);
+            in_chain =
+                sqe.flags & (IoUringSqeFlags::IoLink | IoUringSqeFlags::IoHardLink).bits() != 0;
+            count += 1;
+        }
+
+        count
+    }
+
+    pub fn submit(&mut self) -> Result<usize> {
+        self.submit_and_wait(0)
+    }
+
+    pub fn submit_and_wait(&mut self, wait_nr: u32) -> Result<usize> {
+        self.check_issuer()?;
+        let (submitted, flags) = self.prepare_enter(wait_nr);
+
+        let Some(flags) = flags else {
+            return Ok(submitted as usize);
+        };
+
+        let (ring_fd, registered) = self.enter_fd();
+        let consumed =
+            self.syscalls
+                .enter(ring_fd, submitted, wait_nr, flags | registered, None)?;
+
+        Ok(consumed as usize)
+    }
+
+    /*
+     * Same as submit_and_wait, with a timeout, sigmask or min wait for the
+     * wait. An expired timeout surfaces as ETIME.
+     */
+    pub fn submit_and_wait_with_args(&mut self, wait_nr: u32, arg: &GetEventsArg) -> Result<usize> {
+        self.check_issuer()?;
+        /*
+         * Older kernels take the field for padding and reject it with a bare
+         * EINVAL.
+         */
+        if arg.has_min_wait() && !self.features.contains(IoUringFeatures::MinTimeout) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "the kernel does not support a min wait",
+            ));
+        }
+        let (submitted, flags) = self.prepare_enter(wait_nr);
+        let flags = flags.unwrap_or(IoUringEnterFlags::empty());
+
+        let (ring_fd, registered) = self.enter_fd();
+        let consumed =
+            self.syscalls
+                .enter_ext(ring_fd, submitted, wait_nr, flags | registered, arg)?;
+
+        Ok(consumed as usize)
+    }
+
+    /*
+     * Registers memory for `slots` sets of wait arguments, filled with
+     * set_wait_arg and named by submit_and_wait_registered, so a wait does
+     * not copy its arguments in. Only a ring created with R_DISABLED and not
+     * enabled yet takes the region, from kernel 6.13.
+     */
+    pub fn register_wait_region(&mut self, slots: u32) -> Result<()> {
+        let region = WaitRegion::new(slots)?;
+        let mut desc: io_uring_region_desc = unsafe { std::mem::zeroed() };
+        desc.user_addr = region.address();
+        desc.size = region.len() as u64;
+        desc.flags = IORING_MEM_REGION_TYPE_USER as u32;
+        let registration = io_uring_mem_region_reg {
+            region_uptr: &mut desc as *mut io_uring_region_desc as u64,
+            flags: IORING_MEM_REGION_REG_WAIT_ARG as u64,
+            __resv: [0; 2],
+        };
+        self.register(
+            IoUringOpCode::IoRingRegisterMemRegion,
+            &registration as *const io_uring_mem_region_reg as *const c_void,
+            1,
+        )?;
+        self.wait_region = Some(region);
+
+        Ok(())
+    }
+
+    /*
+     * Stores `arg` in `slot` of the wait region for the waits that name it.
+     */
+    pub fn set_wait_arg(&mut self, slot: u32, arg: &GetEventsArg) -> Result<()> {
+        if arg.has_min_wait() && !self.features.contains(IoUringFeatures::MinTimeout) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "the kernel does not support a min wait",
+            ));
+        }
+        self.wait_region
+            .as_mut()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no wait region is registered"))?
+            .set(slot, arg)
+    }
+
+    /*
+     * Same as submit_and_wait_with_args, with the arguments stored in `slot`
+     * of the wait region.
+     */
+    pub fn submit_and_wait_registered(&mut self, wait_nr: u32, slot: u32) -> Result<usize> {
+        self.check_issuer()?;
+        let Some(region) = self.wait_region.as_ref() else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "no wait region is registered",
+            ));
+        };
+        if slot >= region.slots() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the slot is past the end of the wait region",
+            ));
+        }
+        let (submitted, flags) = self.prepare_enter(wait_nr);
+        let flags = flags.unwrap_or(IoUringEnterFlags::empty());
+
+        let region = self.wait_region.as_ref().expect("checked above");
+        let (ring_fd, registered) = self.enter_fd();
+        let consumed = self.syscalls.enter_ext_reg(
+            ring_fd,
+            submitted,
+            wait_nr,
+            flags | registered,
+            region,
+            slot,
+        )?;
+
+        Ok(consumed as usize)
+    }
+
+    /*
+     * Thread that owns a SINGLE_ISSUER ring, None for other rings.
+     */
+    pub fn issuer(&self) -> Option<ThreadId> {
+        self.issuer.get()
+    }
+
+    /*
+     * The kernel turns away any other task entering a SINGLE_ISSUER ring
+     * with EEXIST, which says little. With DEFER_TASKRUN this covers waiting
+     * for completions too, since their task work only runs on the issuer.
+     */
+    fn check_issuer(&self) -> Result<()> {
+        match self.issuer.get() {
+            Some(issuer) if issuer != thread::current().id() => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "a SINGLE_ISSUER ring can only be entered from the thread that owns it",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+     * The fd to enter the ring through and the flag that goes with it: the
+     * registered index on the thread that registered it, the ring fd
+     * anywhere else.
+     */
+    fn enter_fd(&self) -> (RawFd, IoUringEnterFlags) {
+        match self.registered_ring {
+            Some((index, thread)) if thread == thread::current().id() => {
+                (index as RawFd, IoUringEnterFlags::IoRingEnterRegisteredRing)
+            }
+            _ => (
+                self.ring_file_descriptor.as_raw_fd(),
+                IoUringEnterFlags::empty(),
+            ),
+        }
+    }
+
+    /*
+     * With SQPOLL the kernel thread picks up the entries by itself, it only
+     * has to be woken up once it went idle.
+     */
+    pub fn sq_need_wakeup(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.send_queue.flags() & IORING_SQ_NEED_WAKEUP > 0
+    }
+
+    /*
+     * Flushes the prepared entries and works out the enter flags, None when
+     * no syscall is needed at all.
+     */
+    fn prepare_enter(&mut self, wait_nr: u32) -> (u32, Option<IoUringEnterFlags>) {
+        let count = self.entries_to_flush();
+
+        if let Some(tracer) = &mut self.tracer {
+            for sqe in self.send_queue.pending_sqes().take(count as usize) {
+                tracer.record(TraceEvent::Submitted(sqe.into()));
+            }
+        }
+
+        for sqe in self.send_queue.pending_sqes().take(count as usize) {
+            let expects_completion = expects_completion(sqe);
+            self.spans.submitted(sqe, expects_completion);
+            if expects_completion {
+                *self.awaited.entry(sqe.user_data).or_default() += 1;
+                self.in_flight += 1;
+            }
+        }
+
+        /*
+         * The queue is in order, deferred closes would wait behind entries
+         * held back by the in flight limit. A split ring has its publisher
+         * queue them, see SqPublisher::submit_and_wait.
+         */
+        let submitted = if count == self.send_queue.pending() {
+            if !self.send_queue.producers {
+                self.queue_deferred_closes();
+            }
+            self.send_queue.flush()
+        } else {
+            self.send_queue.flush_first(count)
+        };
+        let mut flags = if wait_nr > 0 {
+            IoUringEnterFlags::IoRingEnterGetEvents
+        } else {
+            IoUringEnterFlags::empty()
+        };
+
+        let needs_enter = if self.flags.contains(IoUringSetupFlags::SqPool) {
+            if submitted > 0 && self.sq_need_wakeup() {
+                flags |= IoUringEnterFlags::IoRingEnterSqWakeup;
+            }
+            !flags.is_empty()
+        } else {
+            submitted > 0 || wait_nr > 0
+        };
+
+        (submitted, needs_enter.then_some(flags))
+    }
+
+    /*
+     * Hard links an ASYNC_CANCEL_FD to a close for every deferred fd that
+     * fits in the submission queue, the rest waits for the next submission.
+     */
+    fn queue_deferred_closes(&mut self) {
+        let deferred_closes = self.deferred_closes.clone();
+        let mut fds = deferred_closes
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        while self.sq_space_left() >= 2 {
+            let Some(fd) = fds.pop() else {
+                break;
+            };
+            let fd = fd.into_raw_fd();
+
+            if let Some(sqe) = self.internal_sqe() {
+                sqe.cancel_fd(fd)
+                    .user_data(DEFERRED_CLOSE_USER_DATA)
+                    .flags(IoUringSqeFlags::IoHardLink | IoUringSqeFlags::CqeSkipSuccess);
+            }
+            if let Some(sqe) = self.internal_sqe() {
+                sqe.close(fd)
+                    .user_data(DEFERRED_CLOSE_USER_DATA)
+                    .flags(IoUringSqeFlags::CqeSkipSuccess);
+            }
+        }
+    }
+
+    /*
+     * Handle for the Drop of types that close their fd through the ring.
+     */
+    pub(crate) fn deferred_closes(&self) -> DeferredCloses {
+        self.deferred_closes.clone()
+    }
+
+    pub fn peek_completion(&self) -> Option<Completion> {
+        if let Some(completion) = self.deferred.front() {
+            return Some(*completion);
+        }
+
+        self.flush_task_work_if_empty();
+        (0..self.complete_queue.ready())
+            .filter_map(|position| self.complete_queue.peek_at(position))
+            .find(|completion| !is_internal(completion.user_data))
+    }
+
+    /*
+     * The entry stays in the ring, and valid, until advance_completions hands
+     * it back to the kernel. Gives access to fields Completion does not copy,
+     * such as the extra words of big cqes. Completions deferred by an OpScope
+     * are not in the ring anymore and only show up in next_completion.
+     */
+    pub fn peek_raw_cqe(&self) -> Option<&io_uring_cqe> {
+        self.flush_task_work_if_empty();
+        self.complete_queue.cqe_at(0)
+    }
+
+    /*
+     * Number of completions that can be reaped without waiting.
+     */
+    pub fn cq_ready(&self) -> u32 {
+        self.flush_task_work_if_empty();
+        self.complete_queue.ready() + self.deferred.len() as u32
+    }
+
+    /*
+     * With IORING_SETUP_TASKRUN_FLAG the kernel sets IORING_SQ_TASKRUN when
+     * completions are sitting in task work, they only reach the ring once the
+     * task enters the kernel.
+     */
+    pub fn task_work_pending(&self) -> bool {
+        self.flags.contains(IoUringSetupFlags::TaskRunFlag)
+            && self.send_queue.flags() & IORING_SQ_TASKRUN > 0
+    }
+
+    /*
+     * Entries the kernel skipped because they were invalid.
+     */
+    pub fn sq_dropped(&self) -> u32 {
+        self.send_queue.dropped()
+    }
+
+    /*
+     * Completions the kernel lost because neither the completion ring nor
+     * its overflow list could take them. Anything but 0 means requests
+     * finished without the application ever hearing about it.
+     */
+    pub fn cq_overflow(&self) -> u32 {
+        self.complete_queue.overflow()
+    }
+
+    pub fn sq_flags(&self) -> SqFlags {
+        fence(Ordering::SeqCst);
+        SqFlags::from_bits_retain(self.send_queue.flags())
+    }
+
+    pub fn cq_flags(&self) -> CqFlags {
+        CqFlags::from_bits_retain(self.complete_queue.flags())
+    }
+
+    /*
+     * Entries prepared but not submitted yet, oldest first, without touching
+     * them. For logging what a hung application queued.
+     */
+    pub fn pending_submissions(&self) -> impl Iterator<Item = SubmissionRecord> + '_ {
+        self.send_queue.pending_sqes().map(SubmissionRecord::from)
+    }
+
+    /*
+     * Completions waiting to be reaped, in the order next_completion hands
+     * them out, without consuming them. Internal completions of the ring are
+     * left out.
+     */
+    pub fn unreaped_completions(&self) -> impl Iterator<Item = Completion> + '_ {
+        let queued = (0..self.complete_queue.ready())
+            .filter_map(|position| self.complete_queue.peek_at(position))
+            .filter(|completion| !is_internal(completion.user_data));
+
+        self.deferred.iter().copied().chain(queued)
+    }
+
+    /*
+     * Everything Debug shows, laid out one field per line, for logging the
+     * state of a ring that stopped making progress.
+     */
+    pub fn dump(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    pub fn stats(&self) -> RingStats {
+        RingStats {
+            sq_dropped: self.sq_dropped(),
+            cq_overflow: self.cq_overflow(),
+            sq_flags: self.sq_flags(),
+            cq_flags: self.cq_flags(),
+        }
+    }
+
+    /*
+     * Takes the next completion straight from the completion queue, skipping
+     * the ones set aside with defer_completion.
+     */
+    pub(crate) fn next_queued_completion(&mut self) -> Option<Completion> {
+        loop {
+            self.flush_task_work_if_empty();
+            let completion = self.complete_queue.peek()?;
+            self.advance_completions(1);
+
+            if !is_internal(completion.user_data) {
+                return Some(completion);
+            }
+        }
+    }
+
+    /*
+     * Submits what is pending and waits for the completion of `user_data`,
+     * setting aside the ones of other requests reaped meanwhile. A wait cut
+     * short by a signal is entered again. When entering fails otherwise the
+     * request is abandoned before the error is returned, so whatever the
+     * caller lent it can be dropped.
+     */
+    pub(crate) fn wait_for_completion(&mut self, user_data: u64) -> Result<Completion> {
+        let result = self.wait_for(user_data);
+        if result.is_err() {
+            self.abandon(&[user_data]);
+        }
+
+        result
+    }
+
+    /*
+     * Same as wait_for_completion for several requests, e.g. the links of a
+     * chain, returns their completions in the same order. When entering
+     * fails, all of the requests not completed yet are abandoned.
+     */
+    pub(crate) fn wait_for_completions(&mut self, user_data: &[u64]) -> Result<Vec<Completion>> {
+        let mut completions = Vec::with_capacity(user_data.len());
+
+        for (position, &next) in user_data.iter().enumerate() {
+            match self.wait_for(next) {
+                Ok(completion) => completions.push(completion),
+                Err(error) => {
+                    self.abandon(&user_data[position..]);
+                    return Err(error);
+                }
+            }
+        }
+
+        Ok(completions)
+    }
+
+    fn wait_for(&mut self, user_data: u64) -> Result<Completion> {
+        if let Some(completion) = self.take_deferred_completion(user_data) {
+            return Ok(completion);
+        }
+
+        loop {
+            while let Some(completion) = self.next_queued_completion() {
+                if completion.user_data == user_data {
+                    return Ok(completion);
+                }
+                self.defer_completion(completion);
+            }
+            match self.submit_and_wait(1) {
+                Err(error) if error.kind() != ErrorKind::Interrupted => return Err(error),
+                _ => {}
+            }
+        }
+    }
+
+    /*
+     * wait_for_completion for futures: submits what is pending and takes the
+     * completion of `user_data` if it is there, otherwise has the waker of
+     * `cx` woken once the ring has completions and returns Pending. Other
+     * completions reaped meanwhile are set aside. When entering fails the
+     * request is abandoned.
+     */
+    pub(crate) fn poll_completion(
+        &mut self,
+        user_data: u64,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Completion>> {
+        let result = self.poll_for(user_data, cx);
+        if let Poll::Ready(Err(_)) = result {
+            self.abandon(&[user_data]);
+        }
+
+        result
+    }
+
+    fn poll_for(&mut self, user_data: u64, cx: &mut Context<'_>) -> Poll<Result<Completion>> {
+        if let Some(completion) = self.take_deferred_completion(user_data) {
+            return Poll::Ready(Ok(completion));
+        }
+
+        loop {
+            match self.submit() {
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Poll::Ready(Err(error)),
+                Ok(_) => break,
+            }
+        }
+
+        while let Some(completion) = self.next_queued_completion() {
+            if completion.user_data == user_data {
+                return Poll::Ready(Ok(completion));
+            }
+            self.defer_completion(completion);
+        }
+
+        match self.register_waker(cx) {
+            Ok(()) => Poll::Pending,
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+
+    /*
+     * Has the waker of `cx` woken once the ring has completions to reap,
+     * for futures and streams with nothing to take yet.
+     */
+    pub(crate) fn register_waker(&mut self, cx: &mut Context<'_>) -> Result<()> {
+        if self.watcher.is_none() {
+            self.watcher = Some(CompletionWatcher::new(&self.ring_file_descriptor)?);
+        }
+        if let Some(watcher) = &self.watcher {
+            watcher.register(cx.waker());
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Makes sure the requests of `user_data` are done with what they were
+     * given. Entries still prepared become nops, requests the kernel has are
+     * cancelled and waited for. Their completions are dropped, so none turns
+     * up for the next request with the same user_data. A ring that can no
+     * longer be entered keeps the caller here rather than let it free a
+     * buffer the kernel may still write to.
+     */
+    pub(crate) fn abandon(&mut self, user_data: &[u64]) {
+        self.send_queue.abandon_pending(user_data);
+        self.deferred
+            .retain(|completion| !user_data.contains(&completion.user_data));
+
+        let mut cancelled = Vec::new();
+        loop {
+            while let Some(completion) = self.next_queued_completion() {
+                if !user_data.contains(&completion.user_data) {
+                    self.defer_completion(completion);
+                }
+            }
+
+            let in_flight: Vec<u64> = user_data
+                .iter()
+                .copied()
+                .filter(|user_data| self.awaited.contains_key(user_data))
+                .collect();
+            if in_flight.is_empty() {
+                return;
+            }
+            for user_data in in_flight {
+                if cancelled.contains(&user_data) {
+                    continue;
+                }
+                let Some(sqe) = self.next_sqe() else {
+                    break;
+                };
+                sqe.cancel(user_data).user_data(ABANDONED_USER_DATA);
+                cancelled.push(user_data);
+            }
+
+            match self.submit_and_wait(1) {
+                Err(error) if error.kind() != ErrorKind::Interrupted => {
+                    debug!("could not drain abandoned requests: {}", error);
+                    thread::sleep(ABANDON_RETRY_DELAY);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /*
+     * Takes the oldest completion of `user_data` set aside with
+     * defer_completion.
+     */
+    pub(crate) fn take_deferred_completion(&mut self, user_data: u64) -> Option<Completion> {
+        let position = self
+            .deferred
+            .iter()
+            .position(|completion| completion.user_data == user_data)?;
+        self.deferred.remove(position)
+    }
+
+    /*
+     * Sets aside a completion reaped on behalf of someone else, e.g. by an
+     * OpScope draining its own operations. next_completion hands these out
+     * before anything still in the completion queue. Futures waiting on the
+     * ring are woken, the ring fd no longer tells them about it.
+     */
+    pub(crate) fn defer_completion(&mut self, completion: Completion) {
+        self.deferred.push_back(completion);
+        if let Some(watcher) = &self.watcher {
+            watcher.wake_all();
+        }
+    }
+
+    fn flush_task_work_if_empty(&self) {
+        if self.complete_queue.ready() > 0
+            || !self.task_work_pending()
+            || self.check_issuer().is_err()
+        {
+            return;
+        }
+
+        let (ring_fd, registered) = self.enter_fd();
+        if let Err(error) = self.syscalls.enter(
+            ring_fd,
+            0,
+            0,
+            IoUringEnterFlags::IoRingEnterGetEvents | registered,
+            None,
+        ) {
+            debug!("could not run the pending task work: {}", error);
+        }
+    }
+
+    pub fn advance_completions(&mut self, count: u32) {
+        for position in 0..count {
+            let Some(completion) = self.complete_queue.peek_at(position) else {
+                break;
+            };
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(TraceEvent::Completed(completion));
+            }
+            self.spans.completed(&completion);
+            if !is_internal(completion.user_data) && !completion.more() {
+                self.completed(completion.user_data);
+            }
+        }
+
+        self.complete_queue.advance(count)
+    }
+
+    /*
+     * Counts off the last completion of a request. Completions nobody waits
+     * for, from a failed entry with CqeSkipSuccess or posted by another
+     * ring, leave the count alone.
+     */
+    fn completed(&mut self, user_data: u64) {
+        let Some(count) = self.awaited.get_mut(&user_data) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.awaited.remove(&user_data);
+        }
+        self.in_flight -= 1;
+    }
+
+    /*
+     * Requests submitted whose last completion was not reaped yet. Entries
+     * with CqeSkipSuccess are not counted. Their failure is only told apart
+     * from the completion of another request while no other entry in flight
+     * uses the same user_data.
+     */
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight
+    }
+
+    /*
+     * What the kernel of this ring offers, as it reported at setup.
+     */
+    pub fn features(&self) -> IoUringFeatures {
+        self.features
+    }
+
+    /*
+     * The flags the ring was set up with.
+     */
+    pub fn setup_flags(&self) -> IoUringSetupFlags {
+        self.flags
+    }
+
+    /*
+     * Size of the submission queue the kernel set up, the requested entries
+     * rounded up to a power of two or clamped, to size bookkeeping by.
+     */
+    pub fn sq_entries(&self) -> u32 {
+        self.send_queue.ring_entries()
+    }
+
+    /*
+     * Size of the completion queue, twice the submission queue unless
+     * CqSize set it.
+     */
+    pub fn cq_entries(&self) -> u32 {
+        self.complete_queue.ring_entries()
+    }
+
+    /*
+     * Where the kernel put the fields of the submission ring, from the start
+     * of its mapping. Like the sizes, they change with resize.
+     */
+    pub fn sq_offsets(&self) -> IoSqRingOffsets {
+        let ring = self.send_queue.ring.address();
+        let queue = &self.send_queue;
+
+        IoSqRingOffsets {
+            head: ring_offset(ring, queue.head),
+            tail: ring_offset(ring, queue.tail),
+            ring_mask: ring_offset(ring, queue.mask),
+            ring_entries: ring_offset(ring, queue.entries),
+            flags: ring_offset(ring, queue.flags),
+            dropped: ring_offset(ring, queue.dropped),
+            array: ring_offset(ring, queue.array),
+            ..Default::default()
+        }
+    }
+
+    /*
+     * Where the kernel put the fields of the completion ring, from the start
+     * of its mapping, which is the submission ring's with SingleMmap.
+     */
+    pub fn cq_offsets(&self) -> IoCqRingOffsets {
+        let ring = match &self.complete_queue.ring {
+            IoUringQueueOwnership::Owns(ring) => ring.address(),
+            IoUringQueueOwnership::Refers => self.send_queue.ring.address(),
+        };
+        let queue = &self.complete_queue;
+
+        IoCqRingOffsets {
+            head: ring_offset(ring, queue.head),
+            tail: ring_offset(ring, queue.tail),
+            ring_mask: ring_offset(ring, queue.mask),
+            ring_entries: ring_offset(ring, queue.entries),
+            overflow: ring_offset(ring, queue.overflow),
+            cqes: ring_offset(ring, queue.cqes),
+            flags: ring_offset(ring, queue.flags),
+            ..Default::default()
+        }
+    }
+
+    /*
+     * Whether Sqe::skip_success takes effect on this kernel.
+     */
+    pub fn supports_cqe_skip(&self) -> bool {
+        self.features.contains(IoUringFeatures::CqeSkip)
+    }
+
+    /*
+     * Stops the ring for good: next_sqe refuses new entries, what was
+     * prepared is submitted together with a cancel of every request in
+     * flight, then completions are reaped until none is in flight or
+     * `timeout` passes. Completions reaped here are kept for
+     * next_completion. Returns how many requests were abandoned, still in
+     * flight when the time ran out.
+     */
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<u32> {
+        let deadline = Instant::now() + timeout;
+        self.shut_down = true;
+
+        if self.sq_space_left() == 0 {
+            self.submit()?;
+        }
+        if let Some(sqe) = self.internal_sqe() {
+            sqe.cancel_any().user_data(SHUTDOWN_USER_DATA);
+        }
+        self.submit()?;
+
+        loop {
+            while let Some(completion) = self.next_queued_completion() {
+                self.defer_completion(completion);
+            }
+
+            let now = Instant::now();
+            if self.in_flight == 0 || now >= deadline {
+                return Ok(self.in_flight);
+            }
+
+            let arg = GetEventsArg::new().timeout(deadline - now);
+            match self.submit_and_wait_with_args(1, &arg) {
+                Err(error) if error.raw_os_error() == Some(ETIME) => {}
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+
+    pub fn enable_tracing(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn disable_tracing(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn wait_completion(&mut self) -> Result<Completion> {
+        loop {
+            if let Some(completion) = self.next_completion() {
+                return Ok(completion);
+            }
+            self.submit_and_wait(1)?;
+        }
+    }
+
+    /*
+     * Hands the ring over in the layout of liburing's struct io_uring. The
+     * mappings and the fd are not released, whoever gets the struct owns
+     * them and must tear them down, e.g. with io_uring_queue_exit.
+     */
+    pub fn into_raw_parts(self) -> RawIoUring {
+        ffi::into_raw_parts(self)
+    }
+
+    /*
+     * from_fd for rings with other entry sizes, which must match the ones
+     * the ring was set up with.
+     */
+    pub fn from_fd_sized(fd: OwnedFd) -> Result<IoUring<'a, S, C>> {
+        adopt::from_fd(fd)
+    }
+
+    /// # Safety
+    ///
+    /// `raw` must describe a live ring set up by liburing, or returned by
+    /// into_raw_parts, and nothing else may use it afterwards: the ring takes
+    /// ownership of the mappings and of the fd.
+    pub unsafe fn from_raw_parts(raw: RawIoUring) -> Result<IoUring<'a, S, C>> {
+        ffi::from_raw_parts(raw)
+    }
+
+    pub(crate) fn register(
+        &self,
+        opcode: IoUringOpCode,
+        arg: *const c_void,
+        nr_args: u32,
+    ) -> Result<i64> {
+        unsafe {
+            self.syscalls
+                .register(&self.ring_file_descriptor, opcode, arg, nr_args)
+        }
+    }
+
+    /*
+     * Restrictions can only be registered while the ring was created with
+     * IORING_SETUP_R_DISABLED and has not been enabled yet.
+     */
+    pub fn register_restrictions(&self, restrictions: &[Restriction]) -> Result<()> {
+        let restrictions: Vec<io_uring_restriction> =
+            restrictions.iter().map(|r| r.into()).collect();
+
+        self.register(
+            IoUringOpCode::IoRingRegisterRestrictions,
+            restrictions.as_ptr() as *const c_void,
+            restrictions.len() as u32,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn enable_rings(&self) -> Result<()> {
+        self.register(IoUringOpCode::IoRingRegisterEnableRings, null(), 0)?;
+        if self.flags.contains(IoUringSetupFlags::SingleIssuer) {
+            self.issuer.set(Some(thread::current().id()));
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Registers the ring fd with the calling thread, which then enters the
+     * ring through its index and skips the fd lookup of every enter. Other
+     * threads keep entering through the fd. The registration goes with the
+     * ring, or when the thread exits.
+     */
+    pub fn register_ring_fd(&mut self) -> Result<()> {
+        if self.registered_ring.is_some() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                "the ring fd is already registered",
+            ));
+        }
+
+        let mut update = io_uring_rsrc_update {
+            offset: u32::MAX,
+            resv: 0,
+            data: self.ring_file_descriptor.as_raw_fd() as u64,
+        };
+        self.register(
+            IoUringOpCode::IoRingRegisterRingFds,
+            &mut update as *mut io_uring_rsrc_update as *const c_void,
+            1,
+        )?;
+        self.registered_ring = Some((update.offset, thread::current().id()));
+
+        Ok(())
+    }
+
+    /*
+     * Drops the registration of register_ring_fd, from the thread that made
+     * it.
+     */
+    pub fn unregister_ring_fd(&mut self) -> Result<()> {
+        let Some((index, thread)) = self.registered_ring else {
+            return Ok(());
+        };
+        if thread != thread::current().id() {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "the ring fd can only be unregistered from the thread that registered it",
+            ));
+        }
+
+        let update = io_uring_rsrc_update {
+            offset: index,
+            resv: 0,
+            data: 0,
+        };
+        self.register(
+            IoUringOpCode::IoRingUnregisterRingFds,
+            &update as *const io_uring_rsrc_update as *const c_void,
+            1,
+        )?;
+        self.registered_ring = None;
+
+        Ok(())
+    }
+
+    pub(crate) fn mappings(&self) -> Vec<&MMap<'a>> {
+        let mut mappings = vec![&self.send_queue.ring, &self.send_queue.sqes];
+        if let IoUringQueueOwnership::Owns(ring) = &self.complete_queue.ring {
+            mappings.push(ring);
+        }
+        mappings
+    }
+
+    pub(crate) fn apply_memory_options(&mut self, options: MemoryOptions) -> Result<()> {
+        for mapping in self.mappings() {
+            if options.dont_fork {
+                mapping.dont_fork()?;
+            }
+            if options.populate {
+                mapping.populate()?;
+            }
+            if options.lock {
+                mapping.lock()?;
+            }
+        }
+        self.memory_options = options;
+
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The buffers must stay valid until they are unregistered or the ring is
+    /// dropped, the kernel keeps reading from and writing to them.
+    pub unsafe fn register_buffers(&self, buffers: &[iovec]) -> Result<()> {
+        for buffer in buffers {
+            if self.memory_options.dont_fork {
+                advise_dont_fork(buffer.iov_base, buffer.iov_len)?;
+            }
+            if self.memory_options.lock {
+                lock_memory(buffer.iov_base, buffer.iov_len)?;
+            }
+        }
+
+        self.register(
+            IoUringOpCode::IoRingRegisterBuffers,
+            buffers.as_ptr() as *const c_void,
+            buffers.len() as u32,
+        )?;
+
+        Ok(())
+    }
+
+    /*
+     * readv into the spare capacity of `buffers`. The ring holds them until
+     * take_buffers is called with the completion of `user_data`. They are
+     * given back right away when the submission queue is full or user_data
+     * already holds buffers.
+     */
+    pub fn readv_owned(
+        &mut self,
+        fd: RawFd,
+        buffers: Vec<OwnedBuf>,
+        offset: u64,
+        user_data: u64,
+    ) -> std::result::Result<(), Vec<OwnedBuf>> {
+        self.submit_owned(Direction::Read, fd, buffers, offset, user_data)
+    }
+
+    /*
+     * writev of the initialized bytes of `buffers`, held like readv_owned.
+     */
+    pub fn writev_owned(
+        &mut self,
+        fd: RawFd,
+        buffers: Vec<OwnedBuf>,
+        offset: u64,
+        user_data: u64,
+    ) -> std::result::Result<(), Vec<OwnedBuf>> {
+        self.submit_owned(Direction::Write, fd, buffers, offset, user_data)
+    }
+
+    /*
+     * Buffers of the completed vectored operation, a read also keeps the
+     * bytes it got.
+     */
+    pub fn take_buffers(&mut self, completion: &Completion) -> Option<Vec<OwnedBuf>> {
+        self.held_buffers
+            .complete(completion.user_data, completion.result)
+    }
+
+    /*
+     * Number of vectored operations whose buffers the ring still holds.
+     */
+    pub fn held_buffers(&self) -> usize {
+        self.held_buffers.len()
+    }
+
+    /*
+     * Boxes `payload`, e.g. the state of the task waiting for an operation,
+     * and returns the user_data to submit the operation with. The ring holds
+     * the payload until take_payload and drops what is left when it goes
+     * away.
+     */
+    pub fn stash_payload<T: Any + Send>(&mut self, payload: T) -> Result<u64> {
+        self.payloads.stash(payload)
+    }
+
+    /*
+     * The payload behind a completion of a multishot operation that is not
+     * done yet.
+     */
+    pub fn payload<T: Any + Send>(&self, completion: &Completion) -> Option<&T> {
+        self.payloads.get(completion.user_data)
+    }
+
+    /*
+     * Takes the payload back on the last completion of its operation, None
+     * for completions with more to come, other user_data, a payload of
+     * another type or one already taken.
+     */
+    pub fn take_payload<T: Any + Send>(&mut self, completion: &Completion) -> Option<T> {
+        if completion.more() {
+            return None;
+        }
+
+        self.payloads.take(completion.user_data)
+    }
+
+    /*
+     * Number of payloads the ring still holds.
+     */
+    pub fn stashed_payloads(&self) -> usize {
+        self.payloads.len()
+    }
+
+    fn submit_owned(
+        &mut self,
+        direction: Direction,
+        fd: RawFd,
+        buffers: Vec<OwnedBuf>,
+        offset: u64,
+        user_data: u64,
+    ) -> std::result::Result<(), Vec<OwnedBuf>> {
+        if self.sq_space_left() == 0 {
+            return Err(buffers);
+        }
+
+        let iovecs = self.held_buffers.hold(user_data, direction, buffers)?;
+        let (iovecs, count) = (iovecs.as_ptr(), iovecs.len() as u32);
+        let default_priority = self.default_priority;
+        let cqe_skip = self.supports_cqe_skip();
+
+        match self.send_queue.next_sqe() {
+            Some(raw) => {
+                let sqe = Sqe::new(raw, default_priority, cqe_skip);
+                unsafe {
+                    match direction {
+                        Direction::Read => sqe.readv(fd, iovecs, count, offset),
+                        Direction::Write => sqe.writev(fd, iovecs, count, offset),
+                    }
+                }
+                .user_data(user_data);
+                Ok(())
+            }
+            None => Err(self.held_buffers.release(user_data).unwrap_or_default()),
+        }
+    }
+
+    /*
+     * Registers buffers owned by the crate, operations then address them
+     * with BufSlice and BufSliceMut instead of raw pointers.
+     */
+    pub fn register_fixed_buffers(&self, buffers: &mut FixedBuffers) -> Result<()> {
+        unsafe { self.register_buffers(&buffers.iovecs()) }
+    }
+
+    pub fn unregister_buffers(&self) -> Result<()> {
+        self.register(IoUringOpCode::IoRingUnregisterBuffers, null(), 0)?;
+
+        Ok(())
+    }
+
+    /*
+     * Registers a file table of `count` empty slots, for operations that
+     * install or use files with file_slot and IoUringSqeFlags::FixedFile.
+     */
+    pub fn register_files_sparse(&self, count: u32) -> Result<()> {
+        let registration = io_uring_rsrc_register {
+            nr: count,
+            flags: IORING_RSRC_REGISTER_SPARSE,
+            resv2: 0,
+            data: 0,
+            tags: 0,
+        };
+
+        self.register(
+            IoUringOpCode::IoRingRegisterFiles2,
+            &registration as *const io_uring_rsrc_register as *const c_void,
+            size_of::<io_uring_rsrc_register>() as u32,
+        )?;
+
+        Ok(())
+    }
+
+    /*
+     * Registers a file table of `count` empty slots and hands them out as
+     * guards that empty their slot when dropped, see FixedTable.
+     */
+    pub fn register_fixed_table(&self, count: u32) -> Result<FixedTable> {
+        FixedTable::register(self, count)
+    }
+
+    /*
+     * Hands the file in `source_slot` of this file table to the ring
+     * `target`, into `target_slot` or a free slot of its table with None,
+     * and returns the slot it landed in. The target reaps a completion with
+     * `user_data` for it. Lets an acceptor ring pass connections to worker
+     * rings without a detour through SCM_RIGHTS. The file stays in this table
+     * as well until it is closed with close_direct.
+     */
+    pub fn send_fixed_file(
+        &mut self,
+        target: &impl AsFd,
+        source_slot: u32,
+        target_slot: Option<u32>,
+        user_data: u64,
+    ) -> Result<u32> {
+        let ring_fd = target.as_fd().as_raw_fd();
+        let slot = self
+            .op(|sqe| sqe.msg_ring_fd(ring_fd, source_slot, target_slot, user_data))
+            .run()?;
+
+        Ok(target_slot.unwrap_or(slot))
+    }
+
+    /*
+     * Has the kernel signal `eventfd` whenever it posts a completion, so a
+     * reactor can wait for the ring together with other fds. The kernel
+     * keeps its own reference to the eventfd.
+     */
+    pub fn register_eventfd(&self, eventfd: &impl AsFd) -> Result<()> {
+        let fd = eventfd.as_fd().as_raw_fd();
+        self.register(
+            IoUringOpCode::IoRingRegisterEventFd,
+            &fd as *const RawFd as *const c_void,
+            1,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unregister_eventfd(&self) -> Result<()> {
+        self.register(IoUringOpCode::IoRingUnregisterEventFd, null(), 0)?;
+
+        Ok(())
+    }
+
+    /*
+     * An Unparker that wakes a thread waiting on this ring by posting a
+     * completion to it, see Unparker::msg_ring.
+     */
+    pub fn unparker(&self) -> Result<Unparker> {
+        Unparker::msg_ring(self)
+    }
+
+    pub fn unregister_files(&self) -> Result<()> {
+        self.register(IoUringOpCode::IoRingUnregisterFiles, null(), 0)?;
+
+        Ok(())
+    }
+
+    pub fn register_buf_ring(
+        &self,
+        group_id: u16,
+        entries: u16,
+        flags: BufRingFlags,
+    ) -> Result<BufRing<'a>> {
+        BufRing::register(self, group_id, entries, flags)
+    }
+
+    pub fn unregister_buf_ring(&self, buf_ring: BufRing<'a>) -> Result<()> {
+        buf_ring.unregister(self)
+    }
+
+    pub fn probe(&self) -> Result<Probe> {
+        let mut probe = Probe::new();
+        self.register(
+            IoUringOpCode::IoRingRegisterProbe,
+            probe.as_mut_ptr(),
+            Probe::MAX_OPERATIONS,
+        )?;
+
+        Ok(probe)
+    }
+
+    /*
+     * Switches the clock the timeouts and deadlines of waits on this ring
+     * run on, CLOCK_MONOTONIC until then. Needs kernel 6.12.
+     */
+    pub fn register_clock(&self, clock: Clock) -> Result<()> {
+        let registration = io_uring_clock_register {
+            clockid: clock.id() as u32,
+            __resv: [0; 3],
+        };
+        self.register(
+            IoUringOpCode::IoRingRegisterClock,
+            &registration as *const io_uring_clock_register as *const c_void,
+            0,
+        )?;
+
+        Ok(())
+    }
+
+    /*
+     * Grows or shrinks the rings to `sq_entries` and `cq_entries`, rounded up
+     * to powers of two, without tearing the ring down. Prepared entries are
+     * submitted first, entries the in flight limit holds back fail it with
+     * WouldBlock until completions are reaped. Completions not reaped yet
+     * move to the new completion
+     * ring, which fails with EOVERFLOW when they do not fit. Only
+     * DEFER_TASKRUN rings with rings the kernel allocated can be resized,
+     * from kernel 6.13. Pointers taken from into_raw_parts or a producer
+     * before the resize refer to the old rings.
+     */
+    pub fn resize(&mut self, sq_entries: u32, cq_entries: u32) -> Result<()> {
+        if !self.flags.contains(IoUringSetupFlags::DeferTaskRun) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "only DEFER_TASKRUN rings can be resized",
+            ));
+        }
+        if self.flags.contains(IoUringSetupFlags::NoMmap) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "rings in application memory cannot be resized",
+            ));
+        }
+        self.submit()?;
+        if self.send_queue.pending() > 0 {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "entries are held back by the in flight limit until completions are reaped",
+            ));
+        }
+
+        let mut params: io_uring_params = unsafe { std::mem::zeroed() };
+        params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
+        params.flags = IORING_SETUP_CQSIZE | (self.flags.bits() & IORING_SETUP_CLAMP);
+        self.register(
+            IoUringOpCode::IoRingRegisterResizeRings,
+            &mut params as *mut io_uring_params as *const c_void,
+            1,
+        )?;
+        params.features = self.features.bits();
+        /*
+         * The kernel does not report where the indirection array of the new
+         * rings is. It follows the cqes, aligned to a cache line.
+         */
+        if params.sq_off.array == 0 {
+            let cqes_end = params.cq_off.cqes as usize + params.cq_entries as usize * C::SIZE;
+            params.sq_off.array = cqes_end.next_multiple_of(RINGS_ALIGN) as u32;
+        }
+
+        let (mut send_queue, complete_queue) =
+            map_queues(&self.ring_file_descriptor, &params, self.syscalls.as_ref())?;
+        /*
+         * The kernel carries the head and tail counters over to the new
+         * rings, the submission queue was flushed so both are at sqe_tail.
+         */
+        send_queue.sqe_head = self.send_queue.sqe_tail;
+        send_queue.sqe_tail = self.send_queue.sqe_tail;
+        self.send_queue = send_queue;
+        self.complete_queue = complete_queue;
+
+        self.apply_memory_options(self.memory_options)
+    }
+
+    /*
+     * Whether the kernel of this ring knows `operation`, asked with a probe.
+     */
+    pub fn supports(&self, operation: IoUringOperation) -> Result<bool> {
+        Ok(self.probe()?.is_supported(operation))
+    }
+
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities {
+            features: self.features,
+            probe: self.probe()?,
+            kernel_version: KernelVersion::current()?,
+        })
+    }
+}
+
+/*
+ * For users that want to specify sq_thread_cpu or sq_thread_idle, this
+ * interface is a convenient helper for mmap()ing the rings.
+ * Returns -errno on error, or zero on success.  On success, 'ring'
+ * contains the necessary information to read/write to the rings.
+ */
+pub(crate) fn io_uring_queue_mmap<'a, S: SqeEntry, C: CqeEntry>(
+    file_descriptor: OwnedFd,
+    io_uring_params: &io_uring_params,
+    syscalls: Arc<dyn UringSyscalls>,
+) -> Result<IoUring<'a, S, C>> {
+    let (send_queue, complete_queue) =
+        map_queues(&file_descriptor, io_uring_params, syscalls.as_ref())?;
+
+    Ok(IoUring {
+        send_queue,
+        complete_queue,
+        flags: IoUringSetupFlags::from_bits_retain(io_uring_params.flags),
+        features: IoUringFeatures::from_bits_retain(io_uring_params.features),
+        memory_options: MemoryOptions::default(),
+        ring_file_descriptor: file_descriptor,
+        syscalls,
+        tracer: None,
+        spans: OpSpans::default(),
+        default_priority: None,
+        deferred: VecDeque::new(),
+        held_buffers: HeldBuffers::default(),
+        payloads: OpPayloads::default(),
+        deferred_closes: DeferredCloses::default(),
+        in_flight: 0,
+        awaited: HashMap::new(),
+        in_flight_limit: None,
+        shut_down: false,
+        wait_region: None,
+        issuer: Cell::new(single_issuer(io_uring_params.flags).then(|| thread::current().id())),
+        watcher: None,
+        registered_ring: None,
+    })
+}
+
+/*
+ * Maps the rings and the sqes the kernel describes in `io_uring_params`, at
+ * setup and again once they are resized.
+ */
+fn map_queues<'a, S: SqeEntry, C: CqeEntry>(
+    file_descriptor: &OwnedFd,
+    io_uring_params: &io_uring_params,
+    syscalls: &dyn UringSyscalls,
+) -> Result<(IoUringSendQueue<'a, S>, IoUringCompleteQueue<'a, C>)> {
+    let mut send_ring_size = io_uring_params.sq_off.array as usize
+        + io_uring_params.sq_entries as usize * size_of::<u32>();
+    let mut complete_ring_size =
+        io_uring_params.cq_off.cqes as usize + io_uring_params.cq_entries as usize * C::SIZE;
+
+    if io_uring_params.features & IORING_FEAT_SINGLE_MMAP > 0 {
+        if complete_ring_size > send_ring_size {
+            send_ring_size = complete_ring_size;
+        }
+        complete_ring_size = send_ring_size;
+    }
+
+    /*
+     * The completion ring is left to fault in as it fills, with a single
+     * mmap that is the mapping of the submission ring.
+     */
+    let send_ring_options = if io_uring_params.features & IORING_FEAT_SINGLE_MMAP > 0 {
+        MapOptions::LAZY
+    } else {
+        MapOptions::POPULATED
+    };
+    let send_ring = syscalls.mmap(
+        file_descriptor,
+        IORING_OFF_SQ_RING as MmapOffset,
+        send_ring_size,
+        send_ring_options,
+    )?;
+
+    let size = io_uring_params.sq_entries as usize * S::SIZE;
+
+    let send_queue_qes = syscalls.mmap(
+        file_descriptor,
+        IORING_OFF_SQES as MmapOffset,
+        size,
+        MapOptions::POPULATED,
+    )?;
+
+    let send_queue = setup_send_ring(send_ring, io_uring_params, send_queue_qes)?;
+
+    let complete_ring = if io_uring_params.features & IORING_FEAT_SINGLE_MMAP > 0 {
+        IoUringQueueOwnership::Refers
+    } else {
+        IoUringQueueOwnership::Owns(syscalls.mmap(
+            file_descriptor,
+            IORING_OFF_CQ_RING as MmapOffset,
+            complete_ring_size,
+            MapOptions::LAZY,
+        )?)
+    };
+
+    let complete_queue = setup_cq_ring(complete_ring, io_uring_params, &send_queue.ring)?;
+
+    Ok((send_queue, complete_queue))
+}
+
+/*
+ * A SINGLE_ISSUER ring belongs to the task that creates it, or to the one
+ * that enables it when it starts disabled.
+ */
+fn single_issuer(flags: u32) -> bool {
+    flags & IORING_SETUP_SINGLE_ISSUER > 0 && flags & IORING_SETUP_R_DISABLED == 0
+}
+
+fn ring_offset(ring: NonNull<c_void>, field: NonNull<c_void>) -> u32 {
+    (field.as_ptr() as usize - ring.as_ptr() as usize) as u32
+}
+
+#[cfg(test)]
+mod when_initializing_io_uring {
+    use crate::io_uring::{
+        IoCqRingOffsets, IoSqRingOffsets, IoUring, IoUringError, IoUringFeatures, IoUringParams,
+        IoUringSetupFlags,
+    };
+    use crate::syscalls::{RealSyscalls, UringSyscalls};
+    use linux_raw_sys::io_uring::io_uring_params;
+
+    #[test]
+    pub fn io_uring_setup_does_not_throw() {
+        let params = IoUringParams {
+            sq_entries: 0,
+            cq_entries: 0,
+            flags: 0,
+            sq_thread_cpu: 0,
+            sq_thread_idle: 0,
+            features: 0,
+            wq_fd: 0,
+            resv: [0, 0, 0],
+            sq_off: IoSqRingOffsets {
+                head: 0,
+                tail: 0,
+                ring_mask: 0,
+                ring_entries: 0,
+                flags: 0,
+                dropped: 0,
+                array: 0,
+                resv1: 0,
+                user_addr: 0,
+            },
+            cq_off: IoCqRingOffsets {
+                head: 0,
+                tail: 0,
+                ring_mask: 0,
+                ring_entries: 0,
+                overflow: 0,
+                cqes: 0,
+                flags: 0,
+                resv1: 0,
+                user_addr: 0,
+            },
+        };
+
+        let io_uring = IoUring::initialize(1, params);
+
+        assert!(io_uring.is_ok());
+    }
+
+    #[test]
+    pub fn the_negotiated_features_and_flags_are_kept() {
+        let params = IoUringParams {
+            flags: (IoUringSetupFlags::SingleIssuer | IoUringSetupFlags::DeferTaskRun).bits(),
+            ..Default::default()
+        };
+
+        let ring = IoUring::initialize(4, params).unwrap();
+
+        assert!(ring.features().contains(IoUringFeatures::SingleMmap));
+        assert_eq!(
+            ring.setup_flags(),
+            IoUringSetupFlags::SingleIssuer | IoUringSetupFlags::DeferTaskRun
+        );
+    }
+
+    #[test]
+    pub fn the_geometry_the_kernel_set_up_is_exposed() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::CqSize.bits(),
+            cq_entries: 100,
+            ..Default::default()
+        };
+        let mut raw: io_uring_params = (&params).try_into().unwrap();
+        drop(RealSyscalls.setup(5, &mut raw).unwrap());
+
+        let ring = IoUring::initialize(5, params).unwrap();
+
+        assert_eq!((ring.sq_entries(), ring.cq_entries()), (8, 128));
+        assert_eq!(ring.sq_offsets(), IoSqRingOffsets::from(raw.sq_off));
+        assert_eq!(ring.cq_offsets(), IoCqRingOffsets::from(raw.cq_off));
+    }
+
+    #[test]
+    pub fn reserved_fields_and_features_are_not_handed_to_the_kernel() {
+        let mut params = IoUringParams {
+            features: u32::MAX,
+            resv: [1, 2, 3],
+            ..Default::default()
+        };
+        params.sq_off.resv1 = 4;
+
+        let raw = io_uring_params::try_from(&params).unwrap();
+
+        assert_eq!(raw.features, 0);
+        assert_eq!(raw.resv, [0; 3]);
+        assert_eq!(raw.sq_off.resv1, 0);
+        assert!(IoUring::initialize(4, params).is_ok());
+    }
+
+    #[test]
+    pub fn setup_flags_the_kernel_refuses_are_refused_with_the_reason() {
+        let params = |flags: IoUringSetupFlags| IoUringParams {
+            flags: flags.bits(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            io_uring_params::try_from(&IoUringParams {
+                flags: 1 << 31,
+                ..Default::default()
+            })
+            .err(),
+            Some(IoUringError::UnknownSetupFlags(1 << 31))
+        );
+        assert_eq!(
+            io_uring_params::try_from(&params(IoUringSetupFlags::DeferTaskRun)).err(),
+            Some(IoUringError::MissingSetupFlag {
+                flag: IoUringSetupFlags::DeferTaskRun,
+                needs: IoUringSetupFlags::SingleIssuer
+            })
+        );
+        assert_eq!(
+            io_uring_params::try_from(&params(
+                IoUringSetupFlags::SingleIssuer
+                    | IoUringSetupFlags::DeferTaskRun
+                    | IoUringSetupFlags::SqPool
+            ))
+            .err(),
+            Some(IoUringError::ConflictingSetupFlags(
+                IoUringSetupFlags::DeferTaskRun,
+                IoUringSetupFlags::SqPool
+            ))
+        );
+        assert_eq!(
+            io_uring_params::try_from(&params(IoUringSetupFlags::CqSize)).err(),
+            Some(IoUringError::MissingCqEntries)
+        );
+    }
+
+    #[test]
+    pub fn the_params_the_kernel_filled_in_convert_back() {
+        let mut raw: io_uring_params = unsafe { std::mem::zeroed() };
+        raw.sq_entries = 8;
+        raw.cq_entries = 16;
+        raw.features = IoUringFeatures::SingleMmap.bits();
+        raw.sq_off.array = 64;
+        raw.cq_off.cqes = 128;
+
+        let params = IoUringParams::from(raw);
+
+        assert_eq!((params.sq_entries, params.cq_entries), (8, 16));
+        assert_eq!(params.features, IoUringFeatures::SingleMmap.bits());
+        assert_eq!((params.sq_off.array, params.cq_off.cqes), (64, 128));
+    }
+}
+
+#[cfg(test)]
+mod when_initializing_io_uring_against_mock_syscalls {
+    use crate::{
+        arch::MmapOffset,
+        io_uring::{IoUring, IoUringParams},
+        syscalls::{
+            mock::{MockSyscalls, SyscallRecord},
+            IoUringOpCode,
+        },
+    };
+    use libc::EPERM;
+    use linux_raw_sys::io_uring::{IORING_OFF_SQES, IORING_OFF_SQ_RING};
+    use std::sync::Arc;
+
+    #[test]
+    pub fn setup_and_single_mmap_are_issued() {
+        let syscalls = Arc::new(MockSyscalls::new());
+
+        let ring = IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone());
+
+        assert!(ring.is_ok());
+        let records = syscalls.records();
+        assert_eq!(
+            records[0],
+            SyscallRecord::Setup {
+                entries: 4,
+                flags: 0
+            }
+        );
+        assert!(
+            matches!(records[1], SyscallRecord::Mmap { offset, .. } if offset == IORING_OFF_SQ_RING as MmapOffset)
+        );
+        assert!(
+            matches!(records[2], SyscallRecord::Mmap { offset, .. } if offset == IORING_OFF_SQES as MmapOffset)
+        );
+        assert_eq!(records.len(), 3);
+    }
+
+    #[test]
+    pub fn the_completion_ring_is_mapped_lazily() {
+        let syscalls = Arc::new(MockSyscalls::new());
+
+        IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone()).unwrap();
+
+        let records = syscalls.records();
+        assert!(matches!(
+            records[1],
+            SyscallRecord::Mmap {
+                populate: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            records[2],
+            SyscallRecord::Mmap { populate: true, .. }
+        ));
+    }
+
+    #[test]
+    pub fn setup_failures_are_reported() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        syscalls.fail_setup(EPERM);
+
+        let ring = IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls);
+
+        assert_eq!(ring.err().unwrap().raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[test]
+    pub fn register_calls_go_through_the_syscalls() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let ring = IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+            .unwrap();
+        syscalls.script_register(Err(EPERM));
+
+        assert!(ring.enable_rings().is_err());
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Register {
+                opcode: IoUringOpCode::IoRingRegisterEnableRings,
+                nr_args: 0
+            })
+        );
+    }
+}
+
+/*
+ * Requests still in flight when the ring goes away may go on writing to
+ * buffers their owners already freed. The ring cancels them and waits for
+ * them, up to DROP_DRAIN_TIMEOUT; entries that were prepared but never
+ * submitted are discarded. Requests it had to abandon are a bug of the
+ * application: a debug build panics, a release build logs them.
+ */
+impl<'a, S: SqeEntry, C: CqeEntry> Drop for IoUring<'a, S, C> {
+    fn drop(&mut self) {
+        if let Err(error) = self.unregister_ring_fd() {
+            debug!("could not unregister the ring fd: {}", error);
+        }
+        if self.in_flight == 0 || !self.syscalls.completes_submissions() {
+            return;
+        }
+
+        let in_flight = self.in_flight;
+        self.send_queue.sqe_tail = self.send_queue.sqe_head;
+        let abandoned = match self.shutdown(DROP_DRAIN_TIMEOUT) {
+            Ok(abandoned) => abandoned,
+            Err(error) => {
+                debug!("could not cancel the requests in flight: {}", error);
+                self.in_flight
+            }
+        };
+        debug!(
+            "the ring was dropped with {} requests in flight, {} abandoned",
+            in_flight, abandoned
+        );
+
+        if abandoned > 0 {
+            if cfg!(debug_assertions) && !thread::panicking() {
+                panic!(
+                    "the ring was dropped with {} requests still in flight",
+                    abandoned
+                );
+            }
+            warn!(
+                "the ring was dropped with {} requests still in flight",
+                abandoned
+            );
+        }
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Debug for IoUring<'a, S, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoUring")
+            .field("fd", &self.ring_file_descriptor.as_raw_fd())
+            .field("send_queue", &self.send_queue)
+            .field("complete_queue", &self.complete_queue)
+            .field("flags", &self.flags)
+            .field("features", &self.features)
+            .field("in_flight", &self.in_flight)
+            .field("deferred", &self.deferred.len())
+            .field("shut_down", &self.shut_down)
+            .field("issuer", &self.issuer.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> AsFd for IoUring<'a, S, C> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.ring_file_descriptor.as_fd()
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> AsRawFd for IoUring<'a, S, C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring_file_descriptor.as_raw_fd()
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Completions for IoUring<'a, S, C> {
+    fn next_completion(&mut self) -> Option<Completion> {
+        self.deferred
+            .pop_front()
+            .or_else(|| self.next_queued_completion())
+    }
+}
+
+#[cfg(test)]
+mod when_submitting_and_completing {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        syscalls::{mock::MockSyscalls, mock::SyscallRecord, IoUringEnterFlags},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    pub fn nop_round_trips_through_the_kernel() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(42);
+
+        assert_eq!(ring.submit_and_wait(1).unwrap(), 1);
+
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.user_data, 42);
+        assert_eq!(completion.result, 0);
+        assert!(ring.next_completion().is_none());
+    }
+
+    #[test]
+    pub fn the_submission_queue_reports_when_it_is_full() {
+        let mut ring = IoUring::initialize(2, IoUringParams::default()).unwrap();
+
+        assert!(ring.next_sqe().is_some());
+        assert!(ring.next_sqe().is_some());
+        assert!(ring.next_sqe().is_none());
+    }
+
+    #[test]
+    pub fn submit_enters_with_the_prepared_entries() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+        ring.next_sqe().unwrap().nop();
+        ring.next_sqe().unwrap().nop();
+
+        assert_eq!(ring.submit().unwrap(), 2);
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 2,
+                min_complete: 0,
+                flags: IoUringEnterFlags::empty(),
+                arg_size: 0
+            })
+        );
+    }
+
+    #[test]
+    pub fn enter_failures_are_reported() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+        syscalls.script_enter(Err(libc::EBUSY));
+        ring.next_sqe().unwrap().nop();
+
+        assert!(ring.submit().is_err());
+    }
+}
+
+#[cfg(test)]
+mod when_entering_with_extended_arguments {
+    use crate::{
+        io_uring::{IoUring, IoUringFeatures, IoUringParams},
+        syscalls::{
+            mock::MockSyscalls, mock::SyscallRecord, Clock, GetEventsArg, IoUringEnterFlags,
+            UringSyscalls,
+        },
+    };
+    use linux_raw_sys::io_uring::io_uring_getevents_arg;
+    use std::{
+        fs::File,
+        io::ErrorKind,
+        os::fd::{AsRawFd, OwnedFd},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn the_getevents_arg_size_and_flag_are_passed() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+        ring.next_sqe().unwrap().nop();
+
+        let arg = GetEventsArg::new().timeout(Duration::from_millis(5));
+        ring.submit_and_wait_with_args(1, &arg).unwrap();
+
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 1,
+                min_complete: 1,
+                flags: IoUringEnterFlags::IoRingEnterGetEvents
+                    | IoUringEnterFlags::IoRingEnterExtArg,
+                arg_size: size_of::<io_uring_getevents_arg>(),
+            })
+        );
+    }
+
+    #[test]
+    pub fn a_plain_enter_passes_the_sigmask_size_and_drops_ext_arg() {
+        let syscalls = MockSyscalls::new();
+        let ring_fd: OwnedFd = File::open("/dev/null").unwrap().into();
+        let sigmask = 0;
+
+        syscalls
+            .enter(
+                ring_fd.as_raw_fd(),
+                0,
+                1,
+                IoUringEnterFlags::IoRingEnterGetEvents | IoUringEnterFlags::IoRingEnterExtArg,
+                Some(&sigmask),
+            )
+            .unwrap();
+
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 0,
+                min_complete: 1,
+                flags: IoUringEnterFlags::IoRingEnterGetEvents,
+                arg_size: 8,
+            })
+        );
+    }
+
+    #[test]
+    pub fn a_deadline_is_an_absolute_time_of_the_registered_clock() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_clock(Clock::Boottime).unwrap();
+        let started = Instant::now();
+
+        let deadline = Clock::Boottime.now().unwrap() + Duration::from_millis(20);
+        let arg = GetEventsArg::new().deadline(deadline);
+        let error = ring.submit_and_wait_with_args(1, &arg).unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::ETIME));
+        assert!(started.elapsed() >= Duration::from_millis(15));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    pub fn a_deadline_sets_the_absolute_timer_flag() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+
+        let arg = GetEventsArg::new().deadline(Duration::from_secs(1));
+        ring.submit_and_wait_with_args(1, &arg).unwrap();
+
+        assert!(matches!(
+            syscalls.records().last(),
+            Some(SyscallRecord::Enter { flags, .. })
+                if flags.contains(IoUringEnterFlags::IoRingEnterAbsTimer)
+        ));
+    }
+
+    #[test]
+    pub fn a_min_wait_needs_kernel_support() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+
+        let arg = GetEventsArg::new().min_wait(Duration::from_millis(1));
+        let error = ring.submit_and_wait_with_args(1, &arg).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    pub fn past_the_min_wait_one_completion_ends_the_wait() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        if !ring.features().contains(IoUringFeatures::MinTimeout) {
+            return;
+        }
+        ring.next_sqe().unwrap().nop();
+        let started = Instant::now();
+
+        let arg = GetEventsArg::new()
+            .timeout(Duration::from_secs(5))
+            .min_wait(Duration::from_millis(10));
+        ring.submit_and_wait_with_args(4, &arg).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(ring.cq_ready(), 1);
+    }
+
+    #[test]
+    pub fn the_kernel_honours_the_timeout() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let arg = GetEventsArg::new().timeout(Duration::from_millis(10));
+
+        let error = ring.submit_and_wait_with_args(1, &arg).unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::ETIME));
+    }
+}
+
+#[cfg(test)]
+mod when_waiting_with_registered_arguments {
+    use crate::{
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+        syscalls::{mock::MockSyscalls, mock::SyscallRecord, GetEventsArg, IoUringEnterFlags},
+    };
+    use linux_raw_sys::io_uring::io_uring_reg_wait;
+    use std::{
+        io::ErrorKind,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn only_the_slot_offset_is_passed() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, IoUringParams::default(), syscalls.clone())
+                .unwrap();
+        assert_eq!(
+            ring.submit_and_wait_registered(1, 0).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        ring.register_wait_region(2).unwrap();
+        ring.set_wait_arg(1, &GetEventsArg::new().deadline(Duration::from_secs(1)))
+            .unwrap();
+        ring.next_sqe().unwrap().nop();
+        ring.submit_and_wait_registered(1, 1).unwrap();
+
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 1,
+                min_complete: 1,
+                flags: IoUringEnterFlags::IoRingEnterGetEvents
+                    | IoUringEnterFlags::IoRingEnterExtArg
+                    | IoUringEnterFlags::IoRingEnterExtArgReg
+                    | IoUringEnterFlags::IoRingEnterAbsTimer,
+                arg_size: size_of::<io_uring_reg_wait>(),
+            })
+        );
+    }
+
+    #[test]
+    pub fn the_kernel_honours_the_timeout_of_the_slot() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::RDisabled.bits(),
+            ..Default::default()
+        };
+        let mut ring = IoUring::initialize(4, params).unwrap();
+        match ring.register_wait_region(4) {
+            Err(error) if error.raw_os_error() == Some(libc::EINVAL) => return,
+            result => result.unwrap(),
+        }
+        ring.enable_rings().unwrap();
+        ring.set_wait_arg(3, &GetEventsArg::new().timeout(Duration::from_millis(5)))
+            .unwrap();
+
+        let started = Instant::now();
+        let error = ring.submit_and_wait_registered(1, 3).unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::ETIME));
+        assert!(started.elapsed() >= Duration::from_millis(5));
+    }
+}
+
+#[cfg(test)]
+mod when_submitting_to_an_sqpoll_ring {
+    use crate::{
+        cqe::Completions,
+        io_uring::{atomic_u32, IoUring, IoUringParams, IoUringSetupFlags},
+        syscalls::{mock::MockSyscalls, mock::SyscallRecord, IoUringEnterFlags},
+    };
+    use linux_raw_sys::io_uring::IORING_SQ_NEED_WAKEUP;
+    use std::sync::{atomic::Ordering, Arc};
+
+    fn sqpoll_params() -> IoUringParams {
+        IoUringParams {
+            flags: IoUringSetupFlags::SqPool.bits(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn an_awake_poller_needs_no_syscall() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, sqpoll_params(), syscalls.clone()).unwrap();
+        ring.next_sqe().unwrap().nop();
+
+        assert_eq!(ring.submit().unwrap(), 1);
+        assert!(!syscalls
+            .records()
+            .iter()
+            .any(|record| matches!(record, SyscallRecord::Enter { .. })));
+    }
+
+    #[test]
+    pub fn an_idle_poller_is_woken_up() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let mut ring =
+            IoUring::initialize_with_syscalls(4, sqpoll_params(), syscalls.clone()).unwrap();
+        unsafe { atomic_u32(ring.send_queue.flags) }
+            .store(IORING_SQ_NEED_WAKEUP, Ordering::Relaxed);
+        ring.next_sqe().unwrap().nop();
+
+        assert!(ring.sq_need_wakeup());
+        ring.submit().unwrap();
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 1,
+                min_complete: 0,
+                flags: IoUringEnterFlags::IoRingEnterSqWakeup,
+                arg_size: 0
+            })
+        );
+    }
+
+    #[test]
+    pub fn nops_round_trip_through_the_poller() {
+        let mut ring = IoUring::initialize(4, sqpoll_params()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(3);
+
+        ring.submit().unwrap();
+
+        assert_eq!(ring.wait_completion().unwrap().user_data, 3);
+        assert!(ring.next_completion().is_none());
+    }
+}
+
+#[cfg(test)]
+mod when_completions_wait_in_task_work {
+    use crate::{
+        cqe::Completions,
+        io_uring::{atomic_u32, IoUring, IoUringParams, IoUringSetupFlags},
+        syscalls::{mock::MockSyscalls, mock::SyscallRecord, IoUringEnterFlags},
+    };
+    use linux_raw_sys::io_uring::IORING_SQ_TASKRUN;
+    use std::sync::{atomic::Ordering, Arc};
+
+    fn taskrun_params() -> IoUringParams {
+        IoUringParams {
+            flags: (IoUringSetupFlags::DeferTaskRun
+                | IoUringSetupFlags::SingleIssuer
+                | IoUringSetupFlags::TaskRunFlag)
+                .bits(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn peeking_an_empty_ring_runs_the_task_work() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let ring =
+            IoUring::initialize_with_syscalls(4, taskrun_params(), syscalls.clone()).unwrap();
+        unsafe { atomic_u32(ring.send_queue.flags) }.store(IORING_SQ_TASKRUN, Ordering::Relaxed);
+
+        assert!(ring.peek_completion().is_none());
+        assert_eq!(
+            syscalls.records().last(),
+            Some(&SyscallRecord::Enter {
+                submit: 0,
+                min_complete: 0,
+                flags: IoUringEnterFlags::IoRingEnterGetEvents,
+                arg_size: 0
+            })
+        );
+    }
+
+    #[test]
+    pub fn no_syscall_is_made_without_pending_task_work() {
+        let syscalls = Arc::new(MockSyscalls::new());
+        let ring =
+            IoUring::initialize_with_syscalls(4, taskrun_params(), syscalls.clone()).unwrap();
+
+        assert_eq!(ring.cq_ready(), 0);
+        assert!(!syscalls
+            .records()
+            .iter()
+            .any(|record| matches!(record, SyscallRecord::Enter { .. })));
+    }
+
+    #[test]
+    pub fn deferred_completions_become_visible_when_peeking() {
+        let mut ring = IoUring::initialize(4, taskrun_params()).unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut buffer = [0u8; 4];
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], buffer.as_mut_ptr(), 4, u64::MAX)
+        }
+        .user_data(8);
+        ring.submit().unwrap();
+        unsafe { libc::write(fds[1], b"ping".as_ptr() as *const _, 4) };
+
+        assert!(ring.task_work_pending());
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.user_data, 8);
+        assert_eq!(completion.result, 4);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_resizing_the_rings {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+    };
+    use std::io::ErrorKind;
+
+    fn taskrun_params() -> IoUringParams {
+        IoUringParams {
+            flags: (IoUringSetupFlags::DeferTaskRun | IoUringSetupFlags::SingleIssuer).bits(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn pending_completions_survive_a_bigger_ring() {
+        let mut ring = IoUring::initialize(4, taskrun_params()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.submit_and_wait(1).unwrap();
+
+        match ring.resize(16, 64) {
+            Err(error) if error.raw_os_error() == Some(libc::EINVAL) => return,
+            result => result.unwrap(),
+        }
+
+        assert_eq!(ring.sq_space_left(), 16);
+        assert_eq!(ring.next_completion().unwrap().user_data, 1);
+        for user_data in 0..16 {
+            ring.next_sqe().unwrap().nop().user_data(user_data);
+        }
+        ring.submit_and_wait(16).unwrap();
+        assert_eq!(ring.cq_ready(), 16);
+    }
+
+    #[test]
+    pub fn entries_held_back_by_the_in_flight_limit_are_kept() {
+        let mut ring = IoUring::initialize(4, taskrun_params()).unwrap();
+        ring.set_in_flight_limit(Some(1)).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.next_sqe().unwrap().nop().user_data(2);
+
+        assert_eq!(
+            ring.resize(16, 32).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().user_data, 1);
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().user_data, 2);
+    }
+
+    #[test]
+    pub fn only_defer_taskrun_rings_can_be_resized() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        assert_eq!(
+            ring.resize(16, 32).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+    }
+}
+
+#[cfg(test)]
+mod when_dumping_the_state {
+    use crate::io_uring::{IoUring, IoUringParams};
+
+    #[test]
+    pub fn queued_and_in_flight_entries_show_up() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.submit().unwrap();
+        ring.next_sqe().unwrap().nop().user_data(2);
+
+        let dump = ring.dump();
+
+        assert!(dump.contains("sqe_head: 1"));
+        assert!(dump.contains("sqe_tail: 2"));
+        assert!(dump.contains("in_flight: 1"));
+        assert!(dump.contains("entries: 4"));
+        assert!(dump.contains("SingleMmap"));
+    }
+}
+
+#[cfg(test)]
+mod when_inspecting_the_queues {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        opcode::IoUringOperation,
+        sqe::FsyncFlags,
+    };
+
+    #[test]
+    pub fn prepared_entries_are_listed_until_submitted() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.next_sqe()
+            .unwrap()
+            .fsync(-1, FsyncFlags::empty())
+            .user_data(2);
+
+        let pending: Vec<_> = ring.pending_submissions().collect();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].operation(), Some(IoUringOperation::Nop));
+        assert_eq!(pending[1].operation(), Some(IoUringOperation::Fsync));
+        assert_eq!(pending[1].fd, -1);
+        assert_eq!(pending[1].user_data, 2);
+
+        ring.submit_and_wait(2).unwrap();
+        assert_eq!(ring.pending_submissions().count(), 0);
+    }
+
+    #[test]
+    pub fn prepared_entries_are_listed_across_the_wraparound_of_the_indexes() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.send_queue.sqe_head = u32::MAX;
+        ring.send_queue.sqe_tail = 1;
+
+        let pending: Vec<_> = ring.pending_submissions().collect();
+
+        assert_eq!(pending.len(), 2);
+        assert!(pending
+            .iter()
+            .all(|record| record.operation() == Some(IoUringOperation::Nop)));
+    }
+
+    #[test]
+    pub fn completions_are_listed_without_being_reaped() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.next_sqe().unwrap().nop().user_data(2);
+        ring.submit_and_wait(2).unwrap();
+
+        let user_data: Vec<_> = ring
+            .unreaped_completions()
+            .map(|completion| completion.user_data)
+            .collect();
+
+        assert_eq!(user_data, vec![1, 2]);
+        assert_eq!(ring.cq_ready(), 2);
+    }
+}
+
+#[cfg(test)]
+mod when_passing_fixed_files_between_rings {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        sqe::IoUringSqeFlags,
+        testing::TempFile,
+    };
+    use libc::{AT_FDCWD, O_RDONLY};
+    use std::ffi::CString;
+
+    #[test]
+    pub fn the_target_ring_reads_through_its_new_slot() {
+        let file = TempFile::new(b"moved").unwrap();
+        let c_path = CString::new(file.path().to_str().unwrap()).unwrap();
+
+        let mut acceptor = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut worker = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        acceptor.register_files_sparse(2).unwrap();
+        worker.register_files_sparse(2).unwrap();
+        acceptor
+            .op(|sqe| unsafe { sqe.openat(AT_FDCWD, c_path.as_ptr(), O_RDONLY, 0) }.file_slot(0))
+            .run()
+            .unwrap();
+
+        let slot = acceptor.send_fixed_file(&worker, 0, Some(1), 77).unwrap();
+        assert_eq!(slot, 1);
+
+        worker.submit_and_wait(1).unwrap();
+        let completion = worker.next_completion().unwrap();
+        assert_eq!(completion.user_data, 77);
+        assert_eq!(completion.result, 0);
+
+        let mut buf = [0u8; 5];
+        let read = worker
+            .op(|sqe| {
+                unsafe { sqe.read(1, buf.as_mut_ptr(), 5, 0) }.flags(IoUringSqeFlags::FixedFile)
+            })
+            .run()
+            .unwrap();
+        assert_eq!(read, 5);
+        assert_eq!(&buf, b"moved");
+    }
+
+    #[test]
+    pub fn a_free_slot_is_picked_without_a_target_slot() {
+        let mut acceptor = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let worker = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        acceptor.register_files_sparse(1).unwrap();
+        worker.register_files_sparse(4).unwrap();
+        acceptor
+            .op(|sqe| {
+                unsafe { sqe.openat(AT_FDCWD, c"/dev/null".as_ptr(), O_RDONLY, 0) }.file_slot(0)
+            })
+            .run()
+            .unwrap();
+
+        let slot = acceptor.send_fixed_file(&worker, 0, None, 1).unwrap();
+
+        assert!(slot < 4);
+    }
+}
+
+#[cfg(test)]
+mod when_skipping_successful_completions {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::EBADF;
+    use std::{
+        io::{pipe, Write},
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    pub fn a_successful_entry_posts_nothing_and_is_not_in_flight() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        assert!(ring.supports_cqe_skip());
+
+        ring.next_sqe().unwrap().nop().user_data(1).skip_success();
+        ring.next_sqe().unwrap().nop().user_data(2);
+        ring.submit_and_wait(1).unwrap();
+
+        assert_eq!(ring.next_completion().unwrap().user_data, 2);
+        assert!(ring.next_completion().is_none());
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_failure_does_not_count_off_another_request() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, mut writer) = pipe().unwrap();
+        let mut buf = [0u8; 1];
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), buf.as_mut_ptr(), 1, 0)
+        }
+        .user_data(1);
+        unsafe { ring.next_sqe().unwrap().write(-1, buf.as_ptr(), 1, 0) }
+            .user_data(2)
+            .skip_success();
+        ring.submit_and_wait(1).unwrap();
+
+        let failure = ring.next_completion().unwrap();
+        assert_eq!((failure.user_data, failure.result), (2, -EBADF));
+        assert_eq!(ring.in_flight(), 1);
+
+        writer.write_all(b"!").unwrap();
+        ring.submit_and_wait(1).unwrap();
+        assert_eq!(ring.next_completion().unwrap().user_data, 1);
+        assert_eq!(ring.in_flight(), 0);
+    }
+}
+
+#[cfg(test)]
+mod when_waiting_for_submission_queue_space {
+    use crate::io_uring::{IoUring, IoUringParams, IoUringSetupFlags};
+    use std::io::ErrorKind;
+
+    #[test]
+    pub fn a_full_queue_is_submitted_to_make_room() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        while let Some(sqe) = ring.next_sqe() {
+            sqe.nop();
+        }
+
+        ring.wait_sq_space(4).unwrap();
+
+        assert_eq!(ring.sq_space_left(), 4);
+        assert_eq!(ring.in_flight(), 4);
+    }
+
+    #[test]
+    pub fn the_poll_thread_frees_the_queue_as_it_goes() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::SqPool.bits(),
+            sq_thread_idle: 10,
+            ..Default::default()
+        };
+        let Ok(mut ring) = IoUring::initialize(4, params) else {
+            return;
+        };
+        while let Some(sqe) = ring.next_sqe() {
+            sqe.nop();
+        }
+
+        ring.wait_sq_space(4).unwrap();
+
+        assert_eq!(ring.sq_space_left(), 4);
+    }
+
+    #[test]
+    pub fn more_than_the_queue_holds_is_refused() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = ring.wait_sq_space(5).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[cfg(test)]
+mod when_limiting_requests_in_flight {
+    use crate::{
+        builder::IoUringBuilder,
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        sqe::IoUringSqeFlags,
+    };
+    use std::io::ErrorKind;
+
+    #[test]
+    pub fn entries_over_the_limit_wait_for_completions() {
+        let mut ring = IoUringBuilder::new().in_flight_limit(2).build(8).unwrap();
+        for user_data in 0..4 {
+            ring.next_sqe().unwrap().nop().user_data(user_data);
+        }
+
+        ring.submit_and_wait(2).unwrap();
+        assert_eq!(ring.in_flight(), 2);
+        assert_eq!(ring.sq_space_left(), 6);
+        assert_eq!(ring.next_completion().unwrap().user_data, 0);
+        assert_eq!(ring.next_completion().unwrap().user_data, 1);
+        assert!(ring.next_completion().is_none());
+
+        ring.submit_and_wait(2).unwrap();
+        assert_eq!(ring.sq_space_left(), 8);
+        assert_eq!(ring.next_completion().unwrap().user_data, 2);
+        assert_eq!(ring.next_completion().unwrap().user_data, 3);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_link_chain_is_not_split() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.set_in_flight_limit(Some(1)).unwrap();
+
+        ring.next_sqe()
+            .unwrap()
+            .nop()
+            .user_data(1)
+            .flags(IoUringSqeFlags::IoLink);
+        ring.next_sqe().unwrap().nop().user_data(2);
+        ring.next_sqe().unwrap().nop().user_data(3);
+        ring.submit_and_wait(2).unwrap();
+
+        assert_eq!(ring.in_flight(), 2);
+        assert_eq!(ring.sq_space_left(), 3);
+        assert_eq!(
+            ring.wait_sq_space(4).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    pub fn a_limit_of_zero_is_refused() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = ring.set_in_flight_limit(Some(0)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(ring.in_flight_limit(), None);
+    }
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod when_streaming_completions {
+    use crate::io_uring::{IoUring, IoUringParams};
+    use futures::{executor::block_on, StreamExt};
+    use libc::POLLIN;
+    use std::{
+        io::{pipe, Write},
+        os::fd::AsRawFd,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn pending_requests_are_submitted_and_their_completions_streamed() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        for user_data in 1..=3 {
+            ring.next_sqe().unwrap().nop().user_data(user_data);
+        }
+
+        let user_data: Vec<u64> = block_on(
+            ring.completions()
+                .take(3)
+                .map(|completion| completion.unwrap().user_data)
+                .collect(),
+        );
+
+        assert_eq!(user_data, [1, 2, 3]);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn the_stream_waits_for_completions_still_to_come() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, mut writer) = pipe().unwrap();
+        ring.next_sqe()
+            .unwrap()
+            .poll_add(reader.as_raw_fd(), POLLIN as u32)
+            .user_data(7);
+
+        let notifier = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            writer.write_all(b"!").unwrap();
+            writer
+        });
+        let completion = block_on(ring.completions().next()).unwrap().unwrap();
+        drop(notifier.join().unwrap());
+
+        assert_eq!(completion.user_data, 7);
+        assert_eq!(ring.in_flight(), 0);
+    }
+}
+
+#[cfg(test)]
+mod when_shutting_down {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::ECANCELED;
+    use std::time::Duration;
+
+    #[test]
+    pub fn requests_are_counted_until_their_completion_is_reaped() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        ring.next_sqe().unwrap().nop();
+        ring.next_sqe().unwrap().nop();
+        ring.submit_and_wait(2).unwrap();
+        assert_eq!(ring.in_flight(), 2);
+
+        ring.next_completion().unwrap();
+        ring.next_completion().unwrap();
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn pending_requests_are_cancelled_and_kept_for_the_caller() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        let mut buffer = [0u8; 8];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], buffer.as_mut_ptr(), 8, 0)
+                .user_data(1);
+        }
+        ring.next_sqe().unwrap().nop().user_data(2);
+
+        let abandoned = ring.shutdown(Duration::from_secs(1)).unwrap();
+
+        assert_eq!(abandoned, 0);
+        assert!(ring.is_shut_down());
+        assert!(ring.next_sqe().is_none());
+        let mut completions = [
+            ring.next_completion().unwrap(),
+            ring.next_completion().unwrap(),
+        ];
+        completions.sort_by_key(|completion| completion.user_data);
+        assert_eq!(completions[0].result, -ECANCELED);
+        assert_eq!(completions[1].result, 0);
+        assert!(ring.next_completion().is_none());
+
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_owned_by_a_single_issuer {
+    use crate::io_uring::{IoUring, IoUringParams, IoUringSetupFlags};
+    use std::{io::ErrorKind, thread};
+
+    fn deferred_taskrun() -> IoUringParams {
+        IoUringParams {
+            flags: (IoUringSetupFlags::SingleIssuer | IoUringSetupFlags::DeferTaskRun).bits(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn the_creating_thread_is_the_issuer() {
+        let ring = IoUring::initialize(4, deferred_taskrun()).unwrap();
+        let shared = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        assert_eq!(ring.issuer(), Some(thread::current().id()));
+        assert_eq!(shared.issuer(), None);
+    }
+
+    #[test]
+    pub fn a_disabled_ring_belongs_to_the_thread_that_enables_it() {
+        let mut params = deferred_taskrun();
+        params.flags |= IoUringSetupFlags::RDisabled.bits();
+        let ring = IoUring::initialize(4, params).unwrap();
+        assert_eq!(ring.issuer(), None);
+
+        ring.enable_rings().unwrap();
+
+        assert_eq!(ring.issuer(), Some(thread::current().id()));
+    }
+
+    #[test]
+    pub fn other_threads_get_a_clear_error_instead_of_eexist() {
+        let (mut publisher, producer) = IoUring::initialize(4, deferred_taskrun())
+            .unwrap()
+            .into_producers();
+        producer.push(|sqe| sqe.nop().user_data(5)).unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let error = publisher.submit_and_wait(1).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::Unsupported);
+            });
+        });
+
+        publisher.submit_and_wait(1).unwrap();
+        assert_eq!(publisher.next_completion().unwrap().user_data, 5);
+    }
+}
+
+#[cfg(test)]
+mod when_moving_a_ring_between_threads {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+        submitter::Submitter,
+    };
+    use std::{io::ErrorKind, thread};
+
+    fn assert_send<T: Send>() {}
+    fn assert_send_and_sync<T: Send + Sync>() {}
+
+    #[test]
+    pub fn the_ring_goes_on_in_the_other_thread() {
+        assert_send::<IoUring<'static>>();
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(5);
+
+        let mut ring = thread::spawn(move || {
+            ring.submit_and_wait(1).unwrap();
+            ring
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(ring.next_completion().unwrap().user_data, 5);
+    }
+
+    #[test]
+    pub fn handles_that_share_the_ring_go_to_any_thread() {
+        assert_send_and_sync::<Submitter<'static>>();
+        let submitter = IoUring::initialize(4, IoUringParams::default())
+            .unwrap()
+            .into_submitter();
+
+        let other = submitter.clone();
+        thread::spawn(move || other.push(|sqe| sqe.nop().user_data(7)).unwrap())
+            .join()
+            .unwrap();
+        submitter.submit_and_wait(1).unwrap();
+
+        assert_eq!(submitter.next_completion().unwrap().user_data, 7);
+    }
+
+    #[test]
+    pub fn a_single_issuer_ring_stays_with_its_issuer() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::SingleIssuer.bits(),
+            ..Default::default()
+        };
+        let mut ring = IoUring::initialize(4, params).unwrap();
+
+        let error = thread::spawn(move || ring.submit().unwrap_err())
+            .join()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
+
+#[cfg(test)]
+mod when_registering_the_ring_fd {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{io::ErrorKind, thread};
+
+    #[test]
+    pub fn the_ring_is_entered_through_its_index() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_ring_fd().unwrap();
+        ring.next_sqe().unwrap().nop().user_data(3);
+
+        ring.submit_and_wait(1).unwrap();
+
+        assert!(ring.registered_ring.is_some());
+        assert_eq!(ring.next_completion().unwrap().user_data, 3);
+        assert_eq!(
+            ring.register_ring_fd().unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+    }
+
+    #[test]
+    pub fn other_threads_enter_through_the_fd() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.register_ring_fd().unwrap();
+        ring.next_sqe().unwrap().nop().user_data(4);
+
+        let mut ring = thread::spawn(move || {
+            ring.submit_and_wait(1).unwrap();
+            assert_eq!(
+                ring.unregister_ring_fd().unwrap_err().kind(),
+                ErrorKind::Unsupported
+            );
+            ring
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(ring.next_completion().unwrap().user_data, 4);
+        ring.unregister_ring_fd().unwrap();
+        assert!(ring.registered_ring.is_none());
+    }
+}
+
+#[cfg(test)]
+mod when_dropping_a_ring_with_requests_in_flight {
+    use crate::io_uring::{IoUring, IoUringParams, DROP_DRAIN_TIMEOUT};
+    use libc::{pipe2, O_NONBLOCK};
+    use std::time::Instant;
+
+    fn pipe() -> [i32; 2] {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe2(fds.as_mut_ptr(), O_NONBLOCK) }, 0);
+        fds
+    }
+
+    #[test]
+    pub fn they_are_cancelled_before_the_ring_goes_away() {
+        let fds = pipe();
+        let mut buffer = [0u8; 8];
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], buffer.as_mut_ptr(), 8, 0)
+        };
+        ring.submit().unwrap();
+
+        let start = Instant::now();
+        drop(ring);
+
+        assert!(start.elapsed() < DROP_DRAIN_TIMEOUT);
+        assert_eq!(
+            unsafe { libc::write(fds[1], b"late".as_ptr().cast(), 4) },
+            4
+        );
+        let mut late = [0u8; 4];
+        assert_eq!(
+            unsafe { libc::read(fds[0], late.as_mut_ptr().cast(), 4) },
+            4
+        );
+        assert_eq!(buffer, [0; 8]);
+    }
+
+    #[test]
+    pub fn entries_never_submitted_are_discarded() {
+        let fds = pipe();
+        let mut buffer = [0u8; 8];
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], buffer.as_mut_ptr(), 8, 0)
+        };
+        ring.submit().unwrap();
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .write(fds[1], b"lost".as_ptr(), 4, 0)
+        };
+
+        drop(ring);
+
+        let mut written = [0u8; 4];
+        assert_eq!(
+            unsafe { libc::read(fds[0], written.as_mut_ptr().cast(), 4) },
+            -1
+        );
+    }
+}
+
+#[cfg(test)]
+mod when_abandoning_requests {
+    use crate::{cqe::Completions, testing::ring_with_pipe};
+    use std::{io::Write, os::fd::AsRawFd};
+
+    #[test]
+    pub fn a_request_in_flight_is_cancelled_and_reaped() {
+        let (mut ring, reader, mut writer) = ring_with_pipe().unwrap();
+        let mut buf = [0u8; 4];
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), buf.as_mut_ptr(), 4, 0)
+                .user_data(7)
+        };
+        ring.submit().unwrap();
+
+        ring.abandon(&[7]);
+
+        assert_eq!(ring.in_flight(), 0);
+        writer.write_all(b"late").unwrap();
+        ring.submit_and_wait(0).unwrap();
+        assert!(ring.next_completion().is_none());
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[test]
+    pub fn an_entry_never_submitted_becomes_a_nop() {
+        let (mut ring, reader, mut writer) = ring_with_pipe().unwrap();
+        let mut buf = [0u8; 4];
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), buf.as_mut_ptr(), 4, 0)
+                .user_data(7)
+        };
+        ring.next_sqe().unwrap().nop().user_data(8);
+
+        ring.abandon(&[7]);
+        writer.write_all(b"late").unwrap();
+
+        assert_eq!(ring.wait_for_completion(8).unwrap().result, 0);
+        assert_eq!(ring.in_flight(), 0);
+        assert!(ring.next_completion().is_none());
+        assert_eq!(buf, [0; 4]);
     }
 }
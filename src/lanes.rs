@@ -0,0 +1,178 @@
+use crate::{
+    entry::{CqeEntry, SqeEntry},
+    io_uring::IoUring,
+    sqe::{IoUringSqeFlags, Sqe},
+};
+use std::{collections::VecDeque, io::Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    High,
+    Normal,
+    Low,
+}
+
+impl Lane {
+    fn index(self) -> usize {
+        match self {
+            Lane::High => 0,
+            Lane::Normal => 1,
+            Lane::Low => 2,
+        }
+    }
+}
+
+/*
+ * Entries prepared ahead of the submission queue, in three lanes. Each
+ * dispatch moves them into the queue high lane first, so a heartbeat
+ * pushed after a pile of bulk reads still goes out with the next submit,
+ * while the reads wait for room. Entries of a lane keep their order and a
+ * link chain is moved whole or not at all.
+ */
+#[derive(Debug, Default)]
+pub struct Lanes {
+    lanes: [VecDeque<Vec<u8>>; 3],
+}
+
+impl Lanes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+     * Prepares an entry with `prepare` and queues it in `lane`. The entry
+     * gets the default priority of `ring`, the ring it has to be dispatched
+     * to.
+     */
+    pub fn push<S: SqeEntry, C: CqeEntry, F>(
+        &mut self,
+        ring: &IoUring<'_, S, C>,
+        lane: Lane,
+        prepare: F,
+    ) where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        self.lanes[lane.index()].push_back(ring.detached_entry(prepare));
+    }
+
+    pub fn queued(&self, lane: Lane) -> usize {
+        self.lanes[lane.index()].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /*
+     * Moves as many entries as fit into the submission queue of `ring`,
+     * high lane first, returns how many. A lower lane only gets room once
+     * the higher ones are empty.
+     */
+    pub fn dispatch<S: SqeEntry, C: CqeEntry>(&mut self, ring: &mut IoUring<'_, S, C>) -> usize {
+        let mut moved = 0;
+
+        for lane in self.lanes.iter_mut() {
+            while let Some(chain) = chain_len(lane) {
+                if chain > ring.sq_space_left() as usize {
+                    return moved;
+                }
+                for _ in 0..chain {
+                    if ring.next_sqe().is_none() {
+                        return moved;
+                    }
+                    if let Some(entry) = lane.pop_front() {
+                        ring.send_queue.overwrite_last_entry(&entry);
+                    }
+                    moved += 1;
+                }
+            }
+        }
+
+        moved
+    }
+
+    /*
+     * Dispatches and submits, one submit cycle.
+     */
+    pub fn submit<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<usize> {
+        self.dispatch(ring);
+        ring.submit()
+    }
+}
+
+/*
+ * Entries from the front of `lane` up to the end of the first link chain,
+ * None for an empty lane.
+ */
+fn chain_len(lane: &VecDeque<Vec<u8>>) -> Option<usize> {
+    let links = (IoUringSqeFlags::IoLink | IoUringSqeFlags::IoHardLink).bits();
+
+    if lane.is_empty() {
+        return None;
+    }
+    let linked = lane
+        .iter()
+        .take_while(|entry| entry[1] & links != 0)
+        .count();
+
+    Some((linked + 1).min(lane.len()))
+}
+
+#[cfg(test)]
+mod when_submitting_through_lanes {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        lanes::{Lane, Lanes},
+        sqe::IoUringSqeFlags,
+    };
+
+    #[test]
+    pub fn higher_lanes_go_first() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut lanes = Lanes::new();
+        for user_data in 0..3 {
+            lanes.push(&ring, Lane::Low, |sqe| sqe.nop().user_data(user_data));
+        }
+        lanes.push(&ring, Lane::Normal, |sqe| sqe.nop().user_data(10));
+        lanes.push(&ring, Lane::High, |sqe| sqe.nop().user_data(20));
+
+        lanes.submit(&mut ring).unwrap();
+        assert_eq!(lanes.queued(Lane::Low), 1);
+        ring.submit_and_wait(4).unwrap();
+
+        let order: Vec<u64> = std::iter::from_fn(|| ring.next_completion())
+            .map(|completion| completion.user_data)
+            .collect();
+        assert_eq!(order, [20, 10, 0, 1]);
+    }
+
+    #[test]
+    pub fn a_link_chain_waits_until_it_fits_whole() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut lanes = Lanes::new();
+        lanes.push(&ring, Lane::High, |sqe| sqe.nop().user_data(1));
+        lanes.push(&ring, Lane::Normal, |sqe| {
+            sqe.nop().user_data(2).flags(IoUringSqeFlags::IoLink)
+        });
+        lanes.push(&ring, Lane::Normal, |sqe| {
+            sqe.nop().user_data(3).flags(IoUringSqeFlags::IoLink)
+        });
+        lanes.push(&ring, Lane::Normal, |sqe| sqe.nop().user_data(4));
+        ring.next_sqe().unwrap().nop().user_data(0);
+
+        assert_eq!(lanes.dispatch(&mut ring), 1);
+        assert_eq!(lanes.queued(Lane::Normal), 3);
+
+        ring.submit_and_wait(2).unwrap();
+        assert_eq!(lanes.dispatch(&mut ring), 3);
+        assert!(lanes.is_empty());
+    }
+}
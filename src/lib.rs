@@ -1,4 +1,62 @@
+mod adopt;
+pub mod affinity;
 mod arch;
-mod io_uring;
+mod atomic;
+pub mod buf_pool;
+pub mod buf_ring;
+pub mod buffered;
+pub mod builder;
+pub mod capabilities;
+pub mod chain;
+pub mod cmsg;
+pub mod cqe;
+#[cfg(feature = "futures")]
+pub mod datagram;
+pub mod deadline;
+pub mod dispatch;
+pub mod entry;
+#[cfg(feature = "executor")]
+pub mod executor;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault;
+pub mod ffi;
+pub mod fixed_buf;
+pub mod fixed_file;
+pub mod fs;
+pub mod io_uring;
+pub mod lanes;
+pub mod local;
+pub mod memory;
 mod mmap;
+pub mod net;
+pub mod nvme;
+pub mod op;
+pub mod opcode;
+pub mod owned_buf;
+mod payload;
+pub mod peer;
+pub mod probe;
+pub mod process;
+pub mod producer;
+pub mod profile;
+pub mod readiness;
+pub mod retry;
+pub mod sandbox;
+pub mod scope;
+#[cfg(feature = "async-io")]
+pub mod smol;
+mod spans;
+pub mod sqe;
+pub mod stats;
+pub mod submitter;
+pub mod sync;
 mod syscalls;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "futures")]
+pub mod time;
+pub mod trace;
+pub mod unpark;
+mod watcher;
+
+pub use syscalls::{Clock, GetEventsArg, IoUringEnterFlags, IoUringOpCode};
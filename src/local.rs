@@ -0,0 +1,128 @@
+use crate::{
+    cqe::{Completion, Completions},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::{IoUring, IoUringSetupFlags},
+    sqe::Sqe,
+};
+use std::io::{self, ErrorKind, Result};
+
+/*
+ * A ring set up with DEFER_TASKRUN, borrowed by the one thread that submits
+ * to it and reaps it, for tight submit and reap loops. Such a ring only
+ * posts completions inside io_uring_enter of its issuer, and without SQPOLL
+ * the kernel only reads the submission tail and moves the heads in there as
+ * well, so the syscall orders every access to the shared indexes and they
+ * go Relaxed while the ring is borrowed, no acquire and release pairs.
+ *
+ * The borrow is what keeps that sound: nothing else touches the ring until
+ * it ends, so the ring only offers what a submit and reap loop needs.
+ */
+pub struct LocalRing<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> LocalRing<'r, 'a, S, C> {
+    /*
+     * InvalidInput unless the ring was set up with DEFER_TASKRUN, which
+     * implies SINGLE_ISSUER and rules out SQPOLL.
+     */
+    pub fn new(ring: &'r mut IoUring<'a, S, C>) -> Result<Self> {
+        if !ring.setup_flags().contains(IoUringSetupFlags::DeferTaskRun) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "only a DEFER_TASKRUN ring posts completions in io_uring_enter alone",
+            ));
+        }
+
+        ring.send_queue.relaxed = true;
+        ring.complete_queue.relaxed = true;
+
+        Ok(LocalRing { ring })
+    }
+
+    pub fn next_sqe(&mut self) -> Option<Sqe<'_>> {
+        self.ring.next_sqe()
+    }
+
+    pub fn submit(&mut self) -> Result<usize> {
+        self.ring.submit()
+    }
+
+    pub fn submit_and_wait(&mut self, wait_nr: u32) -> Result<usize> {
+        self.ring.submit_and_wait(wait_nr)
+    }
+
+    pub fn cq_ready(&self) -> u32 {
+        self.ring.cq_ready()
+    }
+
+    pub fn ring(&self) -> &IoUring<'a, S, C> {
+        self.ring
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Completions for LocalRing<'_, '_, S, C> {
+    fn next_completion(&mut self) -> Option<Completion> {
+        self.ring.next_completion()
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Drop for LocalRing<'_, '_, S, C> {
+    fn drop(&mut self) {
+        self.ring.send_queue.relaxed = false;
+        self.ring.complete_queue.relaxed = false;
+    }
+}
+
+#[cfg(test)]
+mod when_borrowing_a_ring_locally {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+        local::LocalRing,
+    };
+    use std::io::ErrorKind;
+
+    fn deferred() -> IoUring<'static> {
+        let params = IoUringParams {
+            flags: (IoUringSetupFlags::SingleIssuer | IoUringSetupFlags::DeferTaskRun).bits(),
+            ..Default::default()
+        };
+        IoUring::initialize(4, params).unwrap()
+    }
+
+    #[test]
+    pub fn entries_go_round_the_ring_with_relaxed_indexes() {
+        let mut ring = deferred();
+        {
+            let mut local = LocalRing::new(&mut ring).unwrap();
+            for round in 0..10 {
+                for user_data in 0..4 {
+                    local
+                        .next_sqe()
+                        .unwrap()
+                        .nop()
+                        .user_data(round * 4 + user_data);
+                }
+                local.submit_and_wait(4).unwrap();
+
+                let reaped: Vec<u64> = std::iter::from_fn(|| local.next_completion())
+                    .map(|completion| completion.user_data)
+                    .collect();
+                assert_eq!(reaped, (round * 4..round * 4 + 4).collect::<Vec<_>>());
+            }
+        }
+
+        assert!(!ring.send_queue.relaxed);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn rings_that_post_completions_anywhere_are_refused() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = LocalRing::new(&mut ring).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
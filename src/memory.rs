@@ -0,0 +1,149 @@
+use crate::io_uring::{IoUringError, IoUringParams, IoUringSetupFlags};
+use libc::{sysconf, _SC_PAGESIZE};
+use linux_raw_sys::io_uring::{io_uring_cqe, io_uring_sqe};
+use std::{io::Result, mem::size_of};
+
+pub(crate) const KERNEL_MAX_ENTRIES: u32 = 32768;
+pub(crate) const KERNEL_MAX_CQ_ENTRIES: u32 = 2 * KERNEL_MAX_ENTRIES;
+
+/*
+ * Size of the ring header the kernel places in front of the cqes.
+ */
+const KERNEL_RING_SIZE: usize = 64;
+
+pub(crate) fn page_size() -> usize {
+    unsafe { sysconf(_SC_PAGESIZE) as usize }
+}
+
+/*
+ * Mirrors the kernel rules for turning the requested number of entries into
+ * the real sq and cq sizes: entries are rounded up to a power of two, clamped
+ * when asked to and the cq defaults to twice the sq.
+ */
+pub(crate) fn queue_entries(entries: u32, params: &IoUringParams) -> Result<(u32, u32)> {
+    let flags = IoUringSetupFlags::from_bits_retain(params.flags);
+
+    if entries == 0 {
+        return Err(IoUringError::InvalidArgument.into());
+    }
+
+    let mut sq_entries = entries;
+    if sq_entries > KERNEL_MAX_ENTRIES {
+        if !flags.contains(IoUringSetupFlags::Clamp) {
+            return Err(IoUringError::InvalidArgument.into());
+        }
+        sq_entries = KERNEL_MAX_ENTRIES;
+    }
+    let sq_entries = sq_entries.next_power_of_two();
+
+    let cq_entries = if flags.contains(IoUringSetupFlags::CqSize) {
+        let mut cq_entries = params.cq_entries;
+        if cq_entries == 0 {
+            return Err(IoUringError::InvalidArgument.into());
+        }
+        if cq_entries > KERNEL_MAX_CQ_ENTRIES {
+            if !flags.contains(IoUringSetupFlags::Clamp) {
+                return Err(IoUringError::InvalidArgument.into());
+            }
+            cq_entries = KERNEL_MAX_CQ_ENTRIES;
+        }
+        let cq_entries = cq_entries.next_power_of_two();
+        if cq_entries < sq_entries {
+            return Err(IoUringError::InvalidArgument.into());
+        }
+        cq_entries
+    } else {
+        2 * sq_entries
+    };
+
+    Ok((sq_entries, cq_entries))
+}
+
+/*
+ * The kernel allocates each ring as a power of two number of pages.
+ */
+fn ring_pages(size: usize, page_size: usize) -> usize {
+    let pages = (size - 1) / page_size;
+    let order = usize::BITS - pages.leading_zeros();
+
+    1 << order
+}
+
+/*
+ * Amount of memory the kernel pins for a ring created with these parameters,
+ * the same value liburing's io_uring_mlock_size reports. Useful to size
+ * RLIMIT_MEMLOCK or container limits before creating the ring.
+ */
+pub fn ring_memory_size_params(entries: u32, params: &IoUringParams) -> Result<usize> {
+    let flags = IoUringSetupFlags::from_bits_retain(params.flags);
+    let (sq_entries, cq_entries) = queue_entries(entries, params)?;
+    let page_size = page_size();
+
+    let mut cqe_size = size_of::<io_uring_cqe>();
+    if flags.contains(IoUringSetupFlags::Cqe32) {
+        cqe_size += size_of::<io_uring_cqe>();
+    }
+    let cq_size = (cqe_size * cq_entries as usize + KERNEL_RING_SIZE + 63) & !63;
+
+    let mut sqe_size = size_of::<io_uring_sqe>();
+    if flags.contains(IoUringSetupFlags::Sqe128) {
+        sqe_size += 64;
+    }
+    let sq_size = sqe_size * sq_entries as usize;
+
+    Ok((ring_pages(cq_size, page_size) + ring_pages(sq_size, page_size)) * page_size)
+}
+
+pub fn ring_memory_size(entries: u32, flags: IoUringSetupFlags) -> Result<usize> {
+    let params = IoUringParams {
+        flags: flags.bits(),
+        ..Default::default()
+    };
+
+    ring_memory_size_params(entries, &params)
+}
+
+#[cfg(test)]
+mod when_calculating_ring_memory_size {
+    use crate::{
+        io_uring::{IoUringParams, IoUringSetupFlags},
+        memory::{page_size, ring_memory_size, ring_memory_size_params},
+    };
+
+    #[test]
+    pub fn small_rings_take_one_page_per_ring() {
+        let size = ring_memory_size(1, IoUringSetupFlags::empty()).unwrap();
+
+        assert_eq!(size, 2 * page_size());
+    }
+
+    #[test]
+    pub fn big_entries_double_the_sq_size() {
+        let normal = ring_memory_size(4096, IoUringSetupFlags::empty()).unwrap();
+        let big = ring_memory_size(4096, IoUringSetupFlags::Sqe128).unwrap();
+
+        assert!(big > normal);
+    }
+
+    #[test]
+    pub fn zero_entries_are_rejected() {
+        assert!(ring_memory_size(0, IoUringSetupFlags::empty()).is_err());
+    }
+
+    #[test]
+    pub fn too_many_entries_need_clamp() {
+        assert!(ring_memory_size(1 << 20, IoUringSetupFlags::empty()).is_err());
+        assert!(ring_memory_size(1 << 20, IoUringSetupFlags::Clamp).is_ok());
+    }
+
+    #[test]
+    pub fn cq_size_smaller_than_sq_is_rejected() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::CqSize.bits(),
+            cq_entries: 2,
+            ..Default::default()
+        };
+
+        assert!(ring_memory_size_params(8, &params).is_err());
+    }
+}
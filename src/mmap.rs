@@ -1,12 +1,14 @@
-use anyhow::{bail, Result};
-use errno::errno;
+use crate::{
+    arch::{mmap, MmapOffset},
+    memory::page_size,
+};
 use libc::{
-    c_void, exit, mmap, munmap, off_t, strerror, MAP_FAILED, MAP_POPULATE, MAP_SHARED, PROT_READ,
-    PROT_WRITE,
+    c_int, c_void, exit, madvise, mlock, munmap, MADV_DONTFORK, MADV_POPULATE_WRITE, MAP_POPULATE,
+    MAP_SHARED, PROT_READ, PROT_WRITE,
 };
 use log::debug;
 use std::{
-    ffi::CStr,
+    io::{self, Result},
     marker::PhantomData,
     os::fd::{AsRawFd, OwnedFd},
     ptr::{null_mut, NonNull},
@@ -14,6 +16,63 @@ use std::{
 
 const UNMAP_FAILED: i32 = -1;
 
+/*
+ * madvise only takes page aligned ranges, so the range is widened to the
+ * pages that contain it.
+ */
+pub(crate) fn advise_dont_fork(addr: *mut c_void, len: usize) -> Result<()> {
+    let page_size = page_size();
+    let start = addr as usize & !(page_size - 1);
+    let end = (addr as usize + len + page_size - 1) & !(page_size - 1);
+
+    if unsafe { madvise(start as *mut c_void, end - start, MADV_DONTFORK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn lock_memory(addr: *mut c_void, len: usize) -> Result<()> {
+    if unsafe { mlock(addr, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/*
+ * How a mapping of the ring fd is set up. A populated mapping is faulted in
+ * by mmap itself, a lazy one page by page on first touch, which keeps a big
+ * completion ring from adding to the setup latency.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MapOptions {
+    pub(crate) populate: bool,
+    pub(crate) prot: c_int,
+    /*
+     * Added to MAP_SHARED.
+     */
+    pub(crate) flags: c_int,
+}
+
+impl MapOptions {
+    pub(crate) const POPULATED: MapOptions = MapOptions {
+        populate: true,
+        prot: PROT_READ | PROT_WRITE,
+        flags: 0,
+    };
+
+    pub(crate) const LAZY: MapOptions = MapOptions {
+        populate: false,
+        ..MapOptions::POPULATED
+    };
+
+    fn mmap_flags(&self) -> c_int {
+        let populate = if self.populate { MAP_POPULATE } else { 0 };
+        MAP_SHARED | populate | self.flags
+    }
+}
+
 pub(crate) struct MMap<'a> {
     addr: NonNull<c_void>,
     len: usize,
@@ -25,32 +84,75 @@ impl<'a> MMap<'a> {
         MMap {
             addr,
             len,
-            __owns_addr: PhantomData::default(),
+            __owns_addr: PhantomData,
         }
     }
 
-    pub(crate) fn new(fd: &OwnedFd, offset: off_t, len: usize) -> Result<Self> {
-        unsafe {
-            match mmap(
+    pub(crate) fn new(
+        fd: &OwnedFd,
+        offset: MmapOffset,
+        len: usize,
+        options: MapOptions,
+    ) -> Result<Self> {
+        let addr = unsafe {
+            mmap(
                 null_mut(),
                 len,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED | MAP_POPULATE,
+                options.prot,
+                options.mmap_flags(),
                 fd.as_raw_fd(),
                 offset,
-            ) {
-                MAP_FAILED => {
-                    let error_number = errno().0;
-                    let error_string = strerror(error_number);
-                    let error = CStr::from_ptr(error_string).to_string_lossy().into_owned();
-                    bail!(error);
-                }
-                addr => {
-                    let result = NonNull::new_unchecked(addr);
-                    Ok(Self::new_with_address(result, len))
-                }
-            }
+            )
+        }?;
+
+        Ok(Self::new_with_address(addr, len))
+    }
+
+    pub(crate) fn anonymous(len: usize) -> Result<Self> {
+        let addr = unsafe {
+            mmap(
+                null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        }?;
+
+        Ok(Self::new_with_address(addr, len))
+    }
+
+    /*
+     * Gives up ownership of the mapping, it won't be unmapped on drop.
+     */
+    pub(crate) fn into_raw(self) -> (NonNull<c_void>, usize) {
+        let parts = (self.addr, self.len);
+        std::mem::forget(self);
+        parts
+    }
+
+    pub(crate) fn dont_fork(&self) -> Result<()> {
+        advise_dont_fork(self.addr.as_ptr(), self.len)
+    }
+
+    /*
+     * Faults in a lazy mapping after the fact. Needs kernel 5.14.
+     */
+    pub(crate) fn populate(&self) -> Result<()> {
+        if unsafe { madvise(self.addr.as_ptr(), self.len, MADV_POPULATE_WRITE) } < 0 {
+            return Err(io::Error::last_os_error());
         }
+
+        Ok(())
+    }
+
+    pub(crate) fn lock(&self) -> Result<()> {
+        lock_memory(self.addr.as_ptr(), self.len)
+    }
+
+    pub(crate) fn address(&self) -> NonNull<c_void> {
+        self.addr
     }
 
     pub(crate) fn add_offset(&self, offset: usize) -> Option<NonNull<c_void>> {
@@ -67,10 +169,7 @@ impl<'a> Drop for MMap<'a> {
         unsafe {
             let error_code = munmap(self.addr.as_ptr(), self.len);
             if error_code == UNMAP_FAILED {
-                let error_number = errno().0;
-                let error_string = strerror(error_number);
-                let error = CStr::from_ptr(error_string).to_string_lossy().into_owned();
-                debug!("{}", &error);
+                debug!("{}", io::Error::last_os_error());
                 exit(1);
             }
         }
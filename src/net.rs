@@ -0,0 +1,1514 @@
+use crate::{
+    cmsg::{received_fds, rights, rights_buffer, ControlMessages},
+    cqe::{Completion, CqeFlags},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    fs::{assume_init, check, poll_run_completion, run, run_completion, until_done, Splicer},
+    io_uring::IoUring,
+    owned_buf::OwnedBuf,
+};
+use libc::{
+    in6_addr, in_addr, iovec, msghdr, sa_family_t, sockaddr, sockaddr_in, sockaddr_in6,
+    sockaddr_storage, socklen_t, AF_INET, AF_INET6, ECANCELED, IPPROTO_TCP, MSG_CMSG_CLOEXEC,
+    MSG_NOSIGNAL, MSG_TRUNC, SOCK_CLOEXEC, SOCK_STREAM,
+};
+use linux_raw_sys::io_uring::{io_uring_recvmsg_out, IORING_NOTIF_USAGE_ZC_COPIED};
+use log::debug;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    future::Future,
+    io::{self, ErrorKind, Result},
+    mem::{forget, size_of, zeroed, MaybeUninit},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream},
+    ops::Range,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    ptr::{null_mut, read_unaligned},
+    slice,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/*
+ * user_data of the entries this module submits and waits for, next to the
+ * ones of fs.
+ */
+const SEND_USER_DATA: u64 = u64::MAX - 12;
+const RECV_USER_DATA: u64 = u64::MAX - 13;
+const SOCKET_USER_DATA: u64 = u64::MAX - 17;
+const ACCEPT_USER_DATA: u64 = u64::MAX - 24;
+/*
+ * Bases of the user_data of the multishot accepts of a TcpListener and of
+ * their cancel, the fd of the listening socket is added to them: accepts
+ * a listener left behind on the ring, e.g. when it was forgotten, never
+ * reach another one. Both ranges lie below the single values above.
+ */
+const LISTEN_USER_DATA: u64 = u64::MAX - (2 << 32);
+const LISTEN_CANCEL_USER_DATA: u64 = u64::MAX - (4 << 32);
+
+/*
+ * Streams `range` of `file` to `socket` with splice through a pipe, the
+ * contents never reach userspace. Blocks until the whole range is sent, or
+ * until end of file, and returns the number of bytes sent. A non-blocking
+ * socket with a full send buffer is polled until it drains.
+ */
+pub fn send_file<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    file: &File,
+    range: Range<u64>,
+) -> Result<u64> {
+    if range.start > range.end {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the range ends before it starts",
+        ));
+    }
+
+    Splicer::new()?.transfer(
+        ring,
+        file.as_raw_fd(),
+        Some(range.start),
+        socket.as_fd().as_raw_fd(),
+        None,
+        Some(range.end - range.start),
+    )
+}
+
+/*
+ * Sends the whole of `buf`, a short send is continued with a send of what
+ * is left. A peer that went away fails with EPIPE, not SIGPIPE.
+ */
+pub fn send_all<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: &[u8],
+) -> Result<()> {
+    let fd = socket.as_fd().as_raw_fd();
+
+    until_done(buf.len(), ErrorKind::WriteZero, |done, len| {
+        let chunk = &buf[done..done + len];
+        run_completion(ring, SEND_USER_DATA, |sqe| unsafe {
+            sqe.send(fd, chunk.as_ptr(), len as u32, MSG_NOSIGNAL)
+        })
+        .map(|completion| completion.result)
+    })
+}
+
+/*
+ * Receives until `buf` is full. UnexpectedEof when the peer shuts down its
+ * side first.
+ */
+pub fn recv_exact<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: &mut [u8],
+) -> Result<()> {
+    let fd = socket.as_fd().as_raw_fd();
+
+    until_done(buf.len(), ErrorKind::UnexpectedEof, |done, len| {
+        let chunk = &mut buf[done..done + len];
+        run_completion(ring, RECV_USER_DATA, |sqe| unsafe {
+            sqe.recv(fd, chunk.as_mut_ptr(), len as u32, 0)
+        })
+        .map(|completion| completion.result)
+    })
+}
+
+/*
+ * Accepts a connection on `listener` and resolves to the new socket and the
+ * address of its peer. The socket is opened with SOCK_CLOEXEC. The accept is
+ * submitted on the first poll and stays armed until a connection comes in,
+ * dropping the future cancels it.
+ */
+pub fn accept<'r, 'f, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    listener: &'f impl AsFd,
+) -> Accept<'r, 'f, 'a, S, C> {
+    Accept {
+        ring,
+        listener: listener.as_fd(),
+        peer: Box::new((
+            unsafe { zeroed() },
+            size_of::<sockaddr_storage>() as socklen_t,
+        )),
+        armed: false,
+    }
+}
+
+pub struct Accept<'r, 'f, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    listener: BorrowedFd<'f>,
+    /*
+     * Where the kernel writes the address of the peer and its length, boxed
+     * as the future may move while the accept is in flight.
+     */
+    peer: Box<(sockaddr_storage, socklen_t)>,
+    armed: bool,
+}
+
+impl<S: SqeEntry, C: CqeEntry> Future for Accept<'_, '_, '_, S, C> {
+    type Output = Result<(OwnedFd, SocketAddr)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let fd = this.listener.as_raw_fd();
+        let addr = &mut this.peer.0 as *mut sockaddr_storage as *mut sockaddr;
+        let len = &mut this.peer.1 as *mut socklen_t;
+
+        let accepted = poll_run_completion(
+            this.ring,
+            ACCEPT_USER_DATA,
+            &mut this.armed,
+            cx,
+            |sqe| unsafe { sqe.accept(fd, addr, len, SOCK_CLOEXEC) },
+        );
+        let completion = match accepted {
+            Poll::Ready(completion) => completion,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        Poll::Ready(completion.and_then(|completion| {
+            let socket = unsafe { OwnedFd::from_raw_fd(check(&completion)? as i32) };
+            let (addr, len) = &*this.peer;
+            let name = unsafe {
+                slice::from_raw_parts(addr as *const sockaddr_storage as *const u8, *len as usize)
+            };
+            let peer = socket_addr(name).ok_or_else(|| {
+                io::Error::new(ErrorKind::Unsupported, "the peer is not an IP socket")
+            })?;
+
+            Ok((socket, peer))
+        }))
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Drop for Accept<'_, '_, '_, S, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.ring.abandon(&[ACCEPT_USER_DATA]);
+        }
+    }
+}
+
+/*
+ * Accepts the connections of `listener` with multishot accepts on `ring`,
+ * see TcpListener.
+ */
+pub fn listen<'r, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    listener: std::net::TcpListener,
+) -> TcpListener<'r, 'a, S, C> {
+    TcpListener {
+        ring,
+        listener,
+        depth: 1,
+        armed: 0,
+        paused: false,
+        accepted: VecDeque::new(),
+    }
+}
+
+/*
+ * A listening socket with `depth` multishot accepts armed on it, one by
+ * default, each posting a completion per connection. An accept the kernel
+ * ends is armed again by the next call to accept.
+ *
+ * For admission control under overload, pause cancels the accepts: new
+ * connections then wait in the listen backlog, sized with set_backlog, and
+ * are refused by the kernel once it is full, until resume arms the accepts
+ * again. Connections accepted while the cancel was on its way are kept and
+ * handed out first.
+ *
+ * accept is a plain blocking call that waits in io_uring_enter until a
+ * connection comes in. Dropping the listener cancels the accepts.
+ */
+pub struct TcpListener<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    listener: std::net::TcpListener,
+    depth: u32,
+    armed: u32,
+    paused: bool,
+    accepted: VecDeque<OwnedFd>,
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> TcpListener<'r, 'a, S, C> {
+    /*
+     * Waits for the next connection and returns its socket, opened with
+     * SOCK_CLOEXEC, and the address of its peer. WouldBlock while paused
+     * once the connections accepted before are handed out.
+     */
+    pub fn accept(&mut self) -> Result<(OwnedFd, SocketAddr)> {
+        loop {
+            if let Some(socket) = self.accepted.pop_front() {
+                return with_peer(socket);
+            }
+            if self.paused {
+                return Err(io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "the listener is paused",
+                ));
+            }
+
+            self.arm()?;
+            let completion = self.ring.wait_for_completion(self.user_data())?;
+            if let Some(socket) = self.accepted(completion)? {
+                return with_peer(socket);
+            }
+        }
+    }
+
+    /*
+     * Cancels the accepts and waits for them to end.
+     */
+    pub fn pause(&mut self) -> Result<()> {
+        self.paused = true;
+        self.disarm()
+    }
+
+    /*
+     * Arms the accepts again after pause.
+     */
+    pub fn resume(&mut self) -> Result<()> {
+        self.paused = false;
+        self.arm()?;
+        self.ring.submit()?;
+
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /*
+     * How many accepts are kept armed at once, from the next time they are
+     * armed: pause and resume to apply it right away.
+     */
+    pub fn set_depth(&mut self, depth: u32) -> Result<()> {
+        if depth == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a listener needs at least one accept armed",
+            ));
+        }
+        self.depth = depth;
+
+        Ok(())
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /*
+     * Resizes the queue of connections the kernel completes before they
+     * are accepted, with listen(2) on the listening socket again. The kernel
+     * caps it at net.core.somaxconn.
+     */
+    pub fn set_backlog(&self, backlog: i32) -> Result<()> {
+        if unsafe { libc::listen(self.listener.as_raw_fd(), backlog) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn user_data(&self) -> u64 {
+        LISTEN_USER_DATA + self.listener.as_raw_fd() as u64
+    }
+
+    fn cancel_user_data(&self) -> u64 {
+        LISTEN_CANCEL_USER_DATA + self.listener.as_raw_fd() as u64
+    }
+
+    fn arm(&mut self) -> Result<()> {
+        let fd = self.listener.as_raw_fd();
+        let user_data = self.user_data();
+        while self.armed < self.depth {
+            if self.ring.sq_space_left() == 0 {
+                self.ring.submit()?;
+            }
+            let Some(sqe) = self.ring.next_sqe() else {
+                return Err(io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "the submission queue is full",
+                ));
+            };
+            unsafe { sqe.accept(fd, null_mut(), null_mut(), SOCK_CLOEXEC) }
+                .accept_multishot()
+                .user_data(user_data);
+            self.armed += 1;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * The socket of a connection, None for a completion that only ended an
+     * accept.
+     */
+    fn accepted(&mut self, completion: Completion) -> Result<Option<OwnedFd>> {
+        if !completion.more() {
+            self.armed -= 1;
+        }
+
+        match completion.result {
+            result if result == -ECANCELED => Ok(None),
+            result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            socket => Ok(Some(unsafe { OwnedFd::from_raw_fd(socket) })),
+        }
+    }
+
+    fn disarm(&mut self) -> Result<()> {
+        if self.armed == 0 {
+            return Ok(());
+        }
+
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        let cancel_user_data = self.cancel_user_data();
+        if let Some(sqe) = self.ring.next_sqe() {
+            sqe.cancel_fd(self.listener.as_raw_fd())
+                .user_data(cancel_user_data);
+        }
+        self.ring.submit()?;
+        self.ring.wait_for_completion(cancel_user_data)?;
+
+        while self.armed > 0 {
+            let completion = self.ring.wait_for_completion(self.user_data())?;
+            match self.accepted(completion) {
+                Ok(Some(socket)) => self.accepted.push_back(socket),
+                Ok(None) => {}
+                Err(error) => debug!("an accept ended with {}", error),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Drop for TcpListener<'r, 'a, S, C> {
+    fn drop(&mut self) {
+        if let Err(error) = self.disarm() {
+            debug!("could not cancel the accepts of the listener: {}", error);
+        }
+    }
+}
+
+fn with_peer(socket: OwnedFd) -> Result<(OwnedFd, SocketAddr)> {
+    let stream = TcpStream::from(socket);
+    let peer = stream.peer_addr()?;
+
+    Ok((stream.into(), peer))
+}
+
+/*
+ * Receives once into the spare capacity of `buf` and resolves to the buffer
+ * with the bytes received appended, their number and the flags of the
+ * completion, e.g. CqeFlags::SockNonEmpty when more is waiting. Zero bytes
+ * is the peer shutting down its side. A failed recv hands the buffer back
+ * in its RecvError. The buffer stays with the future while the recv is in
+ * flight, dropping the future cancels the recv before the buffer goes.
+ */
+pub fn recv<'r, 'f, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    socket: &'f impl AsFd,
+    buf: OwnedBuf,
+    flags: i32,
+) -> Recv<'r, 'f, 'a, S, C> {
+    Recv {
+        ring,
+        socket: socket.as_fd(),
+        buf: Some(buf),
+        flags,
+        armed: false,
+    }
+}
+
+/*
+ * A recv that failed, with the buffer it was given.
+ */
+#[derive(Debug)]
+pub struct RecvError {
+    pub error: io::Error,
+    pub buf: OwnedBuf,
+}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "could not receive: {}", self.error)
+    }
+}
+
+impl Error for RecvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<RecvError> for io::Error {
+    fn from(error: RecvError) -> Self {
+        error.error
+    }
+}
+
+pub struct Recv<'r, 'f, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    socket: BorrowedFd<'f>,
+    /*
+     * Only None once the future resolved.
+     */
+    buf: Option<OwnedBuf>,
+    flags: i32,
+    armed: bool,
+}
+
+impl<S: SqeEntry, C: CqeEntry> Future for Recv<'_, '_, '_, S, C> {
+    type Output = std::result::Result<(OwnedBuf, usize, CqeFlags), RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let fd = this.socket.as_raw_fd();
+        let flags = this.flags;
+        let spare = this.buf.get_or_insert_with(OwnedBuf::default).spare();
+        let len = spare.iov_len.min(u32::MAX as usize) as u32;
+
+        let received = poll_run_completion(
+            this.ring,
+            RECV_USER_DATA,
+            &mut this.armed,
+            cx,
+            |sqe| unsafe { sqe.recv(fd, spare.iov_base as *mut u8, len, flags) },
+        );
+        let completion = match received {
+            Poll::Ready(completion) => completion,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let buf = this.buf.take().unwrap_or_default();
+        let checked =
+            completion.and_then(|completion| Ok((check(&completion)? as usize, completion.flags)));
+        let (received, completion_flags) = match checked {
+            Ok(received) => received,
+            Err(error) => return Poll::Ready(Err(RecvError { error, buf })),
+        };
+        let mut bytes = buf.into_inner();
+        unsafe { bytes.set_len(bytes.len() + received) };
+
+        Poll::Ready(Ok((
+            OwnedBuf::from(bytes),
+            received,
+            CqeFlags::from_raw(completion_flags),
+        )))
+    }
+}
+
+impl<S: SqeEntry, C: CqeEntry> Drop for Recv<'_, '_, '_, S, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.ring.abandon(&[RECV_USER_DATA]);
+        }
+    }
+}
+
+/*
+ * Receives once into a buffer that was never initialized, returns the part
+ * that was filled, empty when the peer shut down its side.
+ */
+pub fn recv_uninit<'b, S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: &'b mut [MaybeUninit<u8>],
+) -> Result<&'b mut [u8]> {
+    let fd = socket.as_fd().as_raw_fd();
+    let len = buf.len().min(u32::MAX as usize) as u32;
+
+    let received = run(ring, RECV_USER_DATA, |sqe| unsafe {
+        sqe.recv(fd, buf.as_mut_ptr() as *mut u8, len, 0)
+    })?;
+
+    Ok(unsafe { assume_init(buf, received as usize) })
+}
+
+/*
+ * Opens a TCP connection to `addr`, giving up with ErrorKind::TimedOut when
+ * it is not established within `timeout`. The connect is linked to a
+ * LINK_TIMEOUT, so an unreachable peer costs the timeout and not the
+ * minutes of SYN retries of a blocking connect.
+ */
+pub fn connect_timeout<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    addr: &SocketAddr,
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => AF_INET,
+        SocketAddr::V6(_) => AF_INET6,
+    };
+    let fd = run(ring, SOCKET_USER_DATA, |sqe| {
+        sqe.socket(domain, SOCK_STREAM | SOCK_CLOEXEC, IPPROTO_TCP)
+    })?;
+    let socket = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+    let (raw, len) = raw_socket_addr(addr);
+    let fd = socket.as_raw_fd();
+    ring.op(|sqe| unsafe { sqe.connect(fd, &raw as *const sockaddr_storage as *const _, len) })
+        .timeout(timeout)
+        .run()?;
+
+    Ok(TcpStream::from(socket))
+}
+
+/*
+ * Sends `buf` with `fds` attached as SCM_RIGHTS over a Unix socket, the
+ * peer gets its own copies of them. A stream socket needs at least one byte
+ * to carry them. Returns the number of bytes sent, the fds go with the
+ * first of them.
+ */
+pub fn send_fds<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: &[u8],
+    fds: &[BorrowedFd<'_>],
+) -> Result<usize> {
+    let fd = socket.as_fd().as_raw_fd();
+    let mut control = rights(fds);
+    let mut iov = iovec {
+        iov_base: buf.as_ptr() as *mut _,
+        iov_len: buf.len(),
+    };
+    let mut msg: msghdr = unsafe { zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() * size_of::<u64>();
+
+    run(ring, SEND_USER_DATA, |sqe| unsafe {
+        sqe.sendmsg(fd, &msg, MSG_NOSIGNAL)
+    })
+    .map(|sent| sent as usize)
+}
+
+/*
+ * Receives into `buf` and takes the fds sent along, up to `max_fds` of
+ * them, the others are closed. The received ones are close-on-exec. Zero
+ * bytes and no fds when the peer shut down.
+ */
+pub fn recv_fds<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: &mut [u8],
+    max_fds: usize,
+) -> Result<(usize, Vec<OwnedFd>)> {
+    let fd = socket.as_fd().as_raw_fd();
+    let mut control = rights_buffer(max_fds);
+    let mut iov = iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut msg: msghdr = unsafe { zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() * size_of::<u64>();
+
+    let received = run(ring, RECV_USER_DATA, |sqe| unsafe {
+        sqe.recvmsg(fd, &mut msg, MSG_CMSG_CLOEXEC)
+    })?;
+
+    let filled = msg.msg_controllen.min(control.len() * size_of::<u64>());
+    let control = unsafe { std::slice::from_raw_parts(control.as_ptr() as *const u8, filled) };
+    /*
+     * The control buffer is rounded up and may have fit a few more.
+     */
+    let mut fds = received_fds(control);
+    fds.truncate(max_fds);
+
+    Ok((received as usize, fds))
+}
+
+/*
+ * A message a multishot recvmsg wrote into a provided buffer, see
+ * Sqe::recvmsg_multishot. The buffer starts with an io_uring_recvmsg_out
+ * header, then has room for the name and the control data as sized by the
+ * msghdr of the entry, then the payload.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct RecvMsgOut<'b> {
+    name: &'b [u8],
+    control: &'b [u8],
+    payload: &'b [u8],
+    payload_len: u32,
+    flags: u32,
+}
+
+impl<'b> RecvMsgOut<'b> {
+    /*
+     * `buf` holds the bytes of the completion, as many as its result tells,
+     * `msg` is the msghdr the entry was prepared with. None when the
+     * buffer is too short for the header, name and control data.
+     */
+    pub fn parse(buf: &'b [u8], msg: &msghdr) -> Option<Self> {
+        let header_len = size_of::<io_uring_recvmsg_out>();
+        let name_len = msg.msg_namelen as usize;
+        let control_len = msg.msg_controllen;
+        let payload_start = header_len + name_len + control_len;
+        if buf.len() < payload_start {
+            return None;
+        }
+
+        let header: io_uring_recvmsg_out = unsafe { read_unaligned(buf.as_ptr().cast()) };
+        let name = &buf[header_len..header_len + name_len];
+        let control = &buf[header_len + name_len..payload_start];
+        let payload = &buf[payload_start..];
+
+        Some(RecvMsgOut {
+            name: &name[..name_len.min(header.namelen as usize)],
+            control: &control[..control_len.min(header.controllen as usize)],
+            payload: &payload[..payload.len().min(header.payloadlen as usize)],
+            payload_len: header.payloadlen,
+            flags: header.flags,
+        })
+    }
+
+    /*
+     * The address of the sender as the kernel wrote it, cut to the room the
+     * msghdr had for it.
+     */
+    pub fn name(&self) -> &'b [u8] {
+        self.name
+    }
+
+    /*
+     * The sender, for IPv4 and IPv6 sockets.
+     */
+    pub fn address(&self) -> Option<SocketAddr> {
+        socket_addr(self.name)
+    }
+
+    pub fn control(&self) -> ControlMessages<'b> {
+        ControlMessages::new(self.control)
+    }
+
+    pub fn payload(&self) -> &'b [u8] {
+        self.payload
+    }
+
+    /*
+     * Length of the message as it was sent, more than payload holds when it
+     * did not fit the buffer.
+     */
+    pub fn payload_len(&self) -> u32 {
+        self.payload_len
+    }
+
+    /*
+     * msg_flags of the message, e.g. MSG_TRUNC or MSG_CTRUNC.
+     */
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.flags & MSG_TRUNC as u32 > 0
+    }
+}
+
+/*
+ * Zero copy send of `buf` to `socket`, with `user_data` on both of its
+ * completions. The returned handle owns the buffer and is fed the
+ * completions of user_data. The buffer is given back right away when the
+ * submission queue is full.
+ */
+pub fn send_zc<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    socket: &impl AsFd,
+    buf: OwnedBuf,
+    user_data: u64,
+) -> std::result::Result<ZcNotification, OwnedBuf> {
+    let Some(sqe) = ring.next_sqe() else {
+        return Err(buf);
+    };
+    unsafe {
+        sqe.send_zc(
+            socket.as_fd().as_raw_fd(),
+            buf.as_ptr(),
+            buf.len() as u32,
+            MSG_NOSIGNAL,
+        )
+    }
+    .user_data(user_data);
+
+    Ok(ZcNotification {
+        user_data,
+        buf: Some(buf),
+        sent: None,
+        notified: false,
+        copied: false,
+    })
+}
+
+/*
+ * A zero copy send in flight. The send completes first, but the kernel may
+ * still read the buffer until the notification lands, only then does
+ * release give it back. Dropped before that, the buffer is leaked rather
+ * than freed under the kernel.
+ */
+#[derive(Debug)]
+pub struct ZcNotification {
+    user_data: u64,
+    buf: Option<OwnedBuf>,
+    sent: Option<i32>,
+    notified: bool,
+    copied: bool,
+}
+
+impl ZcNotification {
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    /*
+     * Takes in a completion, returns whether it belonged to this send. A
+     * send that fails without More set gets no notification, the buffer is
+     * free as soon as its result is in.
+     */
+    pub fn complete(&mut self, completion: &Completion) -> bool {
+        if completion.user_data != self.user_data {
+            return false;
+        }
+
+        if completion.notification() {
+            self.notified = true;
+            self.copied = completion.result as u32 & IORING_NOTIF_USAGE_ZC_COPIED != 0;
+        } else {
+            self.sent = Some(completion.result);
+            self.notified |= !completion.more();
+        }
+
+        true
+    }
+
+    /*
+     * Number of bytes sent, once the send completed.
+     */
+    pub fn result(&self) -> Option<Result<usize>> {
+        self.sent.map(|result| match result {
+            result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            result => Ok(result as usize),
+        })
+    }
+
+    pub fn is_notified(&self) -> bool {
+        self.notified
+    }
+
+    /*
+     * The kernel did not send from the buffer itself but copied the data,
+     * e.g. over loopback or a device without scatter-gather, so zero copy
+     * bought nothing for this socket. Only known once the notification
+     * landed.
+     */
+    pub fn is_copied(&self) -> bool {
+        self.copied
+    }
+
+    /*
+     * The buffer, once the notification landed, otherwise the handle back.
+     */
+    pub fn release(mut self) -> std::result::Result<OwnedBuf, Self> {
+        if !self.notified {
+            return Err(self);
+        }
+
+        Ok(self.buf.take().unwrap_or_default())
+    }
+
+    /*
+     * Waits for both completions, returns the number of bytes sent. The
+     * buffer can be released afterwards, also when the send failed.
+     */
+    pub fn wait<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<usize> {
+        while self.sent.is_none() || !self.notified {
+            let completion = ring.wait_for_completion(self.user_data)?;
+            self.complete(&completion);
+        }
+
+        self.result().unwrap_or(Ok(0))
+    }
+}
+
+impl Drop for ZcNotification {
+    fn drop(&mut self) {
+        if !self.notified {
+            forget(self.buf.take());
+        }
+    }
+}
+
+/*
+ * Reads an AF_INET or AF_INET6 socket address as the kernel wrote it.
+ */
+pub(crate) fn socket_addr(name: &[u8]) -> Option<SocketAddr> {
+    if name.len() < size_of::<sa_family_t>() {
+        return None;
+    }
+
+    match unsafe { read_unaligned(name.as_ptr() as *const sa_family_t) } as i32 {
+        AF_INET if name.len() >= size_of::<sockaddr_in>() => {
+            let addr: sockaddr_in = unsafe { read_unaligned(name.as_ptr().cast()) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        AF_INET6 if name.len() >= size_of::<sockaddr_in6>() => {
+            let addr: sockaddr_in6 = unsafe { read_unaligned(name.as_ptr().cast()) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/*
+ * The socket address as sendmsg and connect take it.
+ */
+pub(crate) fn raw_socket_addr(addr: &SocketAddr) -> (sockaddr_storage, socklen_t) {
+    let mut storage: sockaddr_storage = unsafe { zeroed() };
+
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let raw = sockaddr_in {
+                sin_family: AF_INET as sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: in_addr {
+                    s_addr: u32::from(*addr.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { (&mut storage as *mut sockaddr_storage as *mut sockaddr_in).write(raw) };
+            size_of::<sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let raw = sockaddr_in6 {
+                sin6_family: AF_INET6 as sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe { (&mut storage as *mut sockaddr_storage as *mut sockaddr_in6).write(raw) };
+            size_of::<sockaddr_in6>()
+        }
+    };
+
+    (storage, len as socklen_t)
+}
+
+#[cfg(test)]
+mod when_sending_a_file {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::send_file,
+    };
+    use std::{
+        env::temp_dir,
+        fs::{self, File},
+        io::Read,
+        os::unix::net::UnixStream,
+        process::id,
+        thread,
+    };
+
+    fn file_with(name: &str, contents: &[u8]) -> (File, std::path::PathBuf) {
+        let path = temp_dir().join(format!("vargasync-{}-{}", name, id()));
+        fs::write(&path, contents).unwrap();
+        (File::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    pub fn the_range_reaches_the_peer() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let contents: Vec<u8> = (0..300_000u32).map(|value| value as u8).collect();
+        let (file, path) = file_with("send-file", &contents);
+        let (sender, mut receiver) = UnixStream::pair().unwrap();
+        sender.set_nonblocking(true).unwrap();
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            receiver.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let sent = send_file(&mut ring, &sender, &file, 1000..250_000).unwrap();
+        drop(sender);
+
+        assert_eq!(sent, 249_000);
+        assert_eq!(reader.join().unwrap(), &contents[1000..250_000]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn a_range_past_the_end_stops_at_end_of_file() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (file, path) = file_with("send-file-short", b"static asset");
+        let (sender, mut receiver) = UnixStream::pair().unwrap();
+
+        let sent = send_file(&mut ring, &sender, &file, 7..1000).unwrap();
+        drop(sender);
+
+        let mut received = Vec::new();
+        receiver.read_to_end(&mut received).unwrap();
+        assert_eq!(sent, 5);
+        assert_eq!(received, b"asset");
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_transferring_whole_buffers_over_a_socket {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::{recv_exact, send_all},
+    };
+    use std::{
+        io::{ErrorKind, Read, Write},
+        os::unix::net::UnixStream,
+        thread,
+    };
+
+    #[test]
+    pub fn everything_reaches_the_peer_despite_short_sends() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let contents: Vec<u8> = (0..1_000_000u32).map(|value| value as u8).collect();
+        let (sender, mut receiver) = UnixStream::pair().unwrap();
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            receiver.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        send_all(&mut ring, &sender, &contents).unwrap();
+        drop(sender);
+
+        assert_eq!(reader.join().unwrap(), contents);
+    }
+
+    #[test]
+    pub fn receiving_waits_for_the_rest_of_the_buffer() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (receiver, mut sender) = UnixStream::pair().unwrap();
+
+        let writer = thread::spawn(move || {
+            sender.write_all(b"hello ").unwrap();
+            thread::sleep(std::time::Duration::from_millis(10));
+            sender.write_all(b"world").unwrap();
+        });
+
+        let mut buffer = [0u8; 11];
+        recv_exact(&mut ring, &receiver, &mut buffer).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(&buffer, b"hello world");
+        let error = recv_exact(&mut ring, &receiver, &mut buffer).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+}
+
+#[cfg(test)]
+mod when_passing_fds_over_a_unix_socket {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::{recv_fds, send_fds},
+    };
+    use std::{
+        fs::File,
+        io::{Read, Seek, Write},
+        os::{fd::AsFd, unix::net::UnixStream},
+    };
+
+    #[test]
+    pub fn the_peer_gets_working_copies_of_the_fds() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let mut file = tempfile();
+        file.write_all(b"shared").unwrap();
+        let (pipe_reader, mut pipe_writer) = std::io::pipe().unwrap();
+
+        let sent = send_fds(
+            &mut ring,
+            &sender,
+            b"x",
+            &[file.as_fd(), pipe_reader.as_fd()],
+        )
+        .unwrap();
+        assert_eq!(sent, 1);
+
+        let mut buf = [0u8; 4];
+        let (received, fds) = recv_fds(&mut ring, &receiver, &mut buf, 4).unwrap();
+        assert_eq!(received, 1);
+        assert_eq!(fds.len(), 2);
+
+        let mut copy = File::from(fds.into_iter().next().unwrap());
+        copy.rewind().unwrap();
+        let mut contents = String::new();
+        copy.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "shared");
+        pipe_writer.write_all(b"!").unwrap();
+    }
+
+    #[test]
+    pub fn fds_past_the_limit_are_not_handed_out() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let first = tempfile();
+        let second = tempfile();
+
+        send_fds(&mut ring, &sender, b"x", &[first.as_fd(), second.as_fd()]).unwrap();
+        let (_, fds) = recv_fds(&mut ring, &receiver, &mut [0u8; 1], 1).unwrap();
+
+        assert_eq!(fds.len(), 1);
+    }
+
+    fn tempfile() -> File {
+        let path = std::env::temp_dir().join(format!(
+            "vargasync-fds-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+}
+
+#[cfg(test)]
+mod when_receiving_messages_with_a_multishot_recvmsg {
+    use crate::{
+        buf_ring::BufRingFlags,
+        cmsg::ControlMessage,
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        net::RecvMsgOut,
+    };
+    use libc::{msghdr, setsockopt, sockaddr_storage, socklen_t, IPPROTO_IP, IP_PKTINFO};
+    use std::{
+        mem::{size_of, zeroed},
+        net::UdpSocket,
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    pub fn each_completion_decodes_into_address_control_and_payload() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let enabled = 1u32;
+        assert_eq!(
+            unsafe {
+                setsockopt(
+                    receiver.as_raw_fd(),
+                    IPPROTO_IP,
+                    IP_PKTINFO,
+                    &enabled as *const u32 as *const _,
+                    size_of::<u32>() as socklen_t,
+                )
+            },
+            0
+        );
+
+        let mut buffers = ring.register_buf_ring(5, 4, BufRingFlags::empty()).unwrap();
+        let mut memory = [0u8; 4 * 256];
+        for (buffer_id, chunk) in memory.chunks_mut(256).enumerate() {
+            unsafe { buffers.add(chunk.as_mut_ptr(), 256, buffer_id as u16) };
+        }
+        buffers.commit();
+
+        let mut msg: msghdr = unsafe { zeroed() };
+        msg.msg_namelen = size_of::<sockaddr_storage>() as socklen_t;
+        msg.msg_controllen = 64;
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .recvmsg_multishot(receiver.as_raw_fd(), &msg, 0, 5)
+        }
+        .user_data(9);
+        ring.submit().unwrap();
+
+        let address = receiver.local_addr().unwrap();
+        sender.send_to(b"first", address).unwrap();
+        sender.send_to(b"second", address).unwrap();
+
+        let mut payloads = vec![];
+        while payloads.len() < 2 {
+            ring.submit_and_wait(1).unwrap();
+            while let Some(completion) = ring.next_completion() {
+                assert!(completion.more());
+                let segment = buffers.consume(&completion).unwrap();
+                let start = segment.buffer_id as usize * 256;
+                let message =
+                    RecvMsgOut::parse(&memory[start..start + segment.len as usize], &msg).unwrap();
+
+                assert_eq!(message.address(), Some(sender.local_addr().unwrap()));
+                assert!(!message.is_truncated());
+                assert!(message
+                    .control()
+                    .any(|control| matches!(control, ControlMessage::PacketInfo { .. })));
+                payloads.push(message.payload().to_vec());
+            }
+        }
+
+        assert_eq!(payloads, vec![b"first".to_vec(), b"second".to_vec()]);
+        ring.next_sqe().unwrap().cancel(9).user_data(10);
+        ring.submit_and_wait(2).unwrap();
+        while ring.next_completion().is_some() {}
+        ring.unregister_buf_ring(buffers).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_connecting_with_a_deadline {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::connect_timeout,
+    };
+    use libc::listen;
+    use std::{
+        io::{ErrorKind, Read, Write},
+        net::{TcpListener, TcpStream},
+        os::fd::AsRawFd,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn a_listening_peer_is_connected_to() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut stream = connect_timeout(
+            &mut ring,
+            &listener.local_addr().unwrap(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        stream.write_all(b"hi").unwrap();
+
+        let mut received = [0u8; 2];
+        accepted.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hi");
+    }
+
+    #[test]
+    pub fn a_peer_that_never_answers_times_out() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        /*
+         * With a full accept queue the SYNs of further connects are dropped
+         * and they hang until the peer accepts.
+         */
+        assert_eq!(unsafe { listen(listener.as_raw_fd(), 0) }, 0);
+        let mut pending = vec![];
+        while let Ok(stream) = TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+            pending.push(stream);
+        }
+
+        let error = connect_timeout(&mut ring, &addr, Duration::from_millis(50)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(ring.in_flight(), 0);
+    }
+}
+
+#[cfg(test)]
+mod when_sending_without_copying {
+    use crate::{
+        fixed_buf::{FixedBuffers, FixedParams},
+        io_uring::{IoUring, IoUringParams},
+        net::send_zc,
+        owned_buf::OwnedBuf,
+    };
+    use libc::{c_void, iovec, msghdr};
+    use std::{
+        io::Read,
+        mem::zeroed,
+        net::{TcpListener, TcpStream},
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    pub fn the_buffer_comes_back_once_the_notification_lands() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+
+        let notification =
+            send_zc(&mut ring, &sender, OwnedBuf::from(b"zero copy".to_vec()), 7).unwrap();
+        let mut notification = notification.release().unwrap_err();
+        assert_eq!(notification.wait(&mut ring).unwrap(), 9);
+        assert!(notification.is_notified());
+        /*
+         * Loopback has no device to hand the pages to.
+         */
+        assert!(notification.is_copied());
+
+        let buf = notification.release().unwrap();
+        assert_eq!(&*buf, b"zero copy");
+        let mut received = [0u8; 9];
+        receiver.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"zero copy");
+    }
+
+    #[test]
+    pub fn a_failed_send_still_gives_the_buffer_back() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, _writer) = std::io::pipe().unwrap();
+
+        let mut notification =
+            send_zc(&mut ring, &reader, OwnedBuf::from(b"nope".to_vec()), 7).unwrap();
+
+        assert!(notification.wait(&mut ring).is_err());
+        assert_eq!(&*notification.release().unwrap(), b"nope");
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn registered_buffers_are_sent_by_index() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+        let mut buffers = FixedBuffers::new(1, 64);
+        buffers.buffer_mut(0).unwrap().as_mut_slice()[..10].copy_from_slice(b"fixed send");
+        ring.register_fixed_buffers(&mut buffers).unwrap();
+        let head = buffers.buffer(0).unwrap().slice(0..5).unwrap();
+        let tail = buffers.buffer(0).unwrap().slice(5..10).unwrap();
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .send_zc_fixed(sender.as_raw_fd(), FixedParams::from(&head), 0)
+                .user_data(1);
+        }
+        ring.submit().unwrap();
+        assert_eq!(ring.wait_for_completion(1).unwrap().result, 5);
+        assert!(ring.wait_for_completion(1).unwrap().notification());
+
+        let mut iov = iovec {
+            iov_base: tail.as_slice().as_ptr() as *mut c_void,
+            iov_len: tail.len(),
+        };
+        let mut msg: msghdr = unsafe { zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .sendmsg_zc(sender.as_raw_fd(), &msg, 0, Some(tail.index()))
+                .user_data(2);
+        }
+        ring.submit().unwrap();
+        assert_eq!(ring.wait_for_completion(2).unwrap().result, 5);
+        assert!(ring.wait_for_completion(2).unwrap().notification());
+
+        let mut received = [0u8; 10];
+        receiver.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"fixed send");
+    }
+}
+
+#[cfg(test)]
+mod when_awaiting_typed_results {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::{accept, recv},
+        owned_buf::OwnedBuf,
+    };
+    use futures::{executor::block_on, task::noop_waker};
+    use libc::ENOTSOCK;
+    use std::{
+        future::Future,
+        io::{pipe, Write},
+        net::{TcpListener, TcpStream},
+        pin::Pin,
+        task::Context,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn accept_resolves_to_the_socket_and_its_peer() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let (socket, peer) = block_on(accept(&mut ring, &listener)).unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+
+        client.write_all(b"hello").unwrap();
+        let mut bytes = b"> ".to_vec();
+        bytes.reserve(16);
+        let (buf, received, _) =
+            block_on(recv(&mut ring, &socket, OwnedBuf::from(bytes), 0)).unwrap();
+
+        assert_eq!(received, 5);
+        assert_eq!(&*buf, b"> hello");
+    }
+
+    #[test]
+    pub fn accept_waits_for_a_connection_to_come_in() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let connecting = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            TcpStream::connect(addr).unwrap()
+        });
+        let (_, peer) = block_on(accept(&mut ring, &listener)).unwrap();
+        let client = connecting.join().unwrap();
+
+        assert_eq!(peer, client.local_addr().unwrap());
+    }
+
+    #[test]
+    pub fn a_pending_accept_is_cancelled_when_dropped() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let waker = noop_waker();
+        let mut accepting = accept(&mut ring, &listener);
+        assert!(Pin::new(&mut accepting)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        drop(accepting);
+
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_failed_recv_hands_the_buffer_back() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, _writer) = pipe().unwrap();
+
+        let error = block_on(recv(
+            &mut ring,
+            &reader,
+            OwnedBuf::from(b"kept".to_vec()),
+            0,
+        ))
+        .unwrap_err();
+
+        assert_eq!(error.error.raw_os_error(), Some(ENOTSOCK));
+        assert_eq!(&*error.buf, b"kept");
+    }
+}
+
+#[cfg(test)]
+mod when_listening_with_multishot_accepts {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        net::listen,
+    };
+    use std::{
+        io::ErrorKind,
+        mem::forget,
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    pub fn connections_are_accepted_until_paused() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        {
+            let mut listener = listen(&mut ring, TcpListener::bind("127.0.0.1:0").unwrap());
+            let addr = listener.local_addr().unwrap();
+
+            let first = TcpStream::connect(addr).unwrap();
+            let second = TcpStream::connect(addr).unwrap();
+            assert_eq!(listener.accept().unwrap().1, first.local_addr().unwrap());
+            assert_eq!(listener.accept().unwrap().1, second.local_addr().unwrap());
+
+            listener.pause().unwrap();
+            let waiting = TcpStream::connect(addr).unwrap();
+            assert_eq!(
+                listener.accept().err().unwrap().kind(),
+                ErrorKind::WouldBlock
+            );
+
+            listener.resume().unwrap();
+            assert_eq!(listener.accept().unwrap().1, waiting.local_addr().unwrap());
+        }
+
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_listener_left_on_the_ring_keeps_its_connections() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut forgotten = listen(&mut ring, TcpListener::bind("127.0.0.1:0").unwrap());
+        let forgotten_addr = forgotten.local_addr().unwrap();
+        forgotten.resume().unwrap();
+        forget(forgotten);
+
+        let mut listener = listen(&mut ring, TcpListener::bind("127.0.0.1:0").unwrap());
+        let addr = listener.local_addr().unwrap();
+        let _elsewhere = TcpStream::connect(forgotten_addr).unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+
+        let (socket, peer) = listener.accept().unwrap();
+
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert_eq!(
+            TcpStream::from(socket).local_addr().unwrap(),
+            client.peer_addr().unwrap()
+        );
+    }
+
+    #[test]
+    pub fn several_accepts_can_be_armed_at_once() {
+        let mut ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let mut listener = listen(&mut ring, TcpListener::bind("127.0.0.1:0").unwrap());
+        listener.set_depth(3).unwrap();
+        listener.set_backlog(16).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let clients: Vec<TcpStream> = (0..4).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        let mut peers: Vec<_> = (0..4).map(|_| listener.accept().unwrap().1).collect();
+        let mut expected: Vec<_> = clients.iter().map(|c| c.local_addr().unwrap()).collect();
+        peers.sort();
+        expected.sort();
+
+        assert_eq!(peers, expected);
+        assert_eq!(
+            listener.set_depth(0).err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
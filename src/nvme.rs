@@ -0,0 +1,376 @@
+use crate::{
+    entry::{Cqe32, Sqe128},
+    io_uring::IoUring,
+    sqe::Sqe,
+};
+use linux_raw_sys::io_uring::io_uring_cqe;
+use std::{
+    io::{self, ErrorKind, Result},
+    mem::size_of,
+    os::fd::RawFd,
+    ptr::{copy_nonoverlapping, write_bytes},
+    time::Duration,
+};
+
+/*
+ * NVMe commands passed through the ring to the generic char device of a
+ * namespace, /dev/ng0n1 and the like, with IORING_OP_URING_CMD. The command
+ * takes 72 bytes of the entry and the result dword comes back in the extra
+ * words of the completion, so this only works on rings with Sqe128 and Cqe32
+ * entries, which the types below ask for.
+ */
+
+/*
+ * struct nvme_uring_cmd of linux/nvme_ioctl.h.
+ */
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct NvmeUringCmd {
+    opcode: u8,
+    flags: u8,
+    rsvd1: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    addr: u64,
+    metadata_len: u32,
+    data_len: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_ms: u32,
+    rsvd2: u32,
+}
+
+/*
+ * Room for the command in a 128 byte entry, from the cmd field on.
+ */
+const CMD_AREA_SIZE: usize = 80;
+
+const _: () = assert!(size_of::<NvmeUringCmd>() == 72);
+const _: () = assert!(size_of::<NvmeUringCmd>() <= CMD_AREA_SIZE);
+
+/*
+ * _IOWR('N', nr, struct nvme_uring_cmd).
+ */
+const fn uring_cmd_op(nr: u32) -> u32 {
+    3 << 30 | (size_of::<NvmeUringCmd>() as u32) << 16 | (b'N' as u32) << 8 | nr
+}
+
+pub const NVME_URING_CMD_IO: u32 = uring_cmd_op(0x80);
+pub const NVME_URING_CMD_ADMIN: u32 = uring_cmd_op(0x82);
+
+const NVME_CMD_WRITE: u8 = 0x01;
+const NVME_CMD_READ: u8 = 0x02;
+const NVME_ADMIN_IDENTIFY: u8 = 0x06;
+
+/*
+ * Size of every identify data structure.
+ */
+pub const NVME_IDENTIFY_DATA_SIZE: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeQueue {
+    Io,
+    Admin,
+}
+
+/*
+ * A passthrough command. Data and metadata are given as raw pointers, they
+ * are only read when the command is prepared with `prepare`, which is where
+ * their validity is promised.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmeCommand {
+    queue: NvmeQueue,
+    raw: NvmeUringCmd,
+}
+
+impl NvmeCommand {
+    /*
+     * Any command of the I/O command set, to fill in with the setters.
+     */
+    pub fn io(opcode: u8, nsid: u32) -> Self {
+        NvmeCommand {
+            queue: NvmeQueue::Io,
+            raw: NvmeUringCmd {
+                opcode,
+                nsid,
+                ..Default::default()
+            },
+        }
+    }
+
+    /*
+     * Any admin command, to fill in with the setters.
+     */
+    pub fn admin(opcode: u8) -> Self {
+        NvmeCommand {
+            queue: NvmeQueue::Admin,
+            raw: NvmeUringCmd {
+                opcode,
+                ..Default::default()
+            },
+        }
+    }
+
+    /*
+     * Reads `blocks` logical blocks from `lba` into the `len` bytes at
+     * `buf`, which must hold blocks times the block size of the namespace.
+     */
+    pub fn read(nsid: u32, lba: u64, blocks: u32, buf: *mut u8, len: u32) -> Result<Self> {
+        Self::io(NVME_CMD_READ, nsid)
+            .blocks(lba, blocks)
+            .map(|command| command.data(buf, len))
+    }
+
+    pub fn write(nsid: u32, lba: u64, blocks: u32, buf: *const u8, len: u32) -> Result<Self> {
+        Self::io(NVME_CMD_WRITE, nsid)
+            .blocks(lba, blocks)
+            .map(|command| command.data(buf, len))
+    }
+
+    /*
+     * Identify with controller or namespace structure `cns` into `buf`,
+     * which must hold NVME_IDENTIFY_DATA_SIZE bytes.
+     */
+    pub fn identify(cns: u8, nsid: u32, buf: *mut u8) -> Self {
+        let mut command = Self::admin(NVME_ADMIN_IDENTIFY)
+            .data(buf, NVME_IDENTIFY_DATA_SIZE)
+            .cdw10(cns as u32);
+        command.raw.nsid = nsid;
+        command
+    }
+
+    /*
+     * Starting LBA in cdw10 and cdw11, 0-based number of blocks in the low
+     * 16 bits of cdw12.
+     */
+    fn blocks(mut self, lba: u64, blocks: u32) -> Result<Self> {
+        if blocks == 0 || blocks > u16::MAX as u32 + 1 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a command moves between 1 and 65536 blocks",
+            ));
+        }
+
+        self.raw.cdw10 = lba as u32;
+        self.raw.cdw11 = (lba >> 32) as u32;
+        self.raw.cdw12 = blocks - 1;
+        Ok(self)
+    }
+
+    pub fn data(mut self, buf: *const u8, len: u32) -> Self {
+        self.raw.addr = buf as u64;
+        self.raw.data_len = len;
+        self
+    }
+
+    /*
+     * Separate metadata buffer, for namespaces formatted with metadata that
+     * is not interleaved with the data.
+     */
+    pub fn metadata(mut self, buf: *const u8, len: u32) -> Self {
+        self.raw.metadata = buf as u64;
+        self.raw.metadata_len = len;
+        self
+    }
+
+    pub fn nsid(&self) -> u32 {
+        self.raw.nsid
+    }
+
+    pub fn cdw10(mut self, value: u32) -> Self {
+        self.raw.cdw10 = value;
+        self
+    }
+
+    pub fn cdw11(mut self, value: u32) -> Self {
+        self.raw.cdw11 = value;
+        self
+    }
+
+    pub fn cdw12(mut self, value: u32) -> Self {
+        self.raw.cdw12 = value;
+        self
+    }
+
+    pub fn cdw13(mut self, value: u32) -> Self {
+        self.raw.cdw13 = value;
+        self
+    }
+
+    pub fn cdw14(mut self, value: u32) -> Self {
+        self.raw.cdw14 = value;
+        self
+    }
+
+    pub fn cdw15(mut self, value: u32) -> Self {
+        self.raw.cdw15 = value;
+        self
+    }
+
+    /*
+     * Timeout of the command in the driver, whole milliseconds, instead of
+     * the default of the queue.
+     */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.raw.timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+        self
+    }
+
+    pub fn queue(&self) -> NvmeQueue {
+        self.queue
+    }
+
+    pub fn cmd_op(&self) -> u32 {
+        match self.queue {
+            NvmeQueue::Io => NVME_URING_CMD_IO,
+            NvmeQueue::Admin => NVME_URING_CMD_ADMIN,
+        }
+    }
+}
+
+/// Prepares `command` for the char device `fd` in the next entry of `ring`,
+/// None when the submission queue is full.
+///
+/// # Safety
+///
+/// The data and metadata buffers of `command` must be valid for the
+/// transfer, in the direction of the command, until it completes.
+pub unsafe fn prepare<'r>(
+    ring: &'r mut IoUring<'_, Sqe128, Cqe32>,
+    fd: RawFd,
+    command: &NvmeCommand,
+) -> Option<Sqe<'r>> {
+    let mut sqe = ring.next_sqe()?.uring_cmd(fd, command.cmd_op());
+    let area = sqe.cmd_area();
+    write_bytes(area, 0, CMD_AREA_SIZE);
+    copy_nonoverlapping(
+        &command.raw as *const NvmeUringCmd as *const u8,
+        area,
+        size_of::<NvmeUringCmd>(),
+    );
+
+    Some(sqe)
+}
+
+/*
+ * Outcome of a passthrough command: res is a negative errno when the command
+ * never reached the device, otherwise the NVMe status, with the result dword
+ * of the command in the first extra word of the completion.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmeCompletion {
+    pub status: i32,
+    pub result: u64,
+}
+
+impl NvmeCompletion {
+    /// # Safety
+    ///
+    /// `cqe` must be a completion of a ring with Cqe32 entries, the extra
+    /// words are read past its end.
+    pub unsafe fn from_raw(cqe: &io_uring_cqe) -> Self {
+        NvmeCompletion {
+            status: cqe.res,
+            result: *cqe.big_cqe.as_ptr(),
+        }
+    }
+
+    pub fn into_result(self) -> Result<u64> {
+        match self.status {
+            0 => Ok(self.result),
+            status if status < 0 => Err(io::Error::from_raw_os_error(-status)),
+            status => Err(io::Error::other(format!(
+                "the command failed with NVMe status {:#x}",
+                status
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_passing_nvme_commands_through {
+    use crate::{
+        entry::{Cqe32, Sqe128},
+        io_uring::{IoUring, IoUringParams},
+        nvme::{prepare, NvmeCommand, NvmeCompletion, NvmeQueue, NvmeUringCmd},
+        opcode::IoUringOperation,
+    };
+    use std::{
+        io::{pipe, ErrorKind},
+        os::fd::AsRawFd,
+        ptr::read_unaligned,
+    };
+
+    #[test]
+    pub fn the_ioctl_numbers_match_the_kernel() {
+        assert_eq!(NvmeCommand::io(0, 1).cmd_op(), 0xc048_4e80);
+        assert_eq!(NvmeCommand::admin(0).cmd_op(), 0xc048_4e82);
+    }
+
+    #[test]
+    pub fn a_read_spells_out_the_lba_range() {
+        let mut buf = vec![0u8; 8 * 512];
+        let command =
+            NvmeCommand::read(1, 0x1_0000_0002, 8, buf.as_mut_ptr(), buf.len() as u32).unwrap();
+
+        assert_eq!(command.queue(), NvmeQueue::Io);
+        assert_eq!(
+            (command.raw.cdw10, command.raw.cdw11, command.raw.cdw12),
+            (2, 1, 7)
+        );
+        assert_eq!(command.raw.data_len, 4096);
+        assert_eq!(
+            NvmeCommand::read(1, 0, 0, buf.as_mut_ptr(), 0)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    pub fn the_command_fills_the_big_entry() {
+        let mut ring =
+            IoUring::<Sqe128, Cqe32>::initialize_sized(4, IoUringParams::default()).unwrap();
+        let mut identity = vec![0u8; 4096];
+        let command = NvmeCommand::identify(1, 0, identity.as_mut_ptr());
+
+        unsafe { prepare(&mut ring, 3, &command) }
+            .unwrap()
+            .user_data(9);
+
+        let sqe = ring.send_queue.pending_sqes().next().unwrap();
+        assert_eq!(sqe.opcode, IoUringOperation::UringCmd as u8);
+        assert_eq!(sqe.fd, 3);
+        assert_eq!(
+            unsafe { sqe.__bindgen_anon_1.__bindgen_anon_1.cmd_op },
+            command.cmd_op()
+        );
+        let written: NvmeUringCmd =
+            unsafe { read_unaligned(&sqe.__bindgen_anon_6 as *const _ as *const NvmeUringCmd) };
+        assert_eq!(written, command.raw);
+    }
+
+    #[test]
+    pub fn a_fd_without_passthrough_refuses_the_command() {
+        let mut ring =
+            IoUring::<Sqe128, Cqe32>::initialize_sized(4, IoUringParams::default()).unwrap();
+        let (reader, _writer) = pipe().unwrap();
+        let command = NvmeCommand::admin(0x06);
+
+        unsafe { prepare(&mut ring, reader.as_raw_fd(), &command) }
+            .unwrap()
+            .user_data(9);
+        ring.submit_and_wait(1).unwrap();
+
+        let completion = unsafe { NvmeCompletion::from_raw(ring.peek_raw_cqe().unwrap()) };
+        assert!(completion.status < 0);
+        assert!(completion.into_result().is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+    retry::RetryPolicy,
+    sqe::{IoUringSqeFlags, Sqe},
+};
+use libc::{ECANCELED, ETIME};
+use linux_raw_sys::io_uring::__kernel_timespec;
+use std::{
+    io::{self, ErrorKind, Result},
+    time::Duration,
+};
+
+/*
+ * user_data of an Op and of the LINK_TIMEOUT behind it, not to be used by
+ * requests in flight while an Op runs.
+ */
+const OP_USER_DATA: u64 = u64::MAX - 10;
+const OP_TIMEOUT_USER_DATA: u64 = u64::MAX - 11;
+
+/*
+ * A single operation that is submitted and waited for, see IoUring::op. With
+ * a timeout, a LINK_TIMEOUT is linked behind the entry and an operation that
+ * does not finish in time fails with ErrorKind::TimedOut.
+ */
+pub struct Op<'r, 'a, F, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    prepare: F,
+    timeout: Option<Duration>,
+    retry: Option<RetryPolicy>,
+}
+
+impl<'r, 'a, F, S: SqeEntry, C: CqeEntry> Op<'r, 'a, F, S, C>
+where
+    F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+{
+    pub(crate) fn new(ring: &'r mut IoUring<'a, S, C>, prepare: F) -> Self {
+        Op {
+            ring,
+            prepare,
+            timeout: None,
+            retry: None,
+        }
+    }
+
+    /*
+     * Applies to each attempt when the operation is retried.
+     */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /*
+     * Submits the same entry again when it fails with an error `policy`
+     * covers, the failed attempts are never seen by the caller.
+     */
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /*
+     * Submits the operation and waits for it, returns its result.
+     */
+    pub fn run(self) -> Result<u32> {
+        let ring = self.ring;
+        let timespec = self.timeout.map(|timeout| __kernel_timespec {
+            tv_sec: timeout.as_secs() as i64,
+            tv_nsec: timeout.subsec_nanos() as i64,
+        });
+        let entries = 1 + timespec.is_some() as u32;
+        let mut prepare = Some(self.prepare);
+        let mut entry = Vec::new();
+        let mut attempt = 1;
+
+        loop {
+            if ring.sq_space_left() < entries {
+                ring.submit()?;
+            }
+            if ring.sq_space_left() < entries {
+                return Err(io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "the submission queue is full",
+                ));
+            }
+
+            let Some(sqe) = ring.next_sqe() else {
+                return Err(io::Error::other("the ring was shut down"));
+            };
+            match prepare.take() {
+                Some(prepare) => {
+                    let sqe = prepare(sqe).user_data(OP_USER_DATA);
+                    if timespec.is_some() {
+                        sqe.add_flags(IoUringSqeFlags::IoLink);
+                    }
+                    if self.retry.is_some() {
+                        entry = ring.send_queue.last_entry().unwrap_or_default();
+                    }
+                }
+                None => ring.send_queue.overwrite_last_entry(&entry),
+            }
+            if let Some(timespec) = &timespec {
+                if let Some(sqe) = ring.next_sqe() {
+                    unsafe { sqe.link_timeout(timespec) }.user_data(OP_TIMEOUT_USER_DATA);
+                }
+            }
+            let (completion, timed_out) = if timespec.is_some() {
+                let completions =
+                    ring.wait_for_completions(&[OP_USER_DATA, OP_TIMEOUT_USER_DATA])?;
+                (completions[0], completions[1].result == -ETIME)
+            } else {
+                (ring.wait_for_completion(OP_USER_DATA)?, false)
+            };
+
+            match completion.result {
+                result if result >= 0 => return Ok(result as u32),
+                result if timed_out && result == -ECANCELED => {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        "the operation did not complete in time",
+                    ))
+                }
+                result => {
+                    let opcode = entry.first().copied().unwrap_or_default();
+                    match &self.retry {
+                        Some(policy) if policy.should_retry(opcode, -result, attempt) => {
+                            attempt += 1;
+                        }
+                        _ => return Err(io::Error::from_raw_os_error(-result)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_running_a_single_operation {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        retry::RetryPolicy,
+        sqe::RwFlags,
+    };
+    use libc::{close, pipe, write, EAGAIN};
+    use std::{io::ErrorKind, time::Duration};
+
+    #[test]
+    pub fn an_operation_that_finishes_in_time_returns_its_result() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let result = ring
+            .op(|sqe| sqe.nop())
+            .timeout(Duration::from_secs(5))
+            .run();
+
+        assert_eq!(result.unwrap(), 0);
+        assert!(ring.next_completion().is_none());
+    }
+
+    #[test]
+    pub fn an_operation_still_running_fails_with_timed_out() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        let mut buffer = [0u8; 8];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+
+        let error = ring
+            .op(|sqe| unsafe { sqe.read(fds[0], buffer.as_mut_ptr(), 8, 0) })
+            .timeout(Duration::from_millis(10))
+            .run()
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(ring.next_completion().is_none());
+        assert_eq!(ring.in_flight(), 0);
+
+        unsafe {
+            close(fds[0]);
+            close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn errors_of_the_operation_are_kept() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut buffer = [0u8; 8];
+
+        let error = ring
+            .op(|sqe| unsafe { sqe.read(-1, buffer.as_mut_ptr(), 8, 0) })
+            .timeout(Duration::from_secs(5))
+            .run()
+            .unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    pub fn failures_the_policy_covers_are_submitted_again() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        let mut buffer = [0u8; 8];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+        let policy = RetryPolicy::new(3);
+
+        let error = ring
+            .op(|sqe| {
+                unsafe { sqe.read(fds[0], buffer.as_mut_ptr(), 8, 0) }.rw_flags(RwFlags::NoWait)
+            })
+            .retry(policy.clone())
+            .run()
+            .unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(EAGAIN));
+        assert_eq!(ring.in_flight(), 0);
+
+        assert_eq!(unsafe { write(fds[1], b"ready".as_ptr().cast(), 5) }, 5);
+        let read = ring
+            .op(|sqe| {
+                unsafe { sqe.read(fds[0], buffer.as_mut_ptr(), 8, 0) }.rw_flags(RwFlags::NoWait)
+            })
+            .retry(policy)
+            .run()
+            .unwrap();
+        assert_eq!(read, 5);
+        assert!(ring.next_completion().is_none());
+
+        unsafe {
+            close(fds[0]);
+            close(fds[1]);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use linux_raw_sys::io_uring::io_uring_op;
+
+/*
+ * Operations that can be placed in a submission queue entry.
+ */
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoUringOperation {
+    Nop = io_uring_op::IORING_OP_NOP as u8,
+    Readv = io_uring_op::IORING_OP_READV as u8,
+    Writev = io_uring_op::IORING_OP_WRITEV as u8,
+    Fsync = io_uring_op::IORING_OP_FSYNC as u8,
+    ReadFixed = io_uring_op::IORING_OP_READ_FIXED as u8,
+    WriteFixed = io_uring_op::IORING_OP_WRITE_FIXED as u8,
+    PollAdd = io_uring_op::IORING_OP_POLL_ADD as u8,
+    PollRemove = io_uring_op::IORING_OP_POLL_REMOVE as u8,
+    SyncFileRange = io_uring_op::IORING_OP_SYNC_FILE_RANGE as u8,
+    Sendmsg = io_uring_op::IORING_OP_SENDMSG as u8,
+    Recvmsg = io_uring_op::IORING_OP_RECVMSG as u8,
+    Timeout = io_uring_op::IORING_OP_TIMEOUT as u8,
+    TimeoutRemove = io_uring_op::IORING_OP_TIMEOUT_REMOVE as u8,
+    Accept = io_uring_op::IORING_OP_ACCEPT as u8,
+    AsyncCancel = io_uring_op::IORING_OP_ASYNC_CANCEL as u8,
+    LinkTimeout = io_uring_op::IORING_OP_LINK_TIMEOUT as u8,
+    Connect = io_uring_op::IORING_OP_CONNECT as u8,
+    Fallocate = io_uring_op::IORING_OP_FALLOCATE as u8,
+    Openat = io_uring_op::IORING_OP_OPENAT as u8,
+    Close = io_uring_op::IORING_OP_CLOSE as u8,
+    FilesUpdate = io_uring_op::IORING_OP_FILES_UPDATE as u8,
+    Statx = io_uring_op::IORING_OP_STATX as u8,
+    Read = io_uring_op::IORING_OP_READ as u8,
+    Write = io_uring_op::IORING_OP_WRITE as u8,
+    Fadvise = io_uring_op::IORING_OP_FADVISE as u8,
+    Madvise = io_uring_op::IORING_OP_MADVISE as u8,
+    Send = io_uring_op::IORING_OP_SEND as u8,
+    Recv = io_uring_op::IORING_OP_RECV as u8,
+    Openat2 = io_uring_op::IORING_OP_OPENAT2 as u8,
+    EpollCtl = io_uring_op::IORING_OP_EPOLL_CTL as u8,
+    Splice = io_uring_op::IORING_OP_SPLICE as u8,
+    ProvideBuffers = io_uring_op::IORING_OP_PROVIDE_BUFFERS as u8,
+    RemoveBuffers = io_uring_op::IORING_OP_REMOVE_BUFFERS as u8,
+    Tee = io_uring_op::IORING_OP_TEE as u8,
+    Shutdown = io_uring_op::IORING_OP_SHUTDOWN as u8,
+    Renameat = io_uring_op::IORING_OP_RENAMEAT as u8,
+    Unlinkat = io_uring_op::IORING_OP_UNLINKAT as u8,
+    Mkdirat = io_uring_op::IORING_OP_MKDIRAT as u8,
+    Symlinkat = io_uring_op::IORING_OP_SYMLINKAT as u8,
+    Linkat = io_uring_op::IORING_OP_LINKAT as u8,
+    MsgRing = io_uring_op::IORING_OP_MSG_RING as u8,
+    Fsetxattr = io_uring_op::IORING_OP_FSETXATTR as u8,
+    Setxattr = io_uring_op::IORING_OP_SETXATTR as u8,
+    Fgetxattr = io_uring_op::IORING_OP_FGETXATTR as u8,
+    Getxattr = io_uring_op::IORING_OP_GETXATTR as u8,
+    Socket = io_uring_op::IORING_OP_SOCKET as u8,
+    UringCmd = io_uring_op::IORING_OP_URING_CMD as u8,
+    SendZc = io_uring_op::IORING_OP_SEND_ZC as u8,
+    SendmsgZc = io_uring_op::IORING_OP_SENDMSG_ZC as u8,
+    ReadMultishot = io_uring_op::IORING_OP_READ_MULTISHOT as u8,
+    Waitid = io_uring_op::IORING_OP_WAITID as u8,
+    FutexWait = io_uring_op::IORING_OP_FUTEX_WAIT as u8,
+    FutexWake = io_uring_op::IORING_OP_FUTEX_WAKE as u8,
+    FutexWaitv = io_uring_op::IORING_OP_FUTEX_WAITV as u8,
+    FixedFdInstall = io_uring_op::IORING_OP_FIXED_FD_INSTALL as u8,
+    Ftruncate = io_uring_op::IORING_OP_FTRUNCATE as u8,
+    Bind = io_uring_op::IORING_OP_BIND as u8,
+    Listen = io_uring_op::IORING_OP_LISTEN as u8,
+    RecvZc = io_uring_op::IORING_OP_RECV_ZC as u8,
+    EpollWait = io_uring_op::IORING_OP_EPOLL_WAIT as u8,
+    ReadvFixed = io_uring_op::IORING_OP_READV_FIXED as u8,
+    WritevFixed = io_uring_op::IORING_OP_WRITEV_FIXED as u8,
+    Pipe = io_uring_op::IORING_OP_PIPE as u8,
+}
+
+impl IoUringOperation {
+    pub const ALL: [IoUringOperation; 63] = [
+        IoUringOperation::Nop,
+        IoUringOperation::Readv,
+        IoUringOperation::Writev,
+        IoUringOperation::Fsync,
+        IoUringOperation::ReadFixed,
+        IoUringOperation::WriteFixed,
+        IoUringOperation::PollAdd,
+        IoUringOperation::PollRemove,
+        IoUringOperation::SyncFileRange,
+        IoUringOperation::Sendmsg,
+        IoUringOperation::Recvmsg,
+        IoUringOperation::Timeout,
+        IoUringOperation::TimeoutRemove,
+        IoUringOperation::Accept,
+        IoUringOperation::AsyncCancel,
+        IoUringOperation::LinkTimeout,
+        IoUringOperation::Connect,
+        IoUringOperation::Fallocate,
+        IoUringOperation::Openat,
+        IoUringOperation::Close,
+        IoUringOperation::FilesUpdate,
+        IoUringOperation::Statx,
+        IoUringOperation::Read,
+        IoUringOperation::Write,
+        IoUringOperation::Fadvise,
+        IoUringOperation::Madvise,
+        IoUringOperation::Send,
+        IoUringOperation::Recv,
+        IoUringOperation::Openat2,
+        IoUringOperation::EpollCtl,
+        IoUringOperation::Splice,
+        IoUringOperation::ProvideBuffers,
+        IoUringOperation::RemoveBuffers,
+        IoUringOperation::Tee,
+        IoUringOperation::Shutdown,
+        IoUringOperation::Renameat,
+        IoUringOperation::Unlinkat,
+        IoUringOperation::Mkdirat,
+        IoUringOperation::Symlinkat,
+        IoUringOperation::Linkat,
+        IoUringOperation::MsgRing,
+        IoUringOperation::Fsetxattr,
+        IoUringOperation::Setxattr,
+        IoUringOperation::Fgetxattr,
+        IoUringOperation::Getxattr,
+        IoUringOperation::Socket,
+        IoUringOperation::UringCmd,
+        IoUringOperation::SendZc,
+        IoUringOperation::SendmsgZc,
+        IoUringOperation::ReadMultishot,
+        IoUringOperation::Waitid,
+        IoUringOperation::FutexWait,
+        IoUringOperation::FutexWake,
+        IoUringOperation::FutexWaitv,
+        IoUringOperation::FixedFdInstall,
+        IoUringOperation::Ftruncate,
+        IoUringOperation::Bind,
+        IoUringOperation::Listen,
+        IoUringOperation::RecvZc,
+        IoUringOperation::EpollWait,
+        IoUringOperation::ReadvFixed,
+        IoUringOperation::WritevFixed,
+        IoUringOperation::Pipe,
+    ];
+}
+
+impl TryFrom<u8> for IoUringOperation {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        IoUringOperation::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(value)
+    }
+}
@@ -0,0 +1,216 @@
+use libc::{c_void, iovec};
+use std::{collections::HashMap, mem::forget, ops::Deref};
+
+/*
+ * Buffer handed over to the ring for the duration of an operation. Reads
+ * fill the spare capacity after the initialized bytes, writes send the
+ * initialized bytes.
+ */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OwnedBuf {
+    bytes: Vec<u8>,
+}
+
+impl OwnedBuf {
+    pub fn with_capacity(capacity: usize) -> Self {
+        OwnedBuf {
+            bytes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn spare(&mut self) -> iovec {
+        let len = self.bytes.len();
+        iovec {
+            iov_base: unsafe { self.bytes.as_mut_ptr().add(len) } as *mut c_void,
+            iov_len: self.bytes.capacity() - len,
+        }
+    }
+
+    fn initialized(&mut self) -> iovec {
+        iovec {
+            iov_base: self.bytes.as_mut_ptr() as *mut c_void,
+            iov_len: self.bytes.len(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for OwnedBuf {
+    fn from(bytes: Vec<u8>) -> Self {
+        OwnedBuf { bytes }
+    }
+}
+
+impl Deref for OwnedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+struct HeldOp {
+    direction: Direction,
+    buffers: Vec<OwnedBuf>,
+    /*
+     * Read by the kernel when the entry is issued, which may be long after
+     * the submission, so it is held as long as the buffers.
+     */
+    iovecs: Vec<iovec>,
+}
+
+/*
+ * Buffers of vectored operations the ring holds until their completion, keyed
+ * by user_data. Whatever is still held when the ring goes away is leaked: the
+ * kernel may still be writing to it.
+ */
+#[derive(Default)]
+pub(crate) struct HeldBuffers {
+    ops: HashMap<u64, HeldOp>,
+}
+
+impl HeldBuffers {
+    /*
+     * Keeps the buffers of `user_data`, returns the iovecs to submit, or the
+     * buffers when user_data is already taken.
+     */
+    pub(crate) fn hold(
+        &mut self,
+        user_data: u64,
+        direction: Direction,
+        mut buffers: Vec<OwnedBuf>,
+    ) -> Result<&[iovec], Vec<OwnedBuf>> {
+        if self.ops.contains_key(&user_data) {
+            return Err(buffers);
+        }
+
+        let iovecs = buffers
+            .iter_mut()
+            .map(|buffer| match direction {
+                Direction::Read => buffer.spare(),
+                Direction::Write => buffer.initialized(),
+            })
+            .collect();
+
+        let op = self.ops.entry(user_data).or_insert(HeldOp {
+            direction,
+            buffers,
+            iovecs,
+        });
+
+        Ok(&op.iovecs)
+    }
+
+    /*
+     * Gives the buffers back when the entry could not be submitted after all.
+     */
+    pub(crate) fn release(&mut self, user_data: u64) -> Option<Vec<OwnedBuf>> {
+        self.ops.remove(&user_data).map(|op| op.buffers)
+    }
+
+    /*
+     * Gives the buffers back once the operation completed with `result`. For
+     * reads the bytes the kernel wrote become part of the buffers, in order.
+     */
+    pub(crate) fn complete(&mut self, user_data: u64, result: i32) -> Option<Vec<OwnedBuf>> {
+        let mut op = self.ops.remove(&user_data)?;
+
+        if op.direction == Direction::Read && result > 0 {
+            let mut filled = result as usize;
+            for buffer in op.buffers.iter_mut() {
+                let len = buffer.bytes.len();
+                let count = filled.min(buffer.bytes.capacity() - len);
+                unsafe { buffer.bytes.set_len(len + count) };
+                filled -= count;
+            }
+        }
+
+        Some(op.buffers)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+impl Drop for HeldBuffers {
+    fn drop(&mut self) {
+        for (_, op) in self.ops.drain() {
+            forget(op);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_handing_owned_buffers_to_the_ring {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        owned_buf::OwnedBuf,
+    };
+    use libc::{close, pipe};
+
+    #[test]
+    pub fn vectored_writes_and_reads_give_the_buffers_back() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+
+        let message = vec![OwnedBuf::from(b"hello ".to_vec()), b"world".to_vec().into()];
+        ring.writev_owned(fds[1], message, 0, 1).unwrap();
+        ring.submit_and_wait(1).unwrap();
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.result, 11);
+        let written = ring.take_buffers(&completion).unwrap();
+        assert_eq!(&*written[1], b"world");
+
+        let space = vec![OwnedBuf::with_capacity(4), OwnedBuf::with_capacity(16)];
+        ring.readv_owned(fds[0], space, 0, 2).unwrap();
+        ring.submit_and_wait(1).unwrap();
+        let completion = ring.next_completion().unwrap();
+        let read = ring.take_buffers(&completion).unwrap();
+
+        assert_eq!(&*read[0], b"hell");
+        assert_eq!(&*read[1], b"o world");
+        assert!(ring.take_buffers(&completion).is_none());
+
+        unsafe {
+            close(fds[0]);
+            close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn a_user_data_already_in_use_gets_the_buffers_back() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+
+        ring.readv_owned(fds[0], vec![OwnedBuf::with_capacity(8)], 0, 1)
+            .unwrap();
+        let refused = ring
+            .readv_owned(fds[0], vec![OwnedBuf::with_capacity(8)], 0, 1)
+            .unwrap_err();
+
+        assert_eq!(refused[0].capacity(), 8);
+        assert_eq!(ring.held_buffers(), 1);
+
+        unsafe {
+            close(fds[0]);
+            close(fds[1]);
+        }
+    }
+}
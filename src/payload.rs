@@ -0,0 +1,121 @@
+use log::debug;
+use std::{
+    any::Any,
+    collections::HashMap,
+    io::{self, ErrorKind, Result},
+    mem::size_of,
+};
+
+/*
+ * Payloads of operations the ring holds until their last completion, keyed
+ * by the address of the box, which is what goes out as user_data. The kernel
+ * never reads them, so whatever is left when the ring goes away is dropped,
+ * and reported: an operation whose payload was never taken back is one whose
+ * completion nobody looked at.
+ */
+#[derive(Default)]
+pub(crate) struct OpPayloads {
+    stashed: HashMap<u64, Box<dyn Any + Send>>,
+}
+
+impl OpPayloads {
+    /*
+     * Boxes `payload` and returns the user_data that stands for it. Zero
+     * sized payloads have no address of their own.
+     */
+    pub(crate) fn stash<T: Any + Send>(&mut self, payload: T) -> Result<u64> {
+        if size_of::<T>() == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a zero sized payload has no address to stand for it",
+            ));
+        }
+
+        let payload: Box<dyn Any + Send> = Box::new(payload);
+        let user_data = &*payload as *const (dyn Any + Send) as *const () as u64;
+        self.stashed.insert(user_data, payload);
+
+        Ok(user_data)
+    }
+
+    pub(crate) fn get<T: Any + Send>(&self, user_data: u64) -> Option<&T> {
+        self.stashed.get(&user_data)?.downcast_ref()
+    }
+
+    /*
+     * The payload of `user_data` if it is a T, only once.
+     */
+    pub(crate) fn take<T: Any + Send>(&mut self, user_data: u64) -> Option<T> {
+        if !self.stashed.get(&user_data)?.is::<T>() {
+            return None;
+        }
+
+        self.stashed
+            .remove(&user_data)?
+            .downcast()
+            .ok()
+            .map(|payload| *payload)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.stashed.len()
+    }
+}
+
+impl Drop for OpPayloads {
+    fn drop(&mut self) {
+        if !self.stashed.is_empty() {
+            debug!(
+                "{} operation payloads were never taken back",
+                self.stashed.len()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_stashing_payloads {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::{io::ErrorKind, sync::Arc};
+
+    #[test]
+    pub fn the_payload_comes_back_once_with_the_completion() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let user_data = ring.stash_payload(String::from("request 7")).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(user_data);
+        ring.submit_and_wait(1).unwrap();
+        let completion = ring.next_completion().unwrap();
+
+        assert_eq!(ring.take_payload::<u64>(&completion), None);
+        assert_eq!(
+            ring.take_payload::<String>(&completion).as_deref(),
+            Some("request 7")
+        );
+        assert_eq!(ring.take_payload::<String>(&completion), None);
+        assert_eq!(ring.stashed_payloads(), 0);
+    }
+
+    #[test]
+    pub fn payloads_left_behind_are_dropped_with_the_ring() {
+        let payload = Arc::new(5);
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.stash_payload(payload.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&payload), 2);
+
+        drop(ring);
+
+        assert_eq!(Arc::strong_count(&payload), 1);
+    }
+
+    #[test]
+    pub fn zero_sized_payloads_are_refused() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = ring.stash_payload(()).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    entry::{CqeEntry, SqeEntry},
+    io_uring::IoUring,
+    sqe::Sqe,
+    syscalls::io_uring_send_msg_ring,
+};
+use libc::{pid_t, syscall, SYS_pidfd_getfd, SYS_pidfd_open};
+use linux_raw_sys::io_uring::io_uring_sqe;
+use std::{
+    fs::read_link,
+    io::{self, ErrorKind, Result},
+    mem::zeroed,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+/*
+ * The ring of another process, e.g. of a worker its supervisor launched.
+ * Completions posted to it with MSG_RING show up among the worker's own,
+ * with the user_data and result the sender picked, so the supervisor
+ * signals workers through the rings they already wait on instead of signals
+ * or pipes.
+ *
+ * Since 6.7 the kernel refuses ring fds in SCM_RIGHTS messages, a Unix
+ * socket can only carry the number of the worker's ring fd, see
+ * from_process. Rings received as fds on older kernels go to from_fd.
+ */
+#[derive(Debug)]
+pub struct PeerRing {
+    fd: OwnedFd,
+}
+
+impl PeerRing {
+    /*
+     * InvalidInput unless `fd` is an io_uring.
+     */
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let target = read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))?;
+        if target.as_os_str() != "anon_inode:[io_uring]" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the fd is not an io_uring ring",
+            ));
+        }
+
+        Ok(PeerRing { fd })
+    }
+
+    /*
+     * Copies the ring fd `ring_fd` of the process `pid` with pidfd_getfd,
+     * the worker tells its number, e.g. over a Unix socket. Needs kernel
+     * 5.6 and the right to ptrace the process, which a parent usually has
+     * over its children.
+     */
+    pub fn from_process(pid: pid_t, ring_fd: RawFd) -> Result<Self> {
+        let pidfd = unsafe { syscall(SYS_pidfd_open, pid, 0) };
+        if pidfd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) };
+
+        let fd = unsafe { syscall(SYS_pidfd_getfd, pidfd.as_raw_fd(), ring_fd, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+
+    /*
+     * Posts a completion with `result` and `user_data` to the peer through
+     * `ring`, once the MSG_RING entry completed.
+     */
+    pub fn post<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+        result: u32,
+        user_data: u64,
+    ) -> Result<()> {
+        let ring_fd = self.fd.as_raw_fd();
+        ring.op(|sqe| sqe.msg_ring(ring_fd, result, user_data))
+            .run()
+            .map(|_| ())
+    }
+
+    /*
+     * post without a ring of the sender. Needs kernel 6.13.
+     */
+    pub fn post_ringless(&self, result: u32, user_data: u64) -> Result<()> {
+        let mut raw: io_uring_sqe = unsafe { zeroed() };
+        Sqe::new(&mut raw, None, false).msg_ring(self.fd.as_raw_fd(), result, user_data);
+
+        unsafe { io_uring_send_msg_ring(&raw) }
+    }
+}
+
+impl AsFd for PeerRing {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod when_signalling_a_peer_ring {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        peer::PeerRing,
+    };
+    use std::{
+        fs::File,
+        io::ErrorKind,
+        os::fd::{AsRawFd, OwnedFd},
+        process,
+    };
+
+    #[test]
+    pub fn completions_land_in_the_ring_of_the_process() {
+        let mut worker = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut supervisor = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let peer = PeerRing::from_process(process::id() as i32, worker.as_raw_fd()).unwrap();
+        peer.post(&mut supervisor, 15, 7).unwrap();
+        peer.post_ringless(9, 8).unwrap();
+        worker.submit_and_wait(2).unwrap();
+
+        let first = worker.next_completion().unwrap();
+        let second = worker.next_completion().unwrap();
+        assert_eq!((first.user_data, first.result), (7, 15));
+        assert_eq!((second.user_data, second.result), (8, 9));
+    }
+
+    #[test]
+    pub fn other_fds_are_refused() {
+        let file = File::open("/dev/null").unwrap();
+
+        let error = PeerRing::from_fd(OwnedFd::from(file)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::opcode::IoUringOperation;
+use libc::c_void;
+use linux_raw_sys::io_uring::{io_uring_probe, io_uring_probe_op, IO_URING_OP_SUPPORTED};
+use std::{
+    fmt::{Display, Formatter},
+    mem::zeroed,
+};
+
+#[repr(C)]
+struct ProbeBuffer {
+    header: io_uring_probe,
+    ops: [io_uring_probe_op; Probe::MAX_OPERATIONS as usize],
+}
+
+/*
+ * Result of IORING_REGISTER_PROBE, telling which sqe operations the running
+ * kernel knows about.
+ */
+pub struct Probe {
+    buffer: Box<ProbeBuffer>,
+}
+
+impl Probe {
+    pub(crate) const MAX_OPERATIONS: u32 = 256;
+
+    pub(crate) fn new() -> Self {
+        Probe {
+            buffer: Box::new(unsafe { zeroed() }),
+        }
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *const c_void {
+        &mut *self.buffer as *mut ProbeBuffer as *const c_void
+    }
+
+    pub fn last_operation(&self) -> u8 {
+        self.buffer.header.last_op
+    }
+
+    pub fn is_supported(&self, operation: IoUringOperation) -> bool {
+        let index = operation as usize;
+
+        index < self.buffer.header.ops_len as usize
+            && self.buffer.ops[index].flags as u32 & IO_URING_OP_SUPPORTED != 0
+    }
+
+    pub fn supported_operations(&self) -> Vec<IoUringOperation> {
+        IoUringOperation::ALL
+            .iter()
+            .copied()
+            .filter(|operation| self.is_supported(*operation))
+            .collect()
+    }
+
+    pub fn supported_ops(&self) -> SupportedOps<'_> {
+        SupportedOps { probe: self }
+    }
+}
+
+/*
+ * The operations of the crate against what the probe says of them. Displays
+ * as a table, one operation per line, to log at startup.
+ */
+pub struct SupportedOps<'p> {
+    probe: &'p Probe,
+}
+
+impl SupportedOps<'_> {
+    /*
+     * Whether the kernel supports every one of `operations`, for
+     * applications to check the kernel they need before they start.
+     */
+    pub fn supports_all(&self, operations: &[IoUringOperation]) -> bool {
+        operations
+            .iter()
+            .all(|operation| self.probe.is_supported(*operation))
+    }
+
+    pub fn unsupported(&self) -> Vec<IoUringOperation> {
+        IoUringOperation::ALL
+            .iter()
+            .copied()
+            .filter(|operation| !self.probe.is_supported(*operation))
+            .collect()
+    }
+}
+
+impl Display for SupportedOps<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = IoUringOperation::ALL
+            .iter()
+            .map(|operation| format!("{:?}", operation))
+            .collect();
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+
+        for (operation, name) in IoUringOperation::ALL.iter().zip(&names) {
+            let support = if self.probe.is_supported(*operation) {
+                "supported"
+            } else {
+                "unsupported"
+            };
+            writeln!(f, "{:<width$}  {}", name, support, width = width)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod when_probing_the_ring {
+    use crate::{io_uring::IoUring, io_uring::IoUringParams, opcode::IoUringOperation};
+
+    #[test]
+    pub fn nop_is_always_supported() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let probe = ring.probe().unwrap();
+
+        assert!(probe.is_supported(IoUringOperation::Nop));
+        assert!(probe
+            .supported_operations()
+            .contains(&IoUringOperation::Nop));
+    }
+
+    #[test]
+    pub fn the_table_has_a_line_per_operation() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let probe = ring.probe().unwrap();
+
+        let table = probe.supported_ops().to_string();
+
+        assert_eq!(table.lines().count(), IoUringOperation::ALL.len());
+        assert!(table
+            .lines()
+            .any(|line| line.starts_with("Nop ") && line.ends_with(" supported")));
+    }
+
+    #[test]
+    pub fn every_operation_has_to_be_supported() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let probe = ring.probe().unwrap();
+        let ops = probe.supported_ops();
+
+        assert!(ops.supports_all(&[IoUringOperation::Nop, IoUringOperation::Readv]));
+        assert_eq!(
+            ops.supports_all(&IoUringOperation::ALL),
+            ops.unsupported().is_empty()
+        );
+    }
+}
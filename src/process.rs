@@ -0,0 +1,281 @@
+use crate::{
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    fs::{check, poll_run_completion, run},
+    io_uring::IoUring,
+    opcode::IoUringOperation,
+};
+use libc::{id_t, siginfo_t, CLD_DUMPED, CLD_EXITED, CLD_KILLED, P_PID, WEXITED, WNOHANG};
+use std::{
+    future::Future,
+    io::{self, ErrorKind, Result},
+    mem::zeroed,
+    os::unix::process::ExitStatusExt,
+    pin::Pin,
+    process::{self, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus},
+    task::{Context, Poll},
+};
+
+/*
+ * user_data of the waitid entries of this module.
+ */
+const WAITID_USER_DATA: u64 = u64::MAX - 22;
+
+/*
+ * Spawns `command` with fork and exec as std does, the ring only comes in
+ * to wait for the child. No SIGCHLD handler is needed to learn when it
+ * exits.
+ */
+pub fn spawn(command: &mut Command) -> Result<Child> {
+    let mut child = command.spawn()?;
+
+    Ok(Child {
+        stdin: child.stdin.take(),
+        stdout: child.stdout.take(),
+        stderr: child.stderr.take(),
+        child,
+        status: None,
+    })
+}
+
+/*
+ * A child process reaped through the ring with IORING_OP_WAITID, kernel
+ * 6.7. Like a std Child, dropping it neither kills nor reaps the process.
+ */
+#[derive(Debug)]
+pub struct Child {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    child: process::Child,
+    /*
+     * Set once reaped. The pid may belong to another process from then on,
+     * so it is not signalled or waited for again.
+     */
+    status: Option<ExitStatus>,
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /*
+     * Sends SIGKILL, nothing once the child was reaped.
+     */
+    pub fn kill(&mut self) -> Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+
+        self.child.kill()
+    }
+
+    /*
+     * Waits for the child to exit and reaps it. The waitid is submitted on
+     * the first poll and the future stays Pending while the child runs,
+     * dropping it cancels the wait and leaves the child unreaped.
+     */
+    pub fn wait<'r, 'c, 'a, S: SqeEntry, C: CqeEntry>(
+        &'c mut self,
+        ring: &'r mut IoUring<'a, S, C>,
+    ) -> WaitChild<'r, 'c, 'a, S, C> {
+        WaitChild {
+            ring,
+            child: self,
+            info: Box::new(unsafe { zeroed() }),
+            armed: false,
+        }
+    }
+
+    /*
+     * The exit status when the child already exited, reaping it, None while
+     * it runs.
+     */
+    pub fn try_wait<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<Option<ExitStatus>> {
+        self.reap(ring, WNOHANG)
+    }
+
+    fn reap<S: SqeEntry, C: CqeEntry>(
+        &mut self,
+        ring: &mut IoUring<'_, S, C>,
+        options: i32,
+    ) -> Result<Option<ExitStatus>> {
+        if let Some(status) = self.status {
+            return Ok(Some(status));
+        }
+        check_support(ring)?;
+
+        let mut info: siginfo_t = unsafe { zeroed() };
+        let pid = self.id() as id_t;
+        run(ring, WAITID_USER_DATA, |sqe| unsafe {
+            sqe.waitid(P_PID, pid, &mut info, WEXITED | options)
+        })?;
+
+        /*
+         * With WNOHANG a child still running leaves the info zeroed.
+         */
+        if unsafe { info.si_pid() } == 0 {
+            return Ok(None);
+        }
+        self.status = Some(exit_status(&info));
+
+        Ok(self.status)
+    }
+}
+
+fn check_support<S: SqeEntry, C: CqeEntry>(ring: &IoUring<'_, S, C>) -> Result<()> {
+    if !ring.supports(IoUringOperation::Waitid)? {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "the kernel cannot wait for children through the ring",
+        ));
+    }
+
+    Ok(())
+}
+
+/*
+ * The wait status waitpid(2) would have returned, which ExitStatus wraps.
+ */
+fn exit_status(info: &siginfo_t) -> ExitStatus {
+    let status = unsafe { info.si_status() };
+
+    ExitStatus::from_raw(match info.si_code {
+        CLD_EXITED => (status & 0xff) << 8,
+        CLD_KILLED => status & 0x7f,
+        CLD_DUMPED => (status & 0x7f) | 0x80,
+        _ => status,
+    })
+}
+
+pub struct WaitChild<'r, 'c, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    child: &'c mut Child,
+    /*
+     * Filled by the kernel once the child exits, boxed as the future may
+     * move while the waitid is in flight.
+     */
+    info: Box<siginfo_t>,
+    armed: bool,
+}
+
+impl<'r, 'c, 'a, S: SqeEntry, C: CqeEntry> Future for WaitChild<'r, 'c, 'a, S, C> {
+    type Output = Result<ExitStatus>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(status) = this.child.status {
+            return Poll::Ready(Ok(status));
+        }
+        if !this.armed {
+            if let Err(error) = check_support(this.ring) {
+                return Poll::Ready(Err(error));
+            }
+        }
+
+        let pid = this.child.id() as id_t;
+        let info = &mut *this.info as *mut siginfo_t;
+        let waited = poll_run_completion(
+            this.ring,
+            WAITID_USER_DATA,
+            &mut this.armed,
+            cx,
+            |sqe| unsafe { sqe.waitid(P_PID, pid, info, WEXITED) },
+        );
+        let completion = match waited {
+            Poll::Ready(completion) => completion,
+            Poll::Pending => return Poll::Pending,
+        };
+        if let Err(error) = completion.and_then(|completion| check(&completion)) {
+            return Poll::Ready(Err(error));
+        }
+
+        let status = exit_status(&this.info);
+        this.child.status = Some(status);
+        Poll::Ready(Ok(status))
+    }
+}
+
+impl<'r, 'c, 'a, S: SqeEntry, C: CqeEntry> Drop for WaitChild<'r, 'c, 'a, S, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.ring.abandon(&[WAITID_USER_DATA]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_supervising_children {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        process::spawn,
+    };
+    use futures::{executor::block_on, task::noop_waker};
+    use std::{
+        future::Future,
+        io::Read,
+        os::unix::process::ExitStatusExt,
+        pin::Pin,
+        process::{Command, Stdio},
+        task::Context,
+    };
+
+    #[test]
+    pub fn the_exit_code_comes_back_through_the_ring() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut child = spawn(
+            Command::new("sh")
+                .args(["-c", "echo out; exit 3"])
+                .stdout(Stdio::piped()),
+        )
+        .unwrap();
+
+        let status = block_on(child.wait(&mut ring)).unwrap();
+        assert_eq!(status.code(), Some(3));
+        let mut output = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "out\n");
+
+        assert_eq!(block_on(child.wait(&mut ring)).unwrap(), status);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_killed_child_reports_the_signal() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut child = spawn(Command::new("sleep").arg("10")).unwrap();
+
+        assert_eq!(child.try_wait(&mut ring).unwrap(), None);
+        child.kill().unwrap();
+        let status = block_on(child.wait(&mut ring)).unwrap();
+
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        child.kill().unwrap();
+    }
+
+    #[test]
+    pub fn a_wait_dropped_while_the_child_runs_leaves_it_unreaped() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut child = spawn(Command::new("sleep").arg("10")).unwrap();
+
+        let waker = noop_waker();
+        let mut waiting = child.wait(&mut ring);
+        assert!(Pin::new(&mut waiting)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        drop(waiting);
+        assert_eq!(ring.in_flight(), 0);
+
+        assert_eq!(child.try_wait(&mut ring).unwrap(), None);
+        child.kill().unwrap();
+        block_on(child.wait(&mut ring)).unwrap();
+    }
+}
@@ -0,0 +1,439 @@
+use crate::{
+    cqe::{Completion, Completions},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::{atomic_u32, entry_slot, IoUring, DEFERRED_CLOSE_USER_DATA},
+    sqe::{IoPriority, IoUringSqeFlags, Sqe},
+};
+use libc::c_void;
+use linux_raw_sys::io_uring::io_uring_sqe;
+use std::{
+    cell::UnsafeCell,
+    io::{self, ErrorKind, Result},
+    os::fd::IntoRawFd,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+struct Shared<'a, S: SqeEntry, C: CqeEntry> {
+    /*
+     * Only ever touched by the publisher, producers work on the raw views
+     * below, which point into mappings the ring owns.
+     */
+    ring: UnsafeCell<IoUring<'a, S, C>>,
+    sqes: NonNull<c_void>,
+    head: NonNull<c_void>,
+    mask: u32,
+    entries: u32,
+    default_priority: Option<IoPriority>,
+    cqe_skip: bool,
+    zero_entries: bool,
+    /*
+     * Shadow of the kernel tail, producers claim the entry at `reserved` by
+     * moving it forward.
+     */
+    reserved: AtomicU32,
+    /*
+     * Position last prepared in each slot. Positions are handed out in order,
+     * so a slot holding the position the publisher is at has been filled.
+     */
+    filled: Box<[AtomicU32]>,
+}
+
+/*
+ * The ring is reached only through the publisher, of which there is one, and
+ * producers only write to the slots they reserved.
+ */
+unsafe impl<'a, S: SqeEntry, C: CqeEntry> Send for Shared<'a, S, C> {}
+unsafe impl<'a, S: SqeEntry, C: CqeEntry> Sync for Shared<'a, S, C> {}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Shared<'a, S, C> {
+    /*
+     * Claims `count` consecutive positions, returns the first one.
+     */
+    fn reserve(&self, count: u32) -> Option<u32> {
+        let mut tail = self.reserved.load(Ordering::Relaxed);
+
+        loop {
+            let head = unsafe { atomic_u32(self.head) }.load(Ordering::Acquire);
+            if tail.wrapping_sub(head) + count > self.entries {
+                return None;
+            }
+
+            match self.reserved.compare_exchange_weak(
+                tail,
+                tail.wrapping_add(count),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(tail),
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    /*
+     * The slot of a position, only the one who claimed it may write to it.
+     */
+    fn slot(&self, position: u32) -> *mut io_uring_sqe {
+        unsafe { entry_slot::<S>(self.sqes, position & self.mask, self.zero_entries) }
+    }
+}
+
+/*
+ * Cloneable handle that prepares entries of a ring from any thread. A slot is
+ * claimed with a compare and swap on a userspace shadow of the tail, so
+ * producers never wait for each other; the entries only reach the kernel
+ * once the SqPublisher of the ring submits them.
+ */
+pub struct SqProducer<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    shared: Arc<Shared<'a, S, C>>,
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Clone for SqProducer<'a, S, C> {
+    fn clone(&self) -> Self {
+        SqProducer {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> SqProducer<'a, S, C> {
+    /*
+     * Prepares an entry with `prepare`, WouldBlock when every slot is taken
+     * until the kernel consumes some.
+     */
+    pub fn push<F>(&self, prepare: F) -> Result<()>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        let shared = &*self.shared;
+        let position = shared
+            .reserve(1)
+            .ok_or_else(|| io::Error::new(ErrorKind::WouldBlock, "the submission queue is full"))?;
+
+        let mut reservation = Reservation {
+            shared,
+            position,
+            prepared: false,
+        };
+        let raw = unsafe { &mut *shared.slot(position) };
+        prepare(Sqe::new(raw, shared.default_priority, shared.cqe_skip));
+        reservation.prepared = true;
+
+        Ok(())
+    }
+}
+
+/*
+ * Marks a reserved slot as filled, also when `prepare` panicked: the slot is
+ * then turned into a nop that posts nothing, the publisher would otherwise
+ * stop at it for good.
+ */
+struct Reservation<'s, 'a, S: SqeEntry, C: CqeEntry> {
+    shared: &'s Shared<'a, S, C>,
+    position: u32,
+    prepared: bool,
+}
+
+impl<'s, 'a, S: SqeEntry, C: CqeEntry> Drop for Reservation<'s, 'a, S, C> {
+    fn drop(&mut self) {
+        let index = self.position & self.shared.mask;
+
+        if !self.prepared {
+            let raw = unsafe { &mut *entry_slot::<S>(self.shared.sqes, index, false) };
+            Sqe::new(raw, None, self.shared.cqe_skip)
+                .nop()
+                .flags(IoUringSqeFlags::CqeSkipSuccess);
+        }
+
+        self.shared.filled[index as usize].store(self.position, Ordering::Release);
+    }
+}
+
+/*
+ * The only side of a shared ring that enters the kernel. Entries are
+ * published in the order they were reserved, up to the first one a producer
+ * is still preparing.
+ *
+ * Nothing else may prepare entries on the ring meanwhile, which is why the
+ * ring itself is not reachable from here.
+ */
+pub struct SqPublisher<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    shared: Arc<Shared<'a, S, C>>,
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> SqPublisher<'a, S, C> {
+    pub(crate) fn new(mut ring: IoUring<'a, S, C>) -> (Self, SqProducer<'a, S, C>) {
+        ring.send_queue.producers = true;
+        let send_queue = &ring.send_queue;
+        let mask = send_queue.ring_mask();
+        let entries = send_queue.ring_entries();
+        let start = send_queue.sqe_tail;
+
+        let filled: Box<[AtomicU32]> = (0..entries).map(|_| AtomicU32::new(0)).collect();
+        for offset in 0..entries {
+            let position = start.wrapping_add(offset);
+            filled[(position & mask) as usize]
+                .store(position.wrapping_sub(entries), Ordering::Relaxed);
+        }
+
+        let shared = Arc::new(Shared {
+            sqes: send_queue.sqes.add_offset(0).expect("the sqes are mapped"),
+            head: send_queue.head,
+            mask,
+            entries,
+            default_priority: ring.default_priority,
+            cqe_skip: ring.supports_cqe_skip(),
+            zero_entries: send_queue.zero_entries,
+            reserved: AtomicU32::new(start),
+            filled,
+            ring: UnsafeCell::new(ring),
+        });
+
+        let producer = SqProducer {
+            shared: shared.clone(),
+        };
+
+        (SqPublisher { shared }, producer)
+    }
+
+    pub fn producer(&self) -> SqProducer<'a, S, C> {
+        SqProducer {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /*
+     * Moves the entries producers finished preparing in front of the kernel
+     * tail, returns how many.
+     */
+    pub fn publish(&mut self) -> u32 {
+        let shared = &*self.shared;
+        let ring = unsafe { &mut *shared.ring.get() };
+        let start = ring.send_queue.sqe_tail;
+
+        let mut tail = start;
+        while shared.filled[(tail & shared.mask) as usize].load(Ordering::Acquire) == tail {
+            tail = tail.wrapping_add(1);
+        }
+        ring.send_queue.sqe_tail = tail;
+
+        tail.wrapping_sub(start)
+    }
+
+    pub fn submit(&mut self) -> Result<usize> {
+        self.submit_and_wait(0)
+    }
+
+    /*
+     * Deferred closes, e.g. of a dropped fs::File, are queued first. The ring
+     * cannot put them at its own tail like it does unsplit, producers may be
+     * preparing entries there.
+     */
+    pub fn submit_and_wait(&mut self, wait_nr: u32) -> Result<usize> {
+        self.queue_deferred_closes();
+        self.publish();
+        self.ring().submit_and_wait(wait_nr)
+    }
+
+    pub fn next_completion(&mut self) -> Option<Completion> {
+        self.ring().next_completion()
+    }
+
+    pub fn in_flight(&mut self) -> u32 {
+        self.ring().in_flight()
+    }
+
+    fn ring(&mut self) -> &mut IoUring<'a, S, C> {
+        unsafe { &mut *self.shared.ring.get() }
+    }
+
+    /*
+     * Each close takes two positions claimed like the ones of producers, a
+     * cancel of what is still running on the fd hard linked to the close of
+     * it, as IoUring::queue_deferred_closes does.
+     */
+    fn queue_deferred_closes(&mut self) {
+        let shared = &*self.shared;
+        let deferred_closes = unsafe { &*shared.ring.get() }.deferred_closes();
+        let mut fds = deferred_closes
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        while let Some(fd) = fds.pop() {
+            let Some(position) = shared.reserve(2) else {
+                fds.push(fd);
+                break;
+            };
+            let fd = fd.into_raw_fd();
+            let close = position.wrapping_add(1);
+
+            Sqe::new(
+                unsafe { &mut *shared.slot(position) },
+                None,
+                shared.cqe_skip,
+            )
+            .cancel_fd(fd)
+            .user_data(DEFERRED_CLOSE_USER_DATA)
+            .flags(IoUringSqeFlags::IoHardLink | IoUringSqeFlags::CqeSkipSuccess);
+            Sqe::new(unsafe { &mut *shared.slot(close) }, None, shared.cqe_skip)
+                .close(fd)
+                .user_data(DEFERRED_CLOSE_USER_DATA)
+                .flags(IoUringSqeFlags::CqeSkipSuccess);
+
+            shared.filled[(position & shared.mask) as usize].store(position, Ordering::Release);
+            shared.filled[(close & shared.mask) as usize].store(close, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_producing_entries_from_several_threads {
+    use crate::{
+        fs::File,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{poll, pollfd, POLLHUP};
+    use std::{
+        io::{pipe, ErrorKind, PipeReader},
+        os::fd::{AsRawFd, OwnedFd},
+        panic::{self, AssertUnwindSafe},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn every_entry_reaches_the_kernel_once() {
+        let (mut publisher, producer) = IoUring::initialize(8, IoUringParams::default())
+            .unwrap()
+            .into_producers();
+
+        let mut user_data = thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let producer = producer.clone();
+                scope.spawn(move || {
+                    for index in 0..16 {
+                        while let Err(error) =
+                            producer.push(|sqe| sqe.nop().user_data(thread * 16 + index))
+                        {
+                            assert_eq!(error.kind(), ErrorKind::WouldBlock);
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let mut reaped = Vec::new();
+            while reaped.len() < 64 {
+                publisher.submit().unwrap();
+                while let Some(completion) = publisher.next_completion() {
+                    reaped.push(completion.user_data);
+                }
+                thread::yield_now();
+            }
+            reaped
+        });
+
+        user_data.sort();
+        assert_eq!(user_data, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn a_full_queue_is_reported() {
+        let (_publisher, producer) = IoUring::initialize(2, IoUringParams::default())
+            .unwrap()
+            .into_producers();
+
+        producer.push(|sqe| sqe.nop()).unwrap();
+        producer.push(|sqe| sqe.nop()).unwrap();
+        let error = producer.push(|sqe| sqe.nop()).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    pub fn a_panicking_producer_does_not_hold_the_queue_back() {
+        let (mut publisher, producer) = IoUring::initialize(4, IoUringParams::default())
+            .unwrap()
+            .into_producers();
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            producer
+                .push(|_| panic!("could not prepare the entry"))
+                .unwrap();
+        }));
+        producer.push(|sqe| sqe.nop().user_data(3)).unwrap();
+
+        assert!(panicked.is_err());
+        assert_eq!(publisher.publish(), 2);
+        publisher.submit_and_wait(1).unwrap();
+        assert_eq!(publisher.next_completion().unwrap().user_data, 3);
+        assert!(publisher.next_completion().is_none());
+    }
+
+    #[test]
+    pub fn files_dropped_meanwhile_are_closed_without_losing_entries() {
+        let ring = IoUring::initialize(8, IoUringParams::default()).unwrap();
+        let (readers, mut files): (Vec<PipeReader>, Vec<File>) = (0..8)
+            .map(|_| {
+                let (reader, writer) = pipe().unwrap();
+                let file = File::from(OwnedFd::from(writer)).cancel_on_drop(&ring);
+                (reader, file)
+            })
+            .unzip();
+        let (mut publisher, producer) = ring.into_producers();
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let mut user_data = thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let producer = producer.clone();
+                scope.spawn(move || {
+                    for index in 0..64 {
+                        while producer
+                            .push(|sqe| sqe.nop().user_data(thread * 64 + index))
+                            .is_err()
+                        {
+                            if Instant::now() >= deadline {
+                                return;
+                            }
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let mut reaped = Vec::new();
+            while reaped.len() < 256 {
+                assert!(Instant::now() < deadline, "the publisher got stuck");
+                if reaped.len() >= 16 * (8 - files.len()) {
+                    files.pop();
+                }
+                publisher.submit().unwrap();
+                while let Some(completion) = publisher.next_completion() {
+                    reaped.push(completion.user_data);
+                }
+                thread::yield_now();
+            }
+            reaped
+        });
+
+        user_data.sort();
+        assert_eq!(user_data, (0..256).collect::<Vec<_>>());
+
+        for reader in &readers {
+            let mut hung_up = pollfd {
+                fd: reader.as_raw_fd(),
+                events: 0,
+                revents: 0,
+            };
+            while hung_up.revents & POLLHUP == 0 {
+                publisher.submit().unwrap();
+                assert!(unsafe { poll(&mut hung_up, 1, 1000) } > 0);
+            }
+        }
+        assert_eq!(publisher.in_flight(), 0);
+    }
+}
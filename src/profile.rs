@@ -0,0 +1,166 @@
+use crate::{
+    buf_pool::{BufPool, SizeClass},
+    builder::IoUringBuilder,
+    fixed_buf::FixedBuffers,
+    io_uring::{IoUring, IoUringSetupFlags},
+};
+use std::io::Result;
+
+/*
+ * Provided buffer classes of a NetworkServer ring, in groups 0 and 1: small
+ * messages and headers, and bulk reads.
+ */
+pub const NETWORK_SERVER_BUFFERS: [SizeClass; 2] = [
+    SizeClass {
+        buffer_len: 2048,
+        buffers: 256,
+    },
+    SizeClass {
+        buffer_len: 65536,
+        buffers: 32,
+    },
+];
+
+/*
+ * Size of the fixed buffers of a DirectStorage ring, one per submission
+ * entry up to DIRECT_STORAGE_MAX_BUFFERS.
+ */
+pub const DIRECT_STORAGE_BUFFER_LEN: usize = 128 * 1024;
+pub const DIRECT_STORAGE_MAX_BUFFERS: u32 = 64;
+
+/*
+ * Starting points for the usual kinds of rings, setup flags and
+ * registrations together. The builder of a profile can be tuned further
+ * before it is built, build does the registrations as well.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /*
+     * One thread owning the ring and serving many sockets: SINGLE_ISSUER
+     * and DEFER_TASKRUN, a completion queue eight times the submission
+     * queue for multishot receives and accepts, and provided buffers in
+     * NETWORK_SERVER_BUFFERS.
+     */
+    NetworkServer,
+    /*
+     * O_DIRECT reads and writes on NVMe: IOPOLL, SQPOLL so submitting takes
+     * no syscall, and fixed buffers of DIRECT_STORAGE_BUFFER_LEN. Files must
+     * be opened with O_DIRECT on a device that supports polling.
+     */
+    DirectStorage,
+    /*
+     * Large batches submitted at once and reaped at leisure: SUBMIT_ALL so
+     * one failing entry does not hold back the rest of the batch, and
+     * COOP_TASKRUN since nothing waits on single completions.
+     */
+    Batch,
+}
+
+/*
+ * A ring built from a profile with what the profile registered with it.
+ * The ring is dropped first.
+ */
+pub struct ProfiledRing<'a> {
+    pub ring: IoUring<'a>,
+    pub buffers: Option<BufPool<'a>>,
+    pub fixed_buffers: Option<FixedBuffers>,
+}
+
+impl Profile {
+    /*
+     * The builder of the ring alone, for `entries` submission entries.
+     */
+    pub fn builder(self, entries: u32) -> IoUringBuilder {
+        match self {
+            Profile::NetworkServer => IoUringBuilder::new()
+                .setup_flags(
+                    IoUringSetupFlags::SingleIssuer
+                        | IoUringSetupFlags::DeferTaskRun
+                        | IoUringSetupFlags::CoopTaskRun
+                        | IoUringSetupFlags::Clamp,
+                )
+                .cq_entries(entries.saturating_mul(8)),
+            Profile::DirectStorage => IoUringBuilder::new()
+                .setup_flags(IoUringSetupFlags::IoPoll | IoUringSetupFlags::SqPool),
+            Profile::Batch => IoUringBuilder::new()
+                .setup_flags(IoUringSetupFlags::SubmitAll | IoUringSetupFlags::CoopTaskRun),
+        }
+    }
+
+    pub fn build<'a>(self, entries: u32) -> Result<ProfiledRing<'a>> {
+        let ring = self.builder(entries).build(entries)?;
+
+        match self {
+            Profile::NetworkServer => {
+                let buffers = BufPool::register(&ring, 0, &NETWORK_SERVER_BUFFERS)?;
+                Ok(ProfiledRing {
+                    ring,
+                    buffers: Some(buffers),
+                    fixed_buffers: None,
+                })
+            }
+            Profile::DirectStorage => {
+                let count = ring.sq_entries().min(DIRECT_STORAGE_MAX_BUFFERS);
+                let mut fixed_buffers = FixedBuffers::new(count as u16, DIRECT_STORAGE_BUFFER_LEN);
+                ring.register_fixed_buffers(&mut fixed_buffers)?;
+                Ok(ProfiledRing {
+                    ring,
+                    buffers: None,
+                    fixed_buffers: Some(fixed_buffers),
+                })
+            }
+            Profile::Batch => Ok(ProfiledRing {
+                ring,
+                buffers: None,
+                fixed_buffers: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_building_from_a_profile {
+    use crate::{
+        io_uring::IoUringSetupFlags,
+        profile::{Profile, DIRECT_STORAGE_MAX_BUFFERS},
+    };
+
+    #[test]
+    pub fn a_network_server_gets_a_big_completion_queue_and_buffers() {
+        let profiled = Profile::NetworkServer.build(64).unwrap();
+
+        assert!(profiled
+            .ring
+            .setup_flags()
+            .contains(IoUringSetupFlags::DeferTaskRun));
+        assert_eq!(profiled.ring.cq_entries(), 512);
+        let buffers = profiled.buffers.unwrap();
+        assert_eq!(buffers.group_for(100), Some(0));
+        assert_eq!(buffers.group_for(10000), Some(1));
+    }
+
+    #[test]
+    pub fn direct_storage_gets_fixed_buffers() {
+        let profiled = Profile::DirectStorage.build(256).unwrap();
+
+        assert!(profiled
+            .ring
+            .setup_flags()
+            .contains(IoUringSetupFlags::IoPoll | IoUringSetupFlags::SqPool));
+        assert_eq!(
+            profiled.fixed_buffers.unwrap().len(),
+            DIRECT_STORAGE_MAX_BUFFERS as usize
+        );
+    }
+
+    #[test]
+    pub fn a_batch_ring_registers_nothing() {
+        let profiled = Profile::Batch.build(8).unwrap();
+
+        assert!(profiled
+            .ring
+            .setup_flags()
+            .contains(IoUringSetupFlags::SubmitAll));
+        assert!(profiled.buffers.is_none() && profiled.fixed_buffers.is_none());
+    }
+}
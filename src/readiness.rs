@@ -0,0 +1,269 @@
+use crate::{
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    fs::run,
+    io_uring::IoUring,
+    opcode::IoUringOperation,
+};
+use bitflags::bitflags;
+use libc::{epoll_event, POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI, POLLRDHUP};
+use std::{
+    future::Future,
+    io::{self, ErrorKind, Result},
+    os::fd::{AsRawFd, BorrowedFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/*
+ * user_data of the POLL_ADD of a PollReady.
+ */
+const READY_USER_DATA: u64 = u64::MAX - 18;
+const EPOLL_WAIT_USER_DATA: u64 = u64::MAX - 20;
+
+bitflags! {
+    /*
+     * What to wait for on an fd.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Interest: u32 {
+        const Readable = POLLIN as u32;
+        const Writable = POLLOUT as u32;
+        const Priority = POLLPRI as u32;
+        /*
+         * The peer shut down its writing side, for stream sockets.
+         */
+        const ReadClosed = POLLRDHUP as u32;
+    }
+}
+
+bitflags! {
+    /*
+     * What an fd is ready for, as poll(2) reports it. Error, HangUp and
+     * Invalid come whether they were asked for or not.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Events: u32 {
+        const Readable = POLLIN as u32;
+        const Writable = POLLOUT as u32;
+        const Priority = POLLPRI as u32;
+        const ReadClosed = POLLRDHUP as u32;
+        const Error = POLLERR as u32;
+        const HangUp = POLLHUP as u32;
+        const Invalid = POLLNVAL as u32;
+    }
+}
+
+/*
+ * Waits once for `fd` to be ready for any of `interest` with a POLL_ADD, for
+ * fds the ring has no operation of their own for, e.g. inotify, signalfd or
+ * a device with its own read protocol.
+ *
+ * The first poll prepares the POLL_ADD and submits it, the future then
+ * stays Pending until the fd is ready. Dropped while the poll is armed, it
+ * cancels the poll and waits for the kernel to let go of it.
+ */
+pub fn poll_ready<'r, 'f, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    fd: BorrowedFd<'f>,
+    interest: Interest,
+) -> PollReady<'r, 'f, 'a, S, C> {
+    PollReady {
+        ring,
+        fd,
+        interest,
+        armed: false,
+    }
+}
+
+pub struct PollReady<'r, 'f, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    fd: BorrowedFd<'f>,
+    interest: Interest,
+    armed: bool,
+}
+
+impl<'r, 'f, 'a, S: SqeEntry, C: CqeEntry> PollReady<'r, 'f, 'a, S, C> {
+    fn arm(&mut self) -> Result<()> {
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        let Some(sqe) = self.ring.next_sqe() else {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        };
+        sqe.poll_add(self.fd.as_raw_fd(), self.interest.bits())
+            .user_data(READY_USER_DATA);
+        self.armed = true;
+
+        Ok(())
+    }
+}
+
+impl<'r, 'f, 'a, S: SqeEntry, C: CqeEntry> Future for PollReady<'r, 'f, 'a, S, C> {
+    type Output = Result<Events>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if !this.armed {
+            if let Err(error) = this.arm() {
+                return Poll::Ready(Err(error));
+            }
+        }
+        let completion = match this.ring.poll_completion(READY_USER_DATA, cx) {
+            Poll::Ready(completion) => completion,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.armed = false;
+
+        let completion = completion?;
+        if completion.result < 0 {
+            return Poll::Ready(Err(io::Error::from_raw_os_error(-completion.result)));
+        }
+        Poll::Ready(Ok(Events::from_bits_retain(completion.result as u32)))
+    }
+}
+
+impl<'r, 'f, 'a, S: SqeEntry, C: CqeEntry> Drop for PollReady<'r, 'f, 'a, S, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.ring.abandon(&[READY_USER_DATA]);
+        }
+    }
+}
+
+/*
+ * Waits through the ring for events of an existing epoll set and stores them
+ * in `events`, returns how many. Lets an application with part of its fds
+ * in epoll wait on both from one place instead of blocking in two waits.
+ * Unsupported before IORING_OP_EPOLL_WAIT, kernel 6.15.
+ */
+pub fn epoll_wait<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    epfd: BorrowedFd<'_>,
+    events: &mut [epoll_event],
+) -> Result<usize> {
+    if !ring.supports(IoUringOperation::EpollWait)? {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "the kernel cannot wait for epoll events through the ring",
+        ));
+    }
+
+    let max = events.len().min(i32::MAX as usize) as u32;
+    run(ring, EPOLL_WAIT_USER_DATA, |sqe| unsafe {
+        sqe.epoll_wait(epfd.as_raw_fd(), events.as_mut_ptr(), max)
+    })
+    .map(|count| count as usize)
+}
+
+#[cfg(test)]
+mod when_waiting_for_readiness {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        readiness::{poll_ready, Events, Interest},
+    };
+    use futures::{executor::block_on, task::noop_waker};
+    use std::{
+        future::Future,
+        io::{pipe, Write},
+        os::fd::AsFd,
+        pin::Pin,
+        task::Context,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn the_future_resolves_once_the_fd_is_ready() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, mut writer) = pipe().unwrap();
+
+        let notifier = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            writer.write_all(b"!").unwrap();
+            writer
+        });
+        let events = block_on(poll_ready(&mut ring, reader.as_fd(), Interest::Readable)).unwrap();
+        drop(notifier.join().unwrap());
+
+        assert_eq!(events, Events::Readable);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn a_hang_up_is_reported_without_asking() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, writer) = pipe().unwrap();
+        drop(writer);
+
+        let events = block_on(poll_ready(&mut ring, reader.as_fd(), Interest::Priority)).unwrap();
+
+        assert!(events.contains(Events::HangUp));
+    }
+
+    #[test]
+    pub fn an_armed_poll_is_cancelled_when_dropped() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (reader, _writer) = pipe().unwrap();
+
+        let waker = noop_waker();
+        let mut ready = poll_ready(&mut ring, reader.as_fd(), Interest::Readable);
+        assert!(Pin::new(&mut ready)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        drop(ready);
+
+        assert_eq!(ring.in_flight(), 0);
+    }
+}
+
+#[cfg(test)]
+mod when_waiting_on_an_epoll_set {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        opcode::IoUringOperation,
+        readiness::epoll_wait,
+    };
+    use libc::{epoll_create1, epoll_ctl, epoll_event, EPOLLIN, EPOLL_CLOEXEC, EPOLL_CTL_ADD};
+    use std::{
+        io::{pipe, ErrorKind, Write},
+        os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    #[test]
+    pub fn ready_fds_of_the_set_are_reported() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll_create1(EPOLL_CLOEXEC)) };
+        let (reader, mut writer) = pipe().unwrap();
+        let mut interest = epoll_event {
+            events: EPOLLIN as u32,
+            u64: 42,
+        };
+        assert_eq!(
+            unsafe {
+                epoll_ctl(
+                    epoll.as_raw_fd(),
+                    EPOLL_CTL_ADD,
+                    reader.as_raw_fd(),
+                    &mut interest,
+                )
+            },
+            0
+        );
+        writer.write_all(b"!").unwrap();
+
+        let mut events = [epoll_event { events: 0, u64: 0 }; 4];
+        let result = epoll_wait(&mut ring, epoll.as_fd(), &mut events);
+
+        if !ring.supports(IoUringOperation::EpollWait).unwrap() {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+            return;
+        }
+        assert_eq!(result.unwrap(), 1);
+        let event = events[0];
+        assert_eq!({ event.u64 }, 42);
+        assert_eq!({ event.events }, EPOLLIN as u32);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::opcode::IoUringOperation;
+use libc::{EAGAIN, EINTR};
+use std::collections::HashMap;
+
+/*
+ * Which failed completions an Op submits again. Blocking syscalls retry
+ * EINTR on their own and std hides EAGAIN behind blocking fds, the ring
+ * hands both to the caller, e.g. a read of a socket that raced with the
+ * readiness it was armed on.
+ *
+ * Attempts count the first submission, so one means no retry at all.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    per_operation: HashMap<u8, u32>,
+    errors: Vec<i32>,
+}
+
+impl RetryPolicy {
+    /*
+     * Retries EINTR and EAGAIN of every operation, up to `max_attempts`
+     * submissions in total.
+     */
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            per_operation: HashMap::new(),
+            errors: vec![EINTR, EAGAIN],
+        }
+    }
+
+    /*
+     * Overrides the attempts of `operation`, one leaves it alone.
+     */
+    pub fn operation(mut self, operation: IoUringOperation, max_attempts: u32) -> Self {
+        self.per_operation.insert(operation as u8, max_attempts);
+        self
+    }
+
+    /*
+     * Only retries `errno`s instead of EINTR and EAGAIN.
+     */
+    pub fn errors(mut self, errnos: &[i32]) -> Self {
+        self.errors = errnos.to_vec();
+        self
+    }
+
+    pub fn max_attempts(&self, opcode: u8) -> u32 {
+        self.per_operation
+            .get(&opcode)
+            .copied()
+            .unwrap_or(self.max_attempts)
+    }
+
+    /*
+     * Whether an entry with `opcode` that failed with `errno` on its
+     * `attempt`th submission is submitted again.
+     */
+    pub fn should_retry(&self, opcode: u8, errno: i32, attempt: u32) -> bool {
+        self.errors.contains(&errno) && attempt < self.max_attempts(opcode)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+#[cfg(test)]
+mod when_deciding_on_a_retry {
+    use crate::{opcode::IoUringOperation, retry::RetryPolicy};
+    use libc::{EAGAIN, EBADF, EINTR};
+
+    #[test]
+    pub fn only_the_configured_errors_are_retried() {
+        let policy = RetryPolicy::new(3);
+        let read = IoUringOperation::Read as u8;
+
+        assert!(policy.should_retry(read, EINTR, 1));
+        assert!(policy.should_retry(read, EAGAIN, 2));
+        assert!(!policy.should_retry(read, EBADF, 1));
+        assert!(!policy
+            .clone()
+            .errors(&[EINTR])
+            .should_retry(read, EAGAIN, 1));
+    }
+
+    #[test]
+    pub fn attempts_can_be_set_per_operation() {
+        let policy = RetryPolicy::new(3).operation(IoUringOperation::Write, 1);
+
+        assert!(!policy.should_retry(IoUringOperation::Read as u8, EAGAIN, 3));
+        assert!(policy.should_retry(IoUringOperation::Read as u8, EAGAIN, 2));
+        assert!(!policy.should_retry(IoUringOperation::Write as u8, EAGAIN, 1));
+    }
+}
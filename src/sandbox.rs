@@ -0,0 +1,168 @@
+use crate::{
+    io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+    opcode::IoUringOperation,
+    sqe::IoUringSqeFlags,
+    syscalls::IoUringOpCode,
+};
+use linux_raw_sys::io_uring::{io_uring_register_restriction_op, io_uring_restriction};
+use std::{io::Result, mem::zeroed};
+
+/*
+ * A single entry of the allow-list handed to IORING_REGISTER_RESTRICTIONS.
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Restriction {
+    RegisterOperation(IoUringOpCode),
+    SqeOperation(IoUringOperation),
+    SqeFlagsAllowed(IoUringSqeFlags),
+    SqeFlagsRequired(IoUringSqeFlags),
+}
+
+impl From<&Restriction> for io_uring_restriction {
+    fn from(restriction: &Restriction) -> Self {
+        let mut result: io_uring_restriction = unsafe { zeroed() };
+
+        match *restriction {
+            Restriction::RegisterOperation(opcode) => {
+                result.opcode =
+                    io_uring_register_restriction_op::IORING_RESTRICTION_REGISTER_OP as u16;
+                result.__bindgen_anon_1.register_op = opcode.bits() as u8;
+            }
+            Restriction::SqeOperation(operation) => {
+                result.opcode = io_uring_register_restriction_op::IORING_RESTRICTION_SQE_OP as u16;
+                result.__bindgen_anon_1.sqe_op = operation as u8;
+            }
+            Restriction::SqeFlagsAllowed(flags) => {
+                result.opcode =
+                    io_uring_register_restriction_op::IORING_RESTRICTION_SQE_FLAGS_ALLOWED as u16;
+                result.__bindgen_anon_1.sqe_flags = flags.bits();
+            }
+            Restriction::SqeFlagsRequired(flags) => {
+                result.opcode =
+                    io_uring_register_restriction_op::IORING_RESTRICTION_SQE_FLAGS_REQUIRED as u16;
+                result.__bindgen_anon_1.sqe_flags = flags.bits();
+            }
+        }
+
+        result
+    }
+}
+
+/*
+ * Creates a ring that starts disabled, applies an allow-list of sqe and
+ * register operations and only then enables it. Anything outside of the
+ * allow-list fails with EACCES, which makes the ring safe to hand to code
+ * that should only get a constrained capability set.
+ */
+#[derive(Debug, Default)]
+pub struct SandboxedRing {
+    restrictions: Vec<Restriction>,
+}
+
+impl SandboxedRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_operation(mut self, operation: IoUringOperation) -> Self {
+        self.restrictions.push(Restriction::SqeOperation(operation));
+        self
+    }
+
+    pub fn allow_register(mut self, opcode: IoUringOpCode) -> Self {
+        self.restrictions
+            .push(Restriction::RegisterOperation(opcode));
+        self
+    }
+
+    pub fn allow_sqe_flags(mut self, flags: IoUringSqeFlags) -> Self {
+        self.restrictions.push(Restriction::SqeFlagsAllowed(flags));
+        self
+    }
+
+    pub fn require_sqe_flags(mut self, flags: IoUringSqeFlags) -> Self {
+        self.restrictions.push(Restriction::SqeFlagsRequired(flags));
+        self
+    }
+
+    pub fn restrictions(&self) -> &[Restriction] {
+        &self.restrictions
+    }
+
+    pub fn build<'a>(&self, entries: u32, mut params: IoUringParams) -> Result<IoUring<'a>> {
+        params.flags |= IoUringSetupFlags::RDisabled.bits();
+
+        let ring = IoUring::initialize(entries, params)?;
+        ring.register_restrictions(&self.restrictions)?;
+        ring.enable_rings()?;
+
+        Ok(ring)
+    }
+}
+
+#[cfg(test)]
+mod when_building_a_sandboxed_ring {
+    use crate::{
+        cqe::Completions, io_uring::IoUringParams, opcode::IoUringOperation,
+        sandbox::SandboxedRing, sqe::FsyncFlags, syscalls::IoUringOpCode,
+    };
+    use libc::EACCES;
+    use std::io::ErrorKind;
+
+    #[test]
+    pub fn ring_is_created_and_enabled() {
+        let ring = SandboxedRing::new()
+            .allow_operation(IoUringOperation::Nop)
+            .build(4, IoUringParams::default());
+
+        assert!(ring.is_ok());
+    }
+
+    #[test]
+    pub fn entries_outside_the_allow_list_fail_with_eacces() {
+        let mut ring = SandboxedRing::new()
+            .allow_operation(IoUringOperation::Nop)
+            .build(4, IoUringParams::default())
+            .unwrap();
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.next_sqe()
+            .unwrap()
+            .fsync(-1, FsyncFlags::empty())
+            .user_data(2);
+
+        ring.submit_and_wait(2).unwrap();
+        let mut results: Vec<_> = (0..2)
+            .map(|_| {
+                let completion = ring.next_completion().unwrap();
+                (completion.user_data, completion.result)
+            })
+            .collect();
+        results.sort();
+
+        assert_eq!(results, [(1, 0), (2, -EACCES)]);
+    }
+
+    #[test]
+    pub fn register_operations_outside_the_allow_list_are_denied() {
+        let ring = SandboxedRing::new()
+            .allow_operation(IoUringOperation::Read)
+            .build(4, IoUringParams::default())
+            .unwrap();
+
+        let error = ring.enable_rings().unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    pub fn register_operations_in_the_allow_list_reach_the_kernel() {
+        let ring = SandboxedRing::new()
+            .allow_register(IoUringOpCode::IoRingRegisterEnableRings)
+            .build(4, IoUringParams::default())
+            .unwrap();
+
+        let error = ring.enable_rings().unwrap_err();
+
+        assert_ne!(error.kind(), ErrorKind::PermissionDenied);
+    }
+}
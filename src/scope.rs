@@ -0,0 +1,312 @@
+use crate::{
+    cqe::Completion,
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    fixed_buf::{BufSlice, BufSliceMut, FixedParams},
+    io_uring::IoUring,
+    sqe::Sqe,
+};
+use libc::{EAGAIN, EBUSY};
+use log::debug;
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind, Result},
+    marker::PhantomData,
+    os::fd::RawFd,
+    process::abort,
+};
+
+/*
+ * user_data of the operations of a scope have the top bit set, the rest is a
+ * counter. Zero is left for the cancel requests the scope sends on drop.
+ */
+const SCOPE_USER_DATA: u64 = 1 << 63;
+const CANCEL_USER_DATA: u64 = SCOPE_USER_DATA;
+
+/*
+ * Operations submitted through an OpScope may borrow buffers for 'env, which
+ * outlives the call to IoUring::scope. When the scope ends, normally or by a
+ * panic, whatever is still in flight is cancelled and the scope waits for
+ * every completion, so the kernel is done with the buffers before the borrow
+ * ends.
+ *
+ * The top bit of user_data is reserved for the scope while it is open.
+ * Completions of other requests reaped meanwhile are kept by the ring and
+ * handed out by next_completion afterwards.
+ */
+pub struct OpScope<'r, 'env, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    in_flight: HashSet<u64>,
+    cancels: usize,
+    next_id: u64,
+    __borrows: PhantomData<&'env mut [u8]>,
+}
+
+impl<'r, 'env, 'a, S: SqeEntry, C: CqeEntry> OpScope<'r, 'env, 'a, S, C> {
+    pub(crate) fn new(ring: &'r mut IoUring<'a, S, C>) -> Self {
+        OpScope {
+            ring,
+            in_flight: HashSet::new(),
+            cancels: 0,
+            next_id: 1,
+            __borrows: PhantomData,
+        }
+    }
+
+    /*
+     * Reads into `buf`, returns the user_data of the operation.
+     */
+    pub fn read(&mut self, fd: RawFd, buf: &'env mut [u8], offset: u64) -> Result<u64> {
+        let len = buffer_len(buf)?;
+        let ptr = buf.as_mut_ptr();
+
+        self.push(|sqe| unsafe { sqe.read(fd, ptr, len, offset) })
+    }
+
+    /*
+     * Writes `buf`, returns the user_data of the operation.
+     */
+    pub fn write(&mut self, fd: RawFd, buf: &'env [u8], offset: u64) -> Result<u64> {
+        let len = buffer_len(buf)?;
+
+        self.push(|sqe| unsafe { sqe.write(fd, buf.as_ptr(), len, offset) })
+    }
+
+    pub fn read_fixed(
+        &mut self,
+        fd: RawFd,
+        mut buf: BufSliceMut<'env>,
+        offset: u64,
+    ) -> Result<u64> {
+        let params = FixedParams::from(&mut buf);
+
+        self.push(|sqe| unsafe { sqe.read_fixed(fd, params, offset) })
+    }
+
+    pub fn write_fixed(&mut self, fd: RawFd, buf: BufSlice<'env>, offset: u64) -> Result<u64> {
+        let params = FixedParams::from(&buf);
+
+        self.push(|sqe| unsafe { sqe.write_fixed(fd, params, offset) })
+    }
+
+    pub fn submit(&mut self) -> Result<usize> {
+        self.ring.submit()
+    }
+
+    /*
+     * Submits what is pending and waits for the next operation of the scope
+     * to complete.
+     */
+    pub fn wait(&mut self) -> Result<Completion> {
+        loop {
+            while let Some(completion) = self.ring.next_queued_completion() {
+                if let Some(completion) = self.claim(completion) {
+                    return Ok(completion);
+                }
+            }
+
+            if self.in_flight.is_empty() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "no operation of the scope is in flight",
+                ));
+            }
+
+            self.ring.submit_and_wait(1)?;
+        }
+    }
+
+    /*
+     * Number of operations of the scope that did not complete yet.
+     */
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn push<F>(&mut self, prepare: F) -> Result<u64>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+
+        let user_data = SCOPE_USER_DATA | self.next_id;
+        let sqe = self
+            .ring
+            .next_sqe()
+            .ok_or_else(|| io::Error::new(ErrorKind::WouldBlock, "the submission queue is full"))?;
+        prepare(sqe).user_data(user_data);
+
+        self.next_id += 1;
+        self.in_flight.insert(user_data);
+
+        Ok(user_data)
+    }
+
+    /*
+     * Keeps track of a reaped completion, returns it when it belongs to an
+     * operation of the scope.
+     */
+    fn claim(&mut self, completion: Completion) -> Option<Completion> {
+        if completion.user_data & SCOPE_USER_DATA == 0 {
+            self.ring.defer_completion(completion);
+            None
+        } else if completion.user_data == CANCEL_USER_DATA {
+            self.cancels -= 1;
+            None
+        } else if self.in_flight.remove(&completion.user_data) {
+            Some(completion)
+        } else {
+            None
+        }
+    }
+
+    fn cancel_and_drain(&mut self) -> Result<()> {
+        let mut to_cancel: Vec<u64> = self.in_flight.iter().copied().collect();
+
+        while !self.in_flight.is_empty() || self.cancels > 0 {
+            while self.ring.sq_space_left() > 0 {
+                let Some(user_data) = to_cancel.pop() else {
+                    break;
+                };
+                if let Some(sqe) = self.ring.next_sqe() {
+                    sqe.cancel(user_data).user_data(CANCEL_USER_DATA);
+                    self.cancels += 1;
+                }
+            }
+
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(error) if is_transient(&error) => {}
+                Err(error) => return Err(error),
+            }
+
+            while let Some(completion) = self.ring.next_queued_completion() {
+                self.claim(completion);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'r, 'env, 'a, S: SqeEntry, C: CqeEntry> Drop for OpScope<'r, 'env, 'a, S, C> {
+    fn drop(&mut self) {
+        /*
+         * Returning with requests in flight would let the kernel write to
+         * buffers that are no longer borrowed.
+         */
+        if let Err(error) = self.cancel_and_drain() {
+            debug!("could not drain the operations of the scope: {}", error);
+            abort();
+        }
+    }
+}
+
+fn buffer_len(buf: &[u8]) -> Result<u32> {
+    u32::try_from(buf.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "buffer longer than u32::MAX"))
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::Interrupted
+        || matches!(error.raw_os_error(), Some(EAGAIN) | Some(EBUSY))
+}
+
+#[cfg(test)]
+mod when_scoping_operations {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use libc::{close, pipe, write, ECANCELED};
+    use std::io::ErrorKind;
+
+    fn pipe_fds() -> (i32, i32) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0);
+        (fds[0], fds[1])
+    }
+
+    #[test]
+    pub fn operations_borrow_buffers_from_the_stack() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (read_end, write_end) = pipe_fds();
+        let message = *b"scoped";
+        let mut buffer = [0u8; 6];
+
+        ring.scope(|scope| {
+            let write = scope.write(write_end, &message, 0).unwrap();
+            let read = scope.read(read_end, &mut buffer, 0).unwrap();
+
+            let mut done = [scope.wait().unwrap(), scope.wait().unwrap()];
+            done.sort_by_key(|completion| completion.user_data);
+
+            assert_eq!((done[0].user_data, done[0].result), (write, 6));
+            assert_eq!((done[1].user_data, done[1].result), (read, 6));
+        });
+
+        assert_eq!(&buffer, b"scoped");
+        unsafe {
+            close(read_end);
+            close(write_end);
+        }
+    }
+
+    #[test]
+    pub fn operations_still_in_flight_are_cancelled_when_the_scope_ends() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (read_end, write_end) = pipe_fds();
+        let mut buffer = [0u8; 8];
+
+        let in_flight = ring.scope(|scope| {
+            scope.read(read_end, &mut buffer, 0).unwrap();
+            scope.submit().unwrap();
+            scope.in_flight()
+        });
+
+        assert_eq!(in_flight, 1);
+        assert!(ring.next_completion().is_none());
+
+        unsafe {
+            assert_eq!(write(write_end, b"late".as_ptr().cast(), 4), 4);
+            close(read_end);
+            close(write_end);
+        }
+        assert_eq!(buffer, [0u8; 8]);
+    }
+
+    #[test]
+    pub fn completions_of_other_requests_are_kept() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let (read_end, write_end) = pipe_fds();
+        let mut buffer = [0u8; 8];
+
+        ring.next_sqe().unwrap().nop().user_data(7);
+        ring.submit().unwrap();
+
+        ring.scope(|scope| {
+            scope.read(read_end, &mut buffer, 0).unwrap();
+            scope.submit().unwrap();
+        });
+
+        let completion = ring.next_completion().unwrap();
+        assert_eq!(completion.user_data, 7);
+        assert_ne!(completion.result, -ECANCELED);
+        assert!(ring.next_completion().is_none());
+
+        unsafe {
+            close(read_end);
+            close(write_end);
+        }
+    }
+
+    #[test]
+    pub fn waiting_on_an_empty_scope_fails() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = ring.scope(|scope| scope.wait().unwrap_err());
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
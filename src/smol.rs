@@ -0,0 +1,128 @@
+use crate::{
+    cqe::{Completion, Completions},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+};
+use async_io::Async;
+use std::{
+    future::Future,
+    io::Result,
+    os::fd::{AsFd, OwnedFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/*
+ * A ring driven from the reactor of async-io, the one smol runs on. The ring
+ * fd polls readable while completions are waiting, so a copy of it is
+ * registered as a source and a task waiting for completions parks on the
+ * reactor like one waiting for a socket, no thread of its own blocks in
+ * io_uring_enter.
+ */
+pub struct AsyncRing<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: IoUring<'a, S, C>,
+    source: Async<OwnedFd>,
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> AsyncRing<'a, S, C> {
+    pub fn new(ring: IoUring<'a, S, C>) -> Result<Self> {
+        /*
+         * The copy is what async-io gets to own, the ring fd itself stays
+         * blocking.
+         */
+        let fd = ring.as_fd().try_clone_to_owned()?;
+        let source = Async::new_nonblocking(fd)?;
+
+        Ok(AsyncRing { ring, source })
+    }
+
+    pub fn ring(&self) -> &IoUring<'a, S, C> {
+        &self.ring
+    }
+
+    /*
+     * For preparing entries and reaping completions synchronously.
+     */
+    pub fn ring_mut(&mut self) -> &mut IoUring<'a, S, C> {
+        &mut self.ring
+    }
+
+    pub fn into_inner(self) -> IoUring<'a, S, C> {
+        self.ring
+    }
+
+    /*
+     * Submits what is pending and resolves to the next completion, parking
+     * on the reactor while there is none.
+     */
+    pub fn next_completion(&mut self) -> NextCompletion<'_, 'a, S, C> {
+        NextCompletion { ring: self }
+    }
+
+    fn poll_completion(&mut self, cx: &mut Context<'_>) -> Poll<Result<Completion>> {
+        self.ring.submit()?;
+
+        loop {
+            if let Some(completion) = self.ring.next_completion() {
+                return Poll::Ready(Ok(completion));
+            }
+            match self.source.poll_readable(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pub struct NextCompletion<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut AsyncRing<'a, S, C>,
+}
+
+impl<S: SqeEntry, C: CqeEntry> Future for NextCompletion<'_, '_, S, C> {
+    type Output = Result<Completion>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().ring.poll_completion(cx)
+    }
+}
+
+#[cfg(test)]
+mod when_driving_the_ring_from_async_io {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        smol::AsyncRing,
+    };
+    use async_io::block_on;
+    use std::{
+        io::{pipe, Write},
+        os::fd::AsRawFd,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn a_read_completes_through_the_reactor() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut ring = AsyncRing::new(ring).unwrap();
+        let (reader, mut writer) = pipe().unwrap();
+        let mut buf = [0u8; 5];
+        unsafe {
+            ring.ring_mut()
+                .next_sqe()
+                .unwrap()
+                .read(reader.as_raw_fd(), buf.as_mut_ptr(), 5, 0)
+                .user_data(3);
+        }
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            writer.write_all(b"hello").unwrap();
+        });
+        let completion = block_on(ring.next_completion()).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(completion.user_data, 3);
+        assert_eq!(completion.result, 5);
+        assert_eq!(&buf, b"hello");
+    }
+}
@@ -0,0 +1,192 @@
+use crate::cqe::Completion;
+use linux_raw_sys::io_uring::io_uring_sqe;
+
+/*
+ * One `tracing` span per operation, opened when its entry is submitted under
+ * whatever span is current then, closed by its last completion. Without the
+ * tracing feature this is empty and every call compiles away.
+ */
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+pub(crate) struct OpSpans {
+    pending: std::collections::HashMap<u64, std::collections::VecDeque<PendingOp>>,
+}
+
+#[cfg(feature = "tracing")]
+struct PendingOp {
+    span: tracing::Span,
+    submitted_at: std::time::Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl OpSpans {
+    pub(crate) fn submitted(&mut self, sqe: &io_uring_sqe, expects_completion: bool) {
+        let span = tracing::debug_span!(
+            "io_uring.op",
+            opcode = sqe.opcode,
+            fd = sqe.fd,
+            user_data = sqe.user_data
+        );
+        tracing::trace!(parent: &span, "submitted");
+
+        /*
+         * Entries with CqeSkipSuccess most likely never post a completion,
+         * their span would be held for good.
+         */
+        if expects_completion {
+            self.pending
+                .entry(sqe.user_data)
+                .or_default()
+                .push_back(PendingOp {
+                    span,
+                    submitted_at: std::time::Instant::now(),
+                });
+        }
+    }
+
+    pub(crate) fn completed(&mut self, completion: &Completion) {
+        let Some(ops) = self.pending.get_mut(&completion.user_data) else {
+            return;
+        };
+        let Some(op) = ops.front() else {
+            return;
+        };
+
+        tracing::debug!(
+            parent: &op.span,
+            result = completion.result,
+            flags = completion.flags,
+            latency_us = op.submitted_at.elapsed().as_micros() as u64,
+            "completed"
+        );
+
+        if !completion.more() {
+            ops.pop_front();
+            if ops.is_empty() {
+                self.pending.remove(&completion.user_data);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Default)]
+pub(crate) struct OpSpans {}
+
+#[cfg(not(feature = "tracing"))]
+impl OpSpans {
+    #[inline(always)]
+    pub(crate) fn submitted(&mut self, _sqe: &io_uring_sqe, _expects_completion: bool) {}
+
+    #[inline(always)]
+    pub(crate) fn completed(&mut self, _completion: &Completion) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod when_tracing_operations {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+    };
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    #[derive(Default)]
+    struct Recorded {
+        spans: Vec<(String, u64)>,
+        events: Vec<(u64, String)>,
+    }
+
+    /*
+     * Keeps the name and user_data of each span and the message of each
+     * event with the span it belongs to.
+     */
+    #[derive(Default, Clone)]
+    struct Recorder {
+        recorded: Arc<Mutex<Recorded>>,
+        next_id: Arc<AtomicU64>,
+    }
+
+    #[derive(Default)]
+    struct Fields {
+        user_data: u64,
+        message: String,
+    }
+
+    impl Visit for Fields {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            if field.name() == "user_data" {
+                self.user_data = value;
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            let mut recorded = self.recorded.lock().unwrap();
+            recorded
+                .spans
+                .push((span.metadata().name().to_string(), fields.user_data));
+
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let span = event.parent().map_or(0, |id| id.into_u64());
+            self.recorded
+                .lock()
+                .unwrap()
+                .events
+                .push((span, fields.message));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    pub fn each_operation_gets_a_span_with_its_submission_and_completion() {
+        let recorder = Recorder::default();
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            ring.next_sqe().unwrap().nop().user_data(7);
+            ring.submit_and_wait(1).unwrap();
+            ring.next_completion().unwrap();
+        });
+
+        let recorded = recorder.recorded.lock().unwrap();
+        assert_eq!(recorded.spans, vec![("io_uring.op".to_string(), 7)]);
+        assert_eq!(
+            recorded.events,
+            vec![(1, "submitted".to_string()), (1, "completed".to_string())]
+        );
+        assert!(ring.spans.pending.is_empty());
+    }
+}
@@ -0,0 +1,1187 @@
+use crate::{fixed_buf::FixedParams, opcode::IoUringOperation};
+use bitflags::bitflags;
+use libc::{
+    c_char, epoll_event, id_t, idtype_t, iovec, msghdr, siginfo_t, sockaddr, socklen_t, statx,
+};
+use linux_raw_sys::{
+    general::{RWF_APPEND, RWF_DSYNC, RWF_HIPRI, RWF_NOWAIT, RWF_SYNC},
+    io_uring::{
+        __kernel_timespec, io_uring_msg_ring_flags, io_uring_sqe, io_uring_sqe_flags_bit,
+        IORING_ACCEPT_MULTISHOT, IORING_ASYNC_CANCEL_ALL, IORING_ASYNC_CANCEL_ANY,
+        IORING_ASYNC_CANCEL_FD, IORING_FILE_INDEX_ALLOC, IORING_FSYNC_DATASYNC,
+        IORING_MSG_RING_CQE_SKIP, IORING_RECVSEND_BUNDLE, IORING_RECVSEND_FIXED_BUF,
+        IORING_RECV_MULTISHOT, IORING_SEND_ZC_REPORT_USAGE, IORING_TIMEOUT_MULTISHOT,
+    },
+};
+use std::os::fd::RawFd;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IoUringSqeFlags: u8 {
+        const FixedFile = 1 << io_uring_sqe_flags_bit::IOSQE_FIXED_FILE_BIT as u8;
+        const IoDrain = 1 << io_uring_sqe_flags_bit::IOSQE_IO_DRAIN_BIT as u8;
+        const IoLink = 1 << io_uring_sqe_flags_bit::IOSQE_IO_LINK_BIT as u8;
+        const IoHardLink = 1 << io_uring_sqe_flags_bit::IOSQE_IO_HARDLINK_BIT as u8;
+        const Async = 1 << io_uring_sqe_flags_bit::IOSQE_ASYNC_BIT as u8;
+        const BufferSelect = 1 << io_uring_sqe_flags_bit::IOSQE_BUFFER_SELECT_BIT as u8;
+        const CqeSkipSuccess = 1 << io_uring_sqe_flags_bit::IOSQE_CQE_SKIP_SUCCESS_BIT as u8;
+    }
+}
+
+bitflags! {
+    /*
+     * Per operation flags of preadv2/pwritev2, for read, write, readv and
+     * writev entries.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RwFlags: u32 {
+        const HiPri = RWF_HIPRI; /* polled io, needs an IoPoll ring to be useful */
+        const DSync = RWF_DSYNC; /* per write O_DSYNC */
+        const Sync = RWF_SYNC; /* per write O_SYNC */
+        const NoWait = RWF_NOWAIT; /* fail with EAGAIN instead of blocking */
+        const Append = RWF_APPEND; /* per write O_APPEND */
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FsyncFlags: u32 {
+        const DataSync = IORING_FSYNC_DATASYNC; /* fdatasync instead of fsync */
+    }
+}
+
+const IOPRIO_CLASS_SHIFT: u16 = 13;
+const IOPRIO_LEVEL_MASK: u16 = 0x7;
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriorityClass {
+    None = 0,
+    RealTime = 1,
+    BestEffort = 2,
+    Idle = 3,
+}
+
+/*
+ * I/O priority as understood by ioprio_set(2), level goes from 0, the highest,
+ * to 7. Only honoured by schedulers that implement priorities, e.g. bfq and
+ * mq-deadline.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: IoPriorityClass,
+    pub level: u8,
+}
+
+impl IoPriority {
+    pub fn new(class: IoPriorityClass, level: u8) -> Self {
+        IoPriority { class, level }
+    }
+
+    pub fn bits(&self) -> u16 {
+        ((self.class as u16) << IOPRIO_CLASS_SHIFT) | (self.level as u16 & IOPRIO_LEVEL_MASK)
+    }
+}
+
+/*
+ * Submission queue entry handed out by the ring, in a zeroed slot unless the
+ * ring was told otherwise. Every prep method overwrites the whole entry
+ * either way, so nothing leaks from the operation that used the slot before.
+ */
+pub struct Sqe<'r> {
+    raw: &'r mut io_uring_sqe,
+    default_priority: u16,
+    cqe_skip: bool,
+}
+
+impl<'r> Sqe<'r> {
+    pub(crate) fn new(
+        raw: &'r mut io_uring_sqe,
+        default_priority: Option<IoPriority>,
+        cqe_skip: bool,
+    ) -> Self {
+        Sqe {
+            raw,
+            default_priority: default_priority.map_or(0, |priority| priority.bits()),
+            cqe_skip,
+        }
+    }
+
+    pub(crate) fn prep_rw(
+        &mut self,
+        operation: IoUringOperation,
+        fd: RawFd,
+        addr: u64,
+        len: u32,
+        offset: u64,
+    ) {
+        self.raw.opcode = operation as u8;
+        self.raw.flags = 0;
+        self.raw.ioprio = self.default_priority;
+        self.raw.fd = fd;
+        self.raw.__bindgen_anon_1.off = offset;
+        self.raw.__bindgen_anon_2.addr = addr;
+        self.raw.len = len;
+        self.raw.__bindgen_anon_3.rw_flags = 0;
+        self.raw.user_data = 0;
+        self.raw.__bindgen_anon_4.buf_index = 0;
+        self.raw.personality = 0;
+        self.raw.__bindgen_anon_5.file_index = 0;
+        self.raw.__bindgen_anon_6.bindgen_union_field = [0; 2];
+    }
+
+    /// Escape hatch for operations without a typed prep method yet.
+    ///
+    /// # Safety
+    ///
+    /// The entry is submitted exactly as left, and any pointer stored in it
+    /// must stay valid until the operation completes. On a ring without
+    /// entry zeroing, see IoUring::set_entry_zeroing, the slot may still hold
+    /// the bytes of a previous operation, so every field the kernel reads for
+    /// the chosen opcode must be written.
+    pub unsafe fn raw_sqe(&mut self) -> &mut io_uring_sqe {
+        self.raw
+    }
+
+    pub fn nop(mut self) -> Self {
+        self.prep_rw(IoUringOperation::Nop, -1, 0, 0, 0);
+        /*
+         * The kernel fails a nop with a priority, so the ring default does
+         * not apply to it.
+         */
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Cancels the in-flight request submitted with `user_data`. Completes
+     * with ENOENT when there is no such request and EALREADY when it is
+     * already running and cannot be interrupted.
+     */
+    pub fn cancel(mut self, user_data: u64) -> Self {
+        self.prep_rw(IoUringOperation::AsyncCancel, -1, user_data, 0, 0);
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Cancels every in-flight request on `fd`. Completes with the number of
+     * requests found, or ENOENT when there was none.
+     */
+    pub fn cancel_fd(mut self, fd: RawFd) -> Self {
+        self.prep_rw(IoUringOperation::AsyncCancel, fd, 0, 0, 0);
+        self.raw.__bindgen_anon_3.cancel_flags = IORING_ASYNC_CANCEL_FD | IORING_ASYNC_CANCEL_ALL;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Cancels every in-flight request of the ring.
+     */
+    pub fn cancel_any(mut self) -> Self {
+        self.prep_rw(IoUringOperation::AsyncCancel, -1, 0, 0, 0);
+        self.raw.__bindgen_anon_3.cancel_flags = IORING_ASYNC_CANCEL_ANY | IORING_ASYNC_CANCEL_ALL;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Fails the previous entry of the chain, which must carry IoLink, with
+    /// ECANCELED when it is still running after `timespec`. Completes with
+    /// ETIME when it fired, ECANCELED when the linked entry finished first.
+    ///
+    /// # Safety
+    ///
+    /// `timespec` must stay valid until the entry is submitted, and until it
+    /// completes on an SQPOLL ring.
+    pub unsafe fn link_timeout(mut self, timespec: *const __kernel_timespec) -> Self {
+        self.prep_rw(IoUringOperation::LinkTimeout, -1, timespec as u64, 1, 0);
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Completes with ETIME every `timespec`, `count` times or until it is
+    /// cancelled when `count` is 0. Each completion but the last carries
+    /// CqeFlags::More. Needs a 6.4 kernel.
+    ///
+    /// # Safety
+    ///
+    /// `timespec` must stay valid until the entry is submitted, and until it
+    /// completes on an SQPOLL ring.
+    pub unsafe fn timeout_multishot(
+        mut self,
+        timespec: *const __kernel_timespec,
+        count: u32,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::Timeout,
+            -1,
+            timespec as u64,
+            1,
+            count as u64,
+        );
+        self.raw.__bindgen_anon_3.timeout_flags = IORING_TIMEOUT_MULTISHOT;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of `len` bytes until the operation
+    /// completes.
+    pub unsafe fn read(mut self, fd: RawFd, buf: *mut u8, len: u32, offset: u64) -> Self {
+        self.prep_rw(IoUringOperation::Read, fd, buf as u64, len, offset);
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `buf` must be valid for reads of `len` bytes until the operation
+    /// completes.
+    pub unsafe fn write(mut self, fd: RawFd, buf: *const u8, len: u32, offset: u64) -> Self {
+        self.prep_rw(IoUringOperation::Write, fd, buf as u64, len, offset);
+        self
+    }
+
+    /// Reads into a registered buffer, see FixedBuffers.
+    ///
+    /// # Safety
+    ///
+    /// The slice `buf` was taken from must stay borrowed until the operation
+    /// completes.
+    pub unsafe fn read_fixed(mut self, fd: RawFd, buf: FixedParams, offset: u64) -> Self {
+        self.prep_rw(IoUringOperation::ReadFixed, fd, buf.addr, buf.len, offset);
+        self.raw.__bindgen_anon_4.buf_index = buf.buf_index;
+        self
+    }
+
+    /// Writes from a registered buffer, see FixedBuffers.
+    ///
+    /// # Safety
+    ///
+    /// The slice `buf` was taken from must stay borrowed until the operation
+    /// completes.
+    pub unsafe fn write_fixed(mut self, fd: RawFd, buf: FixedParams, offset: u64) -> Self {
+        self.prep_rw(IoUringOperation::WriteFixed, fd, buf.addr, buf.len, offset);
+        self.raw.__bindgen_anon_4.buf_index = buf.buf_index;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `iovecs` and the buffers they describe must stay valid until the
+    /// operation completes.
+    pub unsafe fn readv(
+        mut self,
+        fd: RawFd,
+        iovecs: *const iovec,
+        count: u32,
+        offset: u64,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::Readv, fd, iovecs as u64, count, offset);
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `iovecs` and the buffers they describe must stay valid until the
+    /// operation completes.
+    pub unsafe fn writev(
+        mut self,
+        fd: RawFd,
+        iovecs: *const iovec,
+        count: u32,
+        offset: u64,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::Writev, fd, iovecs as u64, count, offset);
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of `len` bytes until the operation
+    /// completes. It is ignored when a buffer is selected.
+    pub unsafe fn recv(mut self, fd: RawFd, buf: *mut u8, len: u32, flags: i32) -> Self {
+        self.prep_rw(IoUringOperation::Recv, fd, buf as u64, len, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        /*
+         * ioprio carries the IORING_RECVSEND_* flags here, not a priority.
+         */
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `buf` must be valid for reads of `len` bytes until the operation
+    /// completes. It is ignored when a buffer is selected.
+    pub unsafe fn send(mut self, fd: RawFd, buf: *const u8, len: u32, flags: i32) -> Self {
+        self.prep_rw(IoUringOperation::Send, fd, buf as u64, len, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Sends `buf` without copying it where the socket allows it. Two
+    /// completions carry the user_data: the result of the send, with More
+    /// set, then a notification once the kernel is done with `buf`. The
+    /// notification reports whether the data ended up copied after all,
+    /// which needs kernel 6.2.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for reads of `len` bytes until the notification
+    /// completes, not just the send.
+    pub unsafe fn send_zc(mut self, fd: RawFd, buf: *const u8, len: u32, flags: i32) -> Self {
+        self.prep_rw(IoUringOperation::SendZc, fd, buf as u64, len, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        self.raw.ioprio = IORING_SEND_ZC_REPORT_USAGE as u16;
+        self
+    }
+
+    /// send_zc from a registered buffer, see FixedBuffers. The pages were
+    /// pinned when the buffers were registered, the send skips pinning them
+    /// again.
+    ///
+    /// # Safety
+    ///
+    /// The slice `buf` was taken from must stay borrowed until the
+    /// notification completes, not just the send.
+    pub unsafe fn send_zc_fixed(mut self, fd: RawFd, buf: FixedParams, flags: i32) -> Self {
+        self = self.send_zc(fd, buf.addr as *const u8, buf.len, flags);
+        self.raw.ioprio |= IORING_RECVSEND_FIXED_BUF as u16;
+        self.raw.__bindgen_anon_4.buf_index = buf.buf_index;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `msg`, and the name, control and iovec buffers it points to, must stay
+    /// valid until the operation completes. With a selected buffer the iovecs
+    /// are ignored.
+    pub unsafe fn recvmsg(mut self, fd: RawFd, msg: *mut msghdr, flags: i32) -> Self {
+        self.prep_rw(IoUringOperation::Recvmsg, fd, msg as u64, 1, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Receives a message per completion into buffers of `group_id` until it
+    /// fails or the group runs dry. Each buffer holds the header, name,
+    /// control data and payload of its message, see net::RecvMsgOut. Only
+    /// msg_namelen and msg_controllen of `msg` are used, to size the room
+    /// for the name and the control data in each buffer.
+    ///
+    /// # Safety
+    ///
+    /// `msg` must stay valid until the last completion.
+    pub unsafe fn recvmsg_multishot(
+        self,
+        fd: RawFd,
+        msg: *const msghdr,
+        flags: i32,
+        group_id: u16,
+    ) -> Self {
+        self.recvmsg(fd, msg as *mut msghdr, flags)
+            .buffer_select(group_id)
+            .recv_multishot()
+    }
+
+    /// # Safety
+    ///
+    /// `msg`, and the name, control and iovec buffers it points to, must stay
+    /// valid until the operation completes.
+    pub unsafe fn sendmsg(mut self, fd: RawFd, msg: *const msghdr, flags: i32) -> Self {
+        self.prep_rw(IoUringOperation::Sendmsg, fd, msg as u64, 1, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// sendmsg without copying, with the two completions of send_zc. With
+    /// `buf_index`, the iovecs of `msg` all lie within that registered
+    /// buffer, which needs kernel 6.15.
+    ///
+    /// # Safety
+    ///
+    /// `msg`, and the buffers it points to, must stay valid until the
+    /// notification completes, not just the send.
+    pub unsafe fn sendmsg_zc(
+        mut self,
+        fd: RawFd,
+        msg: *const msghdr,
+        flags: i32,
+        buf_index: Option<u16>,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::SendmsgZc, fd, msg as u64, 1, 0);
+        self.raw.__bindgen_anon_3.msg_flags = flags as u32;
+        self.raw.ioprio = IORING_SEND_ZC_REPORT_USAGE as u16;
+        if let Some(buf_index) = buf_index {
+            self.raw.ioprio |= IORING_RECVSEND_FIXED_BUF as u16;
+            self.raw.__bindgen_anon_4.buf_index = buf_index;
+        }
+        self
+    }
+
+    /// Waits for a state change of the children `idtype` and `id` select
+    /// and stores it in `info`, like waitid(2) with `options`.
+    ///
+    /// # Safety
+    ///
+    /// `info` must be valid for writes until the operation completes.
+    pub unsafe fn waitid(
+        mut self,
+        idtype: idtype_t,
+        id: id_t,
+        info: *mut siginfo_t,
+        options: i32,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::Waitid, id as i32, 0, idtype, info as u64);
+        self.raw.__bindgen_anon_5.file_index = options as u32;
+        self
+    }
+
+    /// Waits until the futex at `futex` is woken, with FUTEX2_* `flags`,
+    /// the size included. Fails with EAGAIN right away when the futex does
+    /// not hold `value` anymore. Only wakes matching `mask` count.
+    ///
+    /// # Safety
+    ///
+    /// `futex` must stay valid until the operation completes.
+    pub unsafe fn futex_wait(
+        mut self,
+        futex: *const u32,
+        value: u64,
+        mask: u64,
+        flags: u32,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::FutexWait,
+            flags as i32,
+            futex as u64,
+            0,
+            value,
+        );
+        self.raw.__bindgen_anon_6.bindgen_union_field[0] = mask;
+        self
+    }
+
+    /// Wakes up to `count` waiters of `futex` whose mask overlaps `mask`,
+    /// the result is how many woke up.
+    ///
+    /// # Safety
+    ///
+    /// `futex` must stay valid until the operation completes.
+    pub unsafe fn futex_wake(
+        mut self,
+        futex: *const u32,
+        count: u64,
+        mask: u64,
+        flags: u32,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::FutexWake,
+            flags as i32,
+            futex as u64,
+            0,
+            count,
+        );
+        self.raw.__bindgen_anon_6.bindgen_union_field[0] = mask;
+        self
+    }
+
+    /*
+     * Passes command `cmd_op` of the driver of `fd` through to it, e.g. NVMe
+     * passthrough on a char device. The payload of the command goes to
+     * cmd_area, the first 16 bytes are zeroed here, the rest of the area of
+     * a 128 byte entry is left to whoever fills it.
+     */
+    pub fn uring_cmd(mut self, fd: RawFd, cmd_op: u32) -> Self {
+        self.prep_rw(IoUringOperation::UringCmd, fd, 0, 0, 0);
+        self.raw.__bindgen_anon_1.__bindgen_anon_1.cmd_op = cmd_op;
+        self
+    }
+
+    /// Start of the command payload of a URING_CMD.
+    ///
+    /// # Safety
+    ///
+    /// Only 16 bytes of it are part of a 64 byte entry, writing more needs a
+    /// ring with Sqe128 entries.
+    pub(crate) unsafe fn cmd_area(&mut self) -> *mut u8 {
+        &mut self.raw.__bindgen_anon_6 as *mut _ as *mut u8
+    }
+
+    /*
+     * Creates a socket like socket(2), the result is its fd.
+     */
+    pub fn socket(mut self, domain: i32, kind: i32, protocol: i32) -> Self {
+        self.prep_rw(
+            IoUringOperation::Socket,
+            domain,
+            0,
+            protocol as u32,
+            kind as u64,
+        );
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `addr` must be valid for reads of `len` bytes until the operation
+    /// completes.
+    pub unsafe fn connect(mut self, fd: RawFd, addr: *const sockaddr, len: socklen_t) -> Self {
+        self.prep_rw(IoUringOperation::Connect, fd, addr as u64, 0, len as u64);
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Accepts a connection on the listening socket `fd` like accept4(2),
+    /// the peer address goes to `addr` and its length to `len`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be valid for writes of `*len` bytes and `len` for reads
+    /// and writes until the operation completes.
+    pub unsafe fn accept(
+        mut self,
+        fd: RawFd,
+        addr: *mut sockaddr,
+        len: *mut socklen_t,
+        flags: i32,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::Accept, fd, addr as u64, 0, len as u64);
+        self.raw.__bindgen_anon_3.accept_flags = flags as u32;
+        /*
+         * ioprio carries the IORING_ACCEPT_* flags here, not a priority.
+         */
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `path` must point to a nul terminated string that stays valid until
+    /// the operation completes.
+    pub unsafe fn openat(
+        mut self,
+        dirfd: RawFd,
+        path: *const c_char,
+        flags: i32,
+        mode: u32,
+    ) -> Self {
+        self.prep_rw(IoUringOperation::Openat, dirfd, path as u64, mode, 0);
+        self.raw.__bindgen_anon_3.open_flags = flags as u32;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Installs the file in `source_slot` of the file table of this ring into
+     * `target_slot` of the table of the ring behind `ring_fd`, or into a
+     * free slot of it with None. That ring gets a completion of its own with
+     * `user_data`, unless skip_target_completion is set. Both completions
+     * carry the slot picked with None as result, 0 with a target slot.
+     */
+    pub fn msg_ring_fd(
+        mut self,
+        ring_fd: RawFd,
+        source_slot: u32,
+        target_slot: Option<u32>,
+        user_data: u64,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::MsgRing,
+            ring_fd,
+            io_uring_msg_ring_flags::IORING_MSG_SEND_FD as u64,
+            0,
+            user_data,
+        );
+        self.raw.__bindgen_anon_6.bindgen_union_field[0] = source_slot as u64;
+        self.raw.__bindgen_anon_5.file_index = match target_slot {
+            Some(slot) => slot + 1,
+            None => IORING_FILE_INDEX_ALLOC as u32,
+        };
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Posts a completion with `result` and `user_data` to the ring behind
+     * `ring_fd`, e.g. to wake a thread waiting on it. This entry completes
+     * with 0 once it is posted.
+     */
+    pub fn msg_ring(mut self, ring_fd: RawFd, result: u32, user_data: u64) -> Self {
+        self.prep_rw(
+            IoUringOperation::MsgRing,
+            ring_fd,
+            io_uring_msg_ring_flags::IORING_MSG_DATA as u64,
+            result,
+            user_data,
+        );
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Keeps a msg_ring entry from posting a completion to the target ring.
+     */
+    pub fn skip_target_completion(self) -> Self {
+        unsafe { self.raw.__bindgen_anon_3.msg_ring_flags |= IORING_MSG_RING_CQE_SKIP };
+        self
+    }
+
+    /*
+     * Completes once `fd` is ready for any of the poll(2) `events`, with the
+     * events that are ready as result.
+     */
+    pub fn poll_add(mut self, fd: RawFd, events: u32) -> Self {
+        self.prep_rw(IoUringOperation::PollAdd, fd, 0, 0, 0);
+        self.raw.__bindgen_anon_3.poll32_events = events;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// Waits for events of the epoll set `epfd` like epoll_wait(2) with no
+    /// timeout, the result is the number of events stored. Needs
+    /// IoUringOperation::EpollWait, see IoUring::supports.
+    ///
+    /// # Safety
+    ///
+    /// `events` must be valid for writes of `max` entries until the
+    /// operation completes.
+    pub unsafe fn epoll_wait(mut self, epfd: RawFd, events: *mut epoll_event, max: u32) -> Self {
+        self.prep_rw(IoUringOperation::EpollWait, epfd, events as u64, max, 0);
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Moves `len` bytes from `fd_in` to `fd_out` without copying them through
+     * userspace, one of the two must be a pipe. None for an offset uses and
+     * advances the file position, it is the only option for a pipe.
+     */
+    pub fn splice(
+        mut self,
+        fd_in: RawFd,
+        off_in: Option<u64>,
+        fd_out: RawFd,
+        off_out: Option<u64>,
+        len: u32,
+        flags: u32,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::Splice,
+            fd_out,
+            off_in.unwrap_or(u64::MAX),
+            len,
+            off_out.unwrap_or(u64::MAX),
+        );
+        self.raw.__bindgen_anon_3.splice_flags = flags;
+        self.raw.__bindgen_anon_5.splice_fd_in = fd_in;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `path` must point to a nul terminated string and `statxbuf` must be
+    /// valid for writes, both until the operation completes.
+    pub unsafe fn statx(
+        mut self,
+        dirfd: RawFd,
+        path: *const c_char,
+        flags: i32,
+        mask: u32,
+        statxbuf: *mut statx,
+    ) -> Self {
+        self.prep_rw(
+            IoUringOperation::Statx,
+            dirfd,
+            path as u64,
+            mask,
+            statxbuf as u64,
+        );
+        self.raw.__bindgen_anon_3.statx_flags = flags as u32;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    pub fn fsync(mut self, fd: RawFd, flags: FsyncFlags) -> Self {
+        self.prep_rw(IoUringOperation::Fsync, fd, 0, 0, 0);
+        self.raw.__bindgen_anon_3.fsync_flags = flags.bits();
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * sync_file_range(2), `flags` are the SYNC_FILE_RANGE_* ones.
+     */
+    pub fn sync_file_range(mut self, fd: RawFd, offset: u64, len: u32, flags: u32) -> Self {
+        self.prep_rw(IoUringOperation::SyncFileRange, fd, 0, len, offset);
+        self.raw.__bindgen_anon_3.sync_range_flags = flags;
+        self.raw.ioprio = 0;
+        self
+    }
+
+    pub fn close(mut self, fd: RawFd) -> Self {
+        self.prep_rw(IoUringOperation::Close, fd, 0, 0, 0);
+        self.raw.ioprio = 0;
+        self
+    }
+
+    /*
+     * Closes the file in `slot` of the registered file table.
+     */
+    pub fn close_direct(self, slot: u32) -> Self {
+        self.close(0).file_slot(slot)
+    }
+
+    /*
+     * Makes an openat or accept install the file in `slot` of the registered
+     * file table instead of returning a regular fd. The kernel refuses
+     * O_CLOEXEC for these, there is no fd to close on exec.
+     */
+    pub fn file_slot(self, slot: u32) -> Self {
+        self.raw.__bindgen_anon_5.file_index = slot + 1;
+        self
+    }
+
+    /*
+     * Lets a recv or send with a selected buffer use as many buffers of the
+     * group as it needs in one completion, see BufRing::consume_bundle.
+     * Needs IoUringFeatures::RecvSendBundle.
+     */
+    pub fn bundle(self) -> Self {
+        self.raw.ioprio |= IORING_RECVSEND_BUNDLE as u16;
+        self
+    }
+
+    /*
+     * Keeps a recv or recvmsg with a selected buffer armed, it posts a
+     * completion per message, flagged with more, until it fails or runs out
+     * of buffers.
+     */
+    pub fn recv_multishot(self) -> Self {
+        self.raw.ioprio |= IORING_RECV_MULTISHOT as u16;
+        self
+    }
+
+    /*
+     * Keeps an accept armed, it posts a completion per connection, flagged
+     * with more, until it fails or is cancelled. Needs kernel 5.19, and a
+     * null addr and len: one buffer would be shared by every connection.
+     */
+    pub fn accept_multishot(self) -> Self {
+        self.raw.ioprio |= IORING_ACCEPT_MULTISHOT as u16;
+        self
+    }
+
+    /*
+     * Only meaningful on read, write, readv and writev entries, other
+     * operations read the same field as their own flags.
+     */
+    pub fn rw_flags(self, flags: RwFlags) -> Self {
+        self.raw.__bindgen_anon_3.rw_flags = flags.bits();
+        self
+    }
+
+    /*
+     * Lets the kernel pick the buffer from the group when the operation runs,
+     * the buffer address of the entry is ignored.
+     */
+    pub fn buffer_select(self, group_id: u16) -> Self {
+        self.raw.flags |= IoUringSqeFlags::BufferSelect.bits();
+        self.raw.__bindgen_anon_4.buf_group = group_id;
+        self
+    }
+
+    pub fn user_data(self, user_data: u64) -> Self {
+        self.raw.user_data = user_data;
+        self
+    }
+
+    pub fn flags(self, flags: IoUringSqeFlags) -> Self {
+        self.raw.flags = flags.bits();
+        self
+    }
+
+    /*
+     * Sets `flags` on top of the ones the entry already has.
+     */
+    pub(crate) fn add_flags(self, flags: IoUringSqeFlags) -> Self {
+        self.raw.flags |= flags.bits();
+        self
+    }
+
+    /*
+     * Posts no completion when the operation succeeds, only a failure shows
+     * up, for fire-and-forget writes. Such entries are not counted as in
+     * flight. On a kernel without IORING_FEAT_CQE_SKIP, before 5.17, this
+     * does nothing and the entry completes as usual.
+     */
+    pub fn skip_success(self) -> Self {
+        if self.cqe_skip {
+            self.add_flags(IoUringSqeFlags::CqeSkipSuccess)
+        } else {
+            self
+        }
+    }
+
+    /*
+     * Overrides the ring's default priority for this entry.
+     */
+    pub fn io_priority(self, priority: IoPriority) -> Self {
+        self.raw.ioprio = priority.bits();
+        self
+    }
+}
+
+#[cfg(test)]
+mod when_using_the_raw_escape_hatch {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        opcode::IoUringOperation,
+    };
+    use linux_raw_sys::io_uring::IORING_NOP_INJECT_RESULT;
+
+    #[test]
+    pub fn hand_written_entries_reach_the_kernel() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut sqe = ring.next_sqe().unwrap().nop().user_data(5);
+
+        unsafe {
+            let raw = sqe.raw_sqe();
+            raw.opcode = IoUringOperation::Nop as u8;
+            raw.__bindgen_anon_3.nop_flags = IORING_NOP_INJECT_RESULT;
+            raw.len = 7;
+        }
+        ring.submit_and_wait(1).unwrap();
+
+        let cqe = ring.peek_raw_cqe().unwrap();
+        assert_eq!(cqe.user_data, 5);
+        assert_eq!(cqe.res, 7);
+        ring.advance_completions(1);
+        assert!(ring.peek_raw_cqe().is_none());
+    }
+}
+
+#[cfg(test)]
+mod when_prioritizing_submissions {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        opcode::IoUringOperation,
+        sqe::{IoPriority, IoPriorityClass},
+    };
+
+    #[test]
+    pub fn the_priority_is_encoded_like_ioprio_set() {
+        assert_eq!(
+            IoPriority::new(IoPriorityClass::RealTime, 0).bits(),
+            1 << 13
+        );
+        assert_eq!(
+            IoPriority::new(IoPriorityClass::BestEffort, 4).bits(),
+            (2 << 13) | 4
+        );
+        assert_eq!(
+            IoPriority::new(IoPriorityClass::Idle, 9).bits(),
+            (3 << 13) | 1
+        );
+    }
+
+    #[test]
+    pub fn entries_take_the_ring_default_unless_overridden() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let idle = IoPriority::new(IoPriorityClass::Idle, 7);
+        let realtime = IoPriority::new(IoPriorityClass::RealTime, 0);
+        ring.set_default_io_priority(Some(idle));
+
+        let mut sqe = ring.next_sqe().unwrap();
+        sqe.prep_rw(IoUringOperation::Read, -1, 0, 0, 0);
+        assert_eq!(unsafe { sqe.raw_sqe() }.ioprio, idle.bits());
+
+        let mut sqe = sqe.io_priority(realtime);
+        assert_eq!(unsafe { sqe.raw_sqe() }.ioprio, realtime.bits());
+    }
+
+    #[test]
+    pub fn a_nop_ignores_the_ring_default() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.set_default_io_priority(Some(IoPriority::new(IoPriorityClass::Idle, 7)));
+
+        ring.next_sqe().unwrap().nop();
+        ring.submit_and_wait(1).unwrap();
+
+        assert_eq!(ring.next_completion().unwrap().result, 0);
+    }
+}
+
+#[cfg(test)]
+mod when_passing_rw_flags {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        sqe::RwFlags,
+    };
+    use std::{
+        fs::{self, OpenOptions},
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    pub fn a_nowait_read_of_an_empty_pipe_fails_with_eagain() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut buffer = [0u8; 8];
+
+        unsafe {
+            ring.next_sqe()
+                .unwrap()
+                .read(fds[0], buffer.as_mut_ptr(), 8, u64::MAX)
+        }
+        .rw_flags(RwFlags::NoWait);
+        ring.submit_and_wait(1).unwrap();
+
+        assert_eq!(ring.next_completion().unwrap().result, -libc::EAGAIN);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    pub fn an_append_write_ignores_the_offset() {
+        let path = std::env::temp_dir().join(format!("bounded-rwf-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        for _ in 0..2 {
+            unsafe {
+                ring.next_sqe()
+                    .unwrap()
+                    .write(file.as_raw_fd(), b"ab".as_ptr(), 2, 0)
+            }
+            .rw_flags(RwFlags::Append);
+            ring.submit_and_wait(1).unwrap();
+            assert_eq!(ring.next_completion().unwrap().result, 2);
+        }
+
+        assert_eq!(fs::read(&path).unwrap(), b"abab");
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod when_preparing_a_used_slot {
+    use crate::{
+        builder::IoUringBuilder,
+        cqe::Completions,
+        fixed_buf::FixedParams,
+        io_uring::{IoUring, IoUringParams},
+        sqe::{FsyncFlags, Sqe},
+    };
+    use linux_raw_sys::io_uring::{__kernel_timespec, io_uring_sqe};
+    use std::{mem::size_of, ptr};
+
+    type Prepare<'p> = Box<dyn Fn(Sqe<'_>) -> Sqe<'_> + 'p>;
+
+    fn bytes(raw: &io_uring_sqe) -> Vec<u8> {
+        unsafe {
+            std::slice::from_raw_parts(raw as *const _ as *const u8, size_of::<io_uring_sqe>())
+        }
+        .to_vec()
+    }
+
+    /*
+     * The same entry prepared in a slot full of garbage and in a clean one.
+     */
+    fn prepared_over(garbage: u8, prepare: &dyn Fn(Sqe<'_>) -> Sqe<'_>) -> Vec<u8> {
+        let mut raw: io_uring_sqe = unsafe { std::mem::zeroed() };
+        unsafe {
+            ptr::write_bytes(
+                &mut raw as *mut io_uring_sqe as *mut u8,
+                garbage,
+                size_of::<io_uring_sqe>(),
+            )
+        };
+        prepare(Sqe::new(&mut raw, None, false));
+        bytes(&raw)
+    }
+
+    #[test]
+    pub fn every_prep_method_overwrites_the_whole_entry() {
+        let timespec = __kernel_timespec {
+            tv_sec: 1,
+            tv_nsec: 0,
+        };
+        let fixed = FixedParams {
+            addr: 0x1000,
+            len: 64,
+            buf_index: 2,
+        };
+        let buf = 0x2000 as *mut u8;
+        let preps: Vec<(&str, Prepare<'_>)> = vec![
+            ("nop", Box::new(|sqe| sqe.nop())),
+            ("cancel", Box::new(|sqe| sqe.cancel(7))),
+            ("cancel_fd", Box::new(|sqe| sqe.cancel_fd(3))),
+            ("cancel_any", Box::new(|sqe| sqe.cancel_any())),
+            (
+                "link_timeout",
+                Box::new(|sqe| unsafe { sqe.link_timeout(&timespec) }),
+            ),
+            (
+                "timeout_multishot",
+                Box::new(|sqe| unsafe { sqe.timeout_multishot(&timespec, 2) }),
+            ),
+            ("read", Box::new(|sqe| unsafe { sqe.read(3, buf, 64, 8) })),
+            ("write", Box::new(|sqe| unsafe { sqe.write(3, buf, 64, 8) })),
+            (
+                "read_fixed",
+                Box::new(|sqe| unsafe { sqe.read_fixed(3, fixed, 8) }),
+            ),
+            (
+                "write_fixed",
+                Box::new(|sqe| unsafe { sqe.write_fixed(3, fixed, 8) }),
+            ),
+            (
+                "readv",
+                Box::new(|sqe| unsafe { sqe.readv(3, ptr::null(), 1, 8) }),
+            ),
+            (
+                "writev",
+                Box::new(|sqe| unsafe { sqe.writev(3, ptr::null(), 1, 8) }),
+            ),
+            ("recv", Box::new(|sqe| unsafe { sqe.recv(3, buf, 64, 0) })),
+            ("send", Box::new(|sqe| unsafe { sqe.send(3, buf, 64, 0) })),
+            (
+                "send_zc",
+                Box::new(|sqe| unsafe { sqe.send_zc(3, buf, 64, 0) }),
+            ),
+            (
+                "send_zc_fixed",
+                Box::new(|sqe| unsafe { sqe.send_zc_fixed(3, fixed, 0) }),
+            ),
+            (
+                "recvmsg",
+                Box::new(|sqe| unsafe { sqe.recvmsg(3, ptr::null_mut(), 0) }),
+            ),
+            (
+                "recvmsg_multishot",
+                Box::new(|sqe| unsafe { sqe.recvmsg_multishot(3, ptr::null(), 0, 1) }),
+            ),
+            (
+                "sendmsg",
+                Box::new(|sqe| unsafe { sqe.sendmsg(3, ptr::null(), 0) }),
+            ),
+            (
+                "sendmsg_zc",
+                Box::new(|sqe| unsafe { sqe.sendmsg_zc(3, ptr::null(), 0, Some(1)) }),
+            ),
+            (
+                "waitid",
+                Box::new(|sqe| unsafe {
+                    sqe.waitid(libc::P_PID, 1, ptr::null_mut(), libc::WEXITED)
+                }),
+            ),
+            (
+                "futex_wait",
+                Box::new(|sqe| unsafe { sqe.futex_wait(ptr::null(), 0, u64::MAX, 2) }),
+            ),
+            (
+                "futex_wake",
+                Box::new(|sqe| unsafe { sqe.futex_wake(ptr::null(), 1, u64::MAX, 2) }),
+            ),
+            ("uring_cmd", Box::new(|sqe| sqe.uring_cmd(3, 1))),
+            (
+                "socket",
+                Box::new(|sqe| sqe.socket(libc::AF_INET, libc::SOCK_STREAM, 0)),
+            ),
+            (
+                "connect",
+                Box::new(|sqe| unsafe { sqe.connect(3, ptr::null(), 16) }),
+            ),
+            (
+                "accept",
+                Box::new(|sqe| unsafe { sqe.accept(3, ptr::null_mut(), ptr::null_mut(), 0) }),
+            ),
+            (
+                "openat",
+                Box::new(|sqe| unsafe { sqe.openat(libc::AT_FDCWD, ptr::null(), 0, 0) }),
+            ),
+            (
+                "msg_ring_fd",
+                Box::new(|sqe| sqe.msg_ring_fd(3, 1, Some(2), 9)),
+            ),
+            ("msg_ring", Box::new(|sqe| sqe.msg_ring(3, 1, 9))),
+            (
+                "poll_add",
+                Box::new(|sqe| sqe.poll_add(3, libc::POLLIN as u32)),
+            ),
+            (
+                "epoll_wait",
+                Box::new(|sqe| unsafe { sqe.epoll_wait(3, ptr::null_mut(), 4) }),
+            ),
+            (
+                "splice",
+                Box::new(|sqe| sqe.splice(3, Some(1), 4, None, 64, 0)),
+            ),
+            (
+                "statx",
+                Box::new(|sqe| unsafe { sqe.statx(3, ptr::null(), 0, 0, ptr::null_mut()) }),
+            ),
+            ("fsync", Box::new(|sqe| sqe.fsync(3, FsyncFlags::DataSync))),
+            (
+                "sync_file_range",
+                Box::new(|sqe| sqe.sync_file_range(3, 8, 64, 0)),
+            ),
+            ("close", Box::new(|sqe| sqe.close(3))),
+        ];
+
+        for (name, prepare) in &preps {
+            assert_eq!(
+                prepared_over(0xa5, prepare.as_ref()),
+                prepared_over(0, prepare.as_ref()),
+                "{} left bytes of the previous operation",
+                name
+            );
+        }
+    }
+
+    #[test]
+    pub fn a_reused_slot_is_handed_out_zeroed() {
+        let mut ring = IoUring::initialize(1, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(9);
+        ring.submit_and_wait(1).unwrap();
+        ring.next_completion().unwrap();
+
+        let mut sqe = ring.next_sqe().unwrap();
+
+        assert!(bytes(unsafe { sqe.raw_sqe() })
+            .iter()
+            .all(|byte| *byte == 0));
+    }
+
+    #[test]
+    pub fn without_zeroing_a_slot_keeps_what_the_last_entry_left() {
+        let mut ring = unsafe { IoUringBuilder::new().without_entry_zeroing() }
+            .build(1)
+            .unwrap();
+        ring.next_sqe().unwrap().nop().user_data(9);
+        ring.submit_and_wait(1).unwrap();
+        ring.next_completion().unwrap();
+
+        let mut sqe = ring.next_sqe().unwrap();
+
+        assert_eq!(unsafe { sqe.raw_sqe() }.user_data, 9);
+        assert!(!ring.entry_zeroing());
+    }
+}
@@ -0,0 +1,102 @@
+use bitflags::bitflags;
+use linux_raw_sys::io_uring::{
+    IORING_CQ_EVENTFD_DISABLED, IORING_SQ_CQ_OVERFLOW, IORING_SQ_NEED_WAKEUP, IORING_SQ_TASKRUN,
+};
+
+bitflags! {
+    /*
+     * The flags word the kernel keeps in the submission ring.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SqFlags: u32 {
+        /*
+         * The SQPOLL thread went idle and needs a wakeup to see new entries.
+         */
+        const NeedWakeup = IORING_SQ_NEED_WAKEUP;
+        /*
+         * Completions did not fit the completion ring and wait in the
+         * kernel, they come in as the ring is reaped.
+         */
+        const CqOverflow = IORING_SQ_CQ_OVERFLOW;
+        /*
+         * Completions sit in task work until the task enters the kernel,
+         * with IORING_SETUP_TASKRUN_FLAG.
+         */
+        const TaskRun = IORING_SQ_TASKRUN;
+    }
+}
+
+bitflags! {
+    /*
+     * The flags word of the completion ring, the one the application writes.
+     */
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CqFlags: u32 {
+        const EventFdDisabled = IORING_CQ_EVENTFD_DISABLED;
+    }
+}
+
+/*
+ * The counters and flags the kernel shares through the rings, read at one
+ * point in time. sq_dropped counts entries the kernel skipped as invalid and
+ * cq_overflow completions it lost because even the overflow list could not
+ * take them, both only go up.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingStats {
+    pub sq_dropped: u32,
+    pub cq_overflow: u32,
+    pub sq_flags: SqFlags,
+    pub cq_flags: CqFlags,
+}
+
+#[cfg(test)]
+mod when_taking_a_snapshot {
+    use crate::{
+        io_uring::{atomic_u32, IoUring, IoUringParams},
+        stats::{CqFlags, SqFlags},
+        syscalls::mock::MockSyscalls,
+    };
+    use std::sync::{atomic::Ordering, Arc};
+
+    #[test]
+    pub fn a_fresh_ring_reports_nothing() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let stats = ring.stats();
+
+        assert_eq!(stats.sq_dropped, 0);
+        assert_eq!(stats.cq_overflow, 0);
+        assert_eq!(stats.sq_flags, SqFlags::empty());
+        assert_eq!(stats.cq_flags, CqFlags::empty());
+    }
+
+    #[test]
+    pub fn the_counters_are_read_from_the_rings() {
+        let ring = IoUring::initialize_with_syscalls(
+            4,
+            IoUringParams::default(),
+            Arc::new(MockSyscalls::new()),
+        )
+        .unwrap();
+        unsafe { atomic_u32(ring.send_queue.dropped) }.store(3, Ordering::Relaxed);
+        unsafe { atomic_u32(ring.complete_queue.overflow) }.store(7, Ordering::Relaxed);
+
+        assert_eq!(ring.sq_dropped(), 3);
+        assert_eq!(ring.cq_overflow(), 7);
+    }
+
+    #[test]
+    pub fn completions_past_the_ring_raise_the_overflow_flag() {
+        let mut ring = IoUring::initialize(2, IoUringParams::default()).unwrap();
+        let cq_entries = ring.complete_queue.ring_entries();
+
+        for _ in 0..cq_entries / 2 + 1 {
+            ring.next_sqe().unwrap().nop();
+            ring.next_sqe().unwrap().nop();
+            ring.submit().unwrap();
+        }
+
+        assert!(ring.sq_flags().contains(SqFlags::CqOverflow));
+        assert_eq!(ring.stats().cq_overflow, 0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    cqe::{Completion, Completions},
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+    sqe::Sqe,
+};
+use std::{
+    io::{self, ErrorKind, Result},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/*
+ * The ring is Send, the mutex serializes every access to it.
+ */
+struct Shared<'a, S: SqeEntry, C: CqeEntry> {
+    ring: Mutex<IoUring<'a, S, C>>,
+}
+
+/*
+ * Cloneable handle to a ring shared by several threads. Every clone can queue
+ * entries, the ring is behind a lock held only while one entry is prepared
+ * or while entering the kernel.
+ *
+ * With SINGLE_ISSUER the kernel only accepts io_uring_enter from the thread
+ * that owns the ring: any thread can push, only that one can submit, which
+ * flushes what the others queued too.
+ */
+pub struct Submitter<'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    shared: Arc<Shared<'a, S, C>>,
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Clone for Submitter<'a, S, C> {
+    fn clone(&self) -> Self {
+        Submitter {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<'a, S: SqeEntry, C: CqeEntry> Submitter<'a, S, C> {
+    pub(crate) fn new(ring: IoUring<'a, S, C>) -> Self {
+        Submitter {
+            shared: Arc::new(Shared {
+                ring: Mutex::new(ring),
+            }),
+        }
+    }
+
+    /*
+     * Queues the entry `prepare` sets up, WouldBlock when the submission
+     * queue is full.
+     */
+    pub fn push<F>(&self, prepare: F) -> Result<()>
+    where
+        F: FnOnce(Sqe<'_>) -> Sqe<'_>,
+    {
+        let mut ring = self.lock();
+        let sqe = ring
+            .next_sqe()
+            .ok_or_else(|| io::Error::new(ErrorKind::WouldBlock, "the submission queue is full"))?;
+        prepare(sqe);
+
+        Ok(())
+    }
+
+    pub fn submit(&self) -> Result<usize> {
+        self.submit_and_wait(0)
+    }
+
+    pub fn submit_and_wait(&self, wait_nr: u32) -> Result<usize> {
+        self.lock().submit_and_wait(wait_nr)
+    }
+
+    pub fn next_completion(&self) -> Option<Completion> {
+        self.lock().next_completion()
+    }
+
+    /*
+     * Runs `f` with the ring locked, for anything the handle has no method
+     * for. Other threads block on the handle meanwhile.
+     */
+    pub fn with_ring<R>(&self, f: impl FnOnce(&mut IoUring<'a, S, C>) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IoUring<'a, S, C>> {
+        self.shared
+            .ring
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod when_sharing_a_ring_between_threads {
+    use crate::{
+        io_uring::{IoUring, IoUringParams, IoUringSetupFlags},
+        submitter::Submitter,
+    };
+    use std::{io::ErrorKind, thread};
+
+    #[test]
+    pub fn every_thread_can_queue_entries() {
+        let submitter = IoUring::initialize(64, IoUringParams::default())
+            .unwrap()
+            .into_submitter();
+
+        thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let submitter = submitter.clone();
+                scope.spawn(move || {
+                    for index in 0..8 {
+                        submitter
+                            .push(|sqe| sqe.nop().user_data(thread * 8 + index))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        submitter.submit_and_wait(32).unwrap();
+
+        let mut user_data: Vec<u64> = (0..32)
+            .map(|_| submitter.next_completion().unwrap().user_data)
+            .collect();
+        user_data.sort();
+        assert_eq!(user_data, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn a_full_queue_is_reported() {
+        let submitter = Submitter::new(IoUring::initialize(1, IoUringParams::default()).unwrap());
+
+        submitter.push(|sqe| sqe.nop()).unwrap();
+        let error = submitter.push(|sqe| sqe.nop()).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    pub fn single_issuer_rings_only_submit_from_their_thread() {
+        let params = IoUringParams {
+            flags: IoUringSetupFlags::SingleIssuer.bits(),
+            ..Default::default()
+        };
+        let submitter = IoUring::initialize(4, params).unwrap().into_submitter();
+
+        let other = submitter.clone();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                other.push(|sqe| sqe.nop().user_data(9)).unwrap();
+                assert_eq!(other.submit().unwrap_err().kind(), ErrorKind::Unsupported);
+            });
+        });
+
+        submitter.submit_and_wait(1).unwrap();
+        assert_eq!(submitter.next_completion().unwrap().user_data, 9);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    entry::{CqeEntry, SqeEntry},
+    fs::run,
+    io_uring::IoUring,
+    opcode::IoUringOperation,
+};
+use libc::{syscall, SYS_futex};
+use linux_raw_sys::general::{
+    FUTEX2_PRIVATE, FUTEX2_SIZE_U32, FUTEX_BITSET_MATCH_ANY, FUTEX_PRIVATE_FLAG, FUTEX_WAKE,
+};
+use std::{
+    cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
+    io::{self, ErrorKind, Result},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/*
+ * Locks whose contended path waits with a FUTEX_WAIT on the ring of the
+ * waiting thread, so a thread that owns a ring blocks on locks and on I/O in
+ * the same place. Releasing wakes the waiter with a plain futex(2), the
+ * releasing thread may have no ring at hand, e.g. in a Drop. Unsupported
+ * before IORING_OP_FUTEX_WAIT, kernel 6.7.
+ */
+
+/*
+ * user_data of the futex waits this module submits and waits for.
+ */
+const FUTEX_WAIT_USER_DATA: u64 = u64::MAX - 21;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/*
+ * Locked with threads waiting, or which may be, the unlock has to wake one.
+ */
+const CONTENDED: u32 = 2;
+
+/*
+ * Waits on `futex` while it holds `value`. Returns once woken, or right away
+ * when the value changed in the meantime.
+ */
+fn futex_wait<S: SqeEntry, C: CqeEntry>(
+    ring: &mut IoUring<'_, S, C>,
+    futex: &AtomicU32,
+    value: u32,
+) -> Result<()> {
+    let result = run(ring, FUTEX_WAIT_USER_DATA, |sqe| unsafe {
+        sqe.futex_wait(
+            futex.as_ptr(),
+            value as u64,
+            FUTEX_BITSET_MATCH_ANY as u64,
+            FUTEX2_SIZE_U32 | FUTEX2_PRIVATE,
+        )
+    });
+
+    match result {
+        Err(error) if error.kind() != ErrorKind::WouldBlock => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn futex_wake(futex: &AtomicU32, count: u32) {
+    unsafe {
+        syscall(
+            SYS_futex,
+            futex.as_ptr(),
+            FUTEX_WAKE | FUTEX_PRIVATE_FLAG,
+            count,
+        )
+    };
+}
+
+fn check_support<S: SqeEntry, C: CqeEntry>(ring: &IoUring<'_, S, C>) -> Result<()> {
+    if !ring.supports(IoUringOperation::FutexWait)? {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "the kernel cannot wait on a futex through the ring",
+        ));
+    }
+
+    Ok(())
+}
+
+pub struct Mutex<T: ?Sized> {
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    /*
+     * Takes the lock, parking on `ring` while another thread holds it.
+     */
+    pub fn lock<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<MutexGuard<'_, T>> {
+        if let Some(guard) = self.try_lock() {
+            return Ok(guard);
+        }
+        check_support(ring)?;
+
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(ring, &self.state, CONTENDED)?;
+        }
+
+        Ok(MutexGuard { mutex: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for Mutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Some(guard) => debug.field("value", &&*guard),
+            None => debug.field("value", &format_args!("<locked>")),
+        };
+        debug.finish()
+    }
+}
+
+pub struct MutexGuard<'m, T: ?Sized> {
+    mutex: &'m Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/*
+ * Counting semaphore, a permit goes back when its Permit is dropped.
+ */
+#[derive(Debug)]
+pub struct Semaphore {
+    permits: AtomicU32,
+    /*
+     * Threads parked or about to park, a release only wakes when there are
+     * some.
+     */
+    waiters: AtomicU32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Semaphore {
+            permits: AtomicU32::new(permits),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub fn available(&self) -> u32 {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Permit { semaphore: self }),
+                Err(current) => permits = current,
+            }
+        }
+
+        None
+    }
+
+    /*
+     * Takes a permit, parking on `ring` while there is none.
+     */
+    pub fn acquire<S: SqeEntry, C: CqeEntry>(
+        &self,
+        ring: &mut IoUring<'_, S, C>,
+    ) -> Result<Permit<'_>> {
+        if let Some(permit) = self.try_acquire() {
+            return Ok(permit);
+        }
+        check_support(ring)?;
+
+        loop {
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            let waited = futex_wait(ring, &self.permits, 0);
+            self.waiters.fetch_sub(1, Ordering::SeqCst);
+            waited?;
+
+            if let Some(permit) = self.try_acquire() {
+                return Ok(permit);
+            }
+        }
+    }
+
+    /*
+     * Hands out `count` more permits, e.g. when a pool grows.
+     */
+    pub fn add_permits(&self, count: u32) {
+        self.permits.fetch_add(count, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            futex_wake(&self.permits, count);
+        }
+    }
+}
+
+pub struct Permit<'s> {
+    semaphore: &'s Semaphore,
+}
+
+impl Permit<'_> {
+    /*
+     * Keeps the permit taken for good.
+     */
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
+
+#[cfg(test)]
+mod when_synchronizing_through_the_ring {
+    use crate::{
+        io_uring::{IoUring, IoUringParams},
+        sync::{Mutex, Semaphore},
+    };
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    pub fn a_contended_lock_parks_until_it_is_released() {
+        let mutex = Arc::new(Mutex::new(0u32));
+        let guard = mutex.try_lock().unwrap();
+
+        let waiter = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+                let mut value = mutex.lock(&mut ring).unwrap();
+                *value += 1;
+                ring.in_flight()
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        drop(guard);
+
+        assert_eq!(waiter.join().unwrap(), 0);
+        assert_eq!(Arc::into_inner(mutex).unwrap().into_inner(), 1);
+    }
+
+    #[test]
+    pub fn threads_take_turns_on_the_lock() {
+        let mutex = Arc::new(Mutex::new(0u64));
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let mutex = mutex.clone();
+                thread::spawn(move || {
+                    let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+                    for _ in 0..1000 {
+                        *mutex.lock(&mut ring).unwrap() += 1;
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(*mutex.try_lock().unwrap(), 4000);
+    }
+
+    #[test]
+    pub fn an_acquire_waits_for_a_permit_to_come_back() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+
+        let waiter = {
+            let semaphore = semaphore.clone();
+            thread::spawn(move || {
+                let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+                semaphore.acquire(&mut ring).unwrap().forget();
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        drop(permit);
+
+        waiter.join().unwrap();
+        assert_eq!(semaphore.available(), 0);
+    }
+}
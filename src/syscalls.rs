@@ -1,104 +1,550 @@
+use crate::{
+    arch::{MmapOffset, SYS_IO_URING_ENTER, SYS_IO_URING_REGISTER, SYS_IO_URING_SETUP},
+    memory::page_size,
+    mmap::{MMap, MapOptions},
+};
 use bitflags::bitflags;
-use libc::{c_long, syscall};
+use libc::{
+    c_long, c_void, clock_gettime, clockid_t, syscall, timespec, CLOCK_BOOTTIME, CLOCK_MONOTONIC,
+};
 use linux_raw_sys::{
-    general::{__NR_io_uring_enter, __NR_io_uring_setup, sigset_t},
-    io_uring::{
-        io_uring_params, IORING_ENTER_EXT_ARG, IORING_ENTER_GETEVENTS,
-        IORING_ENTER_REGISTERED_RING, IORING_ENTER_SQ_WAIT, IORING_ENTER_SQ_WAKEUP,
-    },
+    general::sigset_t,
     io_uring::{
-        IORING_REGISTER_BUFFERS, IORING_REGISTER_BUFFERS2, IORING_REGISTER_BUFFERS_UPDATE,
-        IORING_REGISTER_ENABLE_RINGS, IORING_REGISTER_EVENTFD, IORING_REGISTER_EVENTFD_ASYNC,
-        IORING_REGISTER_FILES, IORING_REGISTER_FILES2, IORING_REGISTER_FILES_UPDATE,
-        IORING_REGISTER_FILES_UPDATE2, IORING_REGISTER_FILE_ALLOC_RANGE, IORING_REGISTER_IOWQ_AFF,
-        IORING_REGISTER_IOWQ_MAX_WORKERS, IORING_REGISTER_LAST, IORING_REGISTER_PBUF_RING,
-        IORING_REGISTER_PERSONALITY, IORING_REGISTER_PROBE, IORING_REGISTER_RESTRICTIONS,
-        IORING_REGISTER_RING_FDS, IORING_REGISTER_SYNC_CANCEL, IORING_REGISTER_USE_REGISTERED_RING,
-        IORING_UNREGISTER_BUFFERS, IORING_UNREGISTER_EVENTFD, IORING_UNREGISTER_FILES,
-        IORING_UNREGISTER_IOWQ_AFF, IORING_UNREGISTER_PBUF_RING, IORING_UNREGISTER_PERSONALITY,
-        IORING_UNREGISTER_RING_FDS,
+        __kernel_timespec, io_uring_getevents_arg, io_uring_params, io_uring_reg_wait,
+        io_uring_register_op, io_uring_sqe, IORING_ENTER_ABS_TIMER, IORING_ENTER_EXT_ARG,
+        IORING_ENTER_EXT_ARG_REG, IORING_ENTER_GETEVENTS, IORING_ENTER_REGISTERED_RING,
+        IORING_ENTER_SQ_WAIT, IORING_ENTER_SQ_WAKEUP, IORING_REG_WAIT_TS,
     },
 };
+use std::io::{self, ErrorKind, Result};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr::null;
+use std::time::Duration;
+
+#[cfg(test)]
+pub(crate) mod mock;
 
 pub(crate) type NumberOfIOsSuccessfullyConsumed = i64;
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct IoUringEnterFlags : u32 {
         const IoRingEnterGetEvents = IORING_ENTER_GETEVENTS;
         const IoRingEnterSqWakeup = IORING_ENTER_SQ_WAKEUP;
         const IoRingEnterSqWait = IORING_ENTER_SQ_WAIT;
         const IoRingEnterExtArg = IORING_ENTER_EXT_ARG;
         const IoRingEnterRegisteredRing = IORING_ENTER_REGISTERED_RING;
+        const IoRingEnterAbsTimer = IORING_ENTER_ABS_TIMER;
+        const IoRingEnterExtArgReg = IORING_ENTER_EXT_ARG_REG;
     }
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct IoUringOpCode: u32 {
-        const IoRingRegisterBuffers = IORING_REGISTER_BUFFERS as u32;
-        const IoRingUnregisterBuffers = IORING_UNREGISTER_BUFFERS as u32;
-        const IoRingRegisterFiles = IORING_REGISTER_FILES as u32;
-        const IoRingUnregisterFiles = IORING_UNREGISTER_FILES as u32;
-        const IoRingRegisterEventFd = IORING_REGISTER_EVENTFD as u32;
-        const IoRingUnregisterEventFd = IORING_UNREGISTER_EVENTFD as u32;
-        const IoRingRegisterFilesUpdate = IORING_REGISTER_FILES_UPDATE as u32;
-        const IoRingRegisterEventFdAsync = IORING_REGISTER_EVENTFD_ASYNC as u32;
-        const IoRingRegisterProbe = IORING_REGISTER_PROBE as u32;
-        const IoRingRegisterPeronality = IORING_REGISTER_PERSONALITY as u32;
-        const IoRingUnregisterPersonality = IORING_UNREGISTER_PERSONALITY as u32;
-        const IoRingRegisterRestrictions = IORING_REGISTER_RESTRICTIONS as u32;
-        const IoRingRegisterEnableRings = IORING_REGISTER_ENABLE_RINGS as u32;
-        const IoRingRegisterFiles2 = IORING_REGISTER_FILES2 as u32;
-        const IoRingRegisterFilesUpdate2 = IORING_REGISTER_FILES_UPDATE2 as u32;
-        const IoRingRegisterBuffers2 = IORING_REGISTER_BUFFERS2 as u32;
-        const IoRingRegisterBuffersUpdate = IORING_REGISTER_BUFFERS_UPDATE as u32;
-        const IoRingRegisterIowqAff = IORING_REGISTER_IOWQ_AFF as u32;
-        const IoRingUnregisterIowqAff = IORING_UNREGISTER_IOWQ_AFF as u32;
-        const IoRingRegisterIowqMaxWorkers = IORING_REGISTER_IOWQ_MAX_WORKERS as u32;
-        const IoRingRegisterRingFds = IORING_REGISTER_RING_FDS as u32;
-        const IoRingUnregisterRingFds = IORING_UNREGISTER_RING_FDS as u32;
-        const IoRingRegisterPbufRing = IORING_REGISTER_PBUF_RING as u32;
-        const IoRingUnregisterPbufRing = IORING_UNREGISTER_PBUF_RING as u32;
-        const IoRingRegisterSyncCancel = IORING_REGISTER_SYNC_CANCEL as u32;
-        const IoRingRegisterFileAllocRange = IORING_REGISTER_FILE_ALLOC_RANGE as u32;
-        const IoRingRegisterLast = IORING_REGISTER_LAST as u32;
-        const IoRingRegisterUseRegisteredRing = IORING_REGISTER_USE_REGISTERED_RING as u32;
+        const IoRingRegisterBuffers = io_uring_register_op::IORING_REGISTER_BUFFERS as u32;
+        const IoRingUnregisterBuffers = io_uring_register_op::IORING_UNREGISTER_BUFFERS as u32;
+        const IoRingRegisterFiles = io_uring_register_op::IORING_REGISTER_FILES as u32;
+        const IoRingUnregisterFiles = io_uring_register_op::IORING_UNREGISTER_FILES as u32;
+        const IoRingRegisterEventFd = io_uring_register_op::IORING_REGISTER_EVENTFD as u32;
+        const IoRingUnregisterEventFd = io_uring_register_op::IORING_UNREGISTER_EVENTFD as u32;
+        const IoRingRegisterFilesUpdate = io_uring_register_op::IORING_REGISTER_FILES_UPDATE as u32;
+        const IoRingRegisterEventFdAsync = io_uring_register_op::IORING_REGISTER_EVENTFD_ASYNC as u32;
+        const IoRingRegisterProbe = io_uring_register_op::IORING_REGISTER_PROBE as u32;
+        const IoRingRegisterPeronality = io_uring_register_op::IORING_REGISTER_PERSONALITY as u32;
+        const IoRingUnregisterPersonality = io_uring_register_op::IORING_UNREGISTER_PERSONALITY as u32;
+        const IoRingRegisterRestrictions = io_uring_register_op::IORING_REGISTER_RESTRICTIONS as u32;
+        const IoRingRegisterEnableRings = io_uring_register_op::IORING_REGISTER_ENABLE_RINGS as u32;
+        const IoRingRegisterFiles2 = io_uring_register_op::IORING_REGISTER_FILES2 as u32;
+        const IoRingRegisterFilesUpdate2 = io_uring_register_op::IORING_REGISTER_FILES_UPDATE2 as u32;
+        const IoRingRegisterBuffers2 = io_uring_register_op::IORING_REGISTER_BUFFERS2 as u32;
+        const IoRingRegisterBuffersUpdate = io_uring_register_op::IORING_REGISTER_BUFFERS_UPDATE as u32;
+        const IoRingRegisterIowqAff = io_uring_register_op::IORING_REGISTER_IOWQ_AFF as u32;
+        const IoRingUnregisterIowqAff = io_uring_register_op::IORING_UNREGISTER_IOWQ_AFF as u32;
+        const IoRingRegisterIowqMaxWorkers = io_uring_register_op::IORING_REGISTER_IOWQ_MAX_WORKERS as u32;
+        const IoRingRegisterRingFds = io_uring_register_op::IORING_REGISTER_RING_FDS as u32;
+        const IoRingUnregisterRingFds = io_uring_register_op::IORING_UNREGISTER_RING_FDS as u32;
+        const IoRingRegisterPbufRing = io_uring_register_op::IORING_REGISTER_PBUF_RING as u32;
+        const IoRingUnregisterPbufRing = io_uring_register_op::IORING_UNREGISTER_PBUF_RING as u32;
+        const IoRingRegisterSyncCancel = io_uring_register_op::IORING_REGISTER_SYNC_CANCEL as u32;
+        const IoRingRegisterFileAllocRange = io_uring_register_op::IORING_REGISTER_FILE_ALLOC_RANGE as u32;
+        const IoRingRegisterLast = io_uring_register_op::IORING_REGISTER_LAST as u32;
+        const IoRingRegisterUseRegisteredRing = io_uring_register_op::IORING_REGISTER_USE_REGISTERED_RING as u32;
+        const IoRingRegisterClock = io_uring_register_op::IORING_REGISTER_CLOCK as u32;
+        const IoRingRegisterResizeRings = io_uring_register_op::IORING_REGISTER_RESIZE_RINGS as u32;
+        const IoRingRegisterMemRegion = io_uring_register_op::IORING_REGISTER_MEM_REGION as u32;
+        const IoRingRegisterSendMsgRing = io_uring_register_op::IORING_REGISTER_SEND_MSG_RING as u32;
+    }
+}
+
+/*
+ * Clocks the timeouts of waits can run on, see IoUring::register_clock.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    #[default]
+    Monotonic,
+    /*
+     * Like Monotonic, but keeps counting while the system is suspended.
+     */
+    Boottime,
+}
+
+impl Clock {
+    pub(crate) fn id(&self) -> clockid_t {
+        match self {
+            Clock::Monotonic => CLOCK_MONOTONIC,
+            Clock::Boottime => CLOCK_BOOTTIME,
+        }
+    }
+
+    /*
+     * The current time of the clock, to compute deadlines from.
+     */
+    pub fn now(&self) -> Result<Duration> {
+        let mut now = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { clock_gettime(self.id(), &mut now) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+    }
+}
+
+/*
+ * Extended arguments for io_uring_enter, passed as struct
+ * io_uring_getevents_arg together with IORING_ENTER_EXT_ARG. The timeout is
+ * relative to the call unless given as a deadline, min_wait needs
+ * IoUringFeatures::MinTimeout.
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GetEventsArg {
+    sigmask: Option<sigset_t>,
+    timeout: Option<Duration>,
+    absolute: bool,
+    min_wait: Option<Duration>,
+}
+
+impl GetEventsArg {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sigmask(mut self, sigmask: sigset_t) -> Self {
+        self.sigmask = Some(sigmask);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self.absolute = false;
+        self
+    }
+
+    /*
+     * Waits until `deadline`, a time of the clock of the ring as Clock::now
+     * tells it, e.g. the next tick of a rate limiter. A deadline already
+     * past fails with ETIME at once. Needs kernel 6.12.
+     */
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.timeout = Some(deadline);
+        self.absolute = true;
+        self
+    }
+
+    /*
+     * Two stage wait: for `min_wait` the wait holds out for all of wait_nr
+     * completions, past it any completion ends it, and the timeout still
+     * bounds the whole. A wait_nr of a batch with a short min_wait gathers
+     * what completes close together without stalling a lone completion for
+     * the whole timeout. Needs IoUringFeatures::MinTimeout.
+     */
+    pub fn min_wait(mut self, min_wait: Duration) -> Self {
+        self.min_wait = Some(min_wait);
+        self
+    }
+
+    pub(crate) fn has_min_wait(&self) -> bool {
+        self.min_wait.is_some()
+    }
+}
+
+/*
+ * Slots of io_uring_reg_wait in memory registered with the ring, see
+ * IoUring::register_wait_region. The kernel reads the wait arguments from
+ * the slot an enter names instead of copying them in on every call. The
+ * arguments are kept alongside, for the sigmask the slot points to and for
+ * the deadline flag, which goes with the enter rather than the slot.
+ */
+pub(crate) struct WaitRegion<'a> {
+    memory: MMap<'a>,
+    args: Box<[GetEventsArg]>,
+}
+
+impl<'a> WaitRegion<'a> {
+    /*
+     * The kernel pins whole pages, the slots are rounded up to fill them.
+     */
+    pub(crate) fn new(slots: u32) -> Result<Self> {
+        if slots == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a wait region needs at least one slot",
+            ));
+        }
+
+        let len = (slots as usize * size_of::<io_uring_reg_wait>()).next_multiple_of(page_size());
+        let memory = MMap::anonymous(len)?;
+        let args = vec![GetEventsArg::default(); len / size_of::<io_uring_reg_wait>()];
+
+        Ok(WaitRegion {
+            memory,
+            args: args.into_boxed_slice(),
+        })
+    }
+
+    pub(crate) fn address(&self) -> u64 {
+        self.memory
+            .add_offset(0)
+            .map_or(0, |address| address.as_ptr() as u64)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.memory.get_len()
+    }
+
+    pub(crate) fn slots(&self) -> u32 {
+        self.args.len() as u32
+    }
+
+    pub(crate) fn offset(&self, slot: u32) -> usize {
+        slot as usize * size_of::<io_uring_reg_wait>()
+    }
+
+    pub(crate) fn is_absolute(&self, slot: u32) -> bool {
+        self.args[slot as usize].absolute
+    }
+
+    pub(crate) fn set(&mut self, slot: u32, arg: &GetEventsArg) -> Result<()> {
+        if slot >= self.slots() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the slot is past the end of the wait region",
+            ));
+        }
+        self.args[slot as usize] = *arg;
+        let arg = &self.args[slot as usize];
+
+        let mut wait: io_uring_reg_wait = unsafe { std::mem::zeroed() };
+        if let Some(timeout) = arg.timeout {
+            wait.ts = __kernel_timespec {
+                tv_sec: timeout.as_secs() as _,
+                tv_nsec: timeout.subsec_nanos() as _,
+            };
+            wait.flags = IORING_REG_WAIT_TS as u32;
+        }
+        if let Some(min_wait) = arg.min_wait {
+            wait.min_wait_usec = u32::try_from(min_wait.as_micros()).unwrap_or(u32::MAX);
+        }
+        if let Some(sigmask) = arg.sigmask.as_ref() {
+            wait.sigmask = sigmask as *const sigset_t as u64;
+            wait.sigmask_sz = size_of::<sigset_t>() as u32;
+        }
+
+        let at = self
+            .memory
+            .add_offset(self.offset(slot))
+            .ok_or_else(|| io::Error::other("could not reach the wait slot"))?;
+        unsafe { (at.as_ptr() as *mut io_uring_reg_wait).write_volatile(wait) };
+
+        Ok(())
     }
 }
 
-pub(crate) unsafe fn io_uring_setup(entries: u32, params: &mut io_uring_params) -> OwnedFd {
+pub(crate) unsafe fn io_uring_setup(entries: u32, params: &mut io_uring_params) -> Result<OwnedFd> {
     let result = syscall(
-        __NR_io_uring_setup as c_long,
+        SYS_IO_URING_SETUP,
         entries as c_long,
         params as *mut io_uring_params,
     );
 
-    OwnedFd::from_raw_fd(result as i32)
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(OwnedFd::from_raw_fd(result as i32))
+}
+
+/// Runs a MSG_RING entry right away, without a ring of its own to submit
+/// it from. Needs kernel 6.13.
+///
+/// # Safety
+///
+/// `sqe` must be a prepared msg_ring entry.
+pub(crate) unsafe fn io_uring_send_msg_ring(sqe: &io_uring_sqe) -> Result<()> {
+    let result = syscall(
+        SYS_IO_URING_REGISTER,
+        -1,
+        IoUringOpCode::IoRingRegisterSendMsgRing.bits(),
+        sqe as *const io_uring_sqe,
+        1,
+    );
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
 }
 
 pub(crate) unsafe fn io_uring_register(
     ring_fd: &OwnedFd,
     opcode: IoUringOpCode,
-    raw_fd: RawFd,
+    arg: *const c_void,
     nr_args: u32,
-) {
+) -> Result<i64> {
+    let result = syscall(
+        SYS_IO_URING_REGISTER,
+        ring_fd.as_raw_fd(),
+        opcode.bits(),
+        arg,
+        nr_args,
+    );
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(result)
 }
 
 pub(crate) unsafe fn io_uring_enter(
-    ring_fd: &OwnedFd,
+    ring_fd: RawFd,
     submit: u32,
     min_complete: u32,
     flags: IoUringEnterFlags,
-    sigset: *mut sigset_t,
-    sz: u32,
-) -> NumberOfIOsSuccessfullyConsumed {
-    syscall(
-        __NR_io_uring_enter as c_long,
-        ring_fd.as_raw_fd(),
+    arg: *const c_void,
+    sz: usize,
+) -> Result<NumberOfIOsSuccessfullyConsumed> {
+    let result = syscall(
+        SYS_IO_URING_ENTER,
+        ring_fd,
         submit,
         min_complete,
         flags.bits(),
-        sigset,
+        arg,
         sz,
-    )
+    );
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(result)
+}
+
+/*
+ * Everything the ring needs from the kernel. The ring only talks to the
+ * kernel through this trait, so the setup, mmap and submit logic can be
+ * exercised against a fake kernel.
+ */
+pub(crate) trait UringSyscalls: Send + Sync {
+    fn setup(&self, entries: u32, params: &mut io_uring_params) -> Result<OwnedFd>;
+
+    /*
+     * Whether completions come for what was submitted, a fake kernel may
+     * only record the submissions. A dropped ring only waits for its
+     * requests in flight when they can complete.
+     */
+    fn completes_submissions(&self) -> bool {
+        true
+    }
+
+    /// # Safety
+    ///
+    /// `arg` must point to whatever the kernel expects for `opcode`, valid for
+    /// `nr_args` elements.
+    unsafe fn register(
+        &self,
+        ring_fd: &OwnedFd,
+        opcode: IoUringOpCode,
+        arg: *const c_void,
+        nr_args: u32,
+    ) -> Result<i64>;
+
+    /// # Safety
+    ///
+    /// `arg` must be null or point to an argument of `sz` bytes matching
+    /// the flags: a sigset_t, or an io_uring_getevents_arg with ExtArg set.
+    /// With ExtArgReg it is the offset of a slot of the registered wait
+    /// region instead.
+    unsafe fn enter_raw(
+        &self,
+        ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        arg: *const c_void,
+        sz: usize,
+    ) -> Result<NumberOfIOsSuccessfullyConsumed>;
+
+    /*
+     * Plain io_uring_enter, the kernel swaps in the sigmask, if any, for the
+     * duration of the wait.
+     */
+    fn enter(
+        &self,
+        ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        sigmask: Option<&sigset_t>,
+    ) -> Result<NumberOfIOsSuccessfullyConsumed> {
+        let flags = flags - IoUringEnterFlags::IoRingEnterExtArg;
+        let (arg, sz) = match sigmask {
+            Some(sigmask) => (
+                sigmask as *const sigset_t as *const c_void,
+                size_of::<sigset_t>(),
+            ),
+            None => (null(), 0),
+        };
+
+        unsafe { self.enter_raw(ring_fd, submit, min_complete, flags, arg, sz) }
+    }
+
+    /*
+     * io_uring_enter2, the argument goes through io_uring_getevents_arg and
+     * sz is the size of that struct rather than of the sigmask.
+     */
+    fn enter_ext(
+        &self,
+        ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        arg: &GetEventsArg,
+    ) -> Result<NumberOfIOsSuccessfullyConsumed> {
+        let mut flags = flags | IoUringEnterFlags::IoRingEnterExtArg;
+        if arg.absolute {
+            flags |= IoUringEnterFlags::IoRingEnterAbsTimer;
+        }
+        let timespec = arg.timeout.map(|timeout| __kernel_timespec {
+            tv_sec: timeout.as_secs() as _,
+            tv_nsec: timeout.subsec_nanos() as _,
+        });
+        let min_wait_usec = match arg.min_wait {
+            Some(min_wait) => u32::try_from(min_wait.as_micros()).unwrap_or(u32::MAX),
+            None => 0,
+        };
+
+        let getevents_arg = io_uring_getevents_arg {
+            sigmask: arg
+                .sigmask
+                .as_ref()
+                .map_or(0, |sigmask| sigmask as *const sigset_t as u64),
+            sigmask_sz: if arg.sigmask.is_some() {
+                size_of::<sigset_t>() as u32
+            } else {
+                0
+            },
+            min_wait_usec,
+            ts: timespec
+                .as_ref()
+                .map_or(0, |timespec| timespec as *const __kernel_timespec as u64),
+        };
+
+        unsafe {
+            self.enter_raw(
+                ring_fd,
+                submit,
+                min_complete,
+                flags,
+                &getevents_arg as *const io_uring_getevents_arg as *const c_void,
+                size_of::<io_uring_getevents_arg>(),
+            )
+        }
+    }
+
+    /*
+     * io_uring_enter2 with the arguments in `slot` of the registered wait
+     * region, only the offset of the slot crosses into the kernel.
+     */
+    fn enter_ext_reg(
+        &self,
+        ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        region: &WaitRegion<'_>,
+        slot: u32,
+    ) -> Result<NumberOfIOsSuccessfullyConsumed> {
+        let mut flags =
+            flags | IoUringEnterFlags::IoRingEnterExtArg | IoUringEnterFlags::IoRingEnterExtArgReg;
+        if region.is_absolute(slot) {
+            flags |= IoUringEnterFlags::IoRingEnterAbsTimer;
+        }
+
+        unsafe {
+            self.enter_raw(
+                ring_fd,
+                submit,
+                min_complete,
+                flags,
+                region.offset(slot) as *const c_void,
+                size_of::<io_uring_reg_wait>(),
+            )
+        }
+    }
+
+    fn mmap<'a>(
+        &self,
+        ring_fd: &OwnedFd,
+        offset: MmapOffset,
+        len: usize,
+        options: MapOptions,
+    ) -> Result<MMap<'a>>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RealSyscalls;
+
+impl UringSyscalls for RealSyscalls {
+    fn setup(&self, entries: u32, params: &mut io_uring_params) -> Result<OwnedFd> {
+        unsafe { io_uring_setup(entries, params) }
+    }
+
+    unsafe fn register(
+        &self,
+        ring_fd: &OwnedFd,
+        opcode: IoUringOpCode,
+        arg: *const c_void,
+        nr_args: u32,
+    ) -> Result<i64> {
+        io_uring_register(ring_fd, opcode, arg, nr_args)
+    }
+
+    unsafe fn enter_raw(
+        &self,
+        ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        arg: *const c_void,
+        sz: usize,
+    ) -> Result<NumberOfIOsSuccessfullyConsumed> {
+        io_uring_enter(ring_fd, submit, min_complete, flags, arg, sz)
+    }
+
+    fn mmap<'a>(
+        &self,
+        ring_fd: &OwnedFd,
+        offset: MmapOffset,
+        len: usize,
+        options: MapOptions,
+    ) -> Result<MMap<'a>> {
+        MMap::new(ring_fd, offset, len, options)
+    }
 }
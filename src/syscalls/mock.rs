@@ -0,0 +1,201 @@
+use crate::{
+    arch::MmapOffset,
+    mmap::{MMap, MapOptions},
+    syscalls::{IoUringEnterFlags, IoUringOpCode, NumberOfIOsSuccessfullyConsumed, UringSyscalls},
+};
+use libc::c_void;
+use linux_raw_sys::io_uring::{
+    io_uring_cqe, io_uring_params, IORING_FEAT_SINGLE_MMAP, IORING_OFF_SQ_RING, IORING_SETUP_CQE32,
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io,
+    mem::size_of,
+    os::fd::{OwnedFd, RawFd},
+    sync::Mutex,
+};
+
+const CQES_OFFSET: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SyscallRecord {
+    Setup {
+        entries: u32,
+        flags: u32,
+    },
+    Register {
+        opcode: IoUringOpCode,
+        nr_args: u32,
+    },
+    Enter {
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        arg_size: usize,
+    },
+    Mmap {
+        offset: MmapOffset,
+        len: usize,
+        populate: bool,
+    },
+}
+
+/*
+ * Fake kernel: records every call and answers with scripted results. Setup
+ * hands out a single mmap layout backed by anonymous memory, so the ring
+ * pointers can be exercised without io_uring being available.
+ */
+#[derive(Default)]
+pub(crate) struct MockSyscalls {
+    records: Mutex<Vec<SyscallRecord>>,
+    setup_errors: Mutex<VecDeque<i32>>,
+    register_results: Mutex<VecDeque<Result<i64, i32>>>,
+    enter_results: Mutex<VecDeque<Result<i64, i32>>>,
+    params: Mutex<Option<io_uring_params>>,
+}
+
+impl MockSyscalls {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn fail_setup(&self, error_number: i32) {
+        self.setup_errors.lock().unwrap().push_back(error_number);
+    }
+
+    pub(crate) fn script_register(&self, result: Result<i64, i32>) {
+        self.register_results.lock().unwrap().push_back(result);
+    }
+
+    pub(crate) fn script_enter(&self, result: Result<i64, i32>) {
+        self.enter_results.lock().unwrap().push_back(result);
+    }
+
+    pub(crate) fn records(&self) -> Vec<SyscallRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    fn record(&self, record: SyscallRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+}
+
+fn scripted(results: &Mutex<VecDeque<Result<i64, i32>>>, default: i64) -> io::Result<i64> {
+    match results.lock().unwrap().pop_front() {
+        Some(Ok(result)) => Ok(result),
+        Some(Err(error_number)) => Err(io::Error::from_raw_os_error(error_number)),
+        None => Ok(default),
+    }
+}
+
+unsafe fn write_u32(map: &MMap, offset: u32, value: u32) {
+    if let Some(pointer) = map.add_offset(offset as usize) {
+        *(pointer.as_ptr() as *mut u32) = value;
+    }
+}
+
+impl UringSyscalls for MockSyscalls {
+    fn setup(&self, entries: u32, params: &mut io_uring_params) -> io::Result<OwnedFd> {
+        self.record(SyscallRecord::Setup {
+            entries,
+            flags: params.flags,
+        });
+
+        if let Some(error_number) = self.setup_errors.lock().unwrap().pop_front() {
+            return Err(io::Error::from_raw_os_error(error_number));
+        }
+
+        let sq_entries = entries.next_power_of_two();
+        let cq_entries = 2 * sq_entries;
+
+        params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
+        params.features = IORING_FEAT_SINGLE_MMAP;
+        params.sq_off.head = 0;
+        params.sq_off.tail = 4;
+        params.cq_off.head = 8;
+        params.cq_off.tail = 12;
+        params.sq_off.ring_mask = 16;
+        params.cq_off.ring_mask = 20;
+        params.sq_off.ring_entries = 24;
+        params.cq_off.ring_entries = 28;
+        params.sq_off.dropped = 32;
+        params.sq_off.flags = 36;
+        params.cq_off.flags = 40;
+        params.cq_off.overflow = 44;
+        params.cq_off.cqes = CQES_OFFSET;
+        let cqe_size = if params.flags & IORING_SETUP_CQE32 > 0 {
+            2 * size_of::<io_uring_cqe>()
+        } else {
+            size_of::<io_uring_cqe>()
+        };
+        params.sq_off.array = CQES_OFFSET + cq_entries * cqe_size as u32;
+
+        *self.params.lock().unwrap() = Some(*params);
+
+        Ok(File::open("/dev/null")?.into())
+    }
+
+    fn completes_submissions(&self) -> bool {
+        false
+    }
+
+    unsafe fn register(
+        &self,
+        _ring_fd: &OwnedFd,
+        opcode: IoUringOpCode,
+        _arg: *const c_void,
+        nr_args: u32,
+    ) -> io::Result<i64> {
+        self.record(SyscallRecord::Register { opcode, nr_args });
+        scripted(&self.register_results, 0)
+    }
+
+    unsafe fn enter_raw(
+        &self,
+        _ring_fd: RawFd,
+        submit: u32,
+        min_complete: u32,
+        flags: IoUringEnterFlags,
+        _arg: *const c_void,
+        sz: usize,
+    ) -> io::Result<NumberOfIOsSuccessfullyConsumed> {
+        self.record(SyscallRecord::Enter {
+            submit,
+            min_complete,
+            flags,
+            arg_size: sz,
+        });
+        scripted(&self.enter_results, submit as i64)
+    }
+
+    fn mmap<'a>(
+        &self,
+        _ring_fd: &OwnedFd,
+        offset: MmapOffset,
+        len: usize,
+        options: MapOptions,
+    ) -> io::Result<MMap<'a>> {
+        self.record(SyscallRecord::Mmap {
+            offset,
+            len,
+            populate: options.populate,
+        });
+
+        let map = MMap::anonymous(len)?;
+
+        if offset == IORING_OFF_SQ_RING as MmapOffset {
+            if let Some(params) = *self.params.lock().unwrap() {
+                unsafe {
+                    write_u32(&map, params.sq_off.ring_mask, params.sq_entries - 1);
+                    write_u32(&map, params.sq_off.ring_entries, params.sq_entries);
+                    write_u32(&map, params.cq_off.ring_mask, params.cq_entries - 1);
+                    write_u32(&map, params.cq_off.ring_entries, params.cq_entries);
+                }
+            }
+        }
+
+        Ok(map)
+    }
+}
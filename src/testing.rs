@@ -0,0 +1,142 @@
+use crate::{
+    fs::File,
+    io_uring::{IoUring, IoUringParams},
+};
+use std::{
+    env::temp_dir,
+    fs::{self, OpenOptions},
+    io::{pipe, PipeReader, PipeWriter, Result},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::id,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/*
+ * Fixtures for tests of code built on the ring: a fresh ring together with
+ * the kind of fd the code under test talks to. Rings get 16 entries, plenty
+ * for a test and small enough to hit a full queue on purpose.
+ */
+const ENTRIES: u32 = 16;
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+pub fn ring() -> Result<IoUring<'static>> {
+    IoUring::initialize(ENTRIES, IoUringParams::default())
+}
+
+/*
+ * A file in the temp directory, opened for reading and writing through the
+ * ring, removed again when dropped. Names are unique per process and call,
+ * so tests running in parallel do not step on each other.
+ */
+pub struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    pub fn new(contents: &[u8]) -> Result<Self> {
+        let path = temp_dir().join(format!(
+            "vargasync-testing-{}-{}",
+            id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, contents)?;
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+        Ok(TempFile {
+            path,
+            file: File::from(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /*
+     * What is on disk right now, read without the ring so it can check what
+     * the ring wrote.
+     */
+    pub fn contents(&self) -> Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn ring_with_temp_file(contents: &[u8]) -> Result<(IoUring<'static>, TempFile)> {
+    Ok((ring()?, TempFile::new(contents)?))
+}
+
+/*
+ * Two connected unix stream sockets, one for the code under test and one
+ * for the test to play the peer.
+ */
+pub fn ring_with_socket_pair() -> Result<(IoUring<'static>, UnixStream, UnixStream)> {
+    let (local, peer) = UnixStream::pair()?;
+    Ok((ring()?, local, peer))
+}
+
+pub fn ring_with_pipe() -> Result<(IoUring<'static>, PipeReader, PipeWriter)> {
+    let (reader, writer) = pipe()?;
+    Ok((ring()?, reader, writer))
+}
+
+#[cfg(test)]
+mod when_using_the_fixtures {
+    use crate::{
+        fs::File,
+        net,
+        testing::{ring_with_pipe, ring_with_socket_pair, ring_with_temp_file},
+    };
+    use std::{
+        io::{Read, Write},
+        os::fd::OwnedFd,
+    };
+
+    #[test]
+    pub fn the_temp_file_goes_away_with_the_fixture() {
+        let (mut ring, file) = ring_with_temp_file(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+
+        assert_eq!(file.file().read_at(&mut ring, &mut buf, 0).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        file.file().write_all_at(&mut ring, b"J", 0).unwrap();
+        assert_eq!(file.contents().unwrap(), b"Jello");
+
+        let path = file.path().to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    pub fn the_socket_pair_is_connected() {
+        let (mut ring, local, mut peer) = ring_with_socket_pair().unwrap();
+
+        net::send_all(&mut ring, &local, b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    pub fn the_pipe_carries_what_is_written() {
+        let (mut ring, reader, mut writer) = ring_with_pipe().unwrap();
+        writer.write_all(b"pong").unwrap();
+
+        let reader = File::from(OwnedFd::from(reader));
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read_at(&mut ring, &mut buf, u64::MAX).unwrap(), 4);
+        assert_eq!(&buf, b"pong");
+    }
+}
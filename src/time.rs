@@ -0,0 +1,238 @@
+use crate::{
+    cqe::Completion,
+    entry::{Cqe16, CqeEntry, Sqe64, SqeEntry},
+    io_uring::IoUring,
+};
+use futures_core::Stream;
+use libc::{ECANCELED, ETIME};
+use linux_raw_sys::io_uring::__kernel_timespec;
+use log::debug;
+use std::{
+    io::{self, ErrorKind, Result},
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/*
+ * user_data of the multishot timeout of a ticker and of its cancel when the
+ * ticker is dropped. One Ticker per ring at a time.
+ */
+const TICK_USER_DATA: u64 = u64::MAX - 25;
+const TICK_CANCEL_USER_DATA: u64 = u64::MAX - 26;
+
+/*
+ * A Stream of the instants a multishot timeout fires at, every `period`,
+ * for heartbeats and periodic housekeeping. See Ticker.
+ */
+pub fn tick<'r, 'a, S: SqeEntry, C: CqeEntry>(
+    ring: &'r mut IoUring<'a, S, C>,
+    period: Duration,
+) -> Result<Ticker<'r, 'a, S, C>> {
+    if period.is_zero() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "a ticker needs a period",
+        ));
+    }
+
+    Ok(Ticker {
+        ring,
+        timespec: Box::new(__kernel_timespec {
+            tv_sec: period.as_secs() as i64,
+            tv_nsec: period.subsec_nanos() as i64,
+        }),
+        armed: false,
+    })
+}
+
+/*
+ * One timeout entry that keeps completing, each completion is a tick. When
+ * the kernel ends the timeout, i.e. it completes without CqeFlags::More, the
+ * next poll arms it again, so the stream itself never ends. Ticks that were
+ * reaped while the stream was not polled come out back to back, with the
+ * instant they were taken from the ring.
+ *
+ * poll_next submits the timeout when it is not armed and returns Pending
+ * until the next tick. Dropping the ticker cancels the timeout.
+ */
+pub struct Ticker<'r, 'a, S: SqeEntry = Sqe64, C: CqeEntry = Cqe16> {
+    ring: &'r mut IoUring<'a, S, C>,
+    timespec: Box<__kernel_timespec>,
+    armed: bool,
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Ticker<'r, 'a, S, C> {
+    pub fn period(&self) -> Duration {
+        Duration::new(self.timespec.tv_sec as u64, self.timespec.tv_nsec as u32)
+    }
+
+    /*
+     * Arms the multishot timeout unless it still is.
+     */
+    fn arm(&mut self) -> Result<()> {
+        if self.armed {
+            return Ok(());
+        }
+
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        let timespec = &*self.timespec as *const __kernel_timespec;
+        let Some(sqe) = self.ring.next_sqe() else {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "the submission queue is full",
+            ));
+        };
+        unsafe { sqe.timeout_multishot(timespec, 0) }.user_data(TICK_USER_DATA);
+        self.armed = true;
+
+        Ok(())
+    }
+
+    /*
+     * Some(instant) for a tick, None for a completion that only ended the
+     * timeout.
+     */
+    fn ticked(&mut self, completion: Completion) -> Result<Option<Instant>> {
+        if !completion.more() {
+            self.armed = false;
+        }
+
+        match completion.result {
+            result if result == -ETIME => Ok(Some(Instant::now())),
+            result if result == -ECANCELED => Ok(None),
+            result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            _ => Ok(None),
+        }
+    }
+
+    fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Result<Instant>> {
+        loop {
+            if let Err(error) = self.arm() {
+                return Poll::Ready(Err(error));
+            }
+            let completion = match self.ring.poll_completion(TICK_USER_DATA, cx) {
+                Poll::Ready(Ok(completion)) => completion,
+                /*
+                 * The ring abandoned the timeout.
+                 */
+                Poll::Ready(Err(error)) => {
+                    self.armed = false;
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            match self.ticked(completion) {
+                Ok(Some(instant)) => return Poll::Ready(Ok(instant)),
+                Ok(None) => {}
+                Err(error) => return Poll::Ready(Err(error)),
+            }
+        }
+    }
+
+    /*
+     * Cancels the timeout and waits for its last completion, the kernel
+     * reads the timespec until then.
+     */
+    fn shut_down(&mut self) -> Result<()> {
+        if !self.armed {
+            return Ok(());
+        }
+
+        if self.ring.sq_space_left() == 0 {
+            self.ring.submit()?;
+        }
+        if let Some(sqe) = self.ring.next_sqe() {
+            sqe.cancel(TICK_USER_DATA).user_data(TICK_CANCEL_USER_DATA);
+        }
+        self.ring.submit()?;
+        self.ring.wait_for_completion(TICK_CANCEL_USER_DATA)?;
+
+        while self.armed {
+            if !self.ring.wait_for_completion(TICK_USER_DATA)?.more() {
+                self.armed = false;
+            }
+        }
+        while self.ring.take_deferred_completion(TICK_USER_DATA).is_some() {}
+
+        Ok(())
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Stream for Ticker<'r, 'a, S, C> {
+    type Item = Result<Instant>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+impl<'r, 'a, S: SqeEntry, C: CqeEntry> Drop for Ticker<'r, 'a, S, C> {
+    fn drop(&mut self) {
+        if let Err(error) = self.shut_down() {
+            debug!("could not cancel the ticker: {}", error);
+            /*
+             * The kernel may still read the timespec.
+             */
+            mem::forget(mem::replace(
+                &mut self.timespec,
+                Box::new(unsafe { mem::zeroed() }),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_ticking {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        time::tick,
+    };
+    use futures::executor::block_on_stream;
+    use std::{
+        io::ErrorKind,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn ticks_come_a_period_apart() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let start = Instant::now();
+
+        let ticks: Vec<Instant> =
+            block_on_stream(tick(&mut ring, Duration::from_millis(10)).unwrap())
+                .take(3)
+                .map(|tick| tick.unwrap())
+                .collect();
+
+        assert!(ticks.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(ticks[2] - start >= Duration::from_millis(30));
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn other_completions_are_set_aside() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.next_sqe().unwrap().nop().user_data(5);
+
+        {
+            let mut ticks = block_on_stream(tick(&mut ring, Duration::from_millis(5)).unwrap());
+            ticks.next().unwrap().unwrap();
+        }
+
+        assert_eq!(ring.next_completion().unwrap().user_data, 5);
+    }
+
+    #[test]
+    pub fn a_zero_period_is_refused() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+
+        let error = tick(&mut ring, Duration::ZERO).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{cqe::Completion, opcode::IoUringOperation};
+use linux_raw_sys::io_uring::io_uring_sqe;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Result, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmissionRecord {
+    pub opcode: u8,
+    pub fd: i32,
+    pub len: u32,
+    pub offset: u64,
+    pub flags: u8,
+    pub user_data: u64,
+}
+
+impl From<&io_uring_sqe> for SubmissionRecord {
+    fn from(sqe: &io_uring_sqe) -> Self {
+        SubmissionRecord {
+            opcode: sqe.opcode,
+            fd: sqe.fd,
+            len: sqe.len,
+            offset: unsafe { sqe.__bindgen_anon_1.off },
+            flags: sqe.flags,
+            user_data: sqe.user_data,
+        }
+    }
+}
+
+impl SubmissionRecord {
+    /*
+     * The operation of the entry, None for opcodes this crate does not know.
+     */
+    pub fn operation(&self) -> Option<IoUringOperation> {
+        IoUringOperation::try_from(self.opcode).ok()
+    }
+}
+
+impl Display for SubmissionRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.operation() {
+            Some(operation) => write!(f, "opcode={:?}", operation)?,
+            None => write!(f, "opcode={}", self.opcode)?,
+        }
+        write!(
+            f,
+            " fd={} len={} offset={} flags={:#x} user_data={}",
+            self.fd, self.len, self.offset, self.flags, self.user_data
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    Submitted(SubmissionRecord),
+    Completed(Completion),
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::Submitted(record) => write!(f, "submit {}", record),
+            TraceEvent::Completed(completion) => write!(
+                f,
+                "complete result={} flags={:#x} user_data={}",
+                completion.result, completion.flags, completion.user_data
+            ),
+        }
+    }
+}
+
+/*
+ * Keeps the last `capacity` submissions and completions that went through
+ * the ring, optionally also appending every event to a file so a hung
+ * process can be inspected from the outside.
+ */
+pub struct Tracer {
+    events: VecDeque<TraceEvent>,
+    capacity: usize,
+    file: Option<BufWriter<File>>,
+}
+
+impl Tracer {
+    pub fn in_memory(capacity: usize) -> Self {
+        Tracer {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            file: None,
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(capacity: usize, path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Tracer {
+            file: Some(BufWriter::new(file)),
+            ..Self::in_memory(capacity)
+        })
+    }
+
+    pub(crate) fn record(&mut self, event: TraceEvent) {
+        if let Some(file) = &mut self.file {
+            /*
+             * Tracing must never take the ring down, a failed write only
+             * costs the file copy of the event.
+             */
+            let _ = writeln!(file, "{}", event).and_then(|_| file.flush());
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter()
+    }
+
+    /*
+     * Submissions still waiting for a completion, the first thing to look at
+     * when a request never comes back.
+     */
+    pub fn pending(&self) -> Vec<SubmissionRecord> {
+        let mut outstanding: HashMap<u64, VecDeque<SubmissionRecord>> = HashMap::new();
+        let mut order = Vec::new();
+
+        for event in &self.events {
+            match event {
+                TraceEvent::Submitted(record) => {
+                    outstanding
+                        .entry(record.user_data)
+                        .or_default()
+                        .push_back(*record);
+                    order.push(record.user_data);
+                }
+                TraceEvent::Completed(completion) => {
+                    if let Some(records) = outstanding.get_mut(&completion.user_data) {
+                        records.pop_front();
+                    }
+                }
+            }
+        }
+
+        order.dedup();
+        order
+            .into_iter()
+            .flat_map(|user_data| outstanding.remove(&user_data).unwrap_or_default())
+            .collect()
+    }
+
+    pub fn dump<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for event in &self.events {
+            writeln!(writer, "{}", event)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod when_tracing_the_ring {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        opcode::IoUringOperation,
+        trace::{TraceEvent, Tracer},
+    };
+
+    #[test]
+    pub fn submissions_and_completions_are_recorded() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(16));
+        ring.next_sqe().unwrap().nop().user_data(3);
+        ring.submit_and_wait(1).unwrap();
+        ring.next_completion().unwrap();
+
+        let events: Vec<TraceEvent> = ring.tracer().unwrap().events().copied().collect();
+
+        assert!(
+            matches!(events[0], TraceEvent::Submitted(record) if record.user_data == 3 && record.opcode == IoUringOperation::Nop as u8)
+        );
+        assert!(
+            matches!(events[1], TraceEvent::Completed(completion) if completion.user_data == 3)
+        );
+        assert!(ring.tracer().unwrap().pending().is_empty());
+    }
+
+    #[test]
+    pub fn unreaped_submissions_are_pending() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(16));
+        ring.next_sqe().unwrap().nop().user_data(1);
+        ring.next_sqe().unwrap().nop().user_data(2);
+        ring.submit_and_wait(2).unwrap();
+        ring.next_completion().unwrap();
+
+        let pending = ring.tracer().unwrap().pending();
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].user_data, 2);
+    }
+
+    #[test]
+    pub fn only_the_last_events_are_kept() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        ring.enable_tracing(Tracer::in_memory(2));
+        for user_data in 0..3 {
+            ring.next_sqe().unwrap().nop().user_data(user_data);
+        }
+        ring.submit().unwrap();
+
+        let mut dump = Vec::new();
+        ring.tracer().unwrap().dump(&mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+
+        assert_eq!(dump.lines().count(), 2);
+        assert!(dump.contains("user_data=2"));
+        assert!(!dump.contains("user_data=0"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{io_uring::UNPARK_USER_DATA, sqe::Sqe, syscalls::io_uring_send_msg_ring};
+use libc::eventfd_write;
+use linux_raw_sys::io_uring::io_uring_sqe;
+use std::{
+    io::{self, Result},
+    mem::zeroed,
+    os::fd::{AsFd, AsRawFd, OwnedFd},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+enum Target {
+    EventFd(OwnedFd),
+    MsgRing(OwnedFd),
+}
+
+/*
+ * Wakes a thread parked on a ring from any other thread, e.g. the sender
+ * of a channel whose receiver runs the uring loop, without signals. Clones
+ * wake the same thread.
+ *
+ * With an eventfd the thread parks on the eventfd registered with
+ * IoUring::register_eventfd, through epoll or a poll entry, and unpark
+ * writes to it. With msg_ring the thread parks in submit_and_wait and
+ * unpark posts a completion to its ring. The ring keeps that completion to
+ * itself: the wait returns, and the thread checks what it was woken for.
+ */
+#[derive(Debug, Clone)]
+pub struct Unparker {
+    target: Arc<Target>,
+}
+
+impl Unparker {
+    /*
+     * Wakes the thread through `eventfd`, usually a copy of the fd
+     * registered with the ring.
+     */
+    pub fn eventfd(eventfd: OwnedFd) -> Self {
+        Unparker {
+            target: Arc::new(Target::EventFd(eventfd)),
+        }
+    }
+
+    /*
+     * Wakes the thread waiting on `ring` with a MSG_RING completion, sent
+     * without a ring of the waking thread. Needs kernel 6.13. The unparker
+     * holds a copy of the ring fd, which keeps the ring alive as long as
+     * one of its clones is.
+     */
+    pub fn msg_ring(ring: &impl AsFd) -> Result<Self> {
+        let ring_fd = ring.as_fd().try_clone_to_owned()?;
+
+        Ok(Unparker {
+            target: Arc::new(Target::MsgRing(ring_fd)),
+        })
+    }
+
+    pub fn unpark(&self) -> Result<()> {
+        match &*self.target {
+            Target::EventFd(eventfd) => {
+                if unsafe { eventfd_write(eventfd.as_raw_fd(), 1) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            }
+            Target::MsgRing(ring_fd) => {
+                let mut raw: io_uring_sqe = unsafe { zeroed() };
+                Sqe::new(&mut raw, None, false).msg_ring(ring_fd.as_raw_fd(), 0, UNPARK_USER_DATA);
+                unsafe { io_uring_send_msg_ring(&raw) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod when_unparking_a_ring_thread {
+    use crate::{
+        cqe::Completions,
+        io_uring::{IoUring, IoUringParams},
+        unpark::Unparker,
+    };
+    use libc::{eventfd, eventfd_read, eventfd_t, EFD_CLOEXEC};
+    use std::{
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn a_posted_completion_ends_the_wait() {
+        let mut ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let unparker = ring.unparker().unwrap();
+
+        let waker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            unparker.unpark().unwrap();
+        });
+        ring.submit_and_wait(1).unwrap();
+        waker.join().unwrap();
+
+        assert!(ring.next_completion().is_none());
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    pub fn the_registered_eventfd_is_signalled() {
+        let ring = IoUring::initialize(4, IoUringParams::default()).unwrap();
+        let fd = unsafe { OwnedFd::from_raw_fd(eventfd(0, EFD_CLOEXEC)) };
+        ring.register_eventfd(&fd).unwrap();
+        let unparker = Unparker::eventfd(fd.try_clone().unwrap());
+
+        thread::spawn(move || unparker.unpark().unwrap())
+            .join()
+            .unwrap();
+
+        let mut value: eventfd_t = 0;
+        assert_eq!(unsafe { eventfd_read(fd.as_raw_fd(), &mut value) }, 0);
+        assert_eq!(value, 1);
+        ring.unregister_eventfd().unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use libc::{eventfd, eventfd_write, poll, pollfd, EFD_CLOEXEC, EINTR, POLLIN};
+use log::debug;
+use std::{
+    io::{self, Result},
+    mem::take,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    sync::{Arc, Condvar, Mutex},
+    task::Waker,
+    thread::{self, JoinHandle},
+};
+
+#[derive(Default)]
+struct Waiting {
+    wakers: Vec<Waker>,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    waiting: Mutex<Waiting>,
+    registered: Condvar,
+}
+
+/*
+ * Wakes the futures of a ring that is not driven from a reactor when it has
+ * completions to reap. The ring fd polls readable while completions are
+ * waiting, so a thread of its own polls a copy of it whenever a future
+ * registered its waker, and wakes them all. Futures then reap through the
+ * ring as usual, none of them blocks in io_uring_enter.
+ */
+pub(crate) struct CompletionWatcher {
+    shared: Arc<Shared>,
+    stop: OwnedFd,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CompletionWatcher {
+    pub(crate) fn new(ring_fd: &OwnedFd) -> Result<Self> {
+        let ring_fd = ring_fd.try_clone()?;
+        let stop = unsafe { eventfd(0, EFD_CLOEXEC) };
+        if stop < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stop = unsafe { OwnedFd::from_raw_fd(stop) };
+
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = shared.clone();
+            let stop = stop.try_clone()?;
+            thread::Builder::new()
+                .name("uring-watcher".to_string())
+                .spawn(move || watch(&shared, &ring_fd, &stop))?
+        };
+
+        Ok(CompletionWatcher {
+            shared,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /*
+     * Has `waker` woken the next time the ring has completions, or now if it
+     * has some already.
+     */
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut waiting = self.shared.waiting.lock().unwrap();
+        if !waiting.wakers.iter().any(|known| known.will_wake(waker)) {
+            waiting.wakers.push(waker.clone());
+        }
+        self.shared.registered.notify_one();
+    }
+
+    /*
+     * Wakes the futures registered so far, e.g. once completions they wait
+     * for were reaped and set aside, where the ring fd no longer tells about
+     * them.
+     */
+    pub(crate) fn wake_all(&self) {
+        let wakers = take(&mut self.shared.waiting.lock().unwrap().wakers);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Drop for CompletionWatcher {
+    fn drop(&mut self) {
+        self.shared.waiting.lock().unwrap().stopped = true;
+        self.shared.registered.notify_one();
+        if unsafe { eventfd_write(self.stop.as_raw_fd(), 1) } < 0 {
+            debug!(
+                "could not stop the completion watcher: {}",
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(shared: &Shared, ring_fd: &OwnedFd, stop: &OwnedFd) {
+    loop {
+        {
+            let mut waiting = shared.waiting.lock().unwrap();
+            while waiting.wakers.is_empty() && !waiting.stopped {
+                waiting = shared.registered.wait(waiting).unwrap();
+            }
+            if waiting.stopped {
+                return;
+            }
+        }
+
+        let mut fds = [
+            pollfd {
+                fd: ring_fd.as_raw_fd(),
+                events: POLLIN,
+                revents: 0,
+            },
+            pollfd {
+                fd: stop.as_raw_fd(),
+                events: POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { poll(fds.as_mut_ptr(), fds.len() as _, -1) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(EINTR) {
+                continue;
+            }
+            debug!("the completion watcher stopped: {}", error);
+            return;
+        }
+        if fds[1].revents != 0 {
+            return;
+        }
+
+        let wakers = take(&mut shared.waiting.lock().unwrap().wakers);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
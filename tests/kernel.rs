@@ -0,0 +1,138 @@
+/*
+ * Smoke tests of the crate against the running kernel, behind the
+ * kernel-tests feature:
+ *
+ *     cargo test --features kernel-tests --test kernel
+ *
+ * Each test is skipped when io_uring is not available, io_uring_setup
+ * failing with ENOSYS or EPERM, e.g. in containers with it disabled.
+ */
+#![cfg(feature = "kernel-tests")]
+
+use bounded::{
+    cqe::Completions,
+    io_uring::{IoUring, IoUringParams},
+    net::{accept, connect_timeout},
+};
+use futures::executor::block_on;
+use libc::{ECANCELED, ENOSYS, EPERM};
+use std::{
+    fs::{remove_file, File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    net::TcpListener,
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
+
+fn ring() -> Option<IoUring<'static>> {
+    match IoUring::initialize(8, IoUringParams::default()) {
+        Ok(ring) => Some(ring),
+        Err(error) if matches!(error.raw_os_error(), Some(ENOSYS) | Some(EPERM)) => {
+            eprintln!("io_uring is not available, skipped: {}", error);
+            None
+        }
+        Err(error) => panic!("could not set up a ring: {}", error),
+    }
+}
+
+#[test]
+pub fn a_file_written_through_the_ring_reads_back() {
+    let Some(mut ring) = ring() else { return };
+    let path = std::env::temp_dir().join(format!("vargasync-kernel-{}", std::process::id()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let mut read_back = [0u8; 11];
+
+    unsafe {
+        ring.next_sqe()
+            .unwrap()
+            .write(fd, b"hello uring".as_ptr(), 11, 0)
+    }
+    .user_data(1);
+    ring.submit_and_wait(1).unwrap();
+    assert_eq!(ring.next_completion().unwrap().result, 11);
+    unsafe {
+        ring.next_sqe()
+            .unwrap()
+            .read(fd, read_back.as_mut_ptr(), 11, 0)
+    }
+    .user_data(2);
+    ring.submit_and_wait(1).unwrap();
+    assert_eq!(ring.next_completion().unwrap().result, 11);
+
+    assert_eq!(&read_back, b"hello uring");
+    let mut contents = String::new();
+    File::open(&path)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "hello uring");
+    remove_file(path).unwrap();
+}
+
+#[test]
+pub fn a_loopback_connection_is_accepted() {
+    let Some(mut ring) = ring() else { return };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut client = connect_timeout(&mut ring, &addr, Duration::from_secs(1)).unwrap();
+    let (server, peer) = block_on(accept(&mut ring, &listener)).unwrap();
+    client.write_all(b"ping").unwrap();
+
+    assert_eq!(peer, client.local_addr().unwrap());
+    let mut received = [0u8; 4];
+    let mut server = std::net::TcpStream::from(server);
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"ping");
+}
+
+#[test]
+pub fn a_timed_out_operation_takes_about_its_timeout() {
+    let Some(mut ring) = ring() else { return };
+    let (reader, _writer) = std::io::pipe().unwrap();
+    let fd = reader.as_raw_fd();
+    let mut buffer = [0u8; 1];
+    let timeout = Duration::from_millis(50);
+
+    let start = Instant::now();
+    let error = ring
+        .op(|sqe| unsafe { sqe.read(fd, buffer.as_mut_ptr(), 1, 0) })
+        .timeout(timeout)
+        .run()
+        .unwrap_err();
+    let elapsed = start.elapsed();
+
+    assert_eq!(error.kind(), ErrorKind::TimedOut);
+    assert!(elapsed >= timeout);
+    assert!(elapsed < timeout * 10);
+}
+
+#[test]
+pub fn a_pending_operation_is_cancelled() {
+    let Some(mut ring) = ring() else { return };
+    let (reader, _writer) = std::io::pipe().unwrap();
+    let mut buffer = [0u8; 1];
+
+    unsafe {
+        ring.next_sqe()
+            .unwrap()
+            .read(reader.as_raw_fd(), buffer.as_mut_ptr(), 1, 0)
+    }
+    .user_data(1);
+    ring.submit().unwrap();
+    ring.next_sqe().unwrap().cancel(1).user_data(2);
+    ring.submit_and_wait(2).unwrap();
+
+    let mut completions: Vec<_> = std::iter::from_fn(|| ring.next_completion())
+        .map(|completion| (completion.user_data, completion.result))
+        .collect();
+    completions.sort();
+    assert_eq!(completions, [(1, -ECANCELED), (2, 0)]);
+}